@@ -0,0 +1,102 @@
+use std::collections::HashSet;
+
+use crate::history::ArchiveEntry;
+use crate::ytdlp;
+
+const CSV_HEADER: &str = "extractor,id,format,job_id,url,title,output_path,downloaded_at";
+
+// yt-dlp's --download-archive format: "<extractor> <id>" on a line per video
+pub fn to_ytdlp(entries: &[ArchiveEntry]) -> String {
+    let mut seen = HashSet::new();
+    entries.iter()
+        .filter(|entry| seen.insert(entry.archive_key.as_str()))
+        .map(|entry| format!("{}\n", entry.archive_key))
+        .collect()
+}
+
+// A row per video and format downloaded, with what the history has on its job
+pub fn to_csv(entries: &[ArchiveEntry]) -> String {
+    let mut csv = format!("{}\n", CSV_HEADER);
+    for entry in entries {
+        let (extractor, id) = entry.archive_key.split_once(' ').unwrap_or(("", &entry.archive_key));
+        let job_id = entry.job_id.to_string();
+        let downloaded_at = entry.downloaded_at.to_string();
+        let fields = [
+            extractor,
+            id,
+            &entry.format,
+            &job_id,
+            entry.url.as_deref().unwrap_or_default(),
+            entry.title.as_deref().unwrap_or_default(),
+            &entry.output_path,
+            &downloaded_at,
+        ];
+        let row: Vec<String> = fields.iter().map(|field| csv_field(field)).collect();
+        csv.push_str(&row.join(","));
+        csv.push('\n');
+    }
+    csv
+}
+
+fn csv_field(field: &str) -> String {
+    // Rows stay on one line so each can be read back on its own
+    let field = field.replace(['\r', '\n'], " ");
+    if field.contains([',', '"']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field
+    }
+}
+
+// Splits a line of CSV, with fields quoted the way csv_field quotes them
+fn csv_fields(line: &str) -> Vec<String> {
+    let mut fields = vec![String::new()];
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        let field = fields.last_mut().unwrap();
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(String::new()),
+            c => field.push(c),
+        }
+    }
+    fields
+}
+
+// Archive keys, with when they were downloaded if the file says, from a yt-dlp download archive
+// or a CSV export. Also returns how many lines were neither.
+pub fn parse(text: &str) -> (Vec<(String, Option<i64>)>, usize) {
+    let mut lines = text.lines().map(str::trim).filter(|line| !line.is_empty()).peekable();
+    let csv = lines.peek().is_some_and(|line| line.starts_with("extractor,id,"));
+    if csv {
+        lines.next();
+    }
+    let mut entries = Vec::new();
+    let mut skipped = 0;
+    for line in lines {
+        let entry = if csv {
+            let fields = csv_fields(line);
+            match &fields[..] {
+                [extractor, id, .., downloaded_at] => {
+                    ytdlp::archive_key(extractor, id).map(|key| (key, downloaded_at.parse().ok()))
+                }
+                _ => None,
+            }
+        } else {
+            match line.split_whitespace().collect::<Vec<_>>()[..] {
+                [extractor, id] => ytdlp::archive_key(extractor, id).map(|key| (key, None)),
+                _ => None,
+            }
+        };
+        match entry {
+            Some(entry) => entries.push(entry),
+            None => skipped += 1,
+        }
+    }
+    (entries, skipped)
+}
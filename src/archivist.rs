@@ -0,0 +1,72 @@
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::jobs::JobId;
+use crate::verify::Verified;
+
+// What yt-dlp is asked for besides the video, so a copy can be kept as the site had it
+pub const YTDLP_ARGS: &[&str] = &["--write-info-json", "--write-thumbnail", "--write-description", "--write-comments"];
+
+// Jobs download here, inside the output directory, until their files are filed away
+pub const INCOMING_DIR: &str = "incoming";
+
+// Where files are filed away, inside the output directory
+const CONTENT_DIR: &str = "sha256";
+
+pub fn output_template(id: JobId) -> String {
+    format!("{}/job-{}/%(title)s [%(id)s].%(ext)s", INCOMING_DIR, id)
+}
+
+// Moves each downloaded file, and the metadata yt-dlp wrote beside it, into a directory named
+// after the file's SHA-256: sha256/<first two digits>/<digest>/. Downloading the same file
+// again leaves the copy already there and refreshes its metadata. Returns where the files
+// ended up.
+pub fn file_away(output_dir: &str, files: Vec<PathBuf>, verified: &[Verified]) -> Result<Vec<PathBuf>> {
+    let mut filed = Vec::new();
+    let mut incoming = Vec::new();
+    for file in files {
+        let Some(checked) = verified.iter().find(|checked| checked.path == file) else {
+            filed.push(file);
+            continue;
+        };
+        let digest = &checked.sha256;
+        let dir = Path::new(output_dir).join(CONTENT_DIR).join(&digest[..2]).join(digest);
+        fs::create_dir_all(&dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+        for path in with_metadata(&file) {
+            let target = dir.join(path.file_name().unwrap_or_default());
+            if path == file && target.exists() {
+                fs::remove_file(&path).with_context(|| format!("Failed to remove {}", path.display()))?;
+                continue;
+            }
+            fs::rename(&path, &target)
+                .with_context(|| format!("Failed to move {} to {}", path.display(), target.display()))?;
+        }
+        if let Some(parent) = file.parent() {
+            incoming.push(parent.to_path_buf());
+        }
+        filed.push(dir.join(file.file_name().unwrap_or_default()));
+    }
+    // Only empty directories go, so nothing another job is still writing is lost
+    for dir in incoming {
+        let _ = fs::remove_dir(&dir);
+    }
+    Ok(filed)
+}
+
+// The file and what yt-dlp wrote beside it under the same name: <name>.info.json,
+// <name>.description and the thumbnail
+fn with_metadata(file: &Path) -> Vec<PathBuf> {
+    let mut paths = vec![file.to_path_buf()];
+    let (Some(dir), Some(stem)) = (file.parent(), file.file_stem().map(|stem| stem.to_string_lossy().into_owned())) else {
+        return paths;
+    };
+    let prefix = format!("{}.", stem);
+    if let Ok(entries) = fs::read_dir(dir) {
+        paths.extend(entries.flatten()
+            .map(|entry| entry.path())
+            .filter(|path| path != file && path.is_file())
+            .filter(|path| path.file_name().is_some_and(|name| name.to_string_lossy().starts_with(&prefix))));
+    }
+    paths
+}
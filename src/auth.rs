@@ -0,0 +1,127 @@
+use serenity::http::Http;
+use serenity::model::channel::Channel;
+use serenity::model::guild::Member;
+use serenity::model::id::{ChannelId, GuildId, RoleId, UserId};
+use serenity::model::permissions::Permissions;
+use std::collections::{HashMap, HashSet};
+
+// Members with these permissions count as admins of their own guild
+pub const MODERATOR_PERMISSIONS: Permissions = Permissions::ADMINISTRATOR.union(Permissions::MANAGE_MESSAGES);
+
+// What a user may do, from least to most
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Access {
+    // Blocked, or missing every allowed role
+    Denied,
+    // May request downloads and cancel their own
+    User,
+    // May also act on anyone's downloads in their guild and change its settings
+    Admin,
+    // Has one of admin_roles: may act on every guild's downloads and use the commands that
+    // change the whole bot
+    Operator,
+}
+
+impl Access {
+    // Whether they may cancel, pin, retry or reprioritize a download requested by `owner` in
+    // `owner_guild`, from `guild`
+    pub fn covers(self, user: UserId, guild: Option<GuildId>, owner: UserId, owner_guild: Option<GuildId>) -> bool {
+        user == owner || self == Access::Operator || self == Access::Admin && guild.is_some() && guild == owner_guild
+    }
+}
+
+pub struct Authorizer {
+    allowed_roles: HashSet<RoleId>,
+    admin_roles: HashSet<RoleId>,
+    // May pass yt-dlp flags with their requests
+    trusted_roles: HashSet<RoleId>,
+    blocked_users: HashSet<UserId>,
+    // Channels with their own allowed roles instead of allowed_roles
+    channel_roles: HashMap<ChannelId, HashSet<RoleId>>,
+    // Users who may request downloads in direct messages
+    dm_users: HashSet<UserId>,
+}
+
+impl Authorizer {
+    pub fn new(
+        allowed_roles: &[u64],
+        admin_roles: &[u64],
+        trusted_roles: &[u64],
+        blocked_users: &[u64],
+        channel_roles: HashMap<u64, Vec<u64>>,
+        dm_users: &[u64],
+    ) -> Self {
+        Authorizer {
+            allowed_roles: allowed_roles.iter().copied().map(RoleId::new).collect(),
+            admin_roles: admin_roles.iter().copied().map(RoleId::new).collect(),
+            trusted_roles: trusted_roles.iter().copied().map(RoleId::new).collect(),
+            blocked_users: blocked_users.iter().copied().map(UserId::new).collect(),
+            channel_roles: channel_roles.into_iter()
+                .map(|(channel, roles)| (ChannelId::new(channel), roles.into_iter().map(RoleId::new).collect()))
+                .collect(),
+            dm_users: dm_users.iter().copied().map(UserId::new).collect(),
+        }
+    }
+
+    pub fn is_trusted(&self, roles: &[RoleId]) -> bool {
+        roles.iter().any(|role| self.trusted_roles.contains(role))
+    }
+
+    pub fn dms_enabled(&self) -> bool {
+        !self.dm_users.is_empty()
+    }
+
+    // Direct messages have no roles, so only the dm_users allowlist counts
+    pub fn dm_access(&self, user: UserId) -> Access {
+        if !self.blocked_users.contains(&user) && self.dm_users.contains(&user) {
+            Access::User
+        } else {
+            Access::Denied
+        }
+    }
+
+    // With no allowed_roles configured everyone who isn't blocked is a user. A channel's own
+    // roles win over the guild's, which win over allowed_roles.
+    pub fn access(
+        &self,
+        user: UserId,
+        channel: ChannelId,
+        roles: &[RoleId],
+        moderator: bool,
+        guild_roles: Option<&[u64]>,
+    ) -> Access {
+        let guild_roles: Option<HashSet<RoleId>> = guild_roles.map(|roles| roles.iter().copied().map(RoleId::new).collect());
+        let allowed_roles = self.channel_roles.get(&channel).or(guild_roles.as_ref()).unwrap_or(&self.allowed_roles);
+        if self.blocked_users.contains(&user) {
+            Access::Denied
+        } else if roles.iter().any(|role| self.admin_roles.contains(role)) {
+            Access::Operator
+        } else if moderator {
+            Access::Admin
+        } else if allowed_roles.is_empty() || roles.iter().any(|role| allowed_roles.contains(role)) {
+            Access::User
+        } else {
+            Access::Denied
+        }
+    }
+}
+
+// Whether the member has MODERATOR_PERMISSIONS in the channel; needs the API
+// because gateway events other than interactions don't include permissions.
+pub async fn can_moderate(http: &Http, channel: ChannelId, member: &Member) -> bool {
+    let guild = match member.guild_id.to_partial_guild(http).await {
+        Ok(guild) => guild,
+        Err(e) => {
+            log::warn!("Failed to look up guild {}: {}", member.guild_id, e);
+            return false;
+        }
+    };
+    match channel.to_channel(http).await {
+        Ok(Channel::Guild(channel)) => guild.user_permissions_in(&channel, member).intersects(MODERATOR_PERMISSIONS),
+        Ok(_) => false,
+        Err(e) => {
+            log::warn!("Failed to look up channel {}: {}", channel, e);
+            false
+        }
+    }
+}
@@ -0,0 +1,115 @@
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+
+// One [[quiet_hours]] entry in the config
+#[derive(Debug, Clone, Deserialize)]
+pub struct QuietHoursSettings {
+    // "HH:MM" in UTC; a window may wrap past midnight, e.g. 22:00 to 06:00
+    pub start: String,
+    pub end: String,
+    // Throttle downloads to this rate during the window instead of holding them until it ends
+    pub rate_limit: Option<String>,
+}
+
+struct Window {
+    // Minutes since midnight
+    start: u32,
+    end: u32,
+    rate_limit: Option<String>,
+}
+
+impl Window {
+    fn contains(&self, minute: u32) -> bool {
+        if self.start < self.end {
+            (self.start..self.end).contains(&minute)
+        } else {
+            minute >= self.start || minute < self.end
+        }
+    }
+}
+
+// What starting a download right now is limited to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Allowance {
+    // With the rate to pass to --limit-rate, if any
+    Start(Option<String>),
+    // Inside a quiet hours window without a rate limit, which ends at this Unix time
+    DeferUntil(i64),
+}
+
+pub struct Bandwidth {
+    rate_limit: Option<String>,
+    windows: Vec<Window>,
+}
+
+impl Bandwidth {
+    pub fn new(rate_limit: Option<&str>, quiet_hours: &[QuietHoursSettings]) -> Result<Self> {
+        let rate_limit = rate_limit.map(parse_rate).transpose().context("Invalid rate_limit")?;
+        let windows = quiet_hours.iter()
+            .map(|window| {
+                let parsed = Window {
+                    start: parse_time(&window.start)?,
+                    end: parse_time(&window.end)?,
+                    rate_limit: window.rate_limit.as_deref().map(parse_rate).transpose()?,
+                };
+                if parsed.start == parsed.end {
+                    bail!("the window is empty");
+                }
+                Ok(parsed)
+            })
+            .enumerate()
+            .map(|(index, window)| window.with_context(|| format!("Invalid quiet_hours entry {}", index + 1)))
+            .collect::<Result<_>>()?;
+        Ok(Bandwidth { rate_limit, windows })
+    }
+
+    // Windows that defer downloads win over ones that only throttle them
+    pub fn check(&self, now: i64) -> Allowance {
+        let secs_of_day = now.rem_euclid(86_400);
+        let minute = (secs_of_day / 60) as u32;
+        let active = || self.windows.iter().filter(|window| window.contains(minute));
+        if let Some(window) = active().find(|window| window.rate_limit.is_none()) {
+            let until = (i64::from(window.end) * 60 - secs_of_day).rem_euclid(86_400);
+            return Allowance::DeferUntil(now + until);
+        }
+        match active().find_map(|window| window.rate_limit.clone()) {
+            Some(rate_limit) => Allowance::Start(Some(rate_limit)),
+            None => Allowance::Start(self.rate_limit.clone()),
+        }
+    }
+}
+
+// A rate as yt-dlp's --limit-rate takes it, e.g. "500K" or "4.2M" bytes per second
+fn parse_rate(rate: &str) -> Result<String> {
+    let rate = rate.trim();
+    let number = rate.strip_suffix(|c: char| "kKmMgG".contains(c)).unwrap_or(rate);
+    if number.parse::<f64>().ok().filter(|&value| value > 0.0).is_none() {
+        bail!("'{}' isn't a rate like 500K or 4.2M", rate);
+    }
+    Ok(rate.to_string())
+}
+
+// A rate like 500K in bytes per second, counting K as 1024 like yt-dlp does
+pub fn rate_bytes(rate: &str) -> Option<f64> {
+    let rate = rate.trim();
+    let (number, unit) = match rate.char_indices().last()? {
+        (index, c) if c.is_ascii_alphabetic() => (&rate[..index], c.to_ascii_lowercase()),
+        _ => (rate, ' '),
+    };
+    let scale = match unit {
+        'k' => 1024.0,
+        'm' => 1024.0 * 1024.0,
+        'g' => 1024.0 * 1024.0 * 1024.0,
+        _ => 1.0,
+    };
+    number.parse::<f64>().ok().filter(|&value| value > 0.0).map(|value| value * scale)
+}
+
+fn parse_time(time: &str) -> Result<u32> {
+    let parsed = time.split_once(':').and_then(|(hours, minutes)| {
+        let hours: u32 = hours.parse().ok().filter(|&hours| hours < 24)?;
+        let minutes: u32 = minutes.parse().ok().filter(|&minutes| minutes < 60)?;
+        Some(hours * 60 + minutes)
+    });
+    parsed.with_context(|| format!("'{}' isn't a time like 08:30", time))
+}
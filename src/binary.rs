@@ -0,0 +1,162 @@
+use anyhow::{bail, Context, Result};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+const RELEASES: &str = "https://github.com/yt-dlp/yt-dlp/releases/latest/download";
+
+static YTDLP: OnceLock<YtDlp> = OnceLock::new();
+
+#[derive(Debug)]
+struct YtDlp {
+    path: PathBuf,
+    // Downloaded by the bot, so it may replace the file itself when updating
+    managed: bool,
+}
+
+// The yt-dlp executable every command runs; plain "yt-dlp" until `init` has located it
+pub fn path() -> &'static Path {
+    YTDLP.get().map_or(Path::new("yt-dlp"), |ytdlp| ytdlp.path.as_path())
+}
+
+// Finds yt-dlp at the configured path or on PATH, or else downloads the latest release
+// into `managed_dir`, and logs its version.
+pub async fn init(configured: Option<&str>, managed_dir: &str, download: bool) -> Result<()> {
+    let managed_path = Path::new(managed_dir).join(release_asset());
+    let ytdlp = if let Some(configured) = configured {
+        let path = PathBuf::from(configured);
+        if !path.is_file() {
+            bail!("ytdlp_path does not exist: {}", configured);
+        }
+        YtDlp { path, managed: false }
+    } else if let Some(path) = find_on_path(if cfg!(windows) { "yt-dlp.exe" } else { "yt-dlp" }) {
+        YtDlp { path, managed: false }
+    } else if managed_path.is_file() {
+        YtDlp { path: managed_path, managed: true }
+    } else if download {
+        log::info!("yt-dlp not found, downloading it to {}", managed_path.display());
+        fetch_release(&managed_path).await?;
+        YtDlp { path: managed_path, managed: true }
+    } else {
+        bail!("yt-dlp was not found on PATH; install it or set ytdlp_path");
+    };
+    let version = version_of(&ytdlp.path).await
+        .with_context(|| format!("Failed to run {}", ytdlp.path.display()))?;
+    log::info!("Using yt-dlp {} at {}", version, ytdlp.path.display());
+    let _ = YTDLP.set(ytdlp);
+    Ok(())
+}
+
+// Locates another program the bot runs, given in the `setting` as a name on PATH or a path,
+// so a missing one fails at startup rather than on every download
+pub fn find_executable(setting: &str, configured: &str) -> Result<PathBuf> {
+    let path = Path::new(configured);
+    if path.components().count() > 1 {
+        if !path.is_file() {
+            bail!("{} does not exist: {}", setting, configured);
+        }
+        return Ok(path.to_path_buf());
+    }
+    let name = if cfg!(windows) && path.extension().is_none() {
+        format!("{}.exe", configured)
+    } else {
+        configured.to_string()
+    };
+    find_on_path(&name)
+        .with_context(|| format!("{} was not found on PATH: {}", setting, configured))
+}
+
+pub async fn version() -> Result<String> {
+    version_of(path()).await
+}
+
+// Updates yt-dlp in place and returns the version it's at afterwards. `yt-dlp -U` handles
+// release binaries; a copy the bot downloaded itself is replaced if that fails.
+pub async fn update() -> Result<String> {
+    let output = tokio::process::Command::new(path())
+        .arg("-U")
+        .output()
+        .await
+        .context("Failed to run yt-dlp -U")?;
+    let managed = YTDLP.get().is_some_and(|ytdlp| ytdlp.managed);
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        if !managed {
+            bail!("yt-dlp -U failed: {}", stderr.trim());
+        }
+        log::warn!("yt-dlp -U failed, downloading the latest release instead: {}", stderr.trim());
+        fetch_release(path()).await?;
+    }
+    version().await
+}
+
+async fn version_of(path: &Path) -> Result<String> {
+    let output = tokio::process::Command::new(path)
+        .arg("--version")
+        .output()
+        .await?;
+    if !output.status.success() {
+        bail!("yt-dlp --version failed with status: {}", output.status);
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_owned())
+}
+
+// Also looks where package managers put programs, which often isn't on the PATH a service
+// gets: Homebrew's directories on macOS, and winget's links and Scoop's shims on Windows
+fn find_on_path(name: &str) -> Option<PathBuf> {
+    let path = std::env::var_os("PATH").unwrap_or_default();
+    std::env::split_paths(&path)
+        .chain(package_dirs())
+        .map(|dir| dir.join(name))
+        .find(|path| path.is_file())
+}
+
+fn package_dirs() -> Vec<PathBuf> {
+    if cfg!(target_os = "macos") {
+        vec![PathBuf::from("/opt/homebrew/bin"), PathBuf::from("/usr/local/bin")]
+    } else if cfg!(windows) {
+        let local = std::env::var_os("LOCALAPPDATA").map(|dir| Path::new(&dir).join("Microsoft").join("WinGet").join("Links"));
+        let scoop = std::env::var_os("USERPROFILE").map(|dir| Path::new(&dir).join("scoop").join("shims"));
+        local.into_iter().chain(scoop).collect()
+    } else {
+        Vec::new()
+    }
+}
+
+// The standalone build for this platform, which doesn't need Python
+fn release_asset() -> &'static str {
+    if cfg!(windows) {
+        "yt-dlp.exe"
+    } else if cfg!(target_os = "macos") {
+        "yt-dlp_macos"
+    } else if cfg!(target_arch = "aarch64") {
+        "yt-dlp_linux_aarch64"
+    } else {
+        "yt-dlp_linux"
+    }
+}
+
+async fn fetch_release(dest: &Path) -> Result<()> {
+    let url = format!("{}/{}", RELEASES, release_asset());
+    let bytes = reqwest::get(&url).await
+        .and_then(reqwest::Response::error_for_status)
+        .with_context(|| format!("Failed to download {}", url))?
+        .bytes().await
+        .with_context(|| format!("Failed to download {}", url))?;
+    if let Some(dir) = dest.parent() {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create {}", dir.display()))?;
+    }
+    // Write next to the destination and rename, so a running copy is never half-written
+    let partial = dest.with_extension("download");
+    std::fs::write(&partial, &bytes)
+        .with_context(|| format!("Failed to write {}", partial.display()))?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(&partial, std::fs::Permissions::from_mode(0o755))
+            .with_context(|| format!("Failed to make {} executable", partial.display()))?;
+    }
+    std::fs::rename(&partial, dest)
+        .with_context(|| format!("Failed to replace {}", dest.display()))?;
+    Ok(())
+}
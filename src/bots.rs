@@ -0,0 +1,144 @@
+use log::{error, info};
+use serde::Deserialize;
+use serenity::async_trait;
+use serenity::gateway::{ConnectionStage, ShardStageUpdateEvent};
+use serenity::model::application::Interaction;
+use serenity::model::channel::{Message, Reaction};
+use serenity::model::gateway::Ready;
+use serenity::model::id::{ChannelId, GuildId};
+use serenity::prelude::*;
+use std::collections::HashSet;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
+use crate::{commands, Handler};
+
+// Name of the identity discord_token logs in as
+pub const MAIN: &str = "main";
+
+// Another Discord identity run from the same process, with its own token and scope but the
+// same queue, history and storage as every other
+#[derive(Debug, Clone, Deserialize)]
+pub struct BotSettings {
+    // Shown in logs, /status and metrics
+    pub name: String,
+    pub token: String,
+    // Guild IDs it serves, out of those the rest of the config allows (default: all of them).
+    // It leaves any other guild it's invited to.
+    #[serde(default)]
+    pub guilds: Vec<u64>,
+    // Channel IDs it listens in (default: every channel the rest of the config allows)
+    #[serde(default)]
+    pub channels: Vec<u64>,
+    // Also take requests by direct message from dm_users
+    #[serde(default)]
+    pub dms: bool,
+    pub shard_count: Option<u32>,
+}
+
+// Passes a client's events on to the shared handler, dropping those from outside its scope
+pub struct Bot {
+    pub name: String,
+    guilds: HashSet<GuildId>,
+    channels: HashSet<ChannelId>,
+    dms: bool,
+    // Resumes the jobs left from before a restart once it's connected
+    primary: bool,
+    handler: Arc<Handler>,
+}
+
+impl Bot {
+    pub fn main(handler: Arc<Handler>) -> Self {
+        Bot {
+            name: MAIN.to_string(),
+            guilds: HashSet::new(),
+            channels: HashSet::new(),
+            dms: true,
+            primary: true,
+            handler,
+        }
+    }
+
+    pub fn new(settings: &BotSettings, handler: Arc<Handler>) -> Self {
+        Bot {
+            name: settings.name.clone(),
+            guilds: settings.guilds.iter().copied().map(GuildId::new).collect(),
+            channels: settings.channels.iter().copied().map(ChannelId::new).collect(),
+            dms: settings.dms,
+            primary: false,
+            handler,
+        }
+    }
+
+    pub fn handler(&self) -> &Arc<Handler> {
+        &self.handler
+    }
+
+    fn serves_guild(&self, guild: GuildId) -> bool {
+        self.guilds.is_empty() || self.guilds.contains(&guild)
+    }
+
+    fn serves(&self, guild: Option<GuildId>, channel: ChannelId) -> bool {
+        match guild {
+            Some(guild) => self.serves_guild(guild) && (self.channels.is_empty() || self.channels.contains(&channel)),
+            None => self.dms,
+        }
+    }
+}
+
+#[async_trait]
+impl EventHandler for Bot {
+    async fn message(&self, ctx: Context, msg: Message) {
+        if self.serves(msg.guild_id, msg.channel_id) {
+            self.handler.message(ctx, msg).await;
+        }
+    }
+
+    async fn reaction_add(&self, ctx: Context, reaction: Reaction) {
+        if self.serves(reaction.guild_id, reaction.channel_id) {
+            self.handler.reaction_add(ctx, reaction).await;
+        }
+    }
+
+    async fn interaction_create(&self, ctx: Context, interaction: Interaction) {
+        let (guild, channel) = match &interaction {
+            Interaction::Command(cmd) | Interaction::Autocomplete(cmd) => (cmd.guild_id, cmd.channel_id),
+            Interaction::Component(component) => (component.guild_id, component.channel_id),
+            _ => return,
+        };
+        if self.serves(guild, channel) {
+            self.handler.interaction_create(ctx, interaction).await;
+        }
+    }
+
+    async fn shard_stage_update(&self, _ctx: Context, event: ShardStageUpdateEvent) {
+        if event.new != event.old {
+            info!("[{}] Shard {} is now {}", self.name, event.shard_id, event.new);
+        }
+        self.handler.gateway.set_shard_connected(&self.name, event.shard_id.0, event.new == ConnectionStage::Connected);
+    }
+
+    async fn ready(&self, ctx: Context, ready: Ready) {
+        let (shard, total) = ready.shard.map_or((0, 1), |shard| (shard.id.0, shard.total));
+        info!("[{}] Shard {} (of {}) connected as {} with {} guild(s)", self.name, shard, total, ready.user.name, ready.guilds.len());
+        let handler = &self.handler;
+        handler.gateway.shard_ready(&self.name, shard, ready.guilds.len());
+        // Ready fires again on reconnects, but jobs must only be resumed once
+        if self.primary && handler.resume_jobs && !handler.resumed.swap(true, Ordering::SeqCst) {
+            handler.resume_unfinished(&ctx.http).await;
+        }
+        for guild in ready.guilds {
+            if !handler.is_allowed_guild(guild.id) || !self.serves_guild(guild.id) {
+                info!("[{}] Leaving unauthorized guild: {}", self.name, guild.id);
+                if let Err(e) = guild.id.leave(&ctx.http).await {
+                    error!("Failed to leave guild {}: {}", guild.id, e);
+                }
+                continue;
+            }
+            // Guild commands update instantly, unlike global ones
+            if let Err(e) = guild.id.set_commands(&ctx.http, commands::definitions()).await {
+                error!("Failed to register slash commands in guild {}: {}", guild.id, e);
+            }
+        }
+    }
+}
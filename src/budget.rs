@@ -0,0 +1,112 @@
+use serenity::model::id::{ChannelId, UserId};
+
+use crate::history::History;
+use crate::messages::Messages;
+use crate::progress::format_bytes;
+
+// Caps on everything a user or a channel has downloaded so far, counted from the history,
+// unlike quotas which only look at a recent window
+#[derive(Debug, Clone, Copy)]
+pub struct Budget {
+    per_user: Option<u64>,
+    per_channel: Option<u64>,
+}
+
+#[derive(Debug)]
+pub struct OverBudget {
+    // The channel's budget rather than the requester's
+    channel: bool,
+    used: u64,
+    limit: u64,
+    size: Option<u64>,
+}
+
+impl OverBudget {
+    // For admins deciding whether to let it through
+    pub fn summary(&self, messages: &Messages) -> String {
+        let key = if self.channel { "budget_summary_channel" } else { "budget_summary_user" };
+        messages.get(key, &[("used", &format_bytes(self.used)), ("limit", &format_bytes(self.limit))])
+    }
+
+    // For the requester
+    pub fn describe(&self, messages: &Messages) -> String {
+        let key = match (self.channel, self.size.is_some()) {
+            (false, false) => "budget_over_user",
+            (false, true) => "budget_over_user_sized",
+            (true, false) => "budget_over_channel",
+            (true, true) => "budget_over_channel_sized",
+        };
+        let size = self.size.map(format_bytes).unwrap_or_default();
+        messages.get(key, &[("size", &size), ("used", &format_bytes(self.used)), ("limit", &format_bytes(self.limit))])
+    }
+}
+
+// Bytes downloaded so far, with the budget when there is one
+pub struct Usage {
+    pub user: (u64, Option<u64>),
+    pub channel: (u64, Option<u64>),
+}
+
+impl Usage {
+    pub fn describe(&self, messages: &Messages) -> String {
+        let part = |(used, limit): (u64, Option<u64>)| match limit {
+            Some(limit) => messages.get("budget_used_of", &[("used", &format_bytes(used)), ("limit", &format_bytes(limit))]),
+            None => format_bytes(used),
+        };
+        messages.get("budget_usage", &[("user", &part(self.user)), ("channel", &part(self.channel))])
+    }
+}
+
+fn gb_to_bytes(gb: Option<f64>) -> Option<u64> {
+    // A budget of 0 means no budget
+    gb.map(|gb| (gb * 1024.0 * 1024.0 * 1024.0) as u64).filter(|&limit| limit > 0)
+}
+
+impl Budget {
+    pub fn new(user_gb: Option<f64>, channel_gb: Option<f64>) -> Self {
+        Budget { per_user: gb_to_bytes(user_gb), per_channel: gb_to_bytes(channel_gb) }
+    }
+
+    // With a channel's own budget in place of the global one
+    pub fn for_channel(&self, channel_gb: Option<f64>) -> Self {
+        match channel_gb {
+            Some(gb) => Budget { per_channel: gb_to_bytes(Some(gb)), ..*self },
+            None => *self,
+        }
+    }
+
+    pub fn is_set(&self) -> bool {
+        self.per_user.is_some() || self.per_channel.is_some()
+    }
+
+    // Best-effort like quotas: history errors count as nothing used
+    pub fn usage(&self, history: &History, user: UserId, channel: ChannelId) -> Usage {
+        let used = |total: anyhow::Result<u64>| {
+            total.unwrap_or_else(|e| {
+                log::error!("Failed to check storage budget: {}", e);
+                0
+            })
+        };
+        Usage {
+            user: (used(history.downloaded_by(user)), self.per_user),
+            channel: (used(history.downloaded_in(channel)), self.per_channel),
+        }
+    }
+
+    // Fails if a download of `size` (or, when it isn't known, any download) would go over
+    pub fn check(&self, history: &History, user: UserId, channel: ChannelId, size: Option<u64>) -> Result<(), OverBudget> {
+        if !self.is_set() {
+            return Ok(());
+        }
+        let usage = self.usage(history, user, channel);
+        for (over_channel, (used, limit)) in [(false, usage.user), (true, usage.channel)] {
+            let Some(limit) = limit else {
+                continue;
+            };
+            if used.saturating_add(size.unwrap_or(0)) > limit || used >= limit {
+                return Err(OverBudget { channel: over_channel, used, limit, size });
+            }
+        }
+        Ok(())
+    }
+}
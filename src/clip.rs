@@ -0,0 +1,93 @@
+use anyhow::{anyhow, bail, Error};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+use crate::progress::format_duration;
+
+// A section of a video to download instead of all of it, in whole seconds
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Clip {
+    pub start: u64,
+    pub end: u64,
+}
+
+impl Clip {
+    // Parses a range like "1:23-2:45", "90-120" or "1:02:03-1:05:00". Returns None for words
+    // that don't look like a range at all, so they can be tried as something else.
+    pub fn parse(word: &str) -> Option<Result<Self, Error>> {
+        let (start, end) = word.split_once('-')?;
+        let shaped = |part: &str| !part.is_empty() && part.chars().all(|c| c.is_ascii_digit() || c == ':');
+        if !shaped(start) || !shaped(end) {
+            return None;
+        }
+        Some(Self::from_parts(start, end).map_err(|e| anyhow!("Invalid time range '{}': {}", word, e)))
+    }
+
+    fn from_parts(start: &str, end: &str) -> Result<Self, Error> {
+        let clip = Clip { start: parse_time(start)?, end: parse_time(end)? };
+        if clip.end <= clip.start {
+            bail!("the end must come after the start");
+        }
+        Ok(clip)
+    }
+
+    // Fails if the video is known to end before the clip does
+    pub fn check_duration(&self, duration: Option<u64>) -> Result<(), Error> {
+        match duration {
+            Some(duration) if self.end > duration => Err(anyhow!(
+                "The video is only {} long, so {} is out of range.",
+                format_duration(duration),
+                self
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    // For yt-dlp's --download-sections
+    pub fn section_arg(&self) -> String {
+        format!("*{}-{}", self.start, self.end)
+    }
+
+    // Marks the output file name with the range, before the extension when the template
+    // ends with one, e.g. "%(id)s.1m23s-2m45s.%(ext)s"
+    pub fn apply_to_template(&self, template: &str) -> String {
+        let suffix = format!("{}-{}", file_time(self.start), file_time(self.end));
+        match template.strip_suffix(".%(ext)s") {
+            Some(stem) => format!("{}.{}.%(ext)s", stem, suffix),
+            None => format!("{}.{}", template, suffix),
+        }
+    }
+}
+
+impl fmt::Display for Clip {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", format_duration(self.start), format_duration(self.end))
+    }
+}
+
+// [[h:]m:]s, where the leading field may be any size and the rest are below 60
+fn parse_time(time: &str) -> Result<u64, Error> {
+    let fields: Vec<&str> = time.split(':').collect();
+    if fields.len() > 3 {
+        bail!("'{}' has too many fields", time);
+    }
+    let mut secs: u64 = 0;
+    for (index, field) in fields.iter().enumerate() {
+        let value: u64 = field.parse().map_err(|_| anyhow!("'{}' isn't a time", time))?;
+        if index > 0 && value >= 60 {
+            bail!("'{}' has a field over 59", time);
+        }
+        secs = secs.saturating_mul(60).saturating_add(value);
+    }
+    Ok(secs)
+}
+
+// Colons aren't allowed in file names everywhere
+fn file_time(secs: u64) -> String {
+    let (hours, minutes, seconds) = (secs / 3600, secs / 60 % 60, secs % 60);
+    if hours > 0 {
+        format!("{}h{:02}m{:02}s", hours, minutes, seconds)
+    } else {
+        format!("{}m{:02}s", minutes, seconds)
+    }
+}
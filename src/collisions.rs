@@ -0,0 +1,163 @@
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::archivist::INCOMING_DIR;
+use crate::jobs::JobId;
+use crate::messages::Messages;
+use crate::verify::{self, Verified};
+
+// What happens when a download's file is already where its output template puts it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum CollisionPolicy {
+    // The new file replaces the old one
+    Overwrite,
+    // The old file stays and the new one is dropped
+    Skip,
+    // Both stay, the new one as "name (1).ext", "name (2).ext", ...
+    RenameWithSuffix,
+    // Like skip when both have the same SHA-256, otherwise like rename-with-suffix
+    DedupeByHash,
+}
+
+impl fmt::Display for CollisionPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            CollisionPolicy::Overwrite => "overwrite",
+            CollisionPolicy::Skip => "skip",
+            CollisionPolicy::RenameWithSuffix => "rename-with-suffix",
+            CollisionPolicy::DedupeByHash => "dedupe-by-hash",
+        })
+    }
+}
+
+// The job downloads into a directory of its own, so yt-dlp never sees the files already there
+pub fn output_template(id: JobId, template: &str) -> String {
+    format!("{}/{}", incoming_dir(id), template)
+}
+
+fn incoming_dir(id: JobId) -> String {
+    format!("{}/job-{}", INCOMING_DIR, id)
+}
+
+// Moves everything the job wrote to where its output template put it inside the output
+// directory, following the policy for each file that's already there. Returns where the job's
+// files ended up, and a line about each of them that collided.
+pub fn move_into_place(
+    output_dir: &str,
+    id: JobId,
+    policy: CollisionPolicy,
+    files: Vec<PathBuf>,
+    verified: &mut [Verified],
+    messages: &Messages,
+) -> Result<(Vec<PathBuf>, Vec<String>)> {
+    let incoming = Path::new(output_dir).join(incoming_dir(id));
+    let mut written = Vec::new();
+    let mut dirs = Vec::new();
+    walk(&incoming, &mut written, &mut dirs)?;
+    written.sort();
+    let mut placed = HashMap::new();
+    let mut notes = Vec::new();
+    for file in written {
+        let Ok(relative) = file.strip_prefix(&incoming) else {
+            continue;
+        };
+        let target = Path::new(output_dir).join(relative);
+        let name = relative.file_name().unwrap_or_default().to_string_lossy().into_owned();
+        let checked = verified.iter_mut().find(|checked| checked.path == file);
+        let sha256 = checked.as_ref().map(|checked| checked.sha256.clone());
+        let (destination, outcome) = place(&file, target, policy, sha256, messages)?;
+        if let (Some(outcome), true) = (outcome, files.contains(&file)) {
+            notes.push(messages.get("collision", &[("name", &name), ("outcome", &outcome), ("policy", &policy)]));
+        }
+        if let Some(checked) = checked {
+            checked.path = destination.clone();
+        }
+        placed.insert(file, destination);
+    }
+    // Deepest first, and only empty ones, like archivist's
+    for dir in dirs.iter().rev().chain([&incoming]) {
+        let _ = fs::remove_dir(dir);
+    }
+    let files = files.into_iter().map(|file| placed.get(&file).cloned().unwrap_or(file)).collect();
+    Ok((files, notes))
+}
+
+// Where the file went, and what was done about a file already at the target
+fn place(file: &Path, target: PathBuf, policy: CollisionPolicy, sha256: Option<String>, messages: &Messages) -> Result<(PathBuf, Option<String>)> {
+    if !target.exists() {
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent).with_context(|| format!("Failed to create {}", parent.display()))?;
+        }
+        rename(file, &target)?;
+        return Ok((target, None));
+    }
+    let same = || -> Result<bool> {
+        let sha256 = match &sha256 {
+            Some(sha256) => sha256.clone(),
+            None => verify::sha256_file(file)?,
+        };
+        Ok(verify::sha256_file(&target)? == sha256)
+    };
+    match policy {
+        CollisionPolicy::Overwrite => {
+            rename(file, &target)?;
+            Ok((target, Some(messages.get("collision_overwritten", &[]))))
+        }
+        CollisionPolicy::Skip => {
+            remove(file)?;
+            Ok((target, Some(messages.get("collision_skipped", &[]))))
+        }
+        CollisionPolicy::DedupeByHash if same()? => {
+            remove(file)?;
+            Ok((target, Some(messages.get("collision_identical", &[]))))
+        }
+        CollisionPolicy::RenameWithSuffix | CollisionPolicy::DedupeByHash => {
+            let free = free_name(&target);
+            rename(file, &free)?;
+            let name = free.file_name().unwrap_or(free.as_os_str()).to_string_lossy().into_owned();
+            Ok((free, Some(messages.get("collision_renamed", &[("name", &name)]))))
+        }
+    }
+}
+
+// "name (1).ext", or the first number that isn't taken
+fn free_name(target: &Path) -> PathBuf {
+    let stem = target.file_stem().unwrap_or_default().to_string_lossy();
+    let extension = target.extension().map(|ext| format!(".{}", ext.to_string_lossy())).unwrap_or_default();
+    (1..)
+        .map(|n| target.with_file_name(format!("{} ({}){}", stem, n, extension)))
+        .find(|candidate| !candidate.exists())
+        .unwrap_or_else(|| target.to_path_buf())
+}
+
+fn rename(from: &Path, to: &Path) -> Result<()> {
+    fs::rename(from, to).with_context(|| format!("Failed to move {} to {}", from.display(), to.display()))
+}
+
+fn remove(file: &Path) -> Result<()> {
+    fs::remove_file(file).with_context(|| format!("Failed to remove {}", file.display()))
+}
+
+// Every file under the directory, and its subdirectories from the top down
+fn walk(dir: &Path, files: &mut Vec<PathBuf>, dirs: &mut Vec<PathBuf>) -> Result<()> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        // Backends that ignore the output template leave it empty
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e).with_context(|| format!("Failed to read {}", dir.display())),
+    };
+    for path in entries.flatten().map(|entry| entry.path()) {
+        if path.is_dir() {
+            dirs.push(path.clone());
+            walk(&path, files, dirs)?;
+        } else {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,1832 @@
+use serenity::builder::{
+    AutocompleteChoice, CreateActionRow, CreateAttachment, CreateAutocompleteResponse, CreateButton, CreateCommand, CreateCommandOption,
+    CreateInteractionResponse, CreateInteractionResponseMessage, CreateSelectMenu, CreateSelectMenuKind,
+    CreateSelectMenuOption, EditInteractionResponse, EditMessage,
+};
+use serenity::model::application::{
+    ButtonStyle, CommandInteraction, CommandOptionType, ComponentInteraction, ComponentInteractionDataKind, ResolvedValue,
+};
+use serenity::model::channel::Attachment;
+use serenity::model::guild::Member;
+use serenity::model::id::{ChannelId, GuildId, UserId};
+use serenity::model::user::User;
+use serenity::prelude::*;
+use log::{error, info};
+use std::path::Path;
+use std::time::Duration;
+
+use crate::archive;
+use crate::auth::{Access, MODERATOR_PERMISSIONS};
+use crate::binary;
+use crate::clip::Clip;
+use crate::disk;
+use crate::downloader::Backend;
+use crate::embed::{self, CardState, JobAction};
+use crate::errors::{self, Workaround};
+use crate::feeds::{self, Feed};
+use crate::format::{self, FormatSpec, PRESETS};
+use crate::geo;
+use crate::guilds;
+use crate::history::{self, Subscription};
+use crate::jobs::{CancelReason, JobId, JobInfo, JobState};
+use crate::messages::Messages;
+use crate::prefs;
+use crate::progress::{format_bytes, format_duration, StatusMessage};
+use crate::queue::Priority;
+use crate::stats;
+use crate::template;
+use crate::usage::Period;
+use crate::ytdlp::{self, Metadata};
+use crate::{is_valid_url, truncate_message, DownloadRequest, Handler, Settings, Submitted};
+
+const HISTORY_PAGE_SIZE: usize = 10;
+
+// Archives bigger than this aren't exported or imported, keeping them under Discord's upload limit
+const MAX_ARCHIVE_BYTES: usize = 8 * 1024 * 1024;
+
+// Servers and users listed by /usage
+const USAGE_TOP: usize = 10;
+
+// Requesters and sites listed by /stats, and the weeks it covers
+const STATS_TOP: usize = 5;
+const DEFAULT_STATS_WEEKS: u32 = 8;
+const MAX_STATS_WEEKS: u64 = 52;
+
+const SEARCH_RESULTS: usize = 5;
+
+// Custom ID of the /search results menu, followed by ":<format>" when one was given
+const SEARCH_MENU: &str = "search";
+
+// Custom ID of the menu under a /probe result, whose values are yt-dlp format selectors
+const FORMAT_MENU: &str = "format";
+
+// Discord's limits on select menus
+const MENU_OPTIONS_LIMIT: usize = 25;
+
+// Discord's limit for select menu labels, descriptions and values
+const MENU_TEXT_LIMIT: usize = 100;
+
+// Custom ID prefix of the buttons under a /history page
+const HISTORY_BUTTON: &str = "history:";
+
+const CUSTOM_ID_LIMIT: usize = 100;
+
+pub fn definitions() -> Vec<CreateCommand> {
+    vec![
+        CreateCommand::new("download")
+            .description("Download a URL")
+            .add_option(
+                CreateCommandOption::new(CommandOptionType::String, "url", "Link to download")
+                    .required(true),
+            )
+            .add_option(format_option())
+            .add_option(CreateCommandOption::new(
+                CommandOptionType::Boolean,
+                "audio",
+                "Extract audio only",
+            ))
+            .add_option(CreateCommandOption::new(
+                CommandOptionType::Boolean,
+                "force",
+                "Download again even if it was downloaded before",
+            ))
+            .add_option(CreateCommandOption::new(
+                CommandOptionType::String,
+                "subs",
+                "Subtitle languages to download, e.g. en or en,de",
+            ))
+            .add_option(CreateCommandOption::new(
+                CommandOptionType::String,
+                "clip",
+                "Only download this time range, e.g. 1:23-2:45",
+            ))
+            .add_option(CreateCommandOption::new(
+                CommandOptionType::Boolean,
+                "chapters",
+                "Also split it into a file per chapter",
+            ))
+            .add_option(backend_option())
+            .add_option(CreateCommandOption::new(
+                CommandOptionType::String,
+                "args",
+                "Extra yt-dlp flags, e.g. --write-info-json (trusted roles only)",
+            ))
+            .add_option(CreateCommandOption::new(
+                CommandOptionType::String,
+                "region",
+                "Download it as if from this country, e.g. US (trusted roles only)",
+            ))
+            .add_option(priority_option("Queue it in another lane (admins only)").required(false)),
+        CreateCommand::new("search")
+            .description("Search YouTube and download one of the results")
+            .add_option(
+                CreateCommandOption::new(CommandOptionType::String, "query", "What to search for")
+                    .required(true),
+            )
+            .add_option(format_option()),
+        CreateCommand::new("probe")
+            .description("Show a video's formats and estimated sizes without downloading it")
+            .add_option(
+                CreateCommandOption::new(CommandOptionType::String, "url", "Link to look up")
+                    .required(true),
+            ),
+        CreateCommand::new("status").description("Show the queue, running downloads, disk space and today's totals"),
+        CreateCommand::new("history")
+            .description("Show past downloads")
+            .add_option(history_subcommand("list", "Show the most recent downloads", None))
+            .add_option(history_subcommand(
+                "search",
+                "Search past downloads by title, uploader or URL",
+                Some(
+                    CreateCommandOption::new(CommandOptionType::String, "term", "Part of the title, uploader or URL")
+                        .required(true)
+                        .max_length(40),
+                ),
+            ))
+            .add_option(history_subcommand(
+                "user",
+                "Show the downloads someone requested",
+                Some(CreateCommandOption::new(CommandOptionType::User, "user", "Whose downloads to show").required(true)),
+            )),
+        CreateCommand::new("pin")
+            .description("Keep a download from being deleted by the retention policy")
+            .add_option(
+                CreateCommandOption::new(CommandOptionType::Integer, "job", "Job ID of the download")
+                    .required(true)
+                    .min_int_value(1),
+            ),
+        CreateCommand::new("unpin")
+            .description("Let the retention policy delete a pinned download again")
+            .add_option(
+                CreateCommandOption::new(CommandOptionType::Integer, "job", "Job ID of the download")
+                    .required(true)
+                    .min_int_value(1),
+            ),
+        CreateCommand::new("cancel")
+            .description("Cancel a running or queued download")
+            .add_option(
+                CreateCommandOption::new(CommandOptionType::Integer, "job", "Job ID to cancel")
+                    .required(true)
+                    .set_autocomplete(true),
+            ),
+        CreateCommand::new("stream")
+            .description("Manage live stream recordings")
+            .add_option(
+                CreateCommandOption::new(
+                    CommandOptionType::SubCommand,
+                    "stop",
+                    "Stop recording a live stream and keep what was recorded",
+                )
+                .add_sub_option(
+                    CreateCommandOption::new(CommandOptionType::Integer, "job", "Job ID of the recording (default: yours)")
+                        .set_autocomplete(true),
+                ),
+            ),
+        CreateCommand::new("ytdlp")
+            .description("Manage the yt-dlp installation")
+            .add_option(CreateCommandOption::new(
+                CommandOptionType::SubCommand,
+                "version",
+                "Show the installed yt-dlp version",
+            ))
+            .add_option(CreateCommandOption::new(
+                CommandOptionType::SubCommand,
+                "update",
+                "Update yt-dlp to the latest release (admins only)",
+            )),
+        CreateCommand::new("config")
+            .description("View or change this server's settings (admins only)")
+            .add_option(CreateCommandOption::new(
+                CommandOptionType::SubCommand,
+                "show",
+                "Show this server's settings",
+            ))
+            .add_option(
+                CreateCommandOption::new(CommandOptionType::SubCommand, "set", "Change one of this server's settings")
+                    .add_sub_option(config_key_option())
+                    .add_sub_option(
+                        CreateCommandOption::new(CommandOptionType::String, "value", "New value, e.g. 720p, 5 or @Role")
+                            .required(true),
+                    ),
+            )
+            .add_option(
+                CreateCommandOption::new(
+                    CommandOptionType::SubCommand,
+                    "reset",
+                    "Go back to the configured value of one of this server's settings",
+                )
+                .add_sub_option(config_key_option()),
+            ),
+        CreateCommand::new("prefs")
+            .description("View or change your own defaults for what you download")
+            .add_option(CreateCommandOption::new(
+                CommandOptionType::SubCommand,
+                "show",
+                "Show your preferences",
+            ))
+            .add_option(
+                CreateCommandOption::new(CommandOptionType::SubCommand, "set", "Change some of your preferences")
+                    .add_sub_option(
+                        CreateCommandOption::new(
+                            CommandOptionType::String,
+                            "values",
+                            "key=value pairs, e.g. format=audio quality=1080p subs=en subfolder=my-music",
+                        )
+                        .required(true),
+                    ),
+            )
+            .add_option(
+                CreateCommandOption::new(CommandOptionType::SubCommand, "reset", "Go back to the default for one of your preferences")
+                    .add_sub_option(prefs_key_option()),
+            ),
+        CreateCommand::new("queue")
+            .description("Stop or restart starting queued downloads (admins only)")
+            .add_option(CreateCommandOption::new(
+                CommandOptionType::SubCommand,
+                "pause",
+                "Start no more queued downloads until resumed",
+            ))
+            .add_option(CreateCommandOption::new(
+                CommandOptionType::SubCommand,
+                "resume",
+                "Start queued downloads again",
+            )),
+        CreateCommand::new("priority")
+            .description("Move a queued download to another lane (admins only)")
+            .add_option(
+                CreateCommandOption::new(CommandOptionType::Integer, "job", "Job ID of the queued download")
+                    .required(true)
+                    .set_autocomplete(true),
+            )
+            .add_option(priority_option("Lane to move it to")),
+        CreateCommand::new("usage")
+            .description("Show how much the bot downloaded, by server and user (admins only)")
+            .add_option(
+                CreateCommandOption::new(CommandOptionType::String, "period", "Period to add up (default: week)")
+                    .add_string_choice("today (UTC)", "today")
+                    .add_string_choice("last 7 days", "week")
+                    .add_string_choice("last 30 days", "month"),
+            )
+            .add_option(CreateCommandOption::new(
+                CommandOptionType::Boolean,
+                "everywhere",
+                "Every server and DMs, not just this server",
+            )),
+        CreateCommand::new("stats")
+            .description("Show the top requesters and sites, and how much was downloaded each week")
+            .add_option(
+                CreateCommandOption::new(CommandOptionType::Integer, "weeks", "Weeks to cover (default: 8)")
+                    .min_int_value(1)
+                    .max_int_value(MAX_STATS_WEEKS),
+            )
+            .add_option(CreateCommandOption::new(
+                CommandOptionType::Boolean,
+                "chart",
+                "Attach a chart of the weekly downloads",
+            ))
+            .add_option(CreateCommandOption::new(
+                CommandOptionType::Boolean,
+                "everywhere",
+                "Every server and DMs, not just this server (admins only)",
+            )),
+        CreateCommand::new("log")
+            .description("Get the full downloader output of a job (admins only)")
+            .add_option(
+                CreateCommandOption::new(CommandOptionType::Integer, "job", "Job ID")
+                    .required(true)
+                    .min_int_value(1),
+            ),
+        CreateCommand::new("archive")
+            .description("Export or import the record of what was already downloaded (admins only)")
+            .add_option(
+                CreateCommandOption::new(CommandOptionType::SubCommand, "export", "Get the archive as a file")
+                    .add_sub_option(
+                        CreateCommandOption::new(CommandOptionType::String, "as", "File format (default: CSV)")
+                            .add_string_choice("CSV with job details", "csv")
+                            .add_string_choice("yt-dlp --download-archive", "ytdlp"),
+                    ),
+            )
+            .add_option(
+                CreateCommandOption::new(
+                    CommandOptionType::SubCommand,
+                    "import",
+                    "Mark the videos in a yt-dlp download archive or CSV export as already downloaded",
+                )
+                .add_sub_option(
+                    CreateCommandOption::new(CommandOptionType::Attachment, "file", "The archive file")
+                        .required(true),
+                ),
+            ),
+        CreateCommand::new("subscribe")
+            .description("Download new episodes or uploads in this channel as they come out")
+            .add_option(
+                CreateCommandOption::new(CommandOptionType::SubCommand, "feed", "Follow a podcast or other RSS or Atom feed")
+                    .add_sub_option(CreateCommandOption::new(CommandOptionType::String, "url", "URL of the feed").required(true))
+                    .add_sub_option(format_option()),
+            )
+            .add_option(
+                CreateCommandOption::new(CommandOptionType::SubCommand, "channel", "Follow a YouTube channel")
+                    .add_sub_option(CreateCommandOption::new(CommandOptionType::String, "url", "URL of the channel").required(true))
+                    .add_sub_option(format_option()),
+            ),
+        CreateCommand::new("unsubscribe")
+            .description("Stop following a feed")
+            .add_option(
+                CreateCommandOption::new(CommandOptionType::Integer, "id", "Subscription ID, from /subscriptions")
+                    .required(true)
+                    .min_int_value(1),
+            ),
+        CreateCommand::new("subscriptions").description("List the feeds followed in this server"),
+        CreateCommand::new("reload-cookies")
+            .description("Reload the cookie settings from the config file (admins only)"),
+        CreateCommand::new("reload")
+            .description("Reload the settings from the config file without restarting (admins only)"),
+    ]
+}
+
+fn format_option() -> CreateCommandOption {
+    let mut option = CreateCommandOption::new(CommandOptionType::String, "format", "Quality or audio-only");
+    for preset in PRESETS {
+        option = option.add_string_choice(preset, preset);
+    }
+    option
+}
+
+fn backend_option() -> CreateCommandOption {
+    let mut option = CreateCommandOption::new(CommandOptionType::String, "via", "What to download it with (default: picked from the URL)");
+    for backend in Backend::ALL {
+        option = option.add_string_choice(backend.to_string(), backend.to_string());
+    }
+    option
+}
+
+// The filters every /history subcommand takes, after any option of its own
+fn history_subcommand(name: &str, description: &str, own: Option<CreateCommandOption>) -> CreateCommandOption {
+    let mut subcommand = CreateCommandOption::new(CommandOptionType::SubCommand, name, description);
+    if let Some(own) = own {
+        subcommand = subcommand.add_sub_option(own);
+    }
+    // /history user has it as its own, required option
+    if name != "user" {
+        subcommand = subcommand.add_sub_option(CreateCommandOption::new(
+            CommandOptionType::User,
+            "user",
+            "Only downloads requested by this user",
+        ));
+    }
+    subcommand
+        .add_sub_option(
+            CreateCommandOption::new(CommandOptionType::String, "domain", "Only downloads from this site, e.g. youtube.com")
+                .max_length(40),
+        )
+        .add_sub_option(CreateCommandOption::new(
+            CommandOptionType::String,
+            "from",
+            "Only downloads requested on or after this date, as YYYY-MM-DD (UTC)",
+        ))
+        .add_sub_option(CreateCommandOption::new(
+            CommandOptionType::String,
+            "to",
+            "Only downloads requested on or before this date, as YYYY-MM-DD (UTC)",
+        ))
+        .add_sub_option(
+            CreateCommandOption::new(CommandOptionType::Integer, "page", "Page number")
+                .min_int_value(1),
+        )
+}
+
+fn priority_option(description: &str) -> CreateCommandOption {
+    let mut option = CreateCommandOption::new(CommandOptionType::String, "priority", description).required(true);
+    for priority in [Priority::High, Priority::Normal, Priority::Low] {
+        option = option.add_string_choice(priority.to_string(), priority.to_string());
+    }
+    option
+}
+
+fn config_key_option() -> CreateCommandOption {
+    let mut option = CreateCommandOption::new(CommandOptionType::String, "key", "Setting to change").required(true);
+    for key in guilds::KEYS {
+        option = option.add_string_choice(*key, *key);
+    }
+    option
+}
+
+fn prefs_key_option() -> CreateCommandOption {
+    let mut option = CreateCommandOption::new(CommandOptionType::String, "key", "Preference to reset").required(true);
+    for key in prefs::KEYS {
+        option = option.add_string_choice(*key, *key);
+    }
+    option
+}
+
+impl Handler {
+    pub(crate) async fn on_command(&self, ctx: &Context, cmd: &CommandInteraction) {
+        let access = self.command_access(cmd);
+        let reply = if !self.is_allowed_location(cmd.guild_id, cmd.channel_id) {
+            self.messages(cmd.guild_id).get("not_allowed_here", &[])
+        } else if access == Access::Denied {
+            self.messages(cmd.guild_id).get("not_allowed_bot", &[])
+        } else {
+            match cmd.data.name.as_str() {
+                "download" => match self.download_request(cmd) {
+                    Ok(request) => return self.download_command(ctx, cmd, request).await,
+                    Err(reply) => reply,
+                },
+                "search" => return self.search_command(ctx, cmd).await,
+                "probe" => return self.probe_command(ctx, cmd).await,
+                "status" => self.status_command(cmd).await,
+                "cancel" => self.cancel_command(cmd, access),
+                "stream" => self.stream_command(cmd, access),
+                "history" => return self.history_command(ctx, cmd).await,
+                "pin" => self.pin_command(cmd, access, true),
+                "unpin" => self.pin_command(cmd, access, false),
+                "ytdlp" => return ytdlp_command(ctx, cmd, access, &self.messages(cmd.guild_id)).await,
+                "config" => self.config_command(cmd, access),
+                "prefs" => self.prefs_command(cmd),
+                "log" => return self.log_command(ctx, cmd, access).await,
+                "archive" => return self.archive_command(ctx, cmd, access).await,
+                "queue" => self.queue_command(cmd, access),
+                "priority" => self.priority_command(cmd, access),
+                "usage" => self.usage_command(ctx, cmd, access),
+                "stats" => return self.stats_command(ctx, cmd, access).await,
+                "subscribe" => return self.subscribe_command(ctx, cmd).await,
+                "unsubscribe" => self.unsubscribe_command(cmd, access),
+                "subscriptions" => self.subscriptions_command(cmd),
+                "reload-cookies" => self.reload_cookies_command(cmd, access),
+                "reload" => self.reload_command(cmd, access),
+                other => self.messages(cmd.guild_id).get("unknown_command", &[("name", &other)]),
+            }
+        };
+        respond(ctx, cmd, reply).await;
+    }
+
+    async fn download_command(&self, ctx: &Context, cmd: &CommandInteraction, request: DownloadRequest) {
+        let url = request.url.clone();
+        // Looking up the URL can take longer than the 3 seconds Discord allows for a response
+        respond(ctx, cmd, self.messages(cmd.guild_id).get("looking_up", &[("url", &url)])).await;
+        self.report_submission(ctx, StatusMessage::Interaction(cmd.token.clone()), request).await;
+    }
+
+    // Submits the request and reports the outcome in the interaction's response
+    async fn report_submission(&self, ctx: &Context, status: StatusMessage, request: DownloadRequest) {
+        let (format, url) = (request.format, request.url.clone());
+        let messages = self.messages(request.guild);
+        let update = match self.submit(&ctx.http, request, Some(status.clone())).await {
+            Ok(Submitted::Duplicate(existing)) => messages.get("already_downloaded_force_option", &[
+                ("when", &format!("<t:{}:R>", existing.downloaded_at)),
+                ("what", &existing.describe(&messages)),
+            ]),
+            Ok(Submitted::Joined { id }) => messages.get("joined", &[("url", &url), ("id", &id)]),
+            // The job shows its own progress once it starts
+            Ok(Submitted::Job { position: 0, .. }) => return,
+            Ok(Submitted::Job { position, card, .. }) => {
+                if let Err(e) = status.edit_card(&ctx.http, card.render(CardState::Queued(position))).await {
+                    error!("Failed to update download response: {}", e);
+                }
+                return;
+            }
+            Ok(Submitted::Playlist { title, queued }) => {
+                messages.get("playlist_queued_as", &[("count", &queued), ("title", &title), ("format", &format)])
+            }
+            Err(e) => e.to_string(),
+        };
+        if let Err(e) = status.edit(&ctx.http, truncate_message(update)).await {
+            error!("Failed to update download response: {}", e);
+        }
+    }
+
+    pub(crate) async fn on_autocomplete(&self, ctx: &Context, cmd: &CommandInteraction) {
+        let typed = cmd.data.autocomplete().map(|opt| opt.value).unwrap_or_default();
+        let choices = self.jobs.list()
+            .into_iter()
+            .filter(|job| job.visible_to(cmd.user.id, cmd.guild_id))
+            .filter(|job| job.id.to_string().starts_with(typed))
+            // /stream only offers live recordings
+            .filter(|job| cmd.data.name != "stream" || job.recording.is_some())
+            .take(25)
+            .map(|job| {
+                // Choice names are limited to 100 characters
+                let label: String = format!("#{} {}", job.id, job.url).chars().take(100).collect();
+                AutocompleteChoice::new(label, job.id)
+            })
+            .collect();
+        let response = CreateInteractionResponse::Autocomplete(
+            CreateAutocompleteResponse::new().set_choices(choices),
+        );
+        if let Err(e) = cmd.create_response(&ctx.http, response).await {
+            error!("Failed to send autocomplete for /{}: {}", cmd.data.name, e);
+        }
+    }
+
+    // Builds the request from /download's options, or returns why it can't be run
+    fn download_request(&self, cmd: &CommandInteraction) -> Result<DownloadRequest, String> {
+        let url = match string_option(cmd, "url") {
+            Some(url) => url,
+            None => return Err(self.messages(cmd.guild_id).get("missing_url", &[])),
+        };
+        if !is_valid_url(url) {
+            return Err(self.messages(cmd.guild_id).get("invalid_url", &[]));
+        }
+        let format = match string_option(cmd, "format").map(str::parse::<FormatSpec>) {
+            Some(Ok(format)) => Some(format),
+            Some(Err(e)) => return Err(e.to_string()),
+            None => None,
+        };
+        let format = match (format, bool_option(cmd, "audio")) {
+            (Some(format), Some(true)) if !format.is_audio() => {
+                return Err(self.messages(cmd.guild_id).get("audio_option_conflict", &[("format", &format)]));
+            }
+            (None, Some(true)) => Some(FormatSpec::Audio(None)),
+            (format, _) => format,
+        };
+        let subtitles = match string_option(cmd, "subs").map(format::parse_subtitle_langs) {
+            Some(Ok(langs)) => Some(langs),
+            Some(Err(e)) => return Err(e.to_string()),
+            None => None,
+        };
+        let clip = match string_option(cmd, "clip").map(str::trim) {
+            Some(range) => match Clip::parse(range) {
+                Some(Ok(clip)) => Some(clip),
+                Some(Err(e)) => return Err(e.to_string()),
+                None => return Err(self.messages(cmd.guild_id).get("not_a_time_range", &[("range", &range)])),
+            },
+            None => None,
+        };
+        let backend = match string_option(cmd, "via") {
+            Some(name) => Some(self.requested_backend(name, cmd.guild_id)?),
+            None => None,
+        };
+        let flags: Vec<&str> = string_option(cmd, "args").unwrap_or_default().split_whitespace().collect();
+        let roles = cmd.member.as_ref().map_or(&[][..], |member| &member.roles[..]);
+        let access = self.command_access(cmd);
+        let trusted = self.is_trusted(access, roles);
+        let extra_args = self.ytdlp_flags(url, &flags, trusted, &cmd.user, cmd.guild_id)?;
+        let geo_country = match string_option(cmd, "region") {
+            Some(_) if !trusted => return Err(self.messages(cmd.guild_id).get("region_not_trusted", &[])),
+            Some(code) => Some(geo::parse_country(code)?),
+            None => None,
+        };
+        let priority = match string_option(cmd, "priority").map(str::parse::<Priority>) {
+            Some(_) if access < Access::Admin => return Err(self.messages(cmd.guild_id).get("priority_admins_only", &[])),
+            Some(priority) => priority?,
+            None => self.member_priority(access, cmd.member.as_deref()),
+        };
+        let request = self.interaction_request(&cmd.user, cmd.channel_id, cmd.guild_id, url, format);
+        Ok(DownloadRequest {
+            priority,
+            force: bool_option(cmd, "force").unwrap_or(false),
+            subtitles: subtitles.or(request.subtitles),
+            clip,
+            backend,
+            split_chapters: bool_option(cmd, "chapters").unwrap_or(false),
+            extra_args,
+            geo_country,
+            ..request
+        })
+    }
+
+    fn interaction_request(
+        &self,
+        user: &User,
+        channel: ChannelId,
+        guild: Option<GuildId>,
+        url: &str,
+        format: Option<FormatSpec>,
+    ) -> DownloadRequest {
+        let prefs = self.prefs.get(user.id);
+        DownloadRequest {
+            url: url.to_owned(),
+            requester: user.id,
+            requester_name: user.name.clone(),
+            requester_avatar: Some(user.face()),
+            channel,
+            guild,
+            format: self.resolve_format(format.or(prefs.format()), guild, channel),
+            force: false,
+            archive_key: None,
+            playlist: None,
+            subtitles: prefs.subs,
+            clip: None,
+            metadata: Metadata::default(),
+            dm: false,
+            live: false,
+            schedule: None,
+            backend: None,
+            split_chapters: false,
+            from_api: false,
+            thread: None,
+            extra_args: Vec::new(),
+            format_id: None,
+            workaround: None,
+            priority: Priority::Normal,
+            geo_country: None,
+            subfolder: prefs.subfolder,
+            spoiler: false,
+        }
+    }
+
+    async fn search_command(&self, ctx: &Context, cmd: &CommandInteraction) {
+        let messages = self.messages(cmd.guild_id);
+        let Some(query) = string_option(cmd, "query").map(str::trim).filter(|query| !query.is_empty()) else {
+            return respond(ctx, cmd, messages.get("missing_query", &[])).await;
+        };
+        let format = match string_option(cmd, "format").map(str::parse::<FormatSpec>) {
+            Some(Ok(format)) => Some(format),
+            Some(Err(e)) => return respond(ctx, cmd, e.to_string()).await,
+            None => None,
+        };
+        respond(ctx, cmd, messages.get("searching", &[("query", &query)])).await;
+        let search = format!("ytsearch{}:{}", SEARCH_RESULTS, query);
+        let edit = match ytdlp::probe(&search, &self.cookies.args_for(&search), &self.extra_args_for(&search).1).await {
+            Ok(info) => {
+                let options: Vec<CreateSelectMenuOption> = info.entries.iter().flatten().filter_map(search_option).collect();
+                if options.is_empty() {
+                    EditInteractionResponse::new().content(messages.get("no_results", &[("query", &query)]))
+                } else {
+                    // The menu carries the format, so picking a result needs nothing else
+                    let custom_id = match format {
+                        Some(format) => format!("{}:{}", SEARCH_MENU, format),
+                        None => SEARCH_MENU.to_string(),
+                    };
+                    let menu = CreateSelectMenu::new(custom_id, CreateSelectMenuKind::String { options })
+                        .placeholder(messages.get("search_placeholder", &[]));
+                    EditInteractionResponse::new()
+                        .content(messages.get("results", &[("query", &query)]))
+                        .components(vec![CreateActionRow::SelectMenu(menu)])
+                }
+            }
+            Err(e) => {
+                error!("Search for {:?} failed: {:#}", query, e);
+                EditInteractionResponse::new().content(truncate_message(messages.get("search_failed", &[("reason", &errors::describe(&e, &messages))])))
+            }
+        };
+        if let Err(e) = cmd.edit_response(&ctx.http, edit).await {
+            error!("Failed to update /search response: {}", e);
+        }
+    }
+
+    async fn probe_command(&self, ctx: &Context, cmd: &CommandInteraction) {
+        let messages = self.messages(cmd.guild_id);
+        let Some(url) = string_option(cmd, "url").map(str::trim) else {
+            return respond(ctx, cmd, messages.get("missing_url", &[])).await;
+        };
+        if !is_valid_url(url) {
+            return respond(ctx, cmd, messages.get("invalid_url", &[])).await;
+        }
+        if let Err(e) = self.live().domains.check(url) {
+            return respond(ctx, cmd, e.describe(&messages)).await;
+        }
+        respond(ctx, cmd, messages.get("looking_up", &[("url", &url)])).await;
+        let edit = match ytdlp::probe(url, &self.cookies.args_for(url), &self.extra_args_for(url).1).await {
+            Ok(info) => {
+                let mut edit = EditInteractionResponse::new().content("").embed(embed::probe_embed(url, &info, &messages));
+                let options: Vec<CreateSelectMenuOption> = info.pickable_formats().into_iter()
+                    .filter_map(|format| format_menu_option(format, &messages))
+                    .take(MENU_OPTIONS_LIMIT)
+                    .collect();
+                if !info.is_playlist() && !info.is_live() && !options.is_empty() {
+                    // The URL is read back from the embed when a format is picked
+                    let menu = CreateSelectMenu::new(FORMAT_MENU, CreateSelectMenuKind::String { options })
+                        .placeholder(messages.get("format_placeholder", &[]));
+                    edit = edit.components(vec![CreateActionRow::SelectMenu(menu)]);
+                }
+                edit
+            }
+            Err(e) => {
+                error!("Probing {} failed: {:#}", url, e);
+                EditInteractionResponse::new().content(truncate_message(messages.get("probe_failed", &[("url", &url), ("reason", &errors::describe(&e, &messages))])))
+            }
+        };
+        if let Err(e) = cmd.edit_response(&ctx.http, edit).await {
+            error!("Failed to update /probe response: {}", e);
+        }
+    }
+
+    // A result picked from the /search menu, a format picked under a /probe result, or a
+    // button under a /history page
+    pub(crate) async fn on_component(&self, ctx: &Context, component: &ComponentInteraction) {
+        if component.data.custom_id.starts_with(HISTORY_BUTTON) {
+            return self.history_button(ctx, component).await;
+        }
+        if let Some((action, id)) = JobAction::parse(&component.data.custom_id) {
+            return self.job_button(ctx, component, action, id).await;
+        }
+        let ComponentInteractionDataKind::StringSelect { values } = &component.data.kind else {
+            return;
+        };
+        let Some(value) = values.first() else {
+            return;
+        };
+        if component.data.custom_id == FORMAT_MENU {
+            return self.format_pick(ctx, component, value).await;
+        }
+        let Some(format) = component.data.custom_id.strip_prefix(SEARCH_MENU) else {
+            return;
+        };
+        let url = value;
+        let format = format.strip_prefix(':').and_then(|format| format.parse::<FormatSpec>().ok());
+        if !self.accept_pick(ctx, component, self.messages(component.guild_id).get("looking_up", &[("url", &url)])).await {
+            return;
+        }
+        let access = self.interaction_access(component.user.id, component.channel_id, component.member.as_ref());
+        let request = DownloadRequest {
+            priority: self.member_priority(access, component.member.as_ref()),
+            ..self.interaction_request(&component.user, component.channel_id, component.guild_id, url, format)
+        };
+        self.report_submission(ctx, StatusMessage::Interaction(component.token.clone()), request).await;
+    }
+
+    async fn format_pick(&self, ctx: &Context, component: &ComponentInteraction, selector: &str) {
+        let url = component.message.embeds.first().and_then(|embed| embed.url.clone());
+        // Values come back from Discord as sent, but aren't taken on trust as yt-dlp arguments
+        let valid = !selector.is_empty()
+            && selector.chars().all(|c| c.is_ascii_alphanumeric() || "-_+/.".contains(c));
+        let Some(url) = url.filter(|url| valid && is_valid_url(url)) else {
+            return;
+        };
+        let text = self.messages(component.guild_id).get("downloading_format", &[("format", &selector), ("url", &url)]);
+        if !self.accept_pick(ctx, component, text).await {
+            return;
+        }
+        let access = self.interaction_access(component.user.id, component.channel_id, component.member.as_ref());
+        let request = DownloadRequest {
+            format_id: Some(selector.to_string()),
+            priority: self.member_priority(access, component.member.as_ref()),
+            ..self.interaction_request(&component.user, component.channel_id, component.guild_id, &url, None)
+        };
+        self.report_submission(ctx, StatusMessage::Interaction(component.token.clone()), request).await;
+    }
+
+    // Answers a pick from a menu by replacing the menu with `content`, so the same option
+    // can't be picked twice. Returns whether the pick may go ahead.
+    async fn accept_pick(&self, ctx: &Context, component: &ComponentInteraction, content: String) -> bool {
+        let access = self.interaction_access(component.user.id, component.channel_id, component.member.as_ref());
+        let allowed = self.is_allowed_location(component.guild_id, component.channel_id);
+        let content = if !allowed {
+            self.messages(component.guild_id).get("not_allowed_here", &[])
+        } else if access == Access::Denied {
+            self.messages(component.guild_id).get("not_allowed_bot", &[])
+        } else {
+            content
+        };
+        let response = CreateInteractionResponse::UpdateMessage(
+            CreateInteractionResponseMessage::new().content(content).components(Vec::new()),
+        );
+        if let Err(e) = component.create_response(&ctx.http, response).await {
+            error!("Failed to respond to menu pick: {}", e);
+            return false;
+        }
+        allowed && access != Access::Denied
+    }
+
+    // A click on one of the buttons under a job card
+    async fn job_button(&self, ctx: &Context, component: &ComponentInteraction, action: JobAction, id: JobId) {
+        let access = self.interaction_access(component.user.id, component.channel_id, component.member.as_ref());
+        let reply = if !self.is_allowed_location(component.guild_id, component.channel_id) {
+            self.messages(component.guild_id).get("not_allowed_here", &[])
+        } else if access == Access::Denied {
+            self.messages(component.guild_id).get("not_allowed_bot", &[])
+        } else {
+            match action {
+                JobAction::Cancel => self.cancel_job(component.user.id, component.guild_id, access, id),
+                JobAction::Pin => self.pin_job(component.user.id, component.guild_id, access, id, true),
+                JobAction::Link => self.job_links(component.user.id, component.guild_id, access, id),
+                JobAction::Retry => match self.retry_request(component.user.id, component.guild_id, access, id) {
+                    Ok(request) => return self.retry_job(ctx, component, request).await,
+                    Err(reply) => reply,
+                },
+                JobAction::RetrySignedIn | JobAction::RetryOtherWay => match self.retry_request(component.user.id, component.guild_id, access, id) {
+                    Ok(request) => {
+                        let workaround = match action {
+                            JobAction::RetrySignedIn => Workaround::LoginCookies,
+                            _ => Workaround::RetryArgs,
+                        };
+                        let request = DownloadRequest { workaround: Some(workaround), ..request };
+                        return self.retry_job(ctx, component, request).await;
+                    }
+                    Err(reply) => reply,
+                },
+            }
+        };
+        let response = CreateInteractionResponse::Message(
+            CreateInteractionResponseMessage::new().content(truncate_message(reply)).ephemeral(true),
+        );
+        if let Err(e) = component.create_response(&ctx.http, response).await {
+            error!("Failed to respond to job #{} button: {}", id, e);
+        }
+    }
+
+    // Where a finished job's files can be found
+    fn job_links(&self, user: UserId, guild: Option<GuildId>, access: Access, id: JobId) -> String {
+        let messages = self.messages(guild);
+        let entry = match self.history.entry(id) {
+            Ok(Some(entry)) => entry,
+            Ok(None) => return messages.get("no_such_download", &[("id", &id)]),
+            Err(e) => {
+                error!("Failed to read download history: {}", e);
+                return messages.get("history_unreadable", &[]);
+            }
+        };
+        // The same downloads /history shows them
+        if !entry.visible_to(user, guild) && access != Access::Operator {
+            return messages.get("no_such_download", &[("id", &id)]);
+        }
+        if entry.deleted {
+            return messages.get("links_deleted", &[("id", &id)]);
+        }
+        let mut lines = vec![messages.get("links_downloaded_from", &[("id", &id), ("url", &entry.url)])];
+        match self.history.locations(id) {
+            Ok(locations) if !locations.is_empty() => lines.extend(locations),
+            Ok(_) => match &entry.output_path {
+                Some(path) => lines.push(format!("`{}`", path)),
+                None => lines.push(messages.get("links_no_files", &[])),
+            },
+            Err(e) => {
+                error!("Failed to read where #{} was stored: {}", id, e);
+                lines.push(messages.get("links_unreadable", &[]));
+            }
+        }
+        lines.join("\n")
+    }
+
+    // The request a finished job was submitted with, if the user may run it again
+    fn retry_request(&self, user: UserId, guild: Option<GuildId>, access: Access, id: JobId) -> Result<DownloadRequest, String> {
+        let messages = self.messages(guild);
+        if self.jobs.get(id).is_some() {
+            return Err(messages.get("retry_running", &[("id", &id)]));
+        }
+        let saved = match self.history.request(id) {
+            Ok(Some(saved)) => saved,
+            Ok(None) => return Err(messages.get("retry_not_saved", &[("id", &id)])),
+            Err(e) => {
+                error!("Failed to read download history: {}", e);
+                return Err(messages.get("history_unreadable", &[]));
+            }
+        };
+        let request: DownloadRequest = serde_json::from_str(&saved).map_err(|e| {
+            error!("Failed to parse the saved request of job #{}: {}", id, e);
+            messages.get("retry_unreadable", &[("id", &id)])
+        })?;
+        if !access.covers(user, guild, request.requester, request.guild) {
+            return Err(messages.get("retry_not_yours", &[("id", &id), ("user", &request.requester.mention())]));
+        }
+        Ok(request)
+    }
+
+    async fn retry_job(&self, ctx: &Context, component: &ComponentInteraction, request: DownloadRequest) {
+        info!("<{}> retried by {}", request.url, component.user.id);
+        let response = CreateInteractionResponse::Message(
+            CreateInteractionResponseMessage::new().content(self.messages(request.guild).get("retrying_url", &[("url", &request.url)])),
+        );
+        if let Err(e) = component.create_response(&ctx.http, response).await {
+            error!("Failed to respond to retry: {}", e);
+            return;
+        }
+        // The old card shouldn't start the same download again
+        let edit = EditMessage::new().components(Vec::new());
+        if let Err(e) = component.channel_id.edit_message(&ctx.http, component.message.id, edit).await {
+            log::debug!("Failed to remove the buttons of the retried job: {}", e);
+        }
+        self.report_submission(ctx, StatusMessage::Interaction(component.token.clone()), request).await;
+    }
+
+    fn usage_command(&self, ctx: &Context, cmd: &CommandInteraction, access: Access) -> String {
+        let messages = self.messages(cmd.guild_id);
+        if access < Access::Admin {
+            return messages.get("usage_admins_only", &[]);
+        }
+        let period = string_option(cmd, "period").and_then(Period::parse).unwrap_or(Period::Week);
+        let guild = cmd.guild_id.filter(|_| !bool_option(cmd, "everywhere").unwrap_or(false));
+        if guild.is_none() && access != Access::Operator {
+            return messages.get("usage_operators_only", &[]);
+        }
+        let usage = match self.history.usage_since(period.since(), guild, USAGE_TOP) {
+            Ok(usage) => usage,
+            Err(e) => {
+                error!("Failed to read usage: {}", e);
+                return messages.get("history_unreadable", &[]);
+            }
+        };
+        let place = messages.get(if guild.is_some() { "this_server" } else { "the_bot" }, &[]);
+        let mut lines = vec![messages.get("usage_total", &[
+            ("bytes", &format_bytes(usage.total)),
+            ("place", &place),
+            ("period", &period.describe(&messages)),
+            ("jobs", &usage.jobs),
+        ])];
+        if guild.is_none() && !usage.by_guild.is_empty() {
+            lines.push(messages.get("usage_servers", &[]));
+            for (guild, bytes) in &usage.by_guild {
+                let name = match guild {
+                    Some(guild) => guild.name(&ctx.cache).unwrap_or_else(|| guild.to_string()),
+                    None => messages.get("usage_direct_messages", &[]),
+                };
+                lines.push(messages.get("usage_server", &[("name", &name), ("bytes", &format_bytes(*bytes))]));
+            }
+        }
+        if !usage.by_user.is_empty() {
+            lines.push(messages.get("usage_users", &[]));
+            for (user, bytes) in &usage.by_user {
+                lines.push(messages.get("usage_user", &[("user", &user.mention()), ("bytes", &format_bytes(*bytes))]));
+            }
+        }
+        lines.join("\n")
+    }
+
+    async fn stats_command(&self, ctx: &Context, cmd: &CommandInteraction, access: Access) {
+        let messages = self.messages(cmd.guild_id);
+        let everywhere = bool_option(cmd, "everywhere").unwrap_or(false);
+        if (everywhere || cmd.guild_id.is_none()) && access != Access::Operator {
+            return respond(ctx, cmd, messages.get("stats_operators_only", &[])).await;
+        }
+        let guild = cmd.guild_id.filter(|_| !everywhere);
+        let weeks = integer_option(cmd, "weeks").map_or(DEFAULT_STATS_WEEKS, |weeks| weeks.clamp(1, MAX_STATS_WEEKS as i64) as u32);
+        let since = stats::since(weeks);
+        let found = match self.history.stats_since(since, guild, STATS_TOP) {
+            Ok(found) => found,
+            Err(e) => {
+                error!("Failed to read stats: {}", e);
+                return respond(ctx, cmd, messages.get("history_unreadable", &[])).await;
+            }
+        };
+        let weekly = stats::weekly(&found, since, weeks);
+        let place = messages.get(if guild.is_some() { "this_server" } else { "the_bot" }, &[]);
+        let mut message = CreateInteractionResponseMessage::new()
+            .content(truncate_message(stats::summary(&found, &weekly, &place, &messages)))
+            .ephemeral(true);
+        if bool_option(cmd, "chart").unwrap_or(false) {
+            match stats::chart(&weekly) {
+                Ok(png) => message = message.add_file(CreateAttachment::bytes(png, "stats.png")),
+                Err(e) => error!("Failed to draw the stats chart: {}", e),
+            }
+        }
+        if let Err(e) = cmd.create_response(&ctx.http, CreateInteractionResponse::Message(message)).await {
+            error!("Failed to send the stats: {}", e);
+        }
+    }
+
+    async fn log_command(&self, ctx: &Context, cmd: &CommandInteraction, access: Access) {
+        let messages = self.messages(cmd.guild_id);
+        if access != Access::Operator {
+            return respond(ctx, cmd, messages.get("log_operators_only", &[])).await;
+        }
+        let Some(id) = integer_option(cmd, "job").filter(|&id| id > 0) else {
+            return respond(ctx, cmd, messages.get("missing_job", &[])).await;
+        };
+        let path = self.job_logs.path(id as JobId);
+        let attachment = match CreateAttachment::path(&path).await {
+            Ok(attachment) => attachment,
+            Err(e) => {
+                log::debug!("Failed to read {}: {}", path.display(), e);
+                return respond(ctx, cmd, messages.get("no_log", &[("id", &id)])).await;
+            }
+        };
+        let response = CreateInteractionResponse::Message(
+            CreateInteractionResponseMessage::new()
+                .content(messages.get("log_of", &[("id", &id)]))
+                .add_file(attachment)
+                .ephemeral(true),
+        );
+        if let Err(e) = cmd.create_response(&ctx.http, response).await {
+            error!("Failed to send the log of job #{}: {}", id, e);
+        }
+    }
+
+    async fn archive_command(&self, ctx: &Context, cmd: &CommandInteraction, access: Access) {
+        if access != Access::Operator {
+            return respond(ctx, cmd, self.messages(cmd.guild_id).get("archive_operators_only", &[])).await;
+        }
+        match cmd.data.options.first().map(|option| option.name.as_str()) {
+            Some("import") => self.import_archive(ctx, cmd).await,
+            _ => self.export_archive(ctx, cmd).await,
+        }
+    }
+
+    async fn export_archive(&self, ctx: &Context, cmd: &CommandInteraction) {
+        let messages = self.messages(cmd.guild_id);
+        let entries = match self.history.archive_entries() {
+            Ok(entries) => entries,
+            Err(e) => {
+                error!("Failed to read the download archive: {}", e);
+                return respond(ctx, cmd, messages.get("archive_unreadable", &[])).await;
+            }
+        };
+        let (file, name) = match subcommand_string_option(cmd, "as") {
+            Some("ytdlp") => (archive::to_ytdlp(&entries), "archive.txt"),
+            _ => (archive::to_csv(&entries), "archive.csv"),
+        };
+        if file.len() > MAX_ARCHIVE_BYTES {
+            return respond(ctx, cmd, messages.get("archive_too_big", &[("size", &format_bytes(file.len() as u64))])).await;
+        }
+        info!("Archive exported by {}", cmd.user.id);
+        let response = CreateInteractionResponse::Message(
+            CreateInteractionResponseMessage::new()
+                .content(messages.get("archive_exported", &[("count", &entries.len())]))
+                .add_file(CreateAttachment::bytes(file.into_bytes(), name))
+                .ephemeral(true),
+        );
+        if let Err(e) = cmd.create_response(&ctx.http, response).await {
+            error!("Failed to send the archive: {}", e);
+        }
+    }
+
+    async fn import_archive(&self, ctx: &Context, cmd: &CommandInteraction) {
+        let messages = self.messages(cmd.guild_id);
+        let Some(file) = subcommand_attachment_option(cmd, "file") else {
+            return respond(ctx, cmd, messages.get("missing_archive", &[])).await;
+        };
+        if file.size as usize > MAX_ARCHIVE_BYTES {
+            return respond(ctx, cmd, messages.get("archive_import_too_big", &[("name", &file.filename)])).await;
+        }
+        respond(ctx, cmd, messages.get("archive_importing", &[("name", &file.filename)])).await;
+        let reply = match file.download().await {
+            Ok(bytes) => {
+                let (entries, skipped) = archive::parse(&String::from_utf8_lossy(&bytes));
+                match self.history.import_archive(&entries) {
+                    Ok(added) => {
+                        info!("{} archive entries imported from {} by {}", added, file.filename, cmd.user.id);
+                        let mut reply = messages.get("archive_imported", &[
+                            ("name", &file.filename),
+                            ("added", &added),
+                            ("known", &(entries.len() - added)),
+                        ]);
+                        if skipped > 0 {
+                            reply.push(' ');
+                            reply.push_str(&messages.get("archive_skipped", &[("count", &skipped)]));
+                        }
+                        reply
+                    }
+                    Err(e) => {
+                        error!("Failed to import {}: {:#}", file.filename, e);
+                        messages.get("archive_import_failed", &[])
+                    }
+                }
+            }
+            Err(e) => {
+                error!("Failed to download {}: {}", file.url, e);
+                messages.get("list_unreadable", &[("name", &file.filename)])
+            }
+        };
+        let status = StatusMessage::Interaction(cmd.token.clone());
+        if let Err(e) = status.edit(&ctx.http, reply).await {
+            error!("Failed to update /archive response: {}", e);
+        }
+    }
+
+    fn reload_cookies_command(&self, cmd: &CommandInteraction, access: Access) -> String {
+        let messages = self.messages(cmd.guild_id);
+        if access != Access::Operator {
+            return messages.get("cookies_operators_only", &[]);
+        }
+        let settings = match Settings::from_env_and_file() {
+            Ok(settings) => settings,
+            Err(e) => return messages.get("config_unreadable", &[("error", &format!("{:#}", e))]),
+        };
+        let config = settings.cookie_config();
+        let mut reply = messages.get("cookies_reloaded", &[("cookies", &config.describe())]);
+        let missing = config.missing_files();
+        if !missing.is_empty() {
+            reply.push('\n');
+            reply.push_str(&messages.get("cookies_missing", &[("files", &missing.join(", "))]));
+        }
+        info!("Cookies reloaded: {}", config.describe());
+        self.cookies.replace(config);
+        reply
+    }
+
+    fn config_command(&self, cmd: &CommandInteraction, access: Access) -> String {
+        let messages = self.messages(cmd.guild_id);
+        if access < Access::Admin {
+            return messages.get("config_admins_only", &[]);
+        }
+        let Some(guild) = cmd.guild_id else {
+            return messages.get("config_in_server", &[]);
+        };
+        let settings = match cmd.data.options.first().map(|option| option.name.as_str()) {
+            Some("show") | None => Ok(self.guild_settings(guild)),
+            Some(subcommand) => {
+                let Some(key) = subcommand_string_option(cmd, "key") else {
+                    return messages.get("missing_setting", &[]);
+                };
+                let value = subcommand_string_option(cmd, "value");
+                if subcommand == "set" && value.is_none() {
+                    return messages.get("missing_value", &[]);
+                }
+                let quota = self.configured_quota(Some(guild));
+                let allowed = |settings: &guilds::GuildSettings| {
+                    quota.check_tightening(settings.max_downloads_per_hour, settings.max_gb_per_day, &messages)
+                };
+                self.guilds.update(&self.history, guild, key, value, allowed).map(|()| {
+                    info!("{} of guild {} set to {:?} by {}", key, guild, value, cmd.user.id);
+                    self.guild_settings(guild)
+                })
+            }
+        };
+        match settings {
+            Ok(settings) => format!("{}\n{}", messages.get("config_settings", &[]), settings.describe()),
+            Err(e) => format!("{:#}", e),
+        }
+    }
+
+    fn reload_command(&self, cmd: &CommandInteraction, access: Access) -> String {
+        let messages = self.messages(cmd.guild_id);
+        if access != Access::Operator {
+            return messages.get("reload_operators_only", &[]);
+        }
+        match self.reload() {
+            Ok(()) => messages.get("reloaded", &[]),
+            Err(e) => messages.get("reload_failed", &[("error", &format!("{:#}", e))]),
+        }
+    }
+
+    async fn status_command(&self, cmd: &CommandInteraction) -> String {
+        let messages = self.messages(cmd.guild_id);
+        let budget = self.budget_for(cmd.channel_id);
+        let mut lines = Vec::new();
+        // Discord only waits 3 seconds for the response
+        let version = match tokio::time::timeout(Duration::from_secs(2), binary::version()).await {
+            Ok(Ok(version)) => version,
+            Ok(Err(_)) => messages.get("ytdlp_not_working", &[]),
+            Err(_) => messages.get("ytdlp_not_responding", &[]),
+        };
+        lines.push(messages.get("status_version", &[("version", &version), ("uptime", &format_duration(self.started.elapsed().as_secs()))]));
+        let shards = self.gateway.shards();
+        if shards.len() > 1 {
+            let connected = shards.iter().filter(|(_, shard)| shard.connected).count();
+            let guilds: usize = shards.iter().map(|(_, shard)| shard.guilds).sum();
+            let mut bots: Vec<&str> = shards.iter().map(|((bot, _), _)| bot.as_str()).collect();
+            bots.dedup();
+            lines.push(messages.get("status_shards", &[
+                ("connected", &connected),
+                ("shards", &shards.len()),
+                ("guilds", &guilds),
+                ("bots", &bots.join(", ")),
+            ]));
+        }
+        let output_dir = self.output_dir_for(cmd.channel_id, cmd.guild_id, None);
+        if let Some((free, total)) = disk::space(Path::new(&output_dir)) {
+            lines.push(messages.get("status_disk", &[("free", &format_bytes(free)), ("total", &format_bytes(total))]));
+        }
+        let midnight = history::now() - history::now().rem_euclid(86_400);
+        match self.history.totals_since(midnight) {
+            Ok(totals) => lines.push(messages.get("status_today", &[
+                ("done", &totals.done),
+                ("bytes", &format_bytes(totals.bytes)),
+                ("failed", &totals.failed),
+                ("cancelled", &totals.cancelled),
+            ])),
+            Err(e) => error!("Failed to read today's totals: {}", e),
+        }
+        if budget.is_set() {
+            lines.push(budget.usage(&self.history, cmd.user.id, cmd.channel_id).describe(&messages));
+        }
+        if self.queue.is_paused() {
+            lines.push(messages.get("status_paused", &[]));
+        }
+        let jobs = self.jobs.list();
+        if jobs.is_empty() {
+            lines.push(messages.get("status_idle", &[]));
+            return lines.join("\n");
+        }
+        let running = jobs.iter().filter(|job| job.state == JobState::Running).count();
+        lines.push(messages.get("status_jobs", &[
+            ("running", &running),
+            ("max", &self.queue.max_concurrent()),
+            ("queued", &(jobs.len() - running)),
+        ]));
+        // Every job counts towards the queue, but only the ones the caller may see are listed
+        let (jobs, hidden): (Vec<_>, Vec<_>) = jobs.into_iter().partition(|job| job.visible_to(cmd.user.id, cmd.guild_id));
+        if !hidden.is_empty() {
+            lines.push(messages.get("status_hidden", &[("count", &hidden.len())]));
+        }
+        for job in jobs {
+            let secs = job.started.elapsed().as_secs();
+            let state = match (job.state, job.progress()) {
+                (JobState::Running, Some(progress)) => {
+                    messages.get("status_running_progress", &[("secs", &secs), ("progress", &progress.describe(&messages))])
+                }
+                (JobState::Running, None) => messages.get("status_running", &[("secs", &secs)]),
+                (JobState::Queued, _) => messages.get("status_queued", &[]),
+            };
+            lines.push(messages.get("status_job", &[
+                ("id", &job.id),
+                ("url", &job.url),
+                ("user", &job.requester.mention()),
+                ("channel", &job.channel.mention()),
+                ("state", &state),
+            ]));
+        }
+        lines.join("\n")
+    }
+
+    fn command_access(&self, cmd: &CommandInteraction) -> Access {
+        self.interaction_access(cmd.user.id, cmd.channel_id, cmd.member.as_deref())
+    }
+
+    fn member_priority(&self, access: Access, member: Option<&Member>) -> Priority {
+        let roles = member.map_or(&[][..], |member| &member.roles[..]);
+        self.priority_for(access, roles, member.is_some_and(|member| member.premium_since.is_some()))
+    }
+
+    fn prefs_command(&self, cmd: &CommandInteraction) -> String {
+        let messages = self.messages(cmd.guild_id);
+        let user = cmd.user.id;
+        let prefs = match cmd.data.options.first().map(|option| option.name.as_str()) {
+            Some("set") => match subcommand_string_option(cmd, "values") {
+                Some(values) => self.prefs.set(&self.history, user, values),
+                None => return messages.get("missing_values", &[]),
+            },
+            Some("reset") => match subcommand_string_option(cmd, "key") {
+                Some(key) => self.prefs.unset(&self.history, user, key),
+                None => return messages.get("missing_preference", &[]),
+            },
+            _ => Ok(self.prefs.get(user)),
+        };
+        match prefs {
+            Ok(prefs) => format!("{}\n{}", messages.get("prefs_yours", &[]), prefs.describe()),
+            Err(e) => format!("{:#}", e),
+        }
+    }
+
+    fn queue_command(&self, cmd: &CommandInteraction, access: Access) -> String {
+        let messages = self.messages(cmd.guild_id);
+        if access != Access::Operator {
+            return messages.get("queue_operators_only", &[]);
+        }
+        let queued = self.jobs.count(JobState::Queued);
+        let running = self.jobs.count(JobState::Running);
+        match cmd.data.options.first().map(|option| option.name.as_str()) {
+            Some("pause") => {
+                if !self.queue.pause() {
+                    return messages.get("queue_already_paused", &[]);
+                }
+                info!("Queue paused by {}", cmd.user.id);
+                let running = if self.pause_suspends_running {
+                    messages.get("queue_suspended", &[("count", &ytdlp::suspend_processes(true))])
+                } else {
+                    messages.get("queue_finishing", &[("count", &running)])
+                };
+                messages.get("queue_paused", &[("queued", &queued), ("running", &running)])
+            }
+            Some("resume") => {
+                if !self.queue.resume() {
+                    return messages.get("queue_not_paused", &[]);
+                }
+                let suspended = ytdlp::suspend_processes(false);
+                info!("Queue resumed by {}", cmd.user.id);
+                if suspended > 0 {
+                    messages.get("queue_resumed_suspended", &[("suspended", &suspended), ("queued", &queued)])
+                } else {
+                    messages.get("queue_resumed", &[("queued", &queued)])
+                }
+            }
+            _ => messages.get("unknown_subcommand", &[]),
+        }
+    }
+
+    fn priority_command(&self, cmd: &CommandInteraction, access: Access) -> String {
+        let messages = self.messages(cmd.guild_id);
+        if access < Access::Admin {
+            return messages.get("priority_change_admins_only", &[]);
+        }
+        let Some(id) = integer_option(cmd, "job").filter(|&id| id > 0) else {
+            return messages.get("missing_job", &[]);
+        };
+        if !self.jobs.get(id as JobId).is_some_and(|job| access.covers(cmd.user.id, cmd.guild_id, job.requester, job.guild)) {
+            return messages.get("not_queued", &[("id", &id)]);
+        }
+        let priority = match string_option(cmd, "priority").map(str::parse::<Priority>) {
+            Some(Ok(priority)) => priority,
+            Some(Err(e)) => return e,
+            None => return messages.get("missing_priority", &[]),
+        };
+        if !self.queue.set_priority(id as JobId, priority) {
+            return messages.get("not_queued", &[("id", &id)]);
+        }
+        info!("Job #{} moved to the {} priority lane by {}", id, priority, cmd.user.id);
+        messages.get("priority_moved", &[("id", &id), ("priority", &priority)])
+    }
+
+    fn interaction_access(&self, user: UserId, channel: ChannelId, member: Option<&Member>) -> Access {
+        let roles = member.map_or(&[][..], |member| &member.roles[..]);
+        // Interactions carry the member's permissions in the channel, so no lookup is needed
+        let moderator = member
+            .and_then(|member| member.permissions)
+            .is_some_and(|permissions| permissions.intersects(MODERATOR_PERMISSIONS));
+        self.member_access(user, member.map(|member| member.guild_id), channel, roles, moderator)
+    }
+
+    fn cancel_command(&self, cmd: &CommandInteraction, access: Access) -> String {
+        match integer_option(cmd, "job") {
+            Some(id) if id > 0 => self.cancel_job(cmd.user.id, cmd.guild_id, access, id as u64),
+            _ => self.messages(cmd.guild_id).get("missing_job", &[]),
+        }
+    }
+
+    fn cancel_job(&self, user: UserId, guild: Option<GuildId>, access: Access, id: JobId) -> String {
+        let messages = self.messages(guild);
+        let Some(job) = self.jobs.get(id).filter(|job| job.visible_to(user, guild) || access == Access::Operator) else {
+            return messages.get("no_active_job", &[("id", &id)]);
+        };
+        if !access.covers(user, guild, job.requester, job.guild) {
+            return messages.get("cancel_not_yours", &[("id", &id), ("user", &job.requester.mention())]);
+        }
+        info!("Job #{} cancelled by {}", id, user);
+        match self.jobs.cancel(id, CancelReason::User(user)) {
+            Some(job) => messages.get("cancelled", &[("id", &job.id), ("url", &job.url)]),
+            None => messages.get("no_active_job", &[("id", &id)]),
+        }
+    }
+
+    fn stream_command(&self, cmd: &CommandInteraction, access: Access) -> String {
+        let messages = self.messages(cmd.guild_id);
+        let recordings = self.jobs.list().into_iter().filter(|job| job.recording.is_some());
+        let job = match subcommand_integer_option(cmd, "job") {
+            Some(id) => match recordings.clone().find(|job| job.id == id as u64) {
+                Some(job) => job,
+                None => return messages.get("not_recording", &[("id", &id)]),
+            },
+            None => {
+                let own: Vec<JobInfo> = recordings.filter(|job| job.requester == cmd.user.id).collect();
+                match &own[..] {
+                    [job] => job.clone(),
+                    [] => return messages.get("no_recordings", &[]),
+                    _ => return messages.get("several_recordings", &[]),
+                }
+            }
+        };
+        if !access.covers(cmd.user.id, cmd.guild_id, job.requester, job.guild) {
+            return messages.get("stop_not_yours", &[("id", &job.id), ("user", &job.requester.mention())]);
+        }
+        if job.state == JobState::Queued {
+            return messages.get("not_recording_yet", &[("id", &job.id)]);
+        }
+        info!("Recording of job #{} stopped by {}", job.id, cmd.user.id);
+        job.stop_recording();
+        messages.get("stopping_recording", &[("id", &job.id), ("url", &job.url)])
+    }
+
+    fn pin_command(&self, cmd: &CommandInteraction, access: Access, pinned: bool) -> String {
+        match integer_option(cmd, "job") {
+            Some(id) if id > 0 => self.pin_job(cmd.user.id, cmd.guild_id, access, id as u64, pinned),
+            _ => self.messages(cmd.guild_id).get("missing_job", &[]),
+        }
+    }
+
+    fn pin_job(&self, user: UserId, guild: Option<GuildId>, access: Access, id: JobId, pinned: bool) -> String {
+        let messages = self.messages(guild);
+        let entry = match self.history.entry(id) {
+            Ok(Some(entry)) => entry,
+            Ok(None) => return messages.get("no_such_download", &[("id", &id)]),
+            Err(e) => {
+                error!("Failed to read download history: {}", e);
+                return messages.get("history_unreadable", &[]);
+            }
+        };
+        if !access.covers(user, guild, entry.requester, entry.guild) {
+            return messages.get("pin_not_yours", &[]);
+        }
+        if entry.deleted {
+            return messages.get("already_deleted", &[("id", &id)]);
+        }
+        if let Err(e) = self.history.set_pinned(id, pinned) {
+            error!("Failed to pin #{}: {:#}", id, e);
+            return messages.get("history_unwritable", &[]);
+        }
+        info!("Job #{} {} by {}", id, if pinned { "pinned" } else { "unpinned" }, user);
+        messages.get(if pinned { "pinned" } else { "unpinned" }, &[("id", &id)])
+    }
+
+    async fn subscribe_command(&self, ctx: &Context, cmd: &CommandInteraction) {
+        let messages = self.messages(cmd.guild_id);
+        let Some(url) = subcommand_string_option(cmd, "url").map(str::trim) else {
+            return respond(ctx, cmd, messages.get("missing_url", &[])).await;
+        };
+        if !is_valid_url(url) {
+            return respond(ctx, cmd, messages.get("invalid_url", &[])).await;
+        }
+        let channel = cmd.data.options.first().is_some_and(|option| option.name == "channel");
+        let Some(guild) = cmd.guild_id else {
+            return respond(ctx, cmd, messages.get("subscribe_in_server", &[])).await;
+        };
+        if let Err(e) = self.live().domains.check(url) {
+            return respond(ctx, cmd, e.describe(&messages)).await;
+        }
+        let format = match subcommand_string_option(cmd, "format").map(str::parse::<FormatSpec>) {
+            Some(Ok(format)) => Some(format),
+            Some(Err(e)) => return respond(ctx, cmd, e.to_string()).await,
+            None => None,
+        };
+        respond(ctx, cmd, messages.get("looking_up", &[("url", &url)])).await;
+        let feed_url = if channel {
+            feeds::youtube_feed(url, &self.cookies.args_for(url)).await
+        } else {
+            Ok(url.to_string())
+        };
+        let reply = match feed_url {
+            Ok(feed_url) => match self.feeds.fetch(&feed_url).await {
+                Ok(feed) => self.subscribe(cmd, guild, &feed_url, format, &feed),
+                Err(e) => messages.get("feed_unreadable", &[("url", &feed_url), ("error", &format!("{:#}", e))]),
+            },
+            Err(e) => messages.get("uploads_not_found", &[("url", &url), ("error", &format!("{:#}", e))]),
+        };
+        if let Err(e) = cmd.edit_response(&ctx.http, EditInteractionResponse::new().content(truncate_message(reply))).await {
+            error!("Failed to update /subscribe response: {}", e);
+        }
+    }
+
+    fn subscribe(&self, cmd: &CommandInteraction, guild: GuildId, url: &str, format: Option<FormatSpec>, feed: &Feed) -> String {
+        let messages = self.messages(Some(guild));
+        let subscription = Subscription {
+            id: 0,
+            url: url.to_string(),
+            title: feed.title.clone(),
+            guild: Some(guild),
+            channel: cmd.channel_id,
+            requester: cmd.user.id,
+            format: format.map(|format| format.to_string()),
+            created_at: history::now(),
+            last_checked: Some(history::now()),
+            last_error: None,
+        };
+        let id = match self.history.subscribe(&subscription) {
+            Ok(Some(id)) => id,
+            Ok(None) => return messages.get("already_subscribed", &[("url", &url)]),
+            Err(e) => {
+                error!("Failed to subscribe to {}: {:#}", url, e);
+                return messages.get("subscription_unsaved", &[]);
+            }
+        };
+        // Only episodes that come out from now on are downloaded
+        let guids: Vec<&str> = feed.episodes.iter().map(|episode| episode.guid.as_str()).collect();
+        if let Err(e) = self.history.mark_episodes_seen(id, &guids) {
+            error!("Failed to save the episodes of {}: {:#}", url, e);
+        }
+        info!(target: "audit", "{} subscribed channel {} to {} (subscription #{})", cmd.user.id, cmd.channel_id, url, id);
+        messages.get("subscribed", &[
+            ("title", &feed.title.as_deref().unwrap_or(url)),
+            ("id", &id),
+            ("minutes", &self.feeds.settings.interval_mins.max(1)),
+            ("skipped", &feed.episodes.len()),
+        ])
+    }
+
+    fn unsubscribe_command(&self, cmd: &CommandInteraction, access: Access) -> String {
+        let messages = self.messages(cmd.guild_id);
+        let Some(id) = integer_option(cmd, "id").filter(|&id| id > 0) else {
+            return messages.get("missing_subscription", &[]);
+        };
+        let subscriptions = match self.history.subscriptions() {
+            Ok(subscriptions) => subscriptions,
+            Err(e) => {
+                error!("Failed to read subscriptions: {:#}", e);
+                return messages.get("subscriptions_unreadable", &[]);
+            }
+        };
+        let Some(subscription) = subscriptions.into_iter().find(|subscription| subscription.id == id && subscription.guild == cmd.guild_id) else {
+            return messages.get("no_such_subscription", &[("id", &id)]);
+        };
+        if subscription.requester != cmd.user.id && access < Access::Admin {
+            return messages.get("unsubscribe_not_yours", &[("id", &id), ("user", &subscription.requester.mention())]);
+        }
+        match self.history.unsubscribe(id) {
+            Ok(_) => {
+                info!(target: "audit", "{} removed subscription #{} to {}", cmd.user.id, id, subscription.url);
+                messages.get("unsubscribed", &[("title", &subscription.title.as_deref().unwrap_or(&subscription.url))])
+            }
+            Err(e) => {
+                error!("Failed to remove subscription #{}: {:#}", id, e);
+                messages.get("subscription_unremoved", &[])
+            }
+        }
+    }
+
+    fn subscriptions_command(&self, cmd: &CommandInteraction) -> String {
+        let messages = self.messages(cmd.guild_id);
+        let subscriptions = match self.history.subscriptions() {
+            Ok(subscriptions) => subscriptions,
+            Err(e) => {
+                error!("Failed to read subscriptions: {:#}", e);
+                return messages.get("subscriptions_unreadable", &[]);
+            }
+        };
+        let lines: Vec<String> = subscriptions.iter()
+            .filter(|subscription| subscription.guild.is_some() && subscription.guild == cmd.guild_id)
+            .map(|subscription| feeds::describe(subscription, &messages))
+            .collect();
+        if lines.is_empty() {
+            return messages.get("no_subscriptions", &[]);
+        }
+        format!("{}\n{}", messages.get("subscriptions", &[("count", &lines.len())]), lines.join("\n"))
+    }
+
+    async fn history_command(&self, ctx: &Context, cmd: &CommandInteraction) {
+        let messages = self.messages(cmd.guild_id);
+        let message = match history_filter(cmd, &messages) {
+            Ok(filter) => {
+                let filter = history::Filter { seen_by: Some(history::Viewer { user: cmd.user.id, guild: cmd.guild_id }), ..filter };
+                let page = subcommand_integer_option(cmd, "page").unwrap_or(1).max(1) as usize;
+                self.history_page(&filter, page, &messages)
+            }
+            Err(reply) => CreateInteractionResponseMessage::new().content(reply),
+        };
+        let response = CreateInteractionResponse::Message(message.ephemeral(true));
+        if let Err(e) = cmd.create_response(&ctx.http, response).await {
+            error!("Failed to respond to /history: {}", e);
+        }
+    }
+
+    // A click on one of the buttons under a /history page
+    async fn history_button(&self, ctx: &Context, component: &ComponentInteraction) {
+        let messages = self.messages(component.guild_id);
+        let message = match parse_history_button(&component.data.custom_id) {
+            // Whoever clicks sees what they're allowed to, not what the page's first viewer was
+            Some((filter, page)) => {
+                let filter = history::Filter { seen_by: Some(history::Viewer { user: component.user.id, guild: component.guild_id }), ..filter };
+                self.history_page(&filter, page.max(1), &messages)
+            }
+            None => CreateInteractionResponseMessage::new().content(messages.get("page_gone", &[])),
+        };
+        let response = CreateInteractionResponse::UpdateMessage(message);
+        if let Err(e) = component.create_response(&ctx.http, response).await {
+            error!("Failed to turn /history page: {}", e);
+        }
+    }
+
+    // One page of matching downloads, with buttons to the pages before and after it
+    fn history_page(&self, filter: &history::Filter, page: usize, messages: &Messages) -> CreateInteractionResponseMessage {
+        let message = CreateInteractionResponseMessage::new();
+        let (entries, total) = match self.history.page(filter, page - 1, HISTORY_PAGE_SIZE) {
+            Ok(result) => result,
+            Err(e) => {
+                error!("Failed to read download history: {}", e);
+                return message.content(messages.get("history_unreadable", &[]));
+            }
+        };
+        if total == 0 {
+            let unfiltered = history::Filter { seen_by: filter.seen_by, ..history::Filter::default() };
+            let reply = if *filter == unfiltered { "history_empty" } else { "history_no_matches" };
+            return message.content(messages.get(reply, &[]));
+        }
+        let pages = total.div_ceil(HISTORY_PAGE_SIZE);
+        if entries.is_empty() {
+            return message.content(messages.get("page_past_end", &[("page", &page), ("pages", &pages)]));
+        }
+        let message = message.embed(embed::history_embed(&entries, &describe_filter(filter, messages), page, pages, total, messages));
+        // Left out if the filter is too long to fit in the buttons; the page option still works then
+        let previous = messages.get("button_previous", &[]);
+        let next = messages.get("button_next", &[]);
+        let buttons: Option<Vec<CreateButton>> = [(page - 1, previous, page > 1), (page + 1, next, page < pages)]
+            .into_iter()
+            .map(|(target, label, enabled)| {
+                let button = CreateButton::new(history_button_id(filter, target)?)
+                    .label(label)
+                    .style(ButtonStyle::Secondary)
+                    .disabled(!enabled);
+                Some(button)
+            })
+            .collect();
+        match buttons {
+            Some(buttons) if pages > 1 => message.components(vec![CreateActionRow::Buttons(buttons)]),
+            _ => message.components(Vec::new()),
+        }
+    }
+}
+
+// The filter a /history subcommand's options describe
+fn history_filter(cmd: &CommandInteraction, messages: &Messages) -> Result<history::Filter, String> {
+    let term = subcommand_string_option(cmd, "term").map(str::trim).filter(|term| !term.is_empty());
+    let domain = match subcommand_string_option(cmd, "domain") {
+        // A pasted URL works too
+        Some(domain) => Some(crate::url_host(domain.trim()).ok_or_else(|| messages.get("not_a_domain", &[("domain", &domain)]))?),
+        None => None,
+    };
+    let from = subcommand_string_option(cmd, "from").map(|date| parse_date(date, messages)).transpose()?;
+    let to = subcommand_string_option(cmd, "to").map(|date| parse_date(date, messages)).transpose()?;
+    if let (Some(from), Some(to)) = (from, to) {
+        if to < from {
+            return Err(messages.get("dates_reversed", &[]));
+        }
+    }
+    Ok(history::Filter {
+        requester: subcommand_user_option(cmd, "user"),
+        term: term.map(str::to_owned),
+        domain,
+        since: from.map(|days| days * 86_400),
+        // Up to the end of that day
+        until: to.map(|days| (days + 1) * 86_400),
+        seen_by: None,
+    })
+}
+
+// Days since the epoch of a YYYY-MM-DD date
+fn parse_date(text: &str, messages: &Messages) -> Result<i64, String> {
+    let invalid = || messages.get("not_a_date", &[("date", &text)]);
+    let parts: Vec<&str> = text.trim().split('-').collect();
+    let [year, month, day] = parts[..] else {
+        return Err(invalid());
+    };
+    let (Ok(year), Ok(month), Ok(day)) = (year.parse::<i64>(), month.parse::<u32>(), day.parse::<u32>()) else {
+        return Err(invalid());
+    };
+    let days = template::days_from_civil(year, month, day);
+    // Catches months and days out of range, which wrap around into some other date
+    if template::civil_date(days) != (year, month, day) {
+        return Err(invalid());
+    }
+    Ok(days)
+}
+
+fn describe_filter(filter: &history::Filter, messages: &Messages) -> String {
+    let date = |secs: i64| {
+        let (year, month, day) = template::civil_date(secs.div_euclid(86_400));
+        format!("{:04}-{:02}-{:02}", year, month, day)
+    };
+    let mut parts = Vec::new();
+    if let Some(term) = &filter.term {
+        parts.push(messages.get("filter_term", &[("term", term)]));
+    }
+    if let Some(domain) = &filter.domain {
+        parts.push(messages.get("filter_domain", &[("domain", domain)]));
+    }
+    if let Some(requester) = filter.requester {
+        parts.push(messages.get("filter_requester", &[("user", &requester.mention())]));
+    }
+    match (filter.since, filter.until) {
+        (Some(since), Some(until)) => parts.push(messages.get("filter_between", &[("from", &date(since)), ("to", &date(until - 1))])),
+        (Some(since), None) => parts.push(messages.get("filter_since", &[("from", &date(since))])),
+        (None, Some(until)) => parts.push(messages.get("filter_until", &[("to", &date(until - 1))])),
+        (None, None) => {}
+    }
+    parts.join(" · ")
+}
+
+// The page and filter a /history button goes to, packed into its custom ID as
+// "history:<page>:<requester>:<from day>:<to day>:<domain>:<term>", or None if that's longer
+// than Discord allows
+fn history_button_id(filter: &history::Filter, page: usize) -> Option<String> {
+    let days = |secs: Option<i64>| secs.map(|secs| secs.div_euclid(86_400).to_string()).unwrap_or_default();
+    let id = format!(
+        "{}{}:{}:{}:{}:{}:{}",
+        HISTORY_BUTTON,
+        page,
+        filter.requester.map(|id| id.to_string()).unwrap_or_default(),
+        days(filter.since),
+        days(filter.until),
+        filter.domain.as_deref().unwrap_or_default(),
+        filter.term.as_deref().unwrap_or_default()
+    );
+    (id.len() <= CUSTOM_ID_LIMIT).then_some(id)
+}
+
+fn parse_history_button(id: &str) -> Option<(history::Filter, usize)> {
+    // The term comes last, so it may contain colons itself
+    let mut parts = id.strip_prefix(HISTORY_BUTTON)?.splitn(6, ':');
+    let page = parts.next()?.parse().ok()?;
+    let requester = match parts.next()? {
+        "" => None,
+        id => Some(UserId::new(id.parse().ok().filter(|&id| id != 0)?)),
+    };
+    let mut secs = || match parts.next()? {
+        "" => Some(None),
+        days => Some(Some(days.parse::<i64>().ok()? * 86_400)),
+    };
+    let since = secs()?;
+    let until = secs()?;
+    let text = |part: &str| (!part.is_empty()).then(|| part.to_owned());
+    let domain = text(parts.next()?);
+    let term = text(parts.next()?);
+    Some((history::Filter { requester, term, domain, since, until, seen_by: None }, page))
+}
+
+fn search_option(entry: &ytdlp::PlaylistEntry) -> Option<CreateSelectMenuOption> {
+    let url = entry.download_url().filter(|url| url.len() <= MENU_TEXT_LIMIT)?;
+    let label: String = entry.title.as_deref().unwrap_or(url).chars().take(MENU_TEXT_LIMIT).collect();
+    let details: Vec<String> = [
+        entry.uploader.clone().or_else(|| entry.channel.clone()),
+        entry.duration.map(|secs| format_duration(secs as u64)),
+    ]
+    .into_iter()
+    .flatten()
+    .collect();
+    let mut option = CreateSelectMenuOption::new(label, url);
+    if !details.is_empty() {
+        option = option.description(details.join(" · ").chars().take(MENU_TEXT_LIMIT).collect::<String>());
+    }
+    Some(option)
+}
+
+fn format_menu_option(format: &ytdlp::FormatInfo, messages: &Messages) -> Option<CreateSelectMenuOption> {
+    let selector = format.selector().filter(|selector| selector.len() <= MENU_TEXT_LIMIT)?;
+    let label: String = format.describe().chars().take(MENU_TEXT_LIMIT).collect();
+    let mut details = vec![match format.size() {
+        Some(size) => format_bytes(size),
+        None => messages.get("size_unknown", &[]),
+    }];
+    if let Some(tbr) = format.tbr {
+        details.push(format!("{:.0} kbit/s", tbr));
+    }
+    if format.is_video_only() {
+        details.push(messages.get("plus_best_audio", &[]));
+    }
+    Some(CreateSelectMenuOption::new(label, selector).description(details.join(" · ")))
+}
+
+async fn ytdlp_command(ctx: &Context, cmd: &CommandInteraction, access: Access, messages: &Messages) {
+    let subcommand = cmd.data.options.first().map(|option| option.name.as_str());
+    match subcommand {
+        Some("update") if access == Access::Operator => {
+            // Updating easily takes longer than Discord waits for a response
+            respond(ctx, cmd, messages.get("ytdlp_updating", &[])).await;
+            let reply = match binary::update().await {
+                Ok(version) => messages.get("ytdlp_updated", &[("version", &version)]),
+                Err(e) => messages.get("ytdlp_update_failed", &[("error", &format!("{:#}", e))]),
+            };
+            let status = StatusMessage::Interaction(cmd.token.clone());
+            if let Err(e) = status.edit(&ctx.http, truncate_message(reply)).await {
+                error!("Failed to update /ytdlp response: {}", e);
+            }
+        }
+        Some("update") => respond(ctx, cmd, messages.get("ytdlp_operators_only", &[])).await,
+        _ => {
+            let reply = match binary::version().await {
+                Ok(version) => messages.get("ytdlp_version", &[("version", &version), ("path", &binary::path().display())]),
+                Err(e) => messages.get("ytdlp_failed", &[("error", &format!("{:#}", e))]),
+            };
+            respond(ctx, cmd, reply).await;
+        }
+    }
+}
+
+async fn respond(ctx: &Context, cmd: &CommandInteraction, reply: String) {
+    let response = CreateInteractionResponse::Message(
+        CreateInteractionResponseMessage::new()
+            .content(truncate_message(reply))
+            .ephemeral(true),
+    );
+    if let Err(e) = cmd.create_response(&ctx.http, response).await {
+        error!("Failed to respond to /{}: {}", cmd.data.name, e);
+    }
+}
+
+fn string_option<'a>(cmd: &'a CommandInteraction, name: &str) -> Option<&'a str> {
+    cmd.data.options().into_iter().find_map(|opt| match opt.value {
+        ResolvedValue::String(value) if opt.name == name => Some(value),
+        _ => None,
+    })
+}
+
+fn integer_option(cmd: &CommandInteraction, name: &str) -> Option<i64> {
+    cmd.data.options().into_iter().find_map(|opt| match opt.value {
+        ResolvedValue::Integer(value) if opt.name == name => Some(value),
+        _ => None,
+    })
+}
+
+// Options of subcommands are nested inside the subcommand's own option
+fn subcommand_integer_option(cmd: &CommandInteraction, name: &str) -> Option<i64> {
+    cmd.data.options().into_iter().find_map(|opt| match opt.value {
+        ResolvedValue::SubCommand(options) => options.into_iter().find_map(|opt| match opt.value {
+            ResolvedValue::Integer(value) if opt.name == name => Some(value),
+            _ => None,
+        }),
+        _ => None,
+    })
+}
+
+fn subcommand_attachment_option<'a>(cmd: &'a CommandInteraction, name: &str) -> Option<&'a Attachment> {
+    cmd.data.options().into_iter().find_map(|opt| match opt.value {
+        ResolvedValue::SubCommand(options) => options.into_iter().find_map(|opt| match opt.value {
+            ResolvedValue::Attachment(value) if opt.name == name => Some(value),
+            _ => None,
+        }),
+        _ => None,
+    })
+}
+
+fn subcommand_string_option<'a>(cmd: &'a CommandInteraction, name: &str) -> Option<&'a str> {
+    cmd.data.options().into_iter().find_map(|opt| match opt.value {
+        ResolvedValue::SubCommand(options) => options.into_iter().find_map(|opt| match opt.value {
+            ResolvedValue::String(value) if opt.name == name => Some(value),
+            _ => None,
+        }),
+        _ => None,
+    })
+}
+
+fn subcommand_user_option(cmd: &CommandInteraction, name: &str) -> Option<UserId> {
+    cmd.data.options().into_iter().find_map(|opt| match opt.value {
+        ResolvedValue::SubCommand(options) => options.into_iter().find_map(|opt| match opt.value {
+            ResolvedValue::User(user, _) if opt.name == name => Some(user.id),
+            _ => None,
+        }),
+        _ => None,
+    })
+}
+
+fn bool_option(cmd: &CommandInteraction, name: &str) -> Option<bool> {
+    cmd.data.options().into_iter().find_map(|opt| match opt.value {
+        ResolvedValue::Boolean(value) if opt.name == name => Some(value),
+        _ => None,
+    })
+}
@@ -0,0 +1,88 @@
+use serenity::http::Http;
+use serenity::model::channel::ReactionType;
+use serenity::model::id::{ChannelId, MessageId, UserId};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::sync::oneshot;
+
+pub const CONFIRM: &str = "✅";
+pub const REJECT: &str = "❌";
+
+// How long the requester has to react before the download is dropped
+pub const TIMEOUT: Duration = Duration::from_secs(120);
+
+// Admins may not be around, so they get longer
+pub const APPROVAL_TIMEOUT: Duration = Duration::from_secs(15 * 60);
+
+// Who may answer a question
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Asked {
+    User(UserId),
+    Admins,
+}
+
+impl Asked {
+    pub fn timeout(&self) -> Duration {
+        match self {
+            Asked::User(_) => TIMEOUT,
+            Asked::Admins => APPROVAL_TIMEOUT,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Answer {
+    Confirmed,
+    Rejected,
+    TimedOut,
+}
+
+// Questions waiting for the requester or an admin to react ✅ or ❌, keyed by the question's message
+#[derive(Default)]
+pub struct Confirmations {
+    pending: Mutex<HashMap<MessageId, (Asked, oneshot::Sender<bool>)>>,
+}
+
+impl Confirmations {
+    pub async fn ask(&self, http: &Http, channel: ChannelId, asked: Asked, question: String) -> serenity::Result<Answer> {
+        let message = channel.say(http, question).await?;
+        let (tx, rx) = oneshot::channel();
+        self.pending.lock().unwrap().insert(message.id, (asked, tx));
+        for emoji in [CONFIRM, REJECT] {
+            if let Err(e) = message.react(http, ReactionType::Unicode(emoji.to_string())).await {
+                log::warn!("Failed to react to confirmation message: {}", e);
+            }
+        }
+        let answer = match tokio::time::timeout(asked.timeout(), rx).await {
+            Ok(Ok(true)) => Answer::Confirmed,
+            Ok(_) => Answer::Rejected,
+            Err(_) => Answer::TimedOut,
+        };
+        self.pending.lock().unwrap().remove(&message.id);
+        let _ = message.delete(http).await;
+        Ok(answer)
+    }
+
+    pub fn asks_admins(&self, message: MessageId) -> bool {
+        self.pending.lock().unwrap().get(&message).is_some_and(|(asked, _)| *asked == Asked::Admins)
+    }
+
+    // Handles a reaction to a pending question, returning whether it was one
+    pub fn answer(&self, message: MessageId, user: UserId, admin: bool, emoji: &str) -> bool {
+        let mut pending = self.pending.lock().unwrap();
+        let Some((asked, _)) = pending.get(&message) else {
+            return false;
+        };
+        let may_answer = match asked {
+            Asked::User(asked) => *asked == user,
+            Asked::Admins => admin,
+        };
+        if may_answer && (emoji == CONFIRM || emoji == REJECT) {
+            if let Some((_, tx)) = pending.remove(&message) {
+                let _ = tx.send(emoji == CONFIRM);
+            }
+        }
+        true
+    }
+}
@@ -0,0 +1,109 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::RwLock;
+
+use crate::domains;
+
+// Which cookies yt-dlp gets for each URL: the most specific site_cookies entry, then
+// cookies_path, then cookies_from_browser
+#[derive(Debug, Clone, Default)]
+pub struct CookieConfig {
+    default: Option<String>,
+    from_browser: Option<String>,
+    // Only used to retry downloads that failed for wanting an account
+    login: Option<String>,
+    sites: Vec<(String, String)>,
+}
+
+impl CookieConfig {
+    pub fn new(default: Option<String>, from_browser: Option<String>, login: Option<String>, sites: &HashMap<String, String>) -> Self {
+        let mut sites: Vec<_> = sites.iter()
+            .map(|(pattern, file)| (pattern.trim().trim_end_matches('.').to_lowercase(), file.clone()))
+            .collect();
+        // Longest first, so the first match is the most specific one
+        sites.sort_by_key(|(pattern, _)| std::cmp::Reverse(pattern.len()));
+        CookieConfig {
+            default: default.filter(|path| !path.is_empty()),
+            from_browser: from_browser.filter(|browser| !browser.is_empty()),
+            login: login.filter(|path| !path.is_empty()),
+            sites,
+        }
+    }
+
+    pub fn args_for(&self, url: &str) -> Vec<String> {
+        let host = crate::url_host(url).unwrap_or_default();
+        let site = self.sites.iter()
+            .find(|(pattern, _)| domains::matches(&host, pattern))
+            .map(|(_, file)| file);
+        if let Some(file) = site.or(self.default.as_ref()) {
+            log::info!("Using cookies file: {}", file);
+            return vec!["--cookies".to_string(), file.clone()];
+        }
+        match &self.from_browser {
+            Some(browser) => vec!["--cookies-from-browser".to_string(), browser.clone()],
+            None => Vec::new(),
+        }
+    }
+
+    pub fn login_args(&self) -> Option<Vec<String>> {
+        let file = self.login.as_ref()?;
+        log::info!("Using login cookies file: {}", file);
+        Some(vec!["--cookies".to_string(), file.clone()])
+    }
+
+    // Configured cookie files that don't exist, which yt-dlp would fail on
+    pub fn missing_files(&self) -> Vec<String> {
+        self.default.iter()
+            .chain(self.login.iter())
+            .chain(self.sites.iter().map(|(_, file)| file))
+            .filter(|file| !Path::new(file).exists())
+            .cloned()
+            .collect()
+    }
+
+    pub fn describe(&self) -> String {
+        let mut parts = Vec::new();
+        if let Some(default) = &self.default {
+            parts.push(format!("default file `{}`", default));
+        }
+        if let Some(browser) = &self.from_browser {
+            parts.push(format!("browser `{}`", browser));
+        }
+        if !self.sites.is_empty() {
+            parts.push(format!("{} site file(s)", self.sites.len()));
+        }
+        if let Some(login) = &self.login {
+            parts.push(format!("login file `{}`", login));
+        }
+        if parts.is_empty() {
+            "no cookies".to_string()
+        } else {
+            parts.join(", ")
+        }
+    }
+}
+
+// The current cookie configuration, replaceable at runtime with /reload-cookies
+pub struct Cookies(RwLock<CookieConfig>);
+
+impl Cookies {
+    pub fn new(config: CookieConfig) -> Self {
+        Cookies(RwLock::new(config))
+    }
+
+    pub fn args_for(&self, url: &str) -> Vec<String> {
+        self.0.read().unwrap().args_for(url)
+    }
+
+    pub fn login_args(&self) -> Option<Vec<String>> {
+        self.0.read().unwrap().login_args()
+    }
+
+    pub fn has_login(&self) -> bool {
+        self.0.read().unwrap().login.is_some()
+    }
+
+    pub fn replace(&self, config: CookieConfig) {
+        *self.0.write().unwrap() = config;
+    }
+}
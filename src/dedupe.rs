@@ -0,0 +1,209 @@
+use anyhow::{Context, Result};
+use log::{error, info, warn};
+use serde::Deserialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, UNIX_EPOCH};
+
+use crate::archivist::INCOMING_DIR;
+use crate::history::History;
+use crate::messages::Messages;
+use crate::progress::format_bytes;
+use crate::retention::IN_PROGRESS_SUFFIXES;
+use crate::verify::{self, Verified};
+
+const MIB: u64 = 1024 * 1024;
+
+// What a duplicate download is replaced with
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LinkKind {
+    // Both names share the same data, which stays until the last of them is deleted; they
+    // have to be on the same filesystem
+    Hardlink,
+    // The new name points at the old file, and breaks if that's deleted or moved
+    Symlink,
+}
+
+// The [dedupe] section of the config
+#[derive(Debug, Clone, Deserialize)]
+pub struct DedupeSettings {
+    #[serde(default = "default_link")]
+    pub link: LinkKind,
+    // How often the files in output_dir are hashed, to find what new downloads duplicate
+    #[serde(default = "default_interval_mins")]
+    pub interval_mins: u64,
+    // Smaller files aren't worth linking
+    #[serde(default = "default_min_mb")]
+    pub min_mb: u64,
+}
+
+fn default_link() -> LinkKind {
+    LinkKind::Hardlink
+}
+
+fn default_interval_mins() -> u64 {
+    360
+}
+
+fn default_min_mb() -> u64 {
+    1
+}
+
+// Indexes output_dir every `interval`. Only files that are new or changed since the last
+// time are hashed, so after the first pass it's mostly walking the directory.
+pub async fn run(output_dir: String, history: Arc<History>, interval: Duration) {
+    let output_dir = Arc::new(output_dir);
+    loop {
+        let (output_dir, history) = (Arc::clone(&output_dir), Arc::clone(&history));
+        // Hashing blocks
+        match tokio::task::spawn_blocking(move || index(Path::new(output_dir.as_str()), &history)).await {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => error!("Indexing the downloads failed: {:#}", e),
+            Err(e) => error!("Indexing the downloads panicked: {}", e),
+        }
+        tokio::time::sleep(interval).await;
+    }
+}
+
+fn index(output_dir: &Path, history: &History) -> Result<()> {
+    let mut indexed = history.indexed_files()?;
+    let mut files = Vec::new();
+    walk(output_dir, &output_dir.join(INCOMING_DIR), &mut files);
+    let mut hashed = (0, 0);
+    for (path, size, modified) in files {
+        if indexed.remove(path.to_string_lossy().as_ref()) == Some((size, modified)) {
+            continue;
+        }
+        match verify::sha256_file(&path) {
+            Ok(sha256) => {
+                history.index_file(&path, size, modified, &sha256);
+                hashed = (hashed.0 + 1, hashed.1 + size);
+            }
+            // Deleted since the walk, most likely
+            Err(e) => warn!("Couldn't hash {}: {:#}", path.display(), e),
+        }
+    }
+    // What's left is gone from the disk
+    let gone: Vec<String> = indexed.into_keys().collect();
+    history.unindex_files(&gone)?;
+    if hashed.0 > 0 || !gone.is_empty() {
+        info!("Indexed {} new or changed files ({}), forgot {} gone ones", hashed.0, format_bytes(hashed.1), gone.len());
+    }
+    Ok(())
+}
+
+// Every regular file with its size and modification time. Symlinks are left out, so the
+// links made for duplicates are never taken for originals.
+fn walk(dir: &Path, incoming: &Path, files: &mut Vec<(PathBuf, u64, i64)>) {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            warn!("Couldn't index {}: {}", dir.display(), e);
+            return;
+        }
+    };
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if name.starts_with('.') || IN_PROGRESS_SUFFIXES.iter().any(|suffix| name.ends_with(suffix)) {
+            continue;
+        }
+        let path = entry.path();
+        // entry.metadata() doesn't follow symlinks
+        let Ok(metadata) = entry.metadata() else {
+            continue;
+        };
+        if metadata.is_dir() && path != incoming {
+            walk(&path, incoming, files);
+        } else if metadata.is_file() {
+            files.push((path, metadata.len(), modified(&metadata)));
+        }
+    }
+}
+
+fn modified(metadata: &fs::Metadata) -> i64 {
+    metadata.modified().ok()
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .map(|since| since.as_secs() as i64)
+        .unwrap_or_default()
+}
+
+// Replaces each finished file that's byte-identical to one already indexed with a link to
+// it, then indexes what's left. Returns a line about each file that was replaced; failing to
+// link only keeps the duplicate.
+pub fn link_duplicates(output_dir: &str, history: &History, settings: &DedupeSettings, verified: &[Verified], messages: &Messages) -> Vec<String> {
+    let mut notes = Vec::new();
+    for file in verified {
+        let original = if file.size >= settings.min_mb * MIB {
+            original_of(history, file)
+        } else {
+            None
+        };
+        if let Some(original) = original {
+            match replace(&file.path, &original, settings.link) {
+                Ok(()) => {
+                    info!("Replaced {} with a {:?} to {}, its duplicate", file.path.display(), settings.link, original.display());
+                    let name = file.path.file_name().unwrap_or(file.path.as_os_str()).to_string_lossy();
+                    let key = match settings.link {
+                        LinkKind::Hardlink => "deduped_hardlink",
+                        LinkKind::Symlink => "deduped_symlink",
+                    };
+                    // Relative to output_dir, like the rest of the result, so no host paths show
+                    let shown = original.strip_prefix(output_dir).ok()
+                        .or_else(|| original.file_name().map(Path::new))
+                        .unwrap_or(&original);
+                    notes.push(messages.get(key, &[("name", &name), ("original", &shown.display())]));
+                    // Symlinks aren't indexed, and a hardlink is the original's data
+                    if settings.link == LinkKind::Symlink {
+                        continue;
+                    }
+                }
+                Err(e) => warn!("Couldn't link {} to its duplicate {}: {:#}", file.path.display(), original.display(), e),
+            }
+        }
+        if let Ok(metadata) = fs::metadata(&file.path) {
+            history.index_file(&file.path, metadata.len(), modified(&metadata), &file.sha256);
+        }
+    }
+    notes
+}
+
+// An indexed file with the same content that's still there unchanged
+fn original_of(history: &History, file: &Verified) -> Option<PathBuf> {
+    let candidates = match history.files_with_hash(&file.sha256, file.size) {
+        Ok(candidates) => candidates,
+        Err(e) => {
+            error!("Failed to look up duplicates of {}: {:#}", file.path.display(), e);
+            return None;
+        }
+    };
+    candidates.into_iter()
+        .filter(|(path, _)| *path != file.path)
+        .find(|(path, indexed)| {
+            fs::symlink_metadata(path).is_ok_and(|metadata| metadata.is_file() && metadata.len() == file.size && modified(&metadata) == *indexed)
+        })
+        .map(|(path, _)| path)
+}
+
+// The link is made beside the file first, so the file is never missing if linking fails
+fn replace(file: &Path, original: &Path, kind: LinkKind) -> Result<()> {
+    let name = file.file_name().unwrap_or_default().to_string_lossy();
+    let temporary = file.with_file_name(format!(".{}.dedupe", name));
+    let _ = fs::remove_file(&temporary);
+    match kind {
+        LinkKind::Hardlink => fs::hard_link(original, &temporary)?,
+        LinkKind::Symlink => {
+            let target = fs::canonicalize(original)?;
+            #[cfg(unix)]
+            std::os::unix::fs::symlink(target, &temporary)?;
+            #[cfg(windows)]
+            std::os::windows::fs::symlink_file(target, &temporary)?;
+        }
+    }
+    if let Err(e) = fs::rename(&temporary, file) {
+        let _ = fs::remove_file(&temporary);
+        return Err(e).with_context(|| format!("Failed to move {} to {}", temporary.display(), file.display()));
+    }
+    Ok(())
+}
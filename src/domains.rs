@@ -0,0 +1,61 @@
+use crate::messages::Messages;
+
+// Which sites the bot may download from. Patterns are domains, which also cover their
+// subdomains, or `*.domain` for subdomains only; a lone `*` matches everything.
+pub struct DomainPolicy {
+    allowed: Vec<String>,
+    blocked: Vec<String>,
+}
+
+#[derive(Debug)]
+pub enum Refused {
+    Blocked(String),
+    NotAllowed(String),
+}
+
+impl Refused {
+    pub fn describe(&self, messages: &Messages) -> String {
+        match self {
+            Refused::Blocked(host) => messages.get("domain_blocked", &[("host", host)]),
+            Refused::NotAllowed(host) => messages.get("domain_not_allowed", &[("host", host)]),
+        }
+    }
+}
+
+impl DomainPolicy {
+    pub fn new(allowed: &[String], blocked: &[String]) -> Self {
+        let normalize = |patterns: &[String]| {
+            patterns.iter()
+                .map(|pattern| pattern.trim().trim_end_matches('.').to_lowercase())
+                .filter(|pattern| !pattern.is_empty())
+                .collect()
+        };
+        DomainPolicy {
+            allowed: normalize(allowed),
+            blocked: normalize(blocked),
+        }
+    }
+
+    // Blocked domains win over allowed ones; an empty allowlist allows everything else
+    pub fn check(&self, url: &str) -> Result<(), Refused> {
+        let host = crate::url_host(url).unwrap_or_default();
+        if self.blocked.iter().any(|pattern| matches(&host, pattern)) {
+            return Err(Refused::Blocked(host));
+        }
+        if !self.allowed.is_empty() && !self.allowed.iter().any(|pattern| matches(&host, pattern)) {
+            return Err(Refused::NotAllowed(host));
+        }
+        Ok(())
+    }
+}
+
+// Whether `host` matches a pattern as described on DomainPolicy
+pub fn matches(host: &str, pattern: &str) -> bool {
+    if pattern == "*" {
+        return true;
+    }
+    match pattern.strip_prefix("*.") {
+        Some(domain) => host.strip_suffix(domain).is_some_and(|prefix| prefix.ends_with('.')),
+        None => crate::host_matches(host, pattern),
+    }
+}
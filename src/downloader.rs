@@ -0,0 +1,546 @@
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use serenity::async_trait;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::str::FromStr;
+use std::time::Instant;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::sync::watch;
+
+use crate::bandwidth;
+use crate::binary;
+use crate::domains;
+use crate::progress::Progress;
+use crate::sandbox;
+use crate::torrent::{self, Torrent, TorrentSettings};
+use crate::ytdlp::{self, DownloadOptions, ProcessGroup, RunningProcess, TimedOut};
+
+// Direct links to files with these extensions are fetched over plain HTTP
+const MEDIA_EXTENSIONS: &[&str] = &[
+    "mp4", "webm", "mkv", "mov", "avi", "mp3", "m4a", "ogg", "opus", "flac", "wav", "gif", "jpg", "jpeg", "png", "webp",
+];
+
+// Programs that can download a URL into the output directory
+#[async_trait]
+pub trait Downloader: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    // Downloads `url`, returning the paths of the files it wrote
+    async fn download(
+        &self,
+        url: &str,
+        options: &DownloadOptions<'_>,
+        progress: &watch::Sender<Option<Progress>>,
+    ) -> Result<Vec<PathBuf>>;
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Backend {
+    #[default]
+    YtDlp,
+    GalleryDl,
+    // A direct link to a media file
+    Http,
+    // A raw HLS playlist or RTMP stream
+    Ffmpeg,
+    // A magnet link or .torrent file, handed to a torrent client
+    Torrent,
+}
+
+impl Backend {
+    pub const ALL: [Backend; 5] = [Backend::YtDlp, Backend::GalleryDl, Backend::Http, Backend::Ffmpeg, Backend::Torrent];
+
+    // What the URL itself says it is, if it's a stream or file that doesn't need yt-dlp
+    fn detect(url: &str) -> Option<Backend> {
+        if torrent::is_magnet(url) || torrent::is_torrent_file(url) {
+            return Some(Backend::Torrent);
+        }
+        let lower = url.to_ascii_lowercase();
+        if lower.starts_with("rtmp://") || lower.starts_with("rtmps://") {
+            return Some(Backend::Ffmpeg);
+        }
+        let path = lower.split(['?', '#']).next().unwrap_or_default();
+        let name = path.rsplit('/').next().unwrap_or_default();
+        match name.rsplit_once('.') {
+            Some((_, "m3u8")) => Some(Backend::Ffmpeg),
+            Some((_, extension)) if MEDIA_EXTENSIONS.contains(&extension) => Some(Backend::Http),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Backend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Backend::YtDlp => "yt-dlp",
+            Backend::GalleryDl => "gallery-dl",
+            Backend::Http => "http",
+            Backend::Ffmpeg => "ffmpeg",
+            Backend::Torrent => "torrent",
+        };
+        f.write_str(name)
+    }
+}
+
+impl FromStr for Backend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim().to_ascii_lowercase();
+        Backend::ALL.into_iter()
+            .find(|backend| backend.to_string() == s || backend.to_string().replace('-', "") == s)
+            .ok_or_else(|| format!("'{}' isn't a downloader; use yt-dlp, gallery-dl, http, ffmpeg or torrent.", s))
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct GalleryDlSettings {
+    // gallery-dl executable (default: found on PATH)
+    pub path: Option<String>,
+    // gallery-dl's own config file and cookies, separate from yt-dlp's
+    pub config: Option<String>,
+    pub cookies: Option<String>,
+    // Domain patterns as in allowed_domains that always go to gallery-dl
+    #[serde(default = "default_sites")]
+    pub sites: Vec<String>,
+    // Also try gallery-dl when yt-dlp reports an unsupported URL
+    #[serde(default = "crate::default_true")]
+    pub fallback: bool,
+}
+
+fn default_sites() -> Vec<String> {
+    [
+        "imgur.com",
+        "flickr.com",
+        "pixiv.net",
+        "deviantart.com",
+        "artstation.com",
+        "danbooru.donmai.us",
+        "gelbooru.com",
+        "imgbox.com",
+        "catbox.moe",
+    ]
+    .map(String::from)
+    .to_vec()
+}
+
+pub struct YtDlp;
+
+#[async_trait]
+impl Downloader for YtDlp {
+    fn name(&self) -> &'static str {
+        "yt-dlp"
+    }
+
+    async fn download(
+        &self,
+        url: &str,
+        options: &DownloadOptions<'_>,
+        progress: &watch::Sender<Option<Progress>>,
+    ) -> Result<Vec<PathBuf>> {
+        ytdlp::download(url, options, progress).await
+    }
+}
+
+// For image and gallery hosts yt-dlp doesn't handle. Only the options that mean something
+// for images apply; formats, subtitles and post-processing are yt-dlp's.
+pub struct GalleryDl {
+    path: PathBuf,
+    config: Option<String>,
+    cookies: Option<String>,
+}
+
+#[async_trait]
+impl Downloader for GalleryDl {
+    fn name(&self) -> &'static str {
+        "gallery-dl"
+    }
+
+    async fn download(
+        &self,
+        url: &str,
+        options: &DownloadOptions<'_>,
+        _progress: &watch::Sender<Option<Progress>>,
+    ) -> Result<Vec<PathBuf>> {
+        log::info!("Downloading URL with gallery-dl: {}", url);
+        std::fs::create_dir_all(options.output_dir)
+            .with_context(|| format!("Failed to create output directory: {}", options.output_dir))?;
+        let mut cmd = sandbox::command(&self.path, Some(options.output_dir));
+        cmd.arg(url).arg("--destination").arg(options.output_dir);
+        if let Some(config) = &self.config {
+            cmd.arg("--config").arg(config);
+        }
+        if let Some(cookies) = &self.cookies {
+            cmd.arg("--cookies").arg(cookies);
+        }
+        if let Some(proxy) = options.proxy {
+            cmd.arg("--proxy").arg(proxy);
+        }
+        if let Some(rate) = options.rate_limit {
+            cmd.arg("--limit-rate").arg(rate);
+        }
+        if let Some(max_items) = options.max_items {
+            cmd.arg("--range").arg(format!("1-{}", max_items));
+        }
+        if options.force {
+            cmd.arg("--no-skip");
+        }
+        cmd.stdout(Stdio::piped()).stderr(Stdio::piped());
+        cmd.kill_on_drop(true);
+        ytdlp::own_process_group(&mut cmd);
+        let mut child = cmd.spawn()
+            .with_context(|| format!("Failed to spawn {}", self.path.display()))?;
+        let mut group = ProcessGroup(child.id());
+        let _running = RunningProcess::start(group.0);
+        if let Some(log) = options.log {
+            log.line("bot", &format!("Running gallery-dl for {}", url));
+        }
+        let stderr = child.stderr.take().context("gallery-dl stderr was not captured")?;
+        let stderr_task = tokio::spawn(ytdlp::read_stderr(stderr, options.log.cloned()));
+        let stdout = child.stdout.take().context("gallery-dl stdout was not captured")?;
+        let mut lines = BufReader::new(stdout).lines();
+        let mut files = Vec::new();
+        loop {
+            let line = tokio::select! {
+                line = lines.next_line() => line.with_context(|| "Failed to read gallery-dl output")?,
+                _ = ytdlp::deadline_passed(options.deadline) => {
+                    log::warn!("Killing gallery-dl for {}: it ran past its deadline", url);
+                    group.terminate();
+                    let _ = child.kill().await;
+                    return Err(TimedOut.into());
+                }
+            };
+            let Some(line) = line else {
+                break;
+            };
+            if let Some(log) = options.log {
+                log.line("stdout", &line);
+            }
+            // gallery-dl prints the path of each file, with "# " in front of ones it already had
+            let path = line.strip_prefix("# ").unwrap_or(&line).trim();
+            if !path.is_empty() {
+                files.push(PathBuf::from(path));
+            }
+        }
+        let status = child.wait().await
+            .with_context(|| "Failed to wait for gallery-dl process")?;
+        group.disarm();
+        let stderr = stderr_task.await.unwrap_or_default();
+        if let Some(log) = options.log {
+            log.line("bot", &format!("gallery-dl exited with {}", status));
+        }
+        if status.success() {
+            Ok(files)
+        } else {
+            Err(anyhow::anyhow!("gallery-dl failed with status: {}\nError output: {}", status, stderr.trim()))
+        }
+    }
+}
+
+// Streams a direct link to a file in the output directory, named after the link or the
+// server's Content-Disposition. It runs in the bot itself, so the sandbox doesn't apply.
+pub struct Http;
+
+#[async_trait]
+impl Downloader for Http {
+    fn name(&self) -> &'static str {
+        "http"
+    }
+
+    async fn download(
+        &self,
+        url: &str,
+        options: &DownloadOptions<'_>,
+        progress: &watch::Sender<Option<Progress>>,
+    ) -> Result<Vec<PathBuf>> {
+        log::info!("Downloading URL over HTTP: {}", url);
+        let mut client = reqwest::Client::builder();
+        if let Some(proxy) = options.proxy.filter(|proxy| !proxy.is_empty()) {
+            client = client.proxy(reqwest::Proxy::all(proxy).context("Invalid proxy")?);
+        }
+        let client = client.build().context("Failed to set up the HTTP client")?;
+        let mut response = client.get(url).send().await
+            .and_then(reqwest::Response::error_for_status)
+            .with_context(|| format!("Failed to download {}", url))?;
+        let name = response.headers().get(reqwest::header::CONTENT_DISPOSITION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(disposition_filename)
+            .unwrap_or_else(|| url_filename(url));
+        std::fs::create_dir_all(options.output_dir)
+            .with_context(|| format!("Failed to create output directory: {}", options.output_dir))?;
+        let path = Path::new(options.output_dir).join(sanitize_filename(&name));
+        if path.is_file() && !options.force {
+            log::info!("{} already exists", path.display());
+            return Ok(vec![path]);
+        }
+        let partial = path.with_file_name(format!("{}.part", path.file_name().unwrap_or_default().to_string_lossy()));
+        let mut file = tokio::fs::File::create(&partial).await
+            .with_context(|| format!("Failed to create {}", partial.display()))?;
+        let total = response.content_length();
+        let rate = options.rate_limit.and_then(bandwidth::rate_bytes);
+        let started = Instant::now();
+        let mut downloaded: u64 = 0;
+        loop {
+            let chunk = tokio::select! {
+                chunk = response.chunk() => chunk.with_context(|| format!("Failed to download {}", url))?,
+                _ = ytdlp::deadline_passed(options.deadline) => {
+                    log::warn!("Stopping the download of {}: it ran past its deadline", url);
+                    drop(file);
+                    if !options.keep_partial_files {
+                        let _ = tokio::fs::remove_file(&partial).await;
+                    }
+                    return Err(TimedOut.into());
+                }
+            };
+            let Some(chunk) = chunk else {
+                break;
+            };
+            file.write_all(&chunk).await
+                .with_context(|| format!("Failed to write {}", partial.display()))?;
+            downloaded += chunk.len() as u64;
+            let elapsed = started.elapsed().as_secs_f64();
+            // Waiting out whatever went over rate_limit keeps the average under it
+            if let Some(rate) = rate {
+                let ahead = downloaded as f64 / rate - elapsed;
+                if ahead > 0.0 {
+                    tokio::time::sleep(std::time::Duration::from_secs_f64(ahead)).await;
+                }
+            }
+            if let Some(meter) = options.meter {
+                meter.observe(downloaded);
+            }
+            let speed = (elapsed > 0.0).then(|| downloaded as f64 / elapsed);
+            progress.send_replace(Some(Progress {
+                downloaded_bytes: downloaded,
+                total_bytes: total,
+                speed,
+                eta: total.zip(speed).map(|(total, speed)| (total.saturating_sub(downloaded) as f64 / speed) as u64),
+            }));
+        }
+        file.flush().await
+            .with_context(|| format!("Failed to write {}", partial.display()))?;
+        drop(file);
+        tokio::fs::rename(&partial, &path).await
+            .with_context(|| format!("Failed to move {} into place", partial.display()))?;
+        Ok(vec![path])
+    }
+}
+
+fn disposition_filename(header: &str) -> Option<String> {
+    let value = header.split(';')
+        .map(str::trim)
+        .find_map(|part| part.strip_prefix("filename="))?;
+    let name = value.trim_matches('"');
+    (!name.is_empty()).then(|| name.to_string())
+}
+
+fn url_filename(url: &str) -> String {
+    let path = url.split(['?', '#']).next().unwrap_or_default();
+    let name = path.split_once("://").map_or(path, |(_, rest)| rest).rsplit('/').next().unwrap_or_default();
+    if name.is_empty() || !url.contains('/') {
+        "download".to_string()
+    } else {
+        name.to_string()
+    }
+}
+
+// Keeps a server-chosen name from escaping the output directory or upsetting the filesystem
+fn sanitize_filename(name: &str) -> String {
+    let name: String = name.chars()
+        .map(|c| if c.is_control() || "/\\:*?\"<>|".contains(c) { '_' } else { c })
+        .collect();
+    let name = name.trim_start_matches('.').trim();
+    if name.is_empty() { "download".to_string() } else { name.to_string() }
+}
+
+// Copies a raw HLS playlist or RTMP stream into an .mp4 without re-encoding it. Live streams
+// can be stopped like yt-dlp's recordings; ffmpeg finishes the file on the interrupt.
+pub struct Ffmpeg {
+    path: String,
+}
+
+#[async_trait]
+impl Downloader for Ffmpeg {
+    fn name(&self) -> &'static str {
+        "ffmpeg"
+    }
+
+    async fn download(
+        &self,
+        url: &str,
+        options: &DownloadOptions<'_>,
+        progress: &watch::Sender<Option<Progress>>,
+    ) -> Result<Vec<PathBuf>> {
+        log::info!("Downloading URL with ffmpeg: {}", url);
+        std::fs::create_dir_all(options.output_dir)
+            .with_context(|| format!("Failed to create output directory: {}", options.output_dir))?;
+        // Playlists are mostly called index.m3u8 or similar, so the host and time tell them apart
+        let stem = Path::new(&url_filename(url)).file_stem().unwrap_or_default().to_string_lossy().into_owned();
+        let host = crate::url_host(url).unwrap_or_default();
+        let name = sanitize_filename(&format!("{}-{}-{}.mp4", host, stem, crate::history::now()));
+        let path = Path::new(options.output_dir).join(name);
+        let mut cmd = sandbox::command(&self.path, Some(options.output_dir));
+        cmd.arg("-hide_banner").arg("-nostdin").arg("-nostats")
+            .arg("-loglevel").arg("error");
+        if let Some(proxy) = options.proxy.filter(|proxy| proxy.starts_with("http")) {
+            cmd.arg("-http_proxy").arg(proxy);
+        }
+        cmd.arg("-i").arg(url)
+            .arg("-c").arg("copy")
+            .arg("-progress").arg("pipe:1")
+            .arg("-y")
+            .arg(&path);
+        cmd.stdout(Stdio::piped()).stderr(Stdio::piped());
+        cmd.kill_on_drop(true);
+        ytdlp::own_process_group(&mut cmd);
+        let mut child = cmd.spawn()
+            .with_context(|| format!("Failed to spawn {}", self.path))?;
+        let mut group = ProcessGroup(child.id());
+        let _running = RunningProcess::start(group.0);
+        let mut stderr = child.stderr.take().context("ffmpeg stderr was not captured")?;
+        let stderr_task = tokio::spawn(async move {
+            let mut buf = String::new();
+            let _ = stderr.read_to_string(&mut buf).await;
+            buf
+        });
+        let stdout = child.stdout.take().context("ffmpeg stdout was not captured")?;
+        let mut lines = BufReader::new(stdout).lines();
+        let mut stopped = false;
+        loop {
+            let line = tokio::select! {
+                line = lines.next_line() => line.with_context(|| "Failed to read ffmpeg output")?,
+                _ = ytdlp::stop_requested(options.stop), if !stopped => {
+                    log::info!("Stopping the recording of {}", url);
+                    group.interrupt();
+                    stopped = true;
+                    continue;
+                }
+                _ = ytdlp::deadline_passed(options.deadline) => {
+                    log::warn!("Killing ffmpeg for {}: it ran past its deadline", url);
+                    group.terminate();
+                    let _ = child.kill().await;
+                    if !options.keep_partial_files {
+                        let _ = std::fs::remove_file(&path);
+                    }
+                    return Err(TimedOut.into());
+                }
+            };
+            let Some(line) = line else {
+                break;
+            };
+            if let Some(size) = line.strip_prefix("total_size=").and_then(|size| size.trim().parse().ok()) {
+                progress.send_replace(Some(Progress { downloaded_bytes: size, total_bytes: None, speed: None, eta: None }));
+            }
+        }
+        let status = child.wait().await
+            .with_context(|| "Failed to wait for ffmpeg process")?;
+        group.disarm();
+        let stderr = stderr_task.await.unwrap_or_default();
+        if (status.success() || stopped) && path.is_file() {
+            Ok(vec![path])
+        } else {
+            Err(anyhow::anyhow!("ffmpeg failed with status: {}\nError output: {}", status, stderr.trim()))
+        }
+    }
+}
+
+// Every backend, and which one each URL goes to: yt-dlp unless the request names another, the
+// URL is a direct link, raw stream or torrent, or it's on one of gallery-dl's sites
+pub struct Downloaders {
+    ytdlp: YtDlp,
+    gallery_dl: Option<GalleryDl>,
+    http: Http,
+    ffmpeg: Ffmpeg,
+    torrent: Option<Torrent>,
+    gallery_sites: Vec<String>,
+    fallback: bool,
+}
+
+impl Downloaders {
+    pub fn new(gallery_dl: Option<&GalleryDlSettings>, ffmpeg_path: &str, torrent: Option<&TorrentSettings>) -> Result<Self> {
+        let ffmpeg = Ffmpeg { path: ffmpeg_path.to_string() };
+        let torrent = torrent.map(Torrent::new).transpose()?;
+        let Some(settings) = gallery_dl else {
+            return Ok(Downloaders {
+                ytdlp: YtDlp,
+                gallery_dl: None,
+                http: Http,
+                ffmpeg,
+                torrent,
+                gallery_sites: Vec::new(),
+                fallback: false,
+            });
+        };
+        let path = binary::find_executable("gallery_dl.path", settings.path.as_deref().unwrap_or("gallery-dl"))?;
+        log::info!("Using gallery-dl at {}", path.display());
+        Ok(Downloaders {
+            ytdlp: YtDlp,
+            gallery_dl: Some(GalleryDl {
+                path,
+                config: settings.config.clone(),
+                cookies: settings.cookies.clone(),
+            }),
+            http: Http,
+            ffmpeg,
+            torrent,
+            gallery_sites: settings.sites.iter()
+                .map(|pattern| pattern.trim().trim_end_matches('.').to_lowercase())
+                .collect(),
+            fallback: settings.fallback,
+        })
+    }
+
+    fn get(&self, backend: Backend) -> Option<&dyn Downloader> {
+        match backend {
+            Backend::YtDlp => Some(&self.ytdlp),
+            Backend::GalleryDl => self.gallery_dl.as_ref().map(|gallery_dl| gallery_dl as &dyn Downloader),
+            Backend::Http => Some(&self.http),
+            Backend::Ffmpeg => Some(&self.ffmpeg),
+            Backend::Torrent => self.torrent.as_ref().map(|torrent| torrent as &dyn Downloader),
+        }
+    }
+
+    pub fn is_available(&self, backend: Backend) -> bool {
+        self.get(backend).is_some()
+    }
+
+    // The backend for a request, given the one it asked for. Clips, audio extraction and
+    // chapter splitting only work with yt-dlp, so those never switch away from it automatically,
+    // except for torrents, which yt-dlp can't download at all.
+    pub fn backend_for(&self, url: &str, requested: Option<Backend>, needs_ytdlp: bool) -> Backend {
+        if let Some(requested) = requested {
+            return requested;
+        }
+        if let Some(detected) = Backend::detect(url).filter(|&detected| detected == Backend::Torrent || !needs_ytdlp) {
+            return detected;
+        }
+        let host = crate::url_host(url).unwrap_or_default();
+        if self.gallery_dl.is_some() && self.gallery_sites.iter().any(|pattern| domains::matches(&host, pattern)) {
+            return Backend::GalleryDl;
+        }
+        Backend::YtDlp
+    }
+
+    pub async fn download(
+        &self,
+        backend: Backend,
+        url: &str,
+        options: &DownloadOptions<'_>,
+        progress: &watch::Sender<Option<Progress>>,
+    ) -> Result<Vec<PathBuf>> {
+        let Some(downloader) = self.get(backend) else {
+            bail!("{} isn't set up on this bot", backend);
+        };
+        let result = downloader.download(url, options, progress).await;
+        match (&self.gallery_dl, result) {
+            (Some(gallery_dl), Err(e)) if self.fallback && backend == Backend::YtDlp && e.to_string().contains("Unsupported URL") => {
+                log::info!("yt-dlp doesn't support {}, trying {}", url, gallery_dl.name());
+                gallery_dl.download(url, options, progress).await
+            }
+            (_, result) => result,
+        }
+    }
+}
@@ -0,0 +1,379 @@
+use serenity::builder::{CreateActionRow, CreateButton, CreateEmbed, CreateEmbedAuthor, CreateEmbedFooter};
+use serenity::model::application::ButtonStyle;
+use serenity::model::id::UserId;
+use serenity::model::{Colour, Timestamp};
+use std::path::Path;
+
+use crate::clip::Clip;
+use crate::errors::Workaround;
+use crate::feeds::Episode;
+use crate::format::{FormatSpec, PRESETS};
+use crate::history::Entry;
+use crate::jobs::JobId;
+use crate::messages::Messages;
+use crate::progress::{format_bytes, format_duration, Progress};
+use crate::ytdlp::{Info, Metadata};
+use crate::{truncate_message, DownloadRequest};
+
+const QUEUED: Colour = Colour(0x95a5a6);
+const DOWNLOADING: Colour = Colour(0x3498db);
+const RECORDING: Colour = Colour(0x9b59b6);
+const TRANSCODING: Colour = Colour(0x2980b9);
+const RETRYING: Colour = Colour(0xf1c40f);
+const DONE: Colour = Colour(0x2ecc71);
+const FAILED: Colour = Colour(0xe74c3c);
+const CANCELLED: Colour = Colour(0xe67e22);
+const PROBED: Colour = Colour(0x1abc9c);
+const HISTORY: Colour = Colour(0x34495e);
+const NEW_UPLOAD: Colour = Colour(0xe91e63);
+
+// Custom ID prefix of the buttons on job cards, followed by "<action>:<job ID>"
+pub const JOB_BUTTON: &str = "job:";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JobAction {
+    Cancel,
+    Retry,
+    // Retries of a download the site wanted an account for
+    RetrySignedIn,
+    RetryOtherWay,
+    Pin,
+    Link,
+}
+
+impl JobAction {
+    fn name(self) -> &'static str {
+        match self {
+            JobAction::Cancel => "cancel",
+            JobAction::Retry => "retry",
+            JobAction::RetrySignedIn => "retry-login",
+            JobAction::RetryOtherWay => "retry-args",
+            JobAction::Pin => "pin",
+            JobAction::Link => "link",
+        }
+    }
+
+    fn button(self, id: JobId, messages: &Messages) -> CreateButton {
+        let (label, style) = match self {
+            JobAction::Cancel => ("button_cancel", ButtonStyle::Danger),
+            JobAction::Retry => ("button_retry", ButtonStyle::Primary),
+            JobAction::RetrySignedIn => ("button_retry_signed_in", ButtonStyle::Primary),
+            JobAction::RetryOtherWay => ("button_retry_other_way", ButtonStyle::Secondary),
+            JobAction::Pin => ("button_pin", ButtonStyle::Secondary),
+            JobAction::Link => ("button_link", ButtonStyle::Secondary),
+        };
+        CreateButton::new(format!("{}{}:{}", JOB_BUTTON, self.name(), id)).label(messages.get(label, &[])).style(style)
+    }
+
+    // The action and job a job card's button is for
+    pub fn parse(custom_id: &str) -> Option<(Self, JobId)> {
+        let (name, id) = custom_id.strip_prefix(JOB_BUTTON)?.split_once(':')?;
+        let action = [JobAction::Cancel, JobAction::Retry, JobAction::RetrySignedIn, JobAction::RetryOtherWay, JobAction::Pin, JobAction::Link]
+            .into_iter()
+            .find(|action| action.name() == name)?;
+        Some((action, id.parse().ok()?))
+    }
+}
+
+// A rendered job card: its embed and the buttons for what can be done with the job now
+pub struct Card {
+    pub embed: CreateEmbed,
+    pub buttons: Vec<CreateActionRow>,
+}
+
+pub enum CardState {
+    Queued(usize),
+    // Waiting for quiet hours to end at this Unix time
+    Deferred(i64),
+    Downloading(Option<Progress>),
+    Recording(Option<Progress>),
+    Transcoding(Option<Progress>),
+    Retrying(String),
+    // With where the files were stored, if anywhere but the output directory
+    Done(Vec<String>),
+    Failed(String),
+    // Failed because the site wants an account, with the retries that might get past that
+    Gated(String, Vec<Workaround>),
+    Cancelled(String),
+}
+
+// Describes a single-video job in its status message, which is re-rendered as the job moves along
+#[derive(Clone)]
+pub struct JobCard {
+    id: JobId,
+    url: String,
+    format: String,
+    clip: Option<Clip>,
+    requester_name: String,
+    requester_avatar: Option<String>,
+    metadata: Metadata,
+    // Shows only the job's progress, for private_results
+    anonymous: bool,
+    messages: Messages,
+}
+
+impl JobCard {
+    pub fn new(id: JobId, request: &DownloadRequest, messages: Messages) -> Self {
+        JobCard {
+            id,
+            url: request.url.clone(),
+            format: request.format_label(),
+            clip: request.clip,
+            requester_name: request.requester_name.clone(),
+            requester_avatar: request.requester_avatar.clone(),
+            metadata: request.metadata.clone(),
+            anonymous: false,
+            messages,
+        }
+    }
+
+    // Leaves out what was downloaded, by whom and how it went, which the requester is sent instead
+    pub fn anonymous(self, anonymous: bool) -> Self {
+        JobCard { anonymous, ..self }
+    }
+
+    pub fn render(&self, state: CardState) -> Card {
+        let messages = &self.messages;
+        let actions: Vec<JobAction> = match &state {
+            CardState::Done(_) => vec![JobAction::Pin, JobAction::Link],
+            CardState::Failed(_) | CardState::Cancelled(_) => vec![JobAction::Retry],
+            CardState::Gated(_, offered) => std::iter::once(JobAction::Retry)
+                .chain(offered.iter().map(|workaround| match workaround {
+                    Workaround::LoginCookies => JobAction::RetrySignedIn,
+                    Workaround::RetryArgs => JobAction::RetryOtherWay,
+                }))
+                .collect(),
+            _ => vec![JobAction::Cancel],
+        };
+        let buttons = vec![CreateActionRow::Buttons(actions.iter().map(|action| action.button(self.id, messages)).collect())];
+        // What an anonymous card says instead of the results and errors
+        let summary = match &state {
+            CardState::Done(_) => Some("card_done_private"),
+            CardState::Failed(_) | CardState::Gated(..) => Some("card_failed_private"),
+            CardState::Retrying(_) => Some("card_retrying"),
+            _ => None,
+        };
+        let (colour, description) = match state {
+            CardState::Queued(position) => (QUEUED, messages.get("card_queued", &[("position", &position)])),
+            CardState::Deferred(until) => (QUEUED, messages.get("card_deferred", &[("until", &until)])),
+            CardState::Downloading(Some(progress)) => (DOWNLOADING, messages.get("card_downloading", &[("progress", &progress.describe(messages))])),
+            CardState::Downloading(None) => (DOWNLOADING, messages.get("card_downloading_started", &[])),
+            CardState::Recording(Some(progress)) => (RECORDING, messages.get("card_recording", &[("size", &format_bytes(progress.downloaded_bytes))])),
+            CardState::Recording(None) => (RECORDING, messages.get("card_recording_started", &[])),
+            CardState::Transcoding(Some(progress)) => (TRANSCODING, messages.get("card_transcoding", &[("progress", &progress.describe(messages))])),
+            CardState::Transcoding(None) => (TRANSCODING, messages.get("card_transcoding_started", &[])),
+            CardState::Retrying(text) => (RETRYING, text),
+            CardState::Done(stored) => {
+                let mut description = messages.get("card_done", &[]);
+                for line in stored {
+                    // Embed descriptions are limited to 4096 characters
+                    if description.len() + line.len() + 1 > 4096 {
+                        break;
+                    }
+                    description.push('\n');
+                    description.push_str(&line);
+                }
+                (DONE, description)
+            }
+            CardState::Failed(reason) | CardState::Gated(reason, _) => (FAILED, messages.get("card_failed", &[("reason", &reason)])),
+            CardState::Cancelled(text) => (CANCELLED, text),
+        };
+        if self.anonymous {
+            let description = summary.map(|key| messages.get(key, &[])).unwrap_or(description);
+            let embed = CreateEmbed::new()
+                .title(messages.get("card_title_private", &[("id", &self.id)]))
+                .colour(colour)
+                .description(description)
+                .footer(CreateEmbedFooter::new(messages.get("card_footer_private", &[("id", &self.id)])));
+            return Card { embed, buttons };
+        }
+        let title = self.metadata.title.as_deref().unwrap_or(&self.url);
+        // Embed titles are limited to 256 characters
+        let mut embed = CreateEmbed::new()
+            .title(title.chars().take(256).collect::<String>())
+            .url(&self.url)
+            .colour(colour)
+            .description(description);
+        if let Some(thumbnail) = &self.metadata.thumbnail {
+            embed = embed.thumbnail(thumbnail);
+        }
+        if let Some(uploader) = &self.metadata.uploader {
+            embed = embed.field(messages.get("field_uploader", &[]), uploader, true);
+        }
+        if let Some(duration) = self.metadata.duration {
+            embed = embed.field(messages.get("field_duration", &[]), format_duration(duration), true);
+        }
+        let format = match &self.clip {
+            Some(clip) => format!("{} · {}", self.format, clip),
+            None => self.format.clone(),
+        };
+        let mut footer = CreateEmbedFooter::new(messages.get("card_footer", &[
+            ("id", &self.id),
+            ("format", &format),
+            ("user", &self.requester_name),
+        ]));
+        if let Some(avatar) = &self.requester_avatar {
+            footer = footer.icon_url(avatar);
+        }
+        Card { embed: embed.footer(footer), buttons }
+    }
+}
+
+// A finished download, as posted in the announce channel
+pub struct Announcement<'a> {
+    pub id: JobId,
+    pub url: &'a str,
+    pub title: Option<&'a str>,
+    pub thumbnail: Option<&'a str>,
+    pub requester: UserId,
+    pub size: u64,
+    // Links or paths of the files
+    pub locations: &'a [String],
+}
+
+impl Announcement<'_> {
+    pub fn render(&self, messages: &Messages) -> CreateEmbed {
+        let title = self.title.unwrap_or(self.url);
+        let mut files = String::new();
+        for (index, location) in self.locations.iter().enumerate() {
+            // Discord's limit for a field's value
+            if files.len() + location.len() + 1 > 1000 {
+                files.push_str(&messages.get("and_more", &[("count", &(self.locations.len() - index))]));
+                break;
+            }
+            files.push_str(location);
+            files.push('\n');
+        }
+        let mut embed = CreateEmbed::new()
+            .title(title.chars().take(256).collect::<String>())
+            .url(self.url)
+            .colour(DONE)
+            .field(messages.get("announce_requested_by", &[]), format!("<@{}>", self.requester), true)
+            .field(messages.get("announce_size", &[]), format_bytes(self.size), true)
+            .field(messages.get("announce_files", &[]), files.trim_end(), false)
+            .footer(CreateEmbedFooter::new(messages.get("announce_footer", &[("id", &self.id)])));
+        if let Some(thumbnail) = self.thumbnail {
+            embed = embed.thumbnail(thumbnail);
+        }
+        embed
+    }
+}
+
+// Announces a new episode or upload of a subscription, with what became of its download
+pub fn upload_embed(feed: &str, episode: &Episode, result: &str, messages: &Messages) -> CreateEmbed {
+    let title = episode.title.as_deref().unwrap_or(&episode.url);
+    let feed: String = feed.chars().take(240).collect();
+    let mut embed = CreateEmbed::new()
+        .author(CreateEmbedAuthor::new(messages.get("new_upload_from", &[("feed", &feed)]).chars().take(256).collect::<String>()))
+        .title(title.chars().take(256).collect::<String>())
+        .url(&episode.url)
+        .description(truncate_message(result.to_string()))
+        .colour(NEW_UPLOAD);
+    if let Some(thumbnail) = &episode.thumbnail {
+        embed = embed.image(thumbnail);
+    }
+    if let Some(published) = episode.published.as_deref().and_then(|published| Timestamp::parse(published).ok()) {
+        embed = embed.timestamp(published);
+    }
+    embed
+}
+
+// What /probe found out about a URL, without downloading anything
+pub fn probe_embed(url: &str, info: &Info, messages: &Messages) -> CreateEmbed {
+    let metadata = info.metadata();
+    let title = metadata.title.as_deref().unwrap_or(url);
+    let mut embed = CreateEmbed::new()
+        .title(title.chars().take(256).collect::<String>())
+        .url(url)
+        .colour(PROBED);
+    if let Some(thumbnail) = &metadata.thumbnail {
+        embed = embed.thumbnail(thumbnail);
+    }
+    if let Some(uploader) = &metadata.uploader {
+        embed = embed.field(messages.get("field_uploader", &[]), uploader, true);
+    }
+    if let Some(duration) = metadata.duration {
+        embed = embed.field(messages.get("field_duration", &[]), format_duration(duration), true);
+    }
+    if info.is_playlist() {
+        return embed.description(messages.get("probe_playlist", &[("count", &info.entries.len())]));
+    }
+    if info.is_live() {
+        return embed.description(messages.get("probe_live", &[]));
+    }
+    let heights = info.heights();
+    let mut available: Vec<String> = heights.iter().map(|height| format!("{}p", height)).collect();
+    if info.has_audio_only() {
+        available.push(messages.get("probe_audio_only", &[]));
+    }
+    if !available.is_empty() {
+        embed = embed.field(messages.get("probe_available", &[]), available.join(", "), false);
+    }
+    // Presets above the best height would download the same as "best"
+    let max_height = heights.first().copied();
+    let sizes: Vec<String> = PRESETS.iter()
+        .filter_map(|preset| preset.parse::<FormatSpec>().ok())
+        .filter(|format| match format {
+            FormatSpec::MaxHeight(height) => max_height.is_some_and(|max| *height < max),
+            FormatSpec::Audio(codec) => codec.is_none(),
+            _ => true,
+        })
+        .filter_map(|format| Some(format!("`{}` ~{}", format, format_bytes(info.size_for(format)?))))
+        .collect();
+    let sizes = if sizes.is_empty() { messages.get("probe_no_sizes", &[]) } else { sizes.join("\n") };
+    let description = if info.pickable_formats().is_empty() {
+        "probe_nothing_downloaded"
+    } else {
+        "probe_nothing_downloaded_menu"
+    };
+    embed.field(messages.get("probe_sizes", &[]), sizes, false).description(messages.get(description, &[]))
+}
+
+// One page of /history; `filters` describes what it was narrowed down to, if anything
+pub fn history_embed(entries: &[Entry], filters: &str, page: usize, pages: usize, total: usize, messages: &Messages) -> CreateEmbed {
+    let mut description = String::new();
+    for entry in entries {
+        let link = match &entry.title {
+            // Brackets would end the link text early
+            Some(title) => format!("[{}]({})", title.replace(['[', ']'], "").chars().take(100).collect::<String>(), entry.url),
+            None => format!("<{}>", entry.url),
+        };
+        let link = match &entry.uploader {
+            Some(uploader) => messages.get("history_by", &[("link", &link), ("uploader", uploader)]),
+            None => link,
+        };
+        let mut line = format!(
+            "#{} <t:{}:R> <@{}> **{}** {} ({}",
+            entry.job_id, entry.requested_at, entry.requester, entry.status, link, entry.format
+        );
+        if let Some(size) = entry.file_size {
+            line.push_str(&format!(", {}", format_bytes(size)));
+        }
+        line.push(')');
+        if entry.title.is_none() {
+            if let Some(name) = entry.output_path.as_deref().map(Path::new).and_then(Path::file_name) {
+                line.push_str(&format!(" `{}`", name.to_string_lossy()));
+            }
+        }
+        if entry.deleted {
+            line.push(' ');
+            line.push_str(&messages.get("history_deleted", &[]));
+        } else if entry.pinned {
+            line.push_str(" 📌");
+        }
+        if description.len() + line.len() + 1 > 4096 {
+            break;
+        }
+        description.push_str(&line);
+        description.push('\n');
+    }
+    let mut embed = CreateEmbed::new()
+        .title(messages.get("history_title", &[]))
+        .colour(HISTORY)
+        .description(description)
+        .footer(CreateEmbedFooter::new(messages.get("history_footer", &[("page", &page), ("pages", &pages), ("total", &total)])));
+    if !filters.is_empty() {
+        embed = embed.field(messages.get("history_filters", &[]), filters, false);
+    }
+    embed
+}
@@ -0,0 +1,158 @@
+use serde::{Deserialize, Serialize};
+
+use crate::messages::Messages;
+use crate::report;
+
+// yt-dlp output for videos the site only shows to signed-in adults
+const AGE_PATTERNS: &[&str] = &[
+    "sign in to confirm your age",
+    "confirm your age",
+    "age-restricted",
+    "age restricted",
+    "inappropriate for some users",
+    "age verification",
+];
+
+// ...and for those it only shows to a signed-in account, or one with access
+const LOGIN_PATTERNS: &[&str] = &[
+    "login required",
+    "requires authentication",
+    "you need to log in",
+    "this video is only available for registered users",
+    "members-only",
+    "join this channel to get access",
+    "only available to channel members",
+    "sign in to confirm you're not a bot",
+    "sign in to confirm you’re not a bot",
+    "use --cookies-from-browser or --cookies",
+    "--username and --password",
+];
+
+// Everything else yt-dlp, ffmpeg or the disk are known to fail with. The first match wins.
+const FAILURES: &[(Failure, &[&str])] = &[
+    (Failure::Quarantined, &["flagged by the virus scanner"]),
+    (Failure::DiskFull, &["no space left on device", "errno 28", "disk quota exceeded"]),
+    (Failure::FfmpegMissing, &["ffmpeg not found", "ffmpeg is not installed", "ffprobe and ffmpeg not found", "ffmpeg could not be found"]),
+    (Failure::GeoBlocked, &[
+        "not available in your country",
+        "not available from your location",
+        "blocked it in your country",
+        "geo restriction",
+        "geo-restricted",
+        "geo restricted",
+    ]),
+    (Failure::Private, &["private video", "this video is private", "has been made private"]),
+    (Failure::Removed, &[
+        "has been removed",
+        "video unavailable",
+        "no longer available",
+        "account associated with this video has been terminated",
+        "http error 404",
+        "http error 410",
+    ]),
+    (Failure::RateLimited, &["http error 429", "too many requests", "rate-limit", "rate limit"]),
+    (Failure::Unsupported, &["unsupported url", "no suitable extractor"]),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Gate {
+    AgeRestricted,
+    LoginRequired,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Failure {
+    GeoBlocked,
+    Private,
+    Removed,
+    RateLimited,
+    Unsupported,
+    DiskFull,
+    FfmpegMissing,
+    Quarantined,
+}
+
+impl Failure {
+    // What the requester is told for it
+    fn explain(self, messages: &Messages) -> String {
+        let key = match self {
+            Failure::GeoBlocked => "failure_geo_blocked",
+            Failure::Private => "failure_private",
+            Failure::Removed => "failure_removed",
+            Failure::RateLimited => "failure_rate_limited",
+            Failure::Unsupported => "failure_unsupported",
+            Failure::DiskFull => "failure_disk_full",
+            Failure::FfmpegMissing => "failure_ffmpeg_missing",
+            Failure::Quarantined => "failure_quarantined",
+        };
+        messages.get(key, &[])
+    }
+}
+
+pub fn classify(error: &anyhow::Error) -> Option<Failure> {
+    let error = format!("{:#}", error).to_lowercase();
+    FAILURES.iter()
+        .find(|(_, patterns)| patterns.iter().any(|pattern| error.contains(pattern)))
+        .map(|(failure, _)| *failure)
+}
+
+// The error as shown to the requester: a short explanation when it's a known kind of
+// failure, otherwise the error's last line
+pub fn describe(error: &anyhow::Error, messages: &Messages) -> String {
+    if let Some(gate) = gate(error) {
+        return gate.problem(messages);
+    }
+    match classify(error) {
+        Some(failure) => failure.explain(messages),
+        None => report::public_error(error),
+    }
+}
+
+// Why a download failed, when it's something an account would get past
+pub fn gate(error: &anyhow::Error) -> Option<Gate> {
+    let error = format!("{:#}", error).to_lowercase();
+    let matches = |patterns: &[&str]| patterns.iter().any(|pattern| error.contains(pattern));
+    if matches(AGE_PATTERNS) {
+        Some(Gate::AgeRestricted)
+    } else if matches(LOGIN_PATTERNS) {
+        Some(Gate::LoginRequired)
+    } else {
+        None
+    }
+}
+
+// What a retry of a gated download does differently
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Workaround {
+    // With login_cookies instead of the site's usual ones
+    LoginCookies,
+    // With login_retry_args added
+    RetryArgs,
+}
+
+impl Gate {
+    // Said instead of yt-dlp's error, with what can be done about it given the workarounds
+    // still on offer and the one this run already tried
+    pub fn explain(self, offered: &[Workaround], tried: Option<Workaround>, messages: &Messages) -> String {
+        let advice = match (offered.contains(&Workaround::LoginCookies), offered.contains(&Workaround::RetryArgs)) {
+            (true, true) => "gate_retry_either",
+            (true, false) => "gate_retry_signed_in",
+            (false, true) => "gate_retry_other_way",
+            (false, false) => match tried {
+                Some(Workaround::LoginCookies) => "gate_signed_in_failed",
+                Some(Workaround::RetryArgs) => "gate_other_way_failed",
+                None => "gate_set_login_cookies",
+            },
+        };
+        format!("{} {}", self.problem(messages), messages.get(advice, &[]))
+    }
+
+    fn problem(self, messages: &Messages) -> String {
+        let key = match self {
+            Gate::AgeRestricted => "gate_age_restricted",
+            Gate::LoginRequired => "gate_login_required",
+        };
+        messages.get(key, &[])
+    }
+}
@@ -0,0 +1,416 @@
+use anyhow::{bail, Context, Result};
+use log::{error, info, warn};
+use serde::Deserialize;
+use serenity::builder::CreateMessage;
+use serenity::http::Http;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::embed;
+use crate::format::FormatSpec;
+use crate::history::Subscription;
+use crate::messages::Messages;
+use crate::queue::Priority;
+use crate::ytdlp::{self, Metadata};
+use crate::{is_valid_url, truncate_message, DownloadRequest, Handler, Submitted};
+
+// Bigger feeds aren't read
+const MAX_FEED_BYTES: usize = 10 * 1024 * 1024;
+
+const FETCH_TIMEOUT: Duration = Duration::from_secs(30);
+
+// Every YouTube channel has a feed of its latest uploads here, followed by its channel ID
+const YOUTUBE_FEED: &str = "https://www.youtube.com/feeds/videos.xml?channel_id=";
+
+// The [feeds] section of the config
+#[derive(Debug, Clone, Deserialize)]
+pub struct FeedSettings {
+    // How often every subscribed feed is checked for new episodes
+    #[serde(default = "default_interval_mins")]
+    pub interval_mins: u64,
+    // Episodes downloaded from one feed per check; the rest are only announced, so a feed
+    // that suddenly republishes its back catalogue doesn't fill the queue
+    #[serde(default = "default_max_new_episodes")]
+    pub max_new_episodes: usize,
+}
+
+impl Default for FeedSettings {
+    fn default() -> Self {
+        FeedSettings { interval_mins: default_interval_mins(), max_new_episodes: default_max_new_episodes() }
+    }
+}
+
+fn default_interval_mins() -> u64 {
+    30
+}
+
+fn default_max_new_episodes() -> usize {
+    5
+}
+
+pub struct Feeds {
+    pub settings: FeedSettings,
+    client: reqwest::Client,
+}
+
+impl Feeds {
+    pub fn new(settings: FeedSettings) -> Self {
+        Feeds { settings, client: reqwest::Client::new() }
+    }
+
+    pub async fn fetch(&self, url: &str) -> Result<Feed> {
+        let response = self.client.get(url)
+            .timeout(FETCH_TIMEOUT)
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status)
+            .with_context(|| format!("Failed to fetch {}", url))?;
+        if response.content_length().is_some_and(|length| length > MAX_FEED_BYTES as u64) {
+            bail!("the feed is bigger than {} MB", MAX_FEED_BYTES / 1024 / 1024);
+        }
+        let bytes = response.bytes().await.with_context(|| format!("Failed to read {}", url))?;
+        if bytes.len() > MAX_FEED_BYTES {
+            bail!("the feed is bigger than {} MB", MAX_FEED_BYTES / 1024 / 1024);
+        }
+        parse(&String::from_utf8_lossy(&bytes))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Feed {
+    pub title: Option<String>,
+    // In the feed's order, which is usually newest first
+    pub episodes: Vec<Episode>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Episode {
+    // The guid or Atom id, or the URL when there's neither
+    pub guid: String,
+    pub title: Option<String>,
+    // The enclosure, or the page the item links to when it has none
+    pub url: String,
+    pub thumbnail: Option<String>,
+    // RFC 3339, as Atom feeds have it
+    pub published: Option<String>,
+}
+
+// An RSS 2.0 or Atom feed. Only what's needed to find the episodes is read, so this is far
+// from a full XML parser: namespaces are ignored and anything unexpected is skipped.
+pub fn parse(xml: &str) -> Result<Feed> {
+    let (items, atom) = match elements(xml, "item") {
+        items if !items.is_empty() => (items, false),
+        _ => (elements(xml, "entry"), true),
+    };
+    if items.is_empty() && !xml.contains("<rss") && !xml.contains("<feed") && !xml.contains("<channel") {
+        bail!("this isn't an RSS or Atom feed");
+    }
+    // The feed's own title comes before its first item
+    let head = items.first()
+        .map(|item| &xml[..item.as_ptr() as usize - xml.as_ptr() as usize])
+        .unwrap_or(xml);
+    let title = text(head, "title");
+    let episodes = items.into_iter()
+        .filter_map(|item| {
+            let url = enclosure(item, atom).or_else(|| link(item, atom))?;
+            let guid = text(item, if atom { "id" } else { "guid" }).unwrap_or_else(|| url.clone());
+            let thumbnail = tags(item, "media:thumbnail").find_map(|tag| attribute(tag, "url"))
+                .or_else(|| tags(item, "itunes:image").find_map(|tag| attribute(tag, "href")));
+            let published = atom.then(|| text(item, "published")).flatten();
+            Some(Episode { guid, title: text(item, "title"), url, thumbnail, published })
+        })
+        .collect();
+    Ok(Feed { title, episodes })
+}
+
+fn enclosure(item: &str, atom: bool) -> Option<String> {
+    let found = if atom {
+        tags(item, "link")
+            .filter(|tag| attribute(tag, "rel").as_deref() == Some("enclosure"))
+            .find_map(|tag| attribute(tag, "href"))
+    } else {
+        tags(item, "enclosure").find_map(|tag| attribute(tag, "url"))
+    };
+    found.filter(|url| is_valid_url(url))
+}
+
+fn link(item: &str, atom: bool) -> Option<String> {
+    let found = if atom {
+        tags(item, "link")
+            .filter(|tag| attribute(tag, "rel").is_none_or(|rel| rel == "alternate"))
+            .find_map(|tag| attribute(tag, "href"))
+    } else {
+        text(item, "link")
+    };
+    found.filter(|url| is_valid_url(url))
+}
+
+// Where an element's name ends, so <item> doesn't match <itemCount>
+fn is_name_end(c: Option<char>) -> bool {
+    c.is_none_or(|c| c == '>' || c == '/' || c.is_whitespace())
+}
+
+// The opening tags of an element, without the "<name" and ">"
+fn tags<'a>(xml: &'a str, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+    let open = format!("<{}", name);
+    let mut rest = xml;
+    std::iter::from_fn(move || loop {
+        let start = rest.find(&open)? + open.len();
+        let after = &rest[start..];
+        rest = after;
+        if !is_name_end(after.chars().next()) {
+            continue;
+        }
+        let end = after.find('>')?;
+        rest = &after[end + 1..];
+        return Some(after[..end].trim_end_matches('/'));
+    })
+}
+
+// The contents of each <name>...</name>, outermost only
+fn elements<'a>(xml: &'a str, name: &str) -> Vec<&'a str> {
+    let (open, close) = (format!("<{}", name), format!("</{}>", name));
+    let mut found = Vec::new();
+    let mut rest = xml;
+    while let Some(start) = rest.find(&open) {
+        let after = &rest[start + open.len()..];
+        let Some(end) = after.find('>') else {
+            break;
+        };
+        if !is_name_end(after.chars().next()) || after[..end].ends_with('/') {
+            rest = &after[end + 1..];
+            continue;
+        }
+        let body = &after[end + 1..];
+        let Some(length) = body.find(&close) else {
+            break;
+        };
+        found.push(&body[..length]);
+        rest = &body[length + close.len()..];
+    }
+    found
+}
+
+// The text of the first <name> element, unescaped
+fn text(xml: &str, name: &str) -> Option<String> {
+    let body = elements(xml, name).into_iter().next()?.trim();
+    let text = match body.strip_prefix("<![CDATA[") {
+        Some(data) => data.strip_suffix("]]>").unwrap_or(data).to_string(),
+        None => unescape(body),
+    };
+    let text = text.trim();
+    (!text.is_empty()).then(|| text.to_string())
+}
+
+fn attribute(tag: &str, name: &str) -> Option<String> {
+    let mut rest = tag;
+    while let Some(index) = rest.find(name) {
+        let before = rest[..index].chars().next_back();
+        let after = rest[index + name.len()..].trim_start();
+        rest = &rest[index + name.len()..];
+        if !before.is_some_and(char::is_whitespace) {
+            continue;
+        }
+        let Some(value) = after.strip_prefix('=').map(str::trim_start) else {
+            continue;
+        };
+        let quote = value.chars().next().filter(|&c| c == '"' || c == '\'')?;
+        let value = &value[1..];
+        return Some(unescape(&value[..value.find(quote)?]));
+    }
+    None
+}
+
+fn unescape(text: &str) -> String {
+    let mut unescaped = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        unescaped.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let entity = rest.find(';').map(|end| &rest[1..end]);
+        let c = entity.and_then(|entity| match entity {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            _ => match entity.strip_prefix("#x").or_else(|| entity.strip_prefix("#X")) {
+                Some(hex) => u32::from_str_radix(hex, 16).ok().and_then(char::from_u32),
+                None => entity.strip_prefix('#').and_then(|code| code.parse().ok()).and_then(char::from_u32),
+            },
+        });
+        match (c, entity) {
+            (Some(c), Some(entity)) => {
+                unescaped.push(c);
+                rest = &rest[entity.len() + 2..];
+            }
+            _ => {
+                unescaped.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    unescaped.push_str(rest);
+    unescaped
+}
+
+pub fn is_youtube_channel(subscription: &Subscription) -> bool {
+    subscription.url.starts_with(YOUTUBE_FEED)
+}
+
+// The uploads feed of a YouTube channel, from its URL. Only /channel/ URLs have the channel
+// ID in them; handles and custom URLs are looked up with yt-dlp.
+pub async fn youtube_feed(url: &str, cookies: &[String]) -> Result<String> {
+    let host = crate::url_host(url).unwrap_or_default();
+    if !crate::host_matches(&host, "youtube.com") {
+        bail!("that isn't a YouTube channel");
+    }
+    let path = url.split_once("://").map_or(url, |(_, rest)| rest);
+    let from_url = path.split(['/', '?', '#'])
+        .skip_while(|segment| *segment != "channel")
+        .nth(1)
+        .map(str::to_owned);
+    let id = match from_url {
+        Some(id) => id,
+        None => {
+            // The channel's own details are all that's needed, not its whole list of videos
+            let args = ["--playlist-items".to_string(), "0".to_string()];
+            let info = ytdlp::probe(url, cookies, &args).await.context("Failed to look up the channel")?;
+            info.channel_id
+                .or(info.id.filter(|id| id.starts_with("UC")))
+                .context("yt-dlp didn't find a channel ID")?
+        }
+    };
+    if !id.starts_with("UC") || !id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+        bail!("'{}' isn't a YouTube channel ID", id);
+    }
+    Ok(format!("{}{}", YOUTUBE_FEED, id))
+}
+
+// Checks every subscription for new episodes, forever
+pub async fn run(handler: Arc<Handler>, http: Arc<Http>) {
+    let mut interval = tokio::time::interval(Duration::from_secs(handler.feeds.settings.interval_mins.max(1) * 60));
+    loop {
+        interval.tick().await;
+        let subscriptions = match handler.history.subscriptions() {
+            Ok(subscriptions) => subscriptions,
+            Err(e) => {
+                error!("Failed to read subscriptions: {:#}", e);
+                continue;
+            }
+        };
+        for subscription in subscriptions {
+            if let Err(e) = handler.check_subscription(&http, &subscription).await {
+                warn!("Failed to check feed {} (subscription #{}): {:#}", subscription.url, subscription.id, e);
+                handler.history.set_subscription_checked(subscription.id, None, Some(&format!("{:#}", e)));
+            }
+        }
+    }
+}
+
+impl Handler {
+    async fn check_subscription(&self, http: &Arc<Http>, subscription: &Subscription) -> Result<()> {
+        let feed = self.feeds.fetch(&subscription.url).await?;
+        let seen = self.history.seen_episodes(subscription.id)?;
+        let new: Vec<&Episode> = feed.episodes.iter().filter(|episode| !seen.contains(&episode.guid)).collect();
+        // Marked before they're queued, so an episode that fails isn't retried every check
+        let guids: Vec<&str> = new.iter().map(|episode| episode.guid.as_str()).collect();
+        self.history.mark_episodes_seen(subscription.id, &guids)?;
+        self.history.set_subscription_checked(subscription.id, feed.title.as_deref(), None);
+        if new.is_empty() {
+            return Ok(());
+        }
+        info!("{} new episode(s) in {} (subscription #{})", new.len(), subscription.url, subscription.id);
+        let bot = http.get_current_user().await.context("Failed to look up the bot's user")?;
+        let name = feed.title.as_deref().or(subscription.title.as_deref()).unwrap_or(&subscription.url);
+        let format = subscription.format.as_deref().and_then(|format| format.parse::<FormatSpec>().ok());
+        let (queued, skipped) = new.split_at(new.len().min(self.feeds.settings.max_new_episodes));
+        let messages = self.messages(subscription.guild);
+        // Oldest first, like they came out
+        for episode in queued.iter().rev() {
+            let request = DownloadRequest {
+                url: episode.url.clone(),
+                requester: bot.id,
+                requester_name: bot.name.clone(),
+                requester_avatar: Some(bot.face()),
+                channel: subscription.channel,
+                guild: subscription.guild,
+                format: self.resolve_format(format, subscription.guild, subscription.channel),
+                force: false,
+                archive_key: None,
+                playlist: None,
+                subtitles: None,
+                clip: None,
+                metadata: Metadata {
+                    title: episode.title.clone(),
+                    thumbnail: episode.thumbnail.clone(),
+                    ..Metadata::default()
+                },
+                dm: false,
+                live: false,
+                schedule: None,
+                backend: None,
+                split_chapters: false,
+                from_api: false,
+                thread: None,
+                extra_args: Vec::new(),
+                format_id: None,
+                workaround: None,
+                // Nobody is waiting on them
+                priority: Priority::Low,
+                geo_country: None,
+                subfolder: None,
+                spoiler: false,
+            };
+            let result = match self.submit(http, request, None).await {
+                Ok(Submitted::Job { id, .. }) => messages.get("episode_queued", &[("id", &id)]),
+                Ok(Submitted::Duplicate(existing)) => messages.get("episode_duplicate", &[("what", &existing.describe(&messages))]),
+                Ok(Submitted::Joined { id }) => messages.get("episode_joined", &[("id", &id)]),
+                Ok(Submitted::Playlist { queued, .. }) => messages.get("episode_playlist", &[("count", &queued)]),
+                Err(e) => messages.get("episode_failed", &[("reason", &e)]),
+            };
+            let message = CreateMessage::new().embed(embed::upload_embed(name, episode, &result, &messages));
+            if let Err(e) = subscription.channel.send_message(http, message).await {
+                error!("Failed to announce a new episode in {}: {}", subscription.channel, e);
+            }
+        }
+        if !skipped.is_empty() {
+            let mut lines = vec![messages.get("episodes_skipped", &[
+                ("feed", &name),
+                ("count", &skipped.len()),
+                ("max", &self.feeds.settings.max_new_episodes),
+            ])];
+            for episode in skipped {
+                let title = episode.title.clone().unwrap_or_else(|| messages.get("untitled", &[]));
+                lines.push(messages.get("episode_skipped", &[("title", &title), ("url", &episode.url)]));
+            }
+            if let Err(e) = subscription.channel.say(http, truncate_message(lines.join("\n"))).await {
+                error!("Failed to announce new episodes in {}: {}", subscription.channel, e);
+            }
+        }
+        Ok(())
+    }
+}
+
+// Listed by /subscriptions
+pub fn describe(subscription: &Subscription, messages: &Messages) -> String {
+    let name = subscription.title.as_deref().unwrap_or(&subscription.url);
+    let key = if is_youtube_channel(subscription) { "subscription_youtube" } else { "subscription" };
+    let mut line = messages.get(key, &[
+        ("id", &subscription.id),
+        ("name", &name),
+        ("url", &subscription.url),
+        ("channel", &subscription.channel),
+    ]);
+    let checked = match (&subscription.last_error, subscription.last_checked) {
+        (Some(error), _) => Some(messages.get("subscription_check_failed", &[("error", error)])),
+        (None, Some(checked)) => Some(messages.get("subscription_checked", &[("when", &checked)])),
+        (None, None) => None,
+    };
+    if let Some(checked) = checked {
+        line.push_str(", ");
+        line.push_str(&checked);
+    }
+    line
+}
+
@@ -0,0 +1,158 @@
+use anyhow::{bail, Result};
+use std::collections::HashSet;
+
+use crate::messages::Messages;
+
+// The yt-dlp flags allowed_ytdlp_flags may list. None of them take a path, run a command, or
+// touch cookies, credentials or where the bot connects to, so whatever a trusted user passes
+// can't write outside output_dir or send anything anywhere.
+const SWITCHES: &[&str] = &[
+    "--write-info-json",
+    "--write-description",
+    "--write-thumbnail",
+    "--write-all-thumbnails",
+    "--write-subs",
+    "--write-auto-subs",
+    "--embed-thumbnail",
+    "--embed-metadata",
+    "--embed-chapters",
+    "--embed-info-json",
+    "--embed-subs",
+    "--no-playlist",
+    "--yes-playlist",
+    "--live-from-start",
+    "--no-mtime",
+    "--xattrs",
+    "--prefer-free-formats",
+    "--keep-video",
+    "--restrict-filenames",
+    "--windows-filenames",
+];
+
+const WITH_VALUE: &[&str] = &[
+    "--sub-langs",
+    "--sub-format",
+    "--convert-subs",
+    "--convert-thumbnails",
+    "--playlist-items",
+    "-I",
+    "--max-downloads",
+    "--sponsorblock-remove",
+    "--sponsorblock-mark",
+    "--format-sort",
+    "-S",
+    "--merge-output-format",
+    "--remux-video",
+    "--recode-video",
+    "--audio-quality",
+    "--match-filters",
+    "--date",
+    "--datebefore",
+    "--dateafter",
+    "--min-filesize",
+    "--max-filesize",
+    "--age-limit",
+    "--referer",
+    "--geo-bypass-country",
+];
+
+// The .info.json includes the cookies sent for each format, so these are refused for URLs
+// that get cookies
+const LEAKS_COOKIES: &[&str] = &["--write-info-json", "--embed-info-json"];
+
+// Allowed when allowed_ytdlp_flags isn't set
+const DEFAULT_ALLOWED: &[&str] = &[
+    "--write-info-json",
+    "--write-description",
+    "--write-thumbnail",
+    "--write-subs",
+    "--write-auto-subs",
+    "--embed-thumbnail",
+    "--embed-metadata",
+    "--embed-chapters",
+    "--embed-subs",
+    "--no-playlist",
+    "--sub-langs",
+    "--sub-format",
+    "--convert-subs",
+    "--playlist-items",
+    "-I",
+    "--sponsorblock-remove",
+    "--sponsorblock-mark",
+    "--format-sort",
+    "-S",
+    "--merge-output-format",
+    "--remux-video",
+    "--match-filters",
+];
+
+// A trusted user's flags without those that would save the cookies, for runs that get
+// cookies the flags weren't checked against
+pub fn without_cookie_leaks(args: Vec<String>) -> Vec<String> {
+    args.into_iter().filter(|arg| !LEAKS_COOKIES.contains(&arg.as_str())).collect()
+}
+
+const MAX_FLAGS: usize = 20;
+const MAX_VALUE_LEN: usize = 100;
+
+// Which yt-dlp flags trusted users may add to their requests
+pub struct FlagPolicy {
+    allowed: HashSet<String>,
+}
+
+impl FlagPolicy {
+    pub fn new(allowed: Option<&[String]>) -> Result<Self> {
+        let allowed: HashSet<String> = match allowed {
+            Some(flags) => flags.iter().map(|flag| flag.trim().to_string()).collect(),
+            None => DEFAULT_ALLOWED.iter().map(|flag| flag.to_string()).collect(),
+        };
+        for flag in &allowed {
+            if !SWITCHES.contains(&flag.as_str()) && !WITH_VALUE.contains(&flag.as_str()) {
+                bail!("allowed_ytdlp_flags: {} isn't one of the flags that can be allowed", flag);
+            }
+        }
+        Ok(FlagPolicy { allowed })
+    }
+
+    // Checks the words a user passed after `--`, returning them as yt-dlp arguments
+    pub fn check(&self, words: &[&str], with_cookies: bool, messages: &Messages) -> Result<Vec<String>, String> {
+        let mut args = Vec::new();
+        let mut words = words.iter().copied();
+        let mut count = 0;
+        while let Some(word) = words.next() {
+            count += 1;
+            if count > MAX_FLAGS {
+                return Err(messages.get("flags_too_many", &[("max", &MAX_FLAGS)]));
+            }
+            let (flag, inline) = match word.split_once('=') {
+                Some((flag, value)) if flag.starts_with("--") => (flag, Some(value)),
+                _ => (word, None),
+            };
+            if !flag.starts_with('-') {
+                return Err(messages.get("flags_not_a_flag", &[("flag", &word)]));
+            }
+            if !self.allowed.contains(flag) {
+                return Err(messages.get("flags_not_allowed", &[("flag", &flag)]));
+            }
+            if with_cookies && LEAKS_COOKIES.contains(&flag) {
+                return Err(messages.get("flags_leak_cookies", &[("flag", &flag)]));
+            }
+            if !WITH_VALUE.contains(&flag) {
+                if inline.is_some() {
+                    return Err(messages.get("flags_no_value", &[("flag", &flag)]));
+                }
+                args.push(flag.to_string());
+                continue;
+            }
+            let value = match inline.or_else(|| words.next()) {
+                Some(value) if !value.starts_with("--") => value,
+                _ => return Err(messages.get("flags_needs_value", &[("flag", &flag)])),
+            };
+            if value.is_empty() || value.len() > MAX_VALUE_LEN || value.chars().any(char::is_control) {
+                return Err(messages.get("flags_invalid_value", &[("flag", &flag)]));
+            }
+            args.extend([flag.to_string(), value.to_string()]);
+        }
+        Ok(args)
+    }
+}
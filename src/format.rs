@@ -0,0 +1,146 @@
+use anyhow::{anyhow, Error};
+use serde::{Deserialize, Serialize, Serializer};
+use std::fmt;
+use std::str::FromStr;
+
+// Offered as choices on /download and listed when a format can't be parsed
+pub const PRESETS: [&str; 11] = [
+    "best", "2160p", "1440p", "1080p", "720p", "480p", "360p", "worst", "audio", "mp3", "opus",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(try_from = "String")]
+pub enum FormatSpec {
+    #[default]
+    Best,
+    Worst,
+    MaxHeight(u32),
+    // None keeps whatever codec the site serves
+    Audio(Option<AudioFormat>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AudioFormat {
+    Mp3,
+    Opus,
+    M4a,
+    Flac,
+}
+
+impl AudioFormat {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AudioFormat::Mp3 => "mp3",
+            AudioFormat::Opus => "opus",
+            AudioFormat::M4a => "m4a",
+            AudioFormat::Flac => "flac",
+        }
+    }
+
+    fn parse(s: &str) -> Option<Self> {
+        match s {
+            "mp3" => Some(AudioFormat::Mp3),
+            "opus" => Some(AudioFormat::Opus),
+            "m4a" => Some(AudioFormat::M4a),
+            "flac" => Some(AudioFormat::Flac),
+            _ => None,
+        }
+    }
+}
+
+impl FormatSpec {
+    pub fn ytdlp_args(&self) -> Vec<String> {
+        match self {
+            FormatSpec::Best => Vec::new(),
+            FormatSpec::Worst => vec!["-f".into(), "wv*+wa/w".into()],
+            FormatSpec::MaxHeight(height) => vec![
+                "-f".into(),
+                format!("bv*[height<={0}]+ba/b[height<={0}]", height),
+            ],
+            FormatSpec::Audio(None) => vec!["-x".into()],
+            FormatSpec::Audio(Some(codec)) => vec![
+                "-x".into(),
+                "--audio-format".into(),
+                codec.as_str().into(),
+            ],
+        }
+    }
+
+    pub fn is_audio(&self) -> bool {
+        matches!(self, FormatSpec::Audio(_))
+    }
+
+    // Fills in the configured codec for a bare "audio" request
+    pub fn with_default_audio(self, codec: Option<AudioFormat>) -> Self {
+        match self {
+            FormatSpec::Audio(None) => FormatSpec::Audio(codec),
+            other => other,
+        }
+    }
+}
+
+impl FromStr for FormatSpec {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim().to_ascii_lowercase();
+        let height = match s.as_str() {
+            "best" => return Ok(FormatSpec::Best),
+            "worst" => return Ok(FormatSpec::Worst),
+            "audio" => return Ok(FormatSpec::Audio(None)),
+            "4k" => Some(2160),
+            "8k" => Some(4320),
+            other => {
+                if let Some(codec) = AudioFormat::parse(other) {
+                    return Ok(FormatSpec::Audio(Some(codec)));
+                }
+                other.strip_suffix('p').and_then(|height| height.parse().ok())
+            }
+        };
+        match height {
+            Some(height @ 144..=4320) => Ok(FormatSpec::MaxHeight(height)),
+            _ => Err(anyhow!("Unknown format '{}'. Use one of: {}", s, PRESETS.join(", "))),
+        }
+    }
+}
+
+// Checks a comma-separated list of subtitle languages for yt-dlp's --sub-langs, which also
+// accepts "all" and regexes like "en.*"
+pub fn parse_subtitle_langs(langs: &str) -> Result<String, Error> {
+    let langs = langs.trim().trim_matches(',');
+    let valid = |lang: &str| {
+        !lang.is_empty() && lang.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | '*'))
+    };
+    if langs.is_empty() || !langs.split(',').all(valid) {
+        return Err(anyhow!("Invalid subtitle languages '{}'. Use codes like en or en,de, or all.", langs));
+    }
+    Ok(langs.to_ascii_lowercase())
+}
+
+impl TryFrom<String> for FormatSpec {
+    type Error = Error;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+// Stored as the same text it's parsed from, for saved jobs
+impl Serialize for FormatSpec {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl fmt::Display for FormatSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FormatSpec::Best => write!(f, "best"),
+            FormatSpec::Worst => write!(f, "worst"),
+            FormatSpec::MaxHeight(height) => write!(f, "{}p", height),
+            FormatSpec::Audio(None) => write!(f, "audio"),
+            FormatSpec::Audio(Some(codec)) => write!(f, "{}", codec.as_str()),
+        }
+    }
+}
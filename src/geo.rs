@@ -0,0 +1,60 @@
+use serde::Deserialize;
+
+// The [geo] section of the config: how yt-dlp gets around videos a site only shows in some
+// countries, by sending a faked X-Forwarded-For header from an address there
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct GeoSettings {
+    // Two-letter country code every download pretends to be in (default: yt-dlp's own guess)
+    pub country: Option<String>,
+    // Country a geo-blocked download is retried from once
+    pub retry_country: Option<String>,
+    // An IP block in CIDR notation to pretend to be in instead of a country
+    pub ip_block: Option<String>,
+    // Proxy for the sites that check the location while signing in
+    pub verification_proxy: Option<String>,
+    // Never fake the header, even for sites yt-dlp would fake it for
+    #[serde(default)]
+    pub disable_bypass: bool,
+}
+
+impl GeoSettings {
+    pub fn validate(&self) -> Result<(), String> {
+        for country in [&self.country, &self.retry_country].into_iter().flatten() {
+            parse_country(country)?;
+        }
+        Ok(())
+    }
+
+    // A request's own country goes before the configured one
+    pub fn args(&self, country: Option<&str>) -> Vec<String> {
+        let mut args = Vec::new();
+        match (country.or(self.country.as_deref()), &self.ip_block) {
+            (Some(country), _) => args.extend(["--geo-bypass-country".to_string(), country.to_string()]),
+            (None, Some(block)) if !self.disable_bypass => args.extend(["--geo-bypass-ip-block".to_string(), block.clone()]),
+            (None, _) if self.disable_bypass => args.push("--no-geo-bypass".to_string()),
+            (None, _) => {}
+        }
+        if let Some(proxy) = &self.verification_proxy {
+            args.extend(["--geo-verification-proxy".to_string(), proxy.clone()]);
+        }
+        args
+    }
+
+    // The arguments a geo-blocked download is retried with, unless it already ran from there
+    pub fn retry_args(&self, country: Option<&str>) -> Option<Vec<String>> {
+        let retry = self.retry_country.as_deref()?;
+        let tried = country.or(self.country.as_deref());
+        (tried.map(str::to_ascii_uppercase).as_deref() != Some(retry.to_ascii_uppercase().as_str()))
+            .then(|| self.args(Some(retry)))
+    }
+}
+
+// A country as yt-dlp takes it: an ISO 3166-1 alpha-2 code like "US"
+pub fn parse_country(code: &str) -> Result<String, String> {
+    let code = code.trim();
+    if code.len() == 2 && code.chars().all(|c| c.is_ascii_alphabetic()) {
+        Ok(code.to_ascii_uppercase())
+    } else {
+        Err(format!("'{}' isn't a two-letter country code like US or DE.", code))
+    }
+}
@@ -0,0 +1,202 @@
+use anyhow::{anyhow, bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use serenity::model::id::GuildId;
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+use crate::format::FormatSpec;
+use crate::history::History;
+use crate::paths;
+
+// Where the output directories set with /config go, inside output_dir
+const GUILDS_DIR: &str = "guilds";
+
+// What /config can change, in the order it shows them
+pub const KEYS: &[&str] = &["output_dir", "format", "max_downloads_per_hour", "max_gb_per_day", "allowed_roles", "announce_channel_id", "language"];
+
+// A guild's own settings; unset fields fall back to the global ones
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct GuildSettings {
+    // Relative to output_dir unless absolute; one set with /config goes in guilds/<guild ID>/
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output_dir: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub format: Option<FormatSpec>,
+    // Counted per guild; 0 means no limit. /config can only lower the configured ones.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_downloads_per_hour: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_gb_per_day: Option<f64>,
+    // Replaces allowed_roles in this guild; empty lets everyone in
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub allowed_roles: Option<Vec<u64>>,
+    // Where this guild's finished downloads are announced, instead of announce_channel_id
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub announce_channel_id: Option<u64>,
+    // Which messages/<language>.toml the bot talks in here
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+}
+
+impl GuildSettings {
+    // Fields set in `other` win
+    fn merged(&self, other: &GuildSettings) -> GuildSettings {
+        GuildSettings {
+            output_dir: other.output_dir.clone().or_else(|| self.output_dir.clone()),
+            format: other.format.or(self.format),
+            max_downloads_per_hour: other.max_downloads_per_hour.or(self.max_downloads_per_hour),
+            max_gb_per_day: other.max_gb_per_day.or(self.max_gb_per_day),
+            allowed_roles: other.allowed_roles.clone().or_else(|| self.allowed_roles.clone()),
+            announce_channel_id: other.announce_channel_id.or(self.announce_channel_id),
+            language: other.language.clone().or_else(|| self.language.clone()),
+        }
+    }
+
+    // Where a /config output directory really is, so it can't be another guild's
+    fn scoped(&self, guild: GuildId) -> GuildSettings {
+        GuildSettings {
+            output_dir: self.output_dir.as_ref().map(|dir| format!("{}/{}/{}", GUILDS_DIR, guild, dir)),
+            ..self.clone()
+        }
+    }
+
+    // Sets one field from its /config value, or unsets it with None
+    fn set(&mut self, key: &str, value: Option<&str>) -> Result<()> {
+        let value = value.map(str::trim);
+        match key {
+            "output_dir" => self.output_dir = value.map(parse_output_dir).transpose()?,
+            "format" => self.format = value.map(str::parse).transpose()?,
+            "max_downloads_per_hour" => {
+                self.max_downloads_per_hour = value
+                    .map(|value| value.parse().map_err(|_| anyhow!("'{}' isn't a whole number", value)))
+                    .transpose()?;
+            }
+            "max_gb_per_day" => {
+                self.max_gb_per_day = value
+                    .map(|value| match value.parse::<f64>() {
+                        Ok(gb) if gb.is_finite() && gb >= 0.0 => Ok(gb),
+                        _ => Err(anyhow!("'{}' isn't a number of GB", value)),
+                    })
+                    .transpose()?;
+            }
+            "allowed_roles" => self.allowed_roles = value.map(parse_roles).transpose()?,
+            "announce_channel_id" => self.announce_channel_id = value.map(parse_channel).transpose()?,
+            "language" => self.language = value.map(parse_language).transpose()?,
+            other => bail!("Unknown setting '{}'. Use one of: {}", other, KEYS.join(", ")),
+        }
+        Ok(())
+    }
+
+    pub fn describe(&self) -> String {
+        let roles = self.allowed_roles.as_ref().map(|roles| {
+            if roles.is_empty() {
+                "everyone".to_string()
+            } else {
+                roles.iter().map(|role| format!("<@&{}>", role)).collect::<Vec<_>>().join(", ")
+            }
+        });
+        let values = [
+            self.output_dir.as_ref().map(|dir| format!("`{}`", dir)),
+            self.format.map(|format| format.to_string()),
+            self.max_downloads_per_hour.map(|limit| limit.to_string()),
+            self.max_gb_per_day.map(|limit| limit.to_string()),
+            roles,
+            self.announce_channel_id.map(|channel| format!("<#{}>", channel)),
+            self.language.clone(),
+        ];
+        KEYS.iter()
+            .zip(values)
+            .map(|(key, value)| format!("{}: {}", key, value.unwrap_or_else(|| "(default)".to_string())))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+// Admins of a guild shouldn't be able to write anywhere else on the host
+fn parse_output_dir(dir: &str) -> Result<String> {
+    if dir.is_empty() || !paths::stays_inside(dir) {
+        bail!("The output directory must be a relative path inside the bot's output directory.");
+    }
+    Ok(dir.to_owned())
+}
+
+// Role mentions or IDs, separated by commas or spaces; "everyone" clears the restriction
+fn parse_roles(value: &str) -> Result<Vec<u64>> {
+    if value.eq_ignore_ascii_case("everyone") {
+        return Ok(Vec::new());
+    }
+    value
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|word| !word.is_empty())
+        .map(|word| {
+            let id = word.strip_prefix("<@&").and_then(|rest| rest.strip_suffix('>')).unwrap_or(word);
+            id.parse().map_err(|_| anyhow!("'{}' isn't a role", word))
+        })
+        .collect()
+}
+
+// A channel mention or ID
+fn parse_channel(value: &str) -> Result<u64> {
+    let id = value.strip_prefix("<#").and_then(|rest| rest.strip_suffix('>')).unwrap_or(value);
+    id.parse().map_err(|_| anyhow!("'{}' isn't a channel", value))
+}
+
+// A language code like de or pt-br, naming a file in the messages directory
+fn parse_language(value: &str) -> Result<String> {
+    let valid = (2..=10).contains(&value.len())
+        && value.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if !valid {
+        bail!("'{}' isn't a language code like de or pt-br", value);
+    }
+    Ok(value.to_ascii_lowercase())
+}
+
+// Guild settings changed through /config, which apply on top of the config file's
+pub struct Guilds {
+    overrides: RwLock<HashMap<GuildId, GuildSettings>>,
+}
+
+impl Guilds {
+    pub fn new(history: &History) -> Result<Self> {
+        let mut overrides = HashMap::new();
+        for (guild, saved) in history.guild_settings()? {
+            let settings = serde_json::from_str(&saved)
+                .with_context(|| format!("Invalid saved settings for guild {}", guild))?;
+            overrides.insert(guild, settings);
+        }
+        Ok(Guilds { overrides: RwLock::new(overrides) })
+    }
+
+    // The quota limits in here are the ones set, not the ones that apply; see overrides
+    pub fn get(&self, guild: GuildId, configured: Option<&GuildSettings>) -> GuildSettings {
+        let configured = configured.cloned().unwrap_or_default();
+        match self.overrides.read().unwrap().get(&guild) {
+            Some(overrides) => configured.merged(&overrides.scoped(guild)),
+            None => configured,
+        }
+    }
+
+    // Only what was changed with /config
+    pub fn overrides(&self, guild: GuildId) -> GuildSettings {
+        self.overrides.read().unwrap().get(&guild).cloned().unwrap_or_default()
+    }
+
+    // Changes one setting and saves it, if `allowed` lets the guild have the settings it ends up
+    // with; that's how the quotas can only be lowered
+    pub fn update(
+        &self,
+        history: &History,
+        guild: GuildId,
+        key: &str,
+        value: Option<&str>,
+        allowed: impl FnOnce(&GuildSettings) -> Result<(), String>,
+    ) -> Result<()> {
+        let mut overrides = self.overrides.write().unwrap();
+        let mut settings = overrides.get(&guild).cloned().unwrap_or_default();
+        settings.set(key, value)?;
+        allowed(&settings).map_err(|e| anyhow!(e))?;
+        history.save_guild_settings(guild, &serde_json::to_string(&settings)?)?;
+        overrides.insert(guild, settings);
+        Ok(())
+    }
+}
@@ -0,0 +1,148 @@
+use anyhow::{Context, Result};
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use axum::routing::get;
+use axum::{Json, Router};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::binary;
+use crate::queue::DownloadQueue;
+
+// Reconnects are routine, so /healthz only fails once the gateway has been down this long
+const DISCONNECT_GRACE: Duration = Duration::from_secs(120);
+
+// Whether the Discord gateway connection is up, and since when it's been up or down. With
+// several shards or bots it only counts as up while every one of them is connected.
+pub struct Gateway {
+    connected: AtomicBool,
+    since: Mutex<Instant>,
+    // By bot name and shard ID
+    shards: Mutex<BTreeMap<(String, u32), Shard>>,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Shard {
+    pub connected: bool,
+    // Guilds the shard was given when it last became ready
+    pub guilds: usize,
+}
+
+impl Gateway {
+    pub fn new() -> Self {
+        Gateway {
+            connected: AtomicBool::new(false),
+            since: Mutex::new(Instant::now()),
+            shards: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    fn set_connected(&self, connected: bool) {
+        let mut since = self.since.lock().unwrap();
+        if self.connected.swap(connected, Ordering::SeqCst) != connected {
+            *since = Instant::now();
+        }
+    }
+
+    pub fn shard_ready(&self, bot: &str, shard: u32, guilds: usize) {
+        let mut shards = self.shards.lock().unwrap();
+        shards.insert((bot.to_string(), shard), Shard { connected: true, guilds });
+        self.set_connected(shards.values().all(|shard| shard.connected));
+    }
+
+    pub fn set_shard_connected(&self, bot: &str, shard: u32, connected: bool) {
+        let mut shards = self.shards.lock().unwrap();
+        shards.entry((bot.to_string(), shard)).or_default().connected = connected;
+        self.set_connected(shards.values().all(|shard| shard.connected));
+    }
+
+    // A bot's client exited, taking every one of its shards with it
+    pub fn disconnect_all(&self, bot: &str) {
+        let mut shards = self.shards.lock().unwrap();
+        shards.retain(|(name, _), _| name != bot);
+        self.set_connected(false);
+    }
+
+    pub fn shards(&self) -> Vec<((String, u32), Shard)> {
+        self.shards.lock().unwrap().iter().map(|(key, &shard)| (key.clone(), shard)).collect()
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.connected.load(Ordering::SeqCst)
+    }
+
+    // How long the gateway has been down, or None while it's connected
+    pub fn down_for(&self) -> Option<Duration> {
+        let since = self.since.lock().unwrap();
+        (!self.is_connected()).then(|| since.elapsed())
+    }
+}
+
+pub struct Health {
+    pub gateway: Arc<Gateway>,
+    pub queue: Arc<DownloadQueue>,
+    pub output_dir: String,
+}
+
+#[derive(Serialize)]
+struct Liveness {
+    ok: bool,
+    gateway: bool,
+    ytdlp: bool,
+    output_dir: bool,
+}
+
+#[derive(Serialize)]
+struct Readiness {
+    ok: bool,
+    gateway: bool,
+    accepting_downloads: bool,
+}
+
+pub async fn serve(addr: &str, health: Arc<Health>) -> Result<()> {
+    let app = Router::new()
+        .route("/healthz", get(healthz))
+        .route("/readyz", get(readyz))
+        .with_state(health);
+    let listener = tokio::net::TcpListener::bind(addr).await
+        .with_context(|| format!("Failed to bind health checks to {}", addr))?;
+    log::info!("Health checks listening on http://{}", addr);
+    axum::serve(listener, app).await.context("Health check server failed")
+}
+
+// Liveness: fails when restarting the bot is likely to help
+async fn healthz(State(health): State<Arc<Health>>) -> impl IntoResponse {
+    let gateway = health.gateway.down_for().is_none_or(|down| down < DISCONNECT_GRACE);
+    let ytdlp = binary::path().is_file();
+    let output_dir = is_writable(Path::new(&health.output_dir)).await;
+    let ok = gateway && ytdlp && output_dir;
+    (status_for(ok), Json(Liveness { ok, gateway, ytdlp, output_dir }))
+}
+
+// Readiness: connected right now and accepting downloads
+async fn readyz(State(health): State<Arc<Health>>) -> impl IntoResponse {
+    let gateway = health.gateway.is_connected();
+    let accepting_downloads = health.queue.is_accepting();
+    let ok = gateway && accepting_downloads;
+    (status_for(ok), Json(Readiness { ok, gateway, accepting_downloads }))
+}
+
+fn status_for(ok: bool) -> StatusCode {
+    if ok {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    }
+}
+
+async fn is_writable(dir: &Path) -> bool {
+    let probe = dir.join(".healthcheck");
+    let written = tokio::fs::create_dir_all(dir).await.is_ok() && tokio::fs::write(&probe, b"ok").await.is_ok();
+    let _ = tokio::fs::remove_file(&probe).await;
+    written
+}
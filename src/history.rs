@@ -0,0 +1,1001 @@
+use anyhow::{Context, Result};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::Serialize;
+use serenity::model::id::{ChannelId, GuildId, UserId};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::jobs::JobId;
+use crate::messages::Messages;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Queued,
+    Running,
+    Done,
+    Failed,
+    Cancelled,
+    // Stopped by a shutdown before it finished
+    Interrupted,
+}
+
+impl Status {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Status::Queued => "queued",
+            Status::Running => "running",
+            Status::Done => "done",
+            Status::Failed => "failed",
+            Status::Cancelled => "cancelled",
+            Status::Interrupted => "interrupted",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub job_id: JobId,
+    pub requester: UserId,
+    pub url: String,
+    pub format: String,
+    pub status: String,
+    pub requested_at: i64,
+    pub output_path: Option<String>,
+    pub file_size: Option<u64>,
+    // Kept by the retention policy
+    pub pinned: bool,
+    // Removed by the retention policy
+    pub deleted: bool,
+    pub title: Option<String>,
+    pub uploader: Option<String>,
+    pub guild: Option<GuildId>,
+    // Whether the results went to the requester alone
+    pub private: bool,
+}
+
+impl Entry {
+    // The same rule as Filter::seen_by
+    pub fn visible_to(&self, user: UserId, guild: Option<GuildId>) -> bool {
+        self.requester == user || guild.is_some() && self.guild == guild && !self.private
+    }
+}
+
+// What a page of history is narrowed down to; every field left as None matches everything
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Filter {
+    pub requester: Option<UserId>,
+    // Part of the title, uploader or URL, case-insensitively
+    pub term: Option<String>,
+    // The site's domain, which also matches its subdomains
+    pub domain: Option<String>,
+    // Requested at or after `since` and before `until`, in seconds since the epoch
+    pub since: Option<i64>,
+    pub until: Option<i64>,
+    // Who's looking, if it isn't the operator: they only see their own downloads, and the
+    // others of their guild that weren't requested in DMs or with private results
+    pub seen_by: Option<Viewer>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Viewer {
+    pub user: UserId,
+    pub guild: Option<GuildId>,
+}
+
+// A finished download whose file the retention policy hasn't removed
+#[derive(Debug, Clone)]
+pub struct StoredFile {
+    pub job_id: JobId,
+    pub output_path: String,
+    pub pinned: bool,
+}
+
+// Format of archive entries imported from elsewhere, which count for every format
+pub const IMPORTED_FORMAT: &str = "*";
+
+// A file already downloaded for some video and format
+#[derive(Debug, Clone)]
+pub struct Archived {
+    // 0 for imported entries, which have no job or file
+    pub job_id: JobId,
+    pub output_path: String,
+    pub downloaded_at: i64,
+}
+
+impl Archived {
+    pub fn is_imported(&self) -> bool {
+        self.job_id == 0
+    }
+
+    // Where it was downloaded, for messages saying it already was
+    pub fn describe(&self, messages: &Messages) -> String {
+        if self.is_imported() {
+            messages.get("archived_imported", &[])
+        } else {
+            messages.get("archived_job", &[("id", &self.job_id), ("path", &self.output_path)])
+        }
+    }
+}
+
+// A row of the archive with what the history has on its job, for exports
+#[derive(Debug, Clone)]
+pub struct ArchiveEntry {
+    pub archive_key: String,
+    pub format: String,
+    pub job_id: JobId,
+    pub url: Option<String>,
+    pub title: Option<String>,
+    pub output_path: String,
+    pub downloaded_at: i64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Failure {
+    pub job_id: JobId,
+    pub requester: String,
+    pub url: String,
+    pub finished_at: Option<i64>,
+    pub error: Option<String>,
+}
+
+// How the downloads requested in some period went
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Totals {
+    pub done: u64,
+    pub failed: u64,
+    pub cancelled: u64,
+    pub bytes: u64,
+}
+
+// Bytes transferred by the downloads requested in some period, biggest first
+#[derive(Debug, Clone, Default)]
+pub struct Usage {
+    pub total: u64,
+    pub jobs: u64,
+    // None for direct messages
+    pub by_guild: Vec<(Option<GuildId>, u64)>,
+    pub by_user: Vec<(UserId, u64)>,
+}
+
+// What /stats shows: the most active requesters and sites by job count, and the bytes
+// transferred each week
+#[derive(Debug, Clone, Default)]
+pub struct Stats {
+    pub jobs: u64,
+    pub total: u64,
+    // (who, jobs, bytes)
+    pub by_user: Vec<(UserId, u64, u64)>,
+    pub by_domain: Vec<(String, u64, u64)>,
+    // Bytes per week, oldest first, with the Unix time each week starts at; weeks without
+    // downloads are left out
+    pub by_week: Vec<(i64, u64)>,
+}
+
+// Weeks start on Monday, four days after the Unix epoch
+pub const WEEK_OFFSET: i64 = 4 * 86_400;
+
+// A feed followed with /subscribe
+#[derive(Debug, Clone)]
+pub struct Subscription {
+    pub id: i64,
+    pub url: String,
+    pub title: Option<String>,
+    pub guild: Option<GuildId>,
+    // Where new episodes are announced and downloaded for
+    pub channel: ChannelId,
+    pub requester: UserId,
+    pub format: Option<String>,
+    pub created_at: i64,
+    pub last_checked: Option<i64>,
+    // Why the last check failed, if it did
+    pub last_error: Option<String>,
+}
+
+pub struct NewEntry<'a> {
+    pub job_id: JobId,
+    pub requester: UserId,
+    pub guild: Option<GuildId>,
+    pub channel: ChannelId,
+    pub url: &'a str,
+    pub format: &'a str,
+    // The serialized request, so the job can be resumed after a restart
+    pub request: Option<&'a str>,
+    pub title: Option<&'a str>,
+    pub uploader: Option<&'a str>,
+    // Whether the results went to the requester alone
+    pub private: bool,
+}
+
+pub struct History {
+    conn: Mutex<Connection>,
+}
+
+impl History {
+    pub fn open(path: &str) -> Result<Self> {
+        if let Some(dir) = Path::new(path).parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            std::fs::create_dir_all(dir)
+                .with_context(|| format!("Failed to create database directory: {}", dir.display()))?;
+        }
+        let conn = Connection::open(path)
+            .with_context(|| format!("Failed to open history database: {}", path))?;
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS downloads (
+                job_id INTEGER PRIMARY KEY,
+                requester INTEGER NOT NULL,
+                guild_id INTEGER,
+                channel_id INTEGER NOT NULL,
+                url TEXT NOT NULL,
+                format TEXT NOT NULL,
+                status TEXT NOT NULL,
+                requested_at INTEGER NOT NULL,
+                finished_at INTEGER,
+                output_path TEXT,
+                file_size INTEGER,
+                error TEXT
+            );
+            CREATE INDEX IF NOT EXISTS downloads_requester ON downloads (requester);
+            CREATE TABLE IF NOT EXISTS archive (
+                archive_key TEXT NOT NULL,
+                format TEXT NOT NULL,
+                job_id INTEGER NOT NULL,
+                output_path TEXT NOT NULL,
+                downloaded_at INTEGER NOT NULL,
+                PRIMARY KEY (archive_key, format)
+            );
+            CREATE TABLE IF NOT EXISTS guild_settings (
+                guild_id INTEGER PRIMARY KEY,
+                settings TEXT NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS user_preferences (
+                user_id INTEGER PRIMARY KEY,
+                preferences TEXT NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS subscriptions (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                url TEXT NOT NULL,
+                title TEXT,
+                guild_id INTEGER,
+                channel_id INTEGER NOT NULL,
+                requester INTEGER NOT NULL,
+                format TEXT,
+                created_at INTEGER NOT NULL,
+                last_checked INTEGER,
+                last_error TEXT,
+                UNIQUE (url, channel_id)
+            );
+            CREATE TABLE IF NOT EXISTS subscription_episodes (
+                subscription_id INTEGER NOT NULL,
+                guid TEXT NOT NULL,
+                seen_at INTEGER NOT NULL,
+                PRIMARY KEY (subscription_id, guid)
+            );
+            CREATE TABLE IF NOT EXISTS file_hashes (
+                path TEXT PRIMARY KEY,
+                size INTEGER NOT NULL,
+                modified INTEGER NOT NULL,
+                sha256 TEXT NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS file_hashes_sha256 ON file_hashes (sha256);
+            CREATE TABLE IF NOT EXISTS secrets (
+                name TEXT PRIMARY KEY,
+                key BLOB NOT NULL
+            );",
+        ).context("Failed to initialize history database")?;
+        // Added after the table was first released
+        for (column, kind) in [
+            ("request", "TEXT"),
+            ("sha256", "TEXT"),
+            ("duration", "REAL"),
+            ("pinned", "INTEGER NOT NULL DEFAULT 0"),
+            ("deleted_at", "INTEGER"),
+            ("title", "TEXT"),
+            ("uploader", "TEXT"),
+            ("domain", "TEXT"),
+            ("locations", "TEXT"),
+            ("transferred", "INTEGER"),
+            ("private", "INTEGER NOT NULL DEFAULT 0"),
+        ] {
+            let exists: bool = conn
+                .query_row(
+                    "SELECT COUNT(*) > 0 FROM pragma_table_info('downloads') WHERE name = ?1",
+                    params![column],
+                    |row| row.get(0),
+                )
+                .context("Failed to inspect history database")?;
+            if !exists {
+                conn.execute_batch(&format!("ALTER TABLE downloads ADD COLUMN {} {};", column, kind))
+                    .context("Failed to migrate history database")?;
+                if column == "domain" {
+                    backfill_search_columns(&conn).context("Failed to migrate history database")?;
+                }
+            }
+        }
+        Ok(History { conn: Mutex::new(conn) })
+    }
+
+    // Job IDs continue from the last recorded one so they stay unique across restarts
+    pub fn last_job_id(&self) -> Result<JobId> {
+        let conn = self.conn.lock().unwrap();
+        let id: Option<i64> = conn
+            .query_row("SELECT MAX(job_id) FROM downloads", [], |row| row.get(0))
+            .optional()?
+            .flatten();
+        Ok(id.unwrap_or(0) as JobId)
+    }
+
+    pub fn record(&self, entry: NewEntry<'_>) {
+        self.execute(
+            "INSERT INTO downloads
+             (job_id, requester, guild_id, channel_id, url, format, status, requested_at, request, title, uploader, domain, private)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)",
+            params![
+                entry.job_id as i64,
+                entry.requester.get() as i64,
+                entry.guild.map(|id| id.get() as i64),
+                entry.channel.get() as i64,
+                entry.url,
+                entry.format,
+                Status::Queued.as_str(),
+                now(),
+                entry.request,
+                entry.title,
+                entry.uploader,
+                crate::url_host(entry.url),
+                entry.private,
+            ],
+        );
+    }
+
+    pub fn set_status(&self, job_id: JobId, status: Status) {
+        let finished_at = matches!(status, Status::Done | Status::Failed | Status::Cancelled | Status::Interrupted).then(now);
+        self.execute(
+            "UPDATE downloads SET status = ?2, finished_at = ?3 WHERE job_id = ?1",
+            params![job_id as i64, status.as_str(), finished_at],
+        );
+    }
+
+    // `sha256` and `duration` are those of the file at output_path
+    pub fn finish_ok(
+        &self,
+        job_id: JobId,
+        output_path: Option<&Path>,
+        file_size: u64,
+        sha256: Option<&str>,
+        duration: Option<f64>,
+    ) {
+        self.execute(
+            "UPDATE downloads SET status = ?2, finished_at = ?3, output_path = ?4, file_size = ?5,
+             sha256 = ?6, duration = ?7
+             WHERE job_id = ?1",
+            params![
+                job_id as i64,
+                Status::Done.as_str(),
+                now(),
+                output_path.map(|path| path.to_string_lossy().into_owned()),
+                file_size as i64,
+                sha256,
+                duration,
+            ],
+        );
+    }
+
+    pub fn finish_err(&self, job_id: JobId, error: &str) {
+        self.execute(
+            "UPDATE downloads SET status = ?2, finished_at = ?3, error = ?4 WHERE job_id = ?1",
+            params![job_id as i64, Status::Failed.as_str(), now(), error],
+        );
+    }
+
+    // Jobs that were queued, running or interrupted when the bot last stopped, oldest first,
+    // with their saved requests (None for jobs recorded before requests were saved)
+    pub fn unfinished(&self) -> Result<Vec<(JobId, Option<String>)>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT job_id, request FROM downloads WHERE status IN (?1, ?2, ?3) ORDER BY job_id",
+        )?;
+        let jobs = stmt
+            .query_map(
+                params![Status::Queued.as_str(), Status::Running.as_str(), Status::Interrupted.as_str()],
+                |row| Ok((row.get::<_, i64>(0)? as JobId, row.get(1)?)),
+            )?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(jobs)
+    }
+
+    // The request a job was submitted with, as JSON
+    pub fn request(&self, job_id: JobId) -> Result<Option<String>> {
+        let conn = self.conn.lock().unwrap();
+        let request = conn
+            .query_row("SELECT request FROM downloads WHERE job_id = ?1", params![job_id as i64], |row| row.get(0))
+            .optional()?
+            .flatten();
+        Ok(request)
+    }
+
+    // Where the storage backend put a finished job's files, one line per file for Discord
+    pub fn set_locations(&self, job_id: JobId, locations: &[String]) {
+        self.execute(
+            "UPDATE downloads SET locations = ?2 WHERE job_id = ?1",
+            params![job_id as i64, locations.join("\n")],
+        );
+    }
+
+    pub fn locations(&self, job_id: JobId) -> Result<Vec<String>> {
+        let conn = self.conn.lock().unwrap();
+        let locations: Option<String> = conn
+            .query_row("SELECT locations FROM downloads WHERE job_id = ?1", params![job_id as i64], |row| row.get(0))
+            .optional()?
+            .flatten();
+        Ok(locations.map(|locations| locations.lines().map(str::to_owned).collect()).unwrap_or_default())
+    }
+
+    pub fn archive(&self, archive_key: &str, format: &str, job_id: JobId, output_path: &Path) {
+        self.execute(
+            "INSERT OR REPLACE INTO archive (archive_key, format, job_id, output_path, downloaded_at)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![archive_key, format, job_id as i64, output_path.to_string_lossy(), now()],
+        );
+    }
+
+    pub fn archived(&self, archive_key: &str, format: &str) -> Option<Archived> {
+        let conn = self.conn.lock().unwrap();
+        let result = conn
+            .query_row(
+                // A download in the format itself wins over an imported entry
+                "SELECT job_id, output_path, downloaded_at FROM archive WHERE archive_key = ?1 AND format IN (?2, ?3)
+                 ORDER BY format = ?3",
+                params![archive_key, format, IMPORTED_FORMAT],
+                |row| {
+                    Ok(Archived {
+                        job_id: row.get::<_, i64>(0)? as JobId,
+                        output_path: row.get(1)?,
+                        downloaded_at: row.get(2)?,
+                    })
+                },
+            )
+            .optional();
+        result.unwrap_or_else(|e| {
+            log::error!("Failed to read download archive: {}", e);
+            None
+        })
+    }
+
+    pub fn archive_entries(&self) -> Result<Vec<ArchiveEntry>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT archive.archive_key, archive.format, archive.job_id, downloads.url, downloads.title,
+                    archive.output_path, archive.downloaded_at
+             FROM archive LEFT JOIN downloads ON downloads.job_id = archive.job_id AND archive.job_id != 0
+             ORDER BY archive.downloaded_at, archive.archive_key",
+        )?;
+        let rows = stmt.query_map([], |row| {
+            Ok(ArchiveEntry {
+                archive_key: row.get(0)?,
+                format: row.get(1)?,
+                job_id: row.get::<_, i64>(2)? as JobId,
+                url: row.get(3)?,
+                title: row.get(4)?,
+                output_path: row.get(5)?,
+                downloaded_at: row.get(6)?,
+            })
+        })?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    // Adds archive keys downloaded elsewhere, each with when it was (default: now), so they're
+    // never downloaded again. Returns how many weren't in the archive yet.
+    pub fn import_archive(&self, entries: &[(String, Option<i64>)]) -> Result<usize> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        let mut added = 0;
+        {
+            let mut stmt = tx.prepare(
+                "INSERT OR IGNORE INTO archive (archive_key, format, job_id, output_path, downloaded_at)
+                 VALUES (?1, ?2, 0, '', ?3)",
+            )?;
+            for (archive_key, downloaded_at) in entries {
+                added += stmt.execute(params![archive_key, IMPORTED_FORMAT, downloaded_at.unwrap_or_else(now)])?;
+            }
+        }
+        tx.commit()?;
+        Ok(added)
+    }
+
+    // When each of the user's requests in the guild (or DMs) since `since` was made, oldest first
+    pub fn requested_since(&self, requester: UserId, guild: Option<GuildId>, since: i64) -> Result<Vec<i64>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT requested_at FROM downloads WHERE requester = ?1 AND guild_id IS ?2 AND requested_at >= ?3
+             ORDER BY requested_at",
+        )?;
+        let times = stmt
+            .query_map(params![requester.get() as i64, guild.map(|id| id.get() as i64), since], |row| row.get(0))?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(times)
+    }
+
+    // When and how much the user's downloads in the guild (or DMs) since `since` finished, oldest first
+    pub fn downloaded_since(&self, requester: UserId, guild: Option<GuildId>, since: i64) -> Result<Vec<(i64, u64)>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT finished_at, file_size FROM downloads
+             WHERE requester = ?1 AND guild_id IS ?2 AND status = ?3 AND finished_at >= ?4 AND file_size IS NOT NULL
+             ORDER BY finished_at",
+        )?;
+        let guild = guild.map(|id| id.get() as i64);
+        let downloads = stmt
+            .query_map(params![requester.get() as i64, guild, Status::Done.as_str(), since], |row| {
+                Ok((row.get(0)?, row.get::<_, i64>(1)? as u64))
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(downloads)
+    }
+
+    // Total size of everything the user has downloaded
+    pub fn downloaded_by(&self, requester: UserId) -> Result<u64> {
+        self.total_size("requester = ?2", requester.get() as i64)
+    }
+
+    // Total size of everything downloaded in the channel
+    pub fn downloaded_in(&self, channel: ChannelId) -> Result<u64> {
+        self.total_size("channel_id = ?2", channel.get() as i64)
+    }
+
+    fn total_size(&self, filter: &str, id: i64) -> Result<u64> {
+        let conn = self.conn.lock().unwrap();
+        let total: i64 = conn.query_row(
+            &format!("SELECT COALESCE(SUM(file_size), 0) FROM downloads WHERE status = ?1 AND {}", filter),
+            params![Status::Done.as_str(), id],
+            |row| row.get(0),
+        )?;
+        Ok(total as u64)
+    }
+
+    // Guild settings changed with /config, as JSON
+    pub fn guild_settings(&self) -> Result<Vec<(GuildId, String)>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare("SELECT guild_id, settings FROM guild_settings")?;
+        let settings = stmt
+            .query_map([], |row| Ok((GuildId::new(row.get::<_, i64>(0)? as u64), row.get(1)?)))?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(settings)
+    }
+
+    // Unlike the download history, these must not be lost silently
+    pub fn save_guild_settings(&self, guild: GuildId, settings: &str) -> Result<()> {
+        self.conn.lock().unwrap()
+            .execute(
+                "INSERT OR REPLACE INTO guild_settings (guild_id, settings) VALUES (?1, ?2)",
+                params![guild.get() as i64, settings],
+            )
+            .context("Failed to save guild settings")?;
+        Ok(())
+    }
+
+    // Preferences set with /prefs, as JSON
+    pub fn user_preferences(&self) -> Result<Vec<(UserId, String)>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare("SELECT user_id, preferences FROM user_preferences")?;
+        let preferences = stmt
+            .query_map([], |row| Ok((UserId::new(row.get::<_, i64>(0)? as u64), row.get(1)?)))?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(preferences)
+    }
+
+    pub fn save_user_preferences(&self, user: UserId, preferences: &str) -> Result<()> {
+        self.conn.lock().unwrap()
+            .execute(
+                "INSERT OR REPLACE INTO user_preferences (user_id, preferences) VALUES (?1, ?2)",
+                params![user.get() as i64, preferences],
+            )
+            .context("Failed to save preferences")?;
+        Ok(())
+    }
+
+    // A random 32-byte key, made the first time it's asked for and kept, so what's signed with
+    // it stays valid across restarts
+    pub fn secret(&self, name: &str) -> Result<Vec<u8>> {
+        let conn = self.conn.lock().unwrap();
+        let existing: Option<Vec<u8>> = conn
+            .query_row("SELECT key FROM secrets WHERE name = ?1", params![name], |row| row.get(0))
+            .optional()?;
+        if let Some(key) = existing {
+            return Ok(key);
+        }
+        let mut key = vec![0; 32];
+        getrandom::getrandom(&mut key).context("Failed to get random bytes")?;
+        conn.execute("INSERT INTO secrets (name, key) VALUES (?1, ?2)", params![name, key])
+            .context("Failed to save the secret")?;
+        Ok(key)
+    }
+
+    // Returns the new subscription's ID, or None when the channel already follows the feed
+    pub fn subscribe(&self, subscription: &Subscription) -> Result<Option<i64>> {
+        let conn = self.conn.lock().unwrap();
+        let added = conn
+            .execute(
+                "INSERT OR IGNORE INTO subscriptions (url, title, guild_id, channel_id, requester, format, created_at, last_checked)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+                params![
+                    subscription.url,
+                    subscription.title,
+                    subscription.guild.map(|guild| guild.get() as i64),
+                    subscription.channel.get() as i64,
+                    subscription.requester.get() as i64,
+                    subscription.format,
+                    subscription.created_at,
+                    subscription.last_checked,
+                ],
+            )
+            .context("Failed to save subscription")?;
+        Ok((added > 0).then(|| conn.last_insert_rowid()))
+    }
+
+    pub fn unsubscribe(&self, id: i64) -> Result<bool> {
+        let conn = self.conn.lock().unwrap();
+        let removed = conn
+            .execute("DELETE FROM subscriptions WHERE id = ?1", params![id])
+            .context("Failed to remove subscription")?;
+        conn.execute("DELETE FROM subscription_episodes WHERE subscription_id = ?1", params![id])
+            .context("Failed to remove subscription")?;
+        Ok(removed > 0)
+    }
+
+    pub fn subscriptions(&self) -> Result<Vec<Subscription>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT id, url, title, guild_id, channel_id, requester, format, created_at, last_checked, last_error
+             FROM subscriptions ORDER BY id",
+        )?;
+        let subscriptions = stmt
+            .query_map([], |row| {
+                Ok(Subscription {
+                    id: row.get(0)?,
+                    url: row.get(1)?,
+                    title: row.get(2)?,
+                    guild: row.get::<_, Option<i64>>(3)?.map(|guild| GuildId::new(guild as u64)),
+                    channel: ChannelId::new(row.get::<_, i64>(4)? as u64),
+                    requester: UserId::new(row.get::<_, i64>(5)? as u64),
+                    format: row.get(6)?,
+                    created_at: row.get(7)?,
+                    last_checked: row.get(8)?,
+                    last_error: row.get(9)?,
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(subscriptions)
+    }
+
+    // Which of a feed's episodes were seen before, so each is only downloaded once
+    pub fn seen_episodes(&self, id: i64) -> Result<HashSet<String>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare("SELECT guid FROM subscription_episodes WHERE subscription_id = ?1")?;
+        let seen = stmt
+            .query_map(params![id], |row| row.get(0))?
+            .collect::<rusqlite::Result<HashSet<String>>>()?;
+        Ok(seen)
+    }
+
+    pub fn mark_episodes_seen(&self, id: i64, guids: &[&str]) -> Result<()> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        for guid in guids {
+            tx.execute(
+                "INSERT OR IGNORE INTO subscription_episodes (subscription_id, guid, seen_at) VALUES (?1, ?2, ?3)",
+                params![id, guid, now()],
+            )?;
+        }
+        tx.commit().context("Failed to save seen episodes")?;
+        Ok(())
+    }
+
+    pub fn set_subscription_checked(&self, id: i64, title: Option<&str>, error: Option<&str>) {
+        self.execute(
+            "UPDATE subscriptions SET last_checked = ?2, title = COALESCE(?3, title), last_error = ?4 WHERE id = ?1",
+            params![id, now(), title, error],
+        );
+    }
+
+    pub fn recent_failures(&self, limit: usize) -> Result<Vec<Failure>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT job_id, requester, url, finished_at, error FROM downloads
+             WHERE status = ?1 ORDER BY job_id DESC LIMIT ?2",
+        )?;
+        let failures = stmt
+            .query_map(params![Status::Failed.as_str(), limit as i64], |row| {
+                Ok(Failure {
+                    job_id: row.get::<_, i64>(0)? as JobId,
+                    requester: (row.get::<_, i64>(1)? as u64).to_string(),
+                    url: row.get(2)?,
+                    finished_at: row.get(3)?,
+                    error: row.get(4)?,
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(failures)
+    }
+
+    pub fn totals_since(&self, since: i64) -> Result<Totals> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT status, COUNT(*), COALESCE(SUM(file_size), 0) FROM downloads
+             WHERE requested_at >= ?1 GROUP BY status",
+        )?;
+        let mut rows = stmt.query(params![since])?;
+        let mut totals = Totals::default();
+        while let Some(row) = rows.next()? {
+            let status: String = row.get(0)?;
+            let count = row.get::<_, i64>(1)? as u64;
+            if status == Status::Done.as_str() {
+                totals.done = count;
+                totals.bytes = row.get::<_, i64>(2)? as u64;
+            } else if status == Status::Failed.as_str() {
+                totals.failed = count;
+            } else if status == Status::Cancelled.as_str() {
+                totals.cancelled = count;
+            }
+        }
+        Ok(totals)
+    }
+
+    // Returns one page of the entries matching the filter, newest first, and how many match in total
+    pub fn page(&self, filter: &Filter, page: usize, per_page: usize) -> Result<(Vec<Entry>, usize)> {
+        let conn = self.conn.lock().unwrap();
+        let requester = filter.requester.map(|id| id.get() as i64);
+        let term = filter.term.as_deref().map(|term| format!("%{}%", escape_like(term)));
+        let domain = filter.domain.as_deref().map(str::to_lowercase);
+        let viewer = filter.seen_by.map(|viewer| viewer.user.get() as i64);
+        let guild = filter.seen_by.and_then(|viewer| viewer.guild).map(|id| id.get() as i64);
+        let conditions = "(?1 IS NULL OR requester = ?1)
+             AND (?2 IS NULL OR title LIKE ?2 ESCAPE '\\' OR uploader LIKE ?2 ESCAPE '\\' OR url LIKE ?2 ESCAPE '\\')
+             AND (?3 IS NULL OR domain = ?3 OR substr(domain, -length(?3) - 1) = '.' || ?3)
+             AND (?4 IS NULL OR requested_at >= ?4)
+             AND (?5 IS NULL OR requested_at < ?5)
+             AND (?6 IS NULL OR requester = ?6 OR (guild_id = ?7 AND NOT private))";
+        let total: i64 = conn.query_row(
+            &format!("SELECT COUNT(*) FROM downloads WHERE {}", conditions),
+            params![requester, term, domain, filter.since, filter.until, viewer, guild],
+            |row| row.get(0),
+        )?;
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM downloads WHERE {} ORDER BY job_id DESC LIMIT ?8 OFFSET ?9",
+            ENTRY_COLUMNS, conditions
+        ))?;
+        let entries = stmt
+            .query_map(
+                params![requester, term, domain, filter.since, filter.until, viewer, guild, per_page as i64, (page * per_page) as i64],
+                entry_from_row,
+            )?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok((entries, total as usize))
+    }
+
+    pub fn entry(&self, job_id: JobId) -> Result<Option<Entry>> {
+        let conn = self.conn.lock().unwrap();
+        let entry = conn
+            .query_row(
+                &format!("SELECT {} FROM downloads WHERE job_id = ?1", ENTRY_COLUMNS),
+                params![job_id as i64],
+                entry_from_row,
+            )
+            .optional()?;
+        Ok(entry)
+    }
+
+    pub fn set_pinned(&self, job_id: JobId, pinned: bool) -> Result<()> {
+        self.conn.lock().unwrap()
+            .execute("UPDATE downloads SET pinned = ?2 WHERE job_id = ?1", params![job_id as i64, pinned])
+            .context("Failed to update history database")?;
+        Ok(())
+    }
+
+    pub fn stored_files(&self) -> Result<Vec<StoredFile>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT job_id, output_path, pinned FROM downloads
+             WHERE status = ?1 AND output_path IS NOT NULL AND deleted_at IS NULL",
+        )?;
+        let files = stmt
+            .query_map(params![Status::Done.as_str()], |row| {
+                Ok(StoredFile {
+                    job_id: row.get::<_, i64>(0)? as JobId,
+                    output_path: row.get(1)?,
+                    pinned: row.get(2)?,
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(files)
+    }
+
+    // What the job's downloader pulled over the network, whether or not it succeeded
+    pub fn set_transferred(&self, job_id: JobId, bytes: u64) {
+        self.execute(
+            "UPDATE downloads SET transferred = ?2 WHERE job_id = ?1",
+            params![job_id as i64, bytes as i64],
+        );
+    }
+
+    // Usage of the downloads requested since `since`, in one guild if given. Downloads from
+    // before transfers were counted go by the size of their files.
+    pub fn usage_since(&self, since: i64, guild: Option<GuildId>, top: usize) -> Result<Usage> {
+        let conn = self.conn.lock().unwrap();
+        let bytes = "COALESCE(transferred, CASE WHEN status = ?3 THEN file_size END, 0)";
+        let conditions = "requested_at >= ?1 AND (?2 IS NULL OR guild_id = ?2)";
+        let guild = guild.map(|id| id.get() as i64);
+        let (total, jobs): (i64, i64) = conn.query_row(
+            &format!("SELECT COALESCE(SUM({}), 0), COUNT(*) FROM downloads WHERE {}", bytes, conditions),
+            params![since, guild, Status::Done.as_str()],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+        let grouped = |column: &str| -> rusqlite::Result<Vec<(Option<i64>, u64)>> {
+            let mut stmt = conn.prepare(&format!(
+                "SELECT {0}, SUM({1}) AS bytes FROM downloads WHERE {2} GROUP BY {0} ORDER BY bytes DESC LIMIT ?4",
+                column, bytes, conditions
+            ))?;
+            let rows = stmt
+                .query_map(params![since, guild, Status::Done.as_str(), top as i64], |row| {
+                    Ok((row.get(0)?, row.get::<_, i64>(1)? as u64))
+                })?
+                .collect();
+            rows
+        };
+        let by_guild = grouped("guild_id")?
+            .into_iter()
+            .map(|(id, bytes)| (id.map(|id| GuildId::new(id as u64)), bytes))
+            .collect();
+        let by_user = grouped("requester")?
+            .into_iter()
+            .filter_map(|(id, bytes)| Some((UserId::new(id? as u64), bytes)))
+            .collect();
+        Ok(Usage { total: total as u64, jobs: jobs as u64, by_guild, by_user })
+    }
+
+    pub fn stats_since(&self, since: i64, guild: Option<GuildId>, top: usize) -> Result<Stats> {
+        let conn = self.conn.lock().unwrap();
+        let bytes = "COALESCE(transferred, CASE WHEN status = ?3 THEN file_size END, 0)";
+        let conditions = "requested_at >= ?1 AND (?2 IS NULL OR guild_id = ?2)";
+        let guild = guild.map(|id| id.get() as i64);
+        let (total, jobs): (i64, i64) = conn.query_row(
+            &format!("SELECT COALESCE(SUM({}), 0), COUNT(*) FROM downloads WHERE {}", bytes, conditions),
+            params![since, guild, Status::Done.as_str()],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+        let mut stmt = conn.prepare(&format!(
+            "SELECT requester, COUNT(*) AS jobs, SUM({0}) FROM downloads WHERE {1}
+             GROUP BY requester ORDER BY jobs DESC LIMIT ?4",
+            bytes, conditions
+        ))?;
+        let by_user = stmt
+            .query_map(params![since, guild, Status::Done.as_str(), top as i64], |row| {
+                Ok((UserId::new(row.get::<_, i64>(0)? as u64), row.get::<_, i64>(1)? as u64, row.get::<_, i64>(2)? as u64))
+            })?
+            .collect::<rusqlite::Result<_>>()?;
+        let mut stmt = conn.prepare(&format!(
+            "SELECT domain, COUNT(*) AS jobs, SUM({0}) FROM downloads WHERE {1} AND domain IS NOT NULL
+             GROUP BY domain ORDER BY jobs DESC LIMIT ?4",
+            bytes, conditions
+        ))?;
+        let by_domain = stmt
+            .query_map(params![since, guild, Status::Done.as_str(), top as i64], |row| {
+                Ok((row.get(0)?, row.get::<_, i64>(1)? as u64, row.get::<_, i64>(2)? as u64))
+            })?
+            .collect::<rusqlite::Result<_>>()?;
+        let mut stmt = conn.prepare(&format!(
+            "SELECT (requested_at - ?4) / 604800 AS week, SUM({0}) FROM downloads WHERE {1}
+             GROUP BY week ORDER BY week",
+            bytes, conditions
+        ))?;
+        let by_week = stmt
+            .query_map(params![since, guild, Status::Done.as_str(), WEEK_OFFSET], |row| {
+                Ok((row.get::<_, i64>(0)? * 604_800 + WEEK_OFFSET, row.get::<_, i64>(1)? as u64))
+            })?
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(Stats { jobs: jobs as u64, total: total as u64, by_user, by_domain, by_week })
+    }
+
+    // Every indexed file's size and modification time, by path
+    pub fn indexed_files(&self) -> Result<HashMap<String, (u64, i64)>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare("SELECT path, size, modified FROM file_hashes")?;
+        let files = stmt
+            .query_map([], |row| Ok((row.get(0)?, (row.get::<_, i64>(1)? as u64, row.get(2)?))))?
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(files)
+    }
+
+    pub fn index_file(&self, path: &Path, size: u64, modified: i64, sha256: &str) {
+        self.execute(
+            "INSERT OR REPLACE INTO file_hashes (path, size, modified, sha256) VALUES (?1, ?2, ?3, ?4)",
+            params![path.to_string_lossy(), size as i64, modified, sha256],
+        );
+    }
+
+    pub fn unindex_files(&self, paths: &[String]) -> Result<()> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        for path in paths {
+            tx.execute("DELETE FROM file_hashes WHERE path = ?1", params![path])?;
+        }
+        tx.commit().context("Failed to forget indexed files")?;
+        Ok(())
+    }
+
+    // The indexed files with this content, and their modification times when they were hashed
+    pub fn files_with_hash(&self, sha256: &str, size: u64) -> Result<Vec<(PathBuf, i64)>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare("SELECT path, modified FROM file_hashes WHERE sha256 = ?1 AND size = ?2 ORDER BY path")?;
+        let files = stmt
+            .query_map(params![sha256, size as i64], |row| Ok((PathBuf::from(row.get::<_, String>(0)?), row.get(1)?)))?
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(files)
+    }
+
+    pub fn mark_deleted(&self, job_id: JobId) {
+        self.execute(
+            "UPDATE downloads SET deleted_at = ?2 WHERE job_id = ?1",
+            params![job_id as i64, now()],
+        );
+    }
+
+    // History is best-effort: a failed write is logged but never fails the download
+    fn execute(&self, sql: &str, params: impl rusqlite::Params) {
+        if let Err(e) = self.conn.lock().unwrap().execute(sql, params) {
+            log::error!("Failed to update history database: {}", e);
+        }
+    }
+}
+
+const ENTRY_COLUMNS: &str =
+    "job_id, requester, url, format, status, requested_at, output_path, file_size, pinned, deleted_at, title, uploader, guild_id, private";
+
+fn entry_from_row(row: &rusqlite::Row<'_>) -> rusqlite::Result<Entry> {
+    Ok(Entry {
+        job_id: row.get::<_, i64>(0)? as JobId,
+        requester: UserId::new(row.get::<_, i64>(1)? as u64),
+        url: row.get(2)?,
+        format: row.get(3)?,
+        status: row.get(4)?,
+        requested_at: row.get(5)?,
+        output_path: row.get(6)?,
+        file_size: row.get::<_, Option<i64>>(7)?.map(|size| size as u64),
+        pinned: row.get(8)?,
+        deleted: row.get::<_, Option<i64>>(9)?.is_some(),
+        title: row.get(10)?,
+        uploader: row.get(11)?,
+        guild: row.get::<_, Option<i64>>(12)?.map(|id| GuildId::new(id as u64)),
+        private: row.get(13)?,
+    })
+}
+
+// Fills in the searched columns of downloads recorded before they existed, from the saved requests
+fn backfill_search_columns(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        "UPDATE downloads SET
+             title = json_extract(request, '$.metadata.title'),
+             uploader = json_extract(request, '$.metadata.uploader')
+         WHERE json_valid(request);",
+    )?;
+    let mut stmt = conn.prepare("SELECT job_id, url FROM downloads")?;
+    let urls = stmt
+        .query_map([], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?)))?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    for (job_id, url) in urls {
+        conn.execute("UPDATE downloads SET domain = ?2 WHERE job_id = ?1", params![job_id, crate::url_host(&url)])?;
+    }
+    Ok(())
+}
+
+// So a search term's own % and _ are matched literally
+fn escape_like(term: &str) -> String {
+    term.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_")
+}
+
+pub fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs() as i64)
+        .unwrap_or_default()
+}
@@ -0,0 +1,92 @@
+use anyhow::Result;
+use serenity::model::id::{ChannelId, UserId};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, TryLockError};
+
+use crate::jobs::JobId;
+use crate::playlist::PlaylistItem;
+
+// Someone who asked for what a job was already downloading
+pub struct Waiter {
+    pub requester: UserId,
+    // Where they're told about it, unless it goes to them alone
+    pub channel: ChannelId,
+    pub private: bool,
+    // For a playlist entry, which is finished with the job's outcome instead of being told
+    pub item: Option<PlaylistItem>,
+}
+
+pub enum Claimed {
+    // Another job downloads it, and the waiter was added to it
+    Joined(JobId),
+    // A new job was started for it, at this queue position
+    Started(JobId, usize),
+}
+
+// Jobs still running by what they download, so the same request made meanwhile joins the
+// job instead of running yt-dlp twice
+#[derive(Default)]
+pub struct InFlight {
+    jobs: Mutex<HashMap<String, (JobId, Vec<Waiter>)>>,
+}
+
+impl InFlight {
+    // Joins the job already downloading `key`, or starts one with `start`, which gets the
+    // waiter back for what it carries. The lock is held throughout, so two requests at once
+    // can't both start a job.
+    pub fn join_or_start(&self, key: String, waiter: Waiter, start: impl FnOnce(Waiter) -> Result<(JobId, usize)>) -> Result<Claimed> {
+        let mut jobs = self.jobs.lock().unwrap();
+        if let Some((id, waiters)) = jobs.get_mut(&key) {
+            waiters.push(waiter);
+            return Ok(Claimed::Joined(*id));
+        }
+        let (id, position) = start(waiter)?;
+        jobs.insert(key, (id, Vec::new()));
+        Ok(Claimed::Started(id, position))
+    }
+
+    // Stops tracking the job, returning who joined it
+    pub fn finish(&self, id: JobId) -> Vec<Waiter> {
+        let mut jobs = self.jobs.lock().unwrap();
+        let Some(key) = jobs.iter().find(|(_, (job, _))| *job == id).map(|(key, _)| key.clone()) else {
+            return Vec::new();
+        };
+        jobs.remove(&key).map(|(_, waiters)| waiters).unwrap_or_default()
+    }
+
+    // Kept by the job, so one that panics or is dropped before it runs doesn't leave later
+    // requests joining it forever
+    pub fn release_on_drop(self: &Arc<Self>, id: JobId) -> Release {
+        Release { in_flight: Arc::clone(self), id }
+    }
+}
+
+pub struct Release {
+    in_flight: Arc<InFlight>,
+    id: JobId,
+}
+
+impl Drop for Release {
+    // A job that couldn't be queued is dropped while join_or_start holds the lock, so it's
+    // finished once that's let go rather than waited for here
+    fn drop(&mut self) {
+        let (in_flight, id) = (Arc::clone(&self.in_flight), self.id);
+        let release = move || {
+            let waiters = in_flight.finish(id);
+            if !waiters.is_empty() {
+                log::warn!("Job #{} ended without telling the {} request(s) that joined it", id, waiters.len());
+            }
+        };
+        match self.in_flight.jobs.try_lock() {
+            Err(TryLockError::WouldBlock) => {
+                if let Ok(runtime) = tokio::runtime::Handle::try_current() {
+                    runtime.spawn(async move { release() });
+                }
+            }
+            locked => {
+                drop(locked);
+                release();
+            }
+        }
+    }
+}
@@ -0,0 +1,241 @@
+use anyhow::{Context, Result};
+use log::{error, info, warn};
+use serde::Deserialize;
+use serenity::http::Http;
+use serenity::model::channel::{Attachment, Channel, Message};
+use serenity::model::id::{ChannelId, GuildId};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use crate::format::FormatSpec;
+use crate::history;
+use crate::playlist::Playlist;
+use crate::queue::Priority;
+use crate::ytdlp::Metadata;
+use crate::{is_valid_url, send_status, DownloadRequest, Handler, Submitted};
+
+// URLs taken from one list; the rest are only counted
+const MAX_LIST_URLS: usize = 1000;
+
+// Bigger attachments aren't read
+const MAX_LIST_BYTES: u32 = 1024 * 1024;
+
+// A file whose last change is more recent than this may still be being written
+const SETTLE_TIME: Duration = Duration::from_secs(5);
+
+// Where ingested lists are moved to, inside the watched folder
+const DONE_DIR: &str = "done";
+
+// The [watch] section of the config
+#[derive(Debug, Clone, Deserialize)]
+pub struct WatchSettings {
+    pub dir: String,
+    // Channel ID the summaries are posted to; its guild's settings apply to the downloads
+    pub channel: u64,
+    pub format: Option<FormatSpec>,
+    #[serde(default = "default_interval_secs")]
+    pub interval_secs: u64,
+}
+
+fn default_interval_secs() -> u64 {
+    30
+}
+
+pub fn is_list(name: &str) -> bool {
+    let name = name.to_ascii_lowercase();
+    name.ends_with(".txt") || name.ends_with(".csv")
+}
+
+// Lists attached to a message may also go by their content type, e.g. text pasted into Discord
+// that it turned into a file
+pub fn is_list_attachment(attachment: &Attachment) -> bool {
+    let content_type = attachment.content_type.as_deref().unwrap_or_default();
+    is_list(&attachment.filename) || content_type.starts_with("text/plain") || content_type.starts_with("text/csv")
+}
+
+// The first http(s) URL on each line, so plain lists and CSV exports with a URL column both
+// work. Blank lines, `#` comments and repeats are skipped.
+pub fn parse_list(text: &str) -> Vec<String> {
+    let mut seen = HashSet::new();
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| {
+            line.split([',', ';', '\t', ' '])
+                .map(|field| field.trim().trim_matches(['"', '\'']))
+                .find(|field| {
+                    let field = field.to_ascii_lowercase();
+                    field.starts_with("http://") || field.starts_with("https://")
+                })
+        })
+        .filter(|url| seen.insert(*url))
+        .map(str::to_owned)
+        .collect()
+}
+
+// Ingests every list dropped into the folder, forever
+pub async fn run(handler: Arc<Handler>, http: Arc<Http>, settings: WatchSettings) {
+    let dir = PathBuf::from(&settings.dir);
+    if let Err(e) = std::fs::create_dir_all(dir.join(DONE_DIR)) {
+        error!("Not watching {} for URL lists: {}", dir.display(), e);
+        return;
+    }
+    info!("Watching {} for URL lists", dir.display());
+    let mut interval = tokio::time::interval(Duration::from_secs(settings.interval_secs.max(1)));
+    loop {
+        interval.tick().await;
+        for path in settled_lists(&dir) {
+            if let Err(e) = handler.ingest_file(&http, &settings, &path).await {
+                error!("Failed to ingest {}: {:#}", path.display(), e);
+            }
+        }
+    }
+}
+
+// Lists in the folder that nothing has written to for a while
+fn settled_lists(dir: &Path) -> Vec<PathBuf> {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            warn!("Failed to read {}: {}", dir.display(), e);
+            return Vec::new();
+        }
+    };
+    let now = SystemTime::now();
+    let mut lists: Vec<PathBuf> = entries
+        .flatten()
+        .filter(|entry| is_list(&entry.file_name().to_string_lossy()))
+        .filter(|entry| {
+            entry.metadata().is_ok_and(|metadata| {
+                metadata.is_file() && metadata.modified().is_ok_and(|modified| {
+                    now.duration_since(modified).is_ok_and(|age| age >= SETTLE_TIME)
+                })
+            })
+        })
+        .map(|entry| entry.path())
+        .collect();
+    lists.sort();
+    lists
+}
+
+impl Handler {
+    async fn ingest_file(&self, http: &Arc<Http>, settings: &WatchSettings, path: &Path) -> Result<()> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let name = path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+        // Moved out of the way first, so a list is never queued twice
+        let done = path.with_file_name(DONE_DIR).join(format!("{}-{}", history::now(), name));
+        std::fs::rename(path, &done)
+            .with_context(|| format!("Failed to move {} to {}", path.display(), done.display()))?;
+        let bot = http.get_current_user().await.context("Failed to look up the bot's user")?;
+        let channel = ChannelId::new(settings.channel);
+        let guild = match channel.to_channel(http).await {
+            Ok(Channel::Guild(channel)) => Some(channel.guild_id),
+            Ok(_) => None,
+            Err(e) => {
+                warn!("Failed to look up channel {}: {}", channel, e);
+                None
+            }
+        };
+        info!(target: "audit", "Ingesting {} from the watch folder", name);
+        let template = DownloadRequest {
+            url: String::new(),
+            requester: bot.id,
+            requester_name: bot.name.clone(),
+            requester_avatar: Some(bot.face()),
+            channel,
+            guild,
+            format: self.resolve_format(settings.format, guild, channel),
+            force: false,
+            archive_key: None,
+            playlist: None,
+            subtitles: None,
+            clip: None,
+            metadata: Metadata::default(),
+            dm: false,
+            live: false,
+            schedule: None,
+            backend: None,
+            split_chapters: false,
+            from_api: false,
+            thread: None,
+            extra_args: Vec::new(),
+            format_id: None,
+            workaround: None,
+            // Nobody is waiting on them
+            priority: Priority::Low,
+            geo_country: None,
+            subfolder: None,
+            spoiler: false,
+        };
+        self.ingest(http, channel, guild, &name, &text, |url| Ok(DownloadRequest { url: url.to_owned(), ..template.clone() })).await;
+        Ok(())
+    }
+
+    // A list attached to a message, with the message's words applying to each of its URLs
+    // like they would to a link
+    pub(crate) async fn ingest_attachment(&self, http: &Arc<Http>, msg: &Message, attachment: &Attachment, trusted: bool, priority: Priority) {
+        let messages = self.messages(msg.guild_id);
+        if attachment.size > MAX_LIST_BYTES {
+            let _ = msg.channel_id.say(http, messages.get("list_too_big", &[("name", &attachment.filename)])).await;
+            return;
+        }
+        let text = match attachment.download().await {
+            Ok(bytes) => String::from_utf8_lossy(&bytes).into_owned(),
+            Err(e) => {
+                error!("Failed to download attachment {}: {}", attachment.url, e);
+                let _ = msg.channel_id.say(http, messages.get("list_unreadable", &[("name", &attachment.filename)])).await;
+                return;
+            }
+        };
+        let options = msg.content.trim_start();
+        let options = options.strip_prefix("!dl").unwrap_or(options);
+        self.ingest(http, msg.channel_id, msg.guild_id, &attachment.filename, &text, |url| {
+            self.parse_request(msg, url, "", options, trusted)
+                .map(|request| DownloadRequest { priority, ..request })
+        })
+        .await;
+    }
+
+    // Queues each URL of a list as a job of its own. They're reported on together like a
+    // playlist's entries: one message counting them as they finish, then a summary.
+    async fn ingest<F>(&self, http: &Arc<Http>, channel: ChannelId, guild: Option<GuildId>, name: &str, text: &str, request_for: F)
+    where
+        F: Fn(&str) -> Result<DownloadRequest, String>,
+    {
+        let messages = self.messages(guild);
+        let urls = parse_list(text);
+        if urls.is_empty() {
+            let _ = channel.say(http, messages.get("list_empty", &[("name", &name)])).await;
+            return;
+        }
+        let taken = urls.len().min(MAX_LIST_URLS);
+        let mut text = messages.get("list_queuing", &[("count", &taken), ("name", &name)]);
+        if urls.len() > taken {
+            text.push(' ');
+            text.push_str(&messages.get("list_truncated", &[("max", &MAX_LIST_URLS), ("left", &(urls.len() - taken))]));
+        }
+        let status = send_status(http, channel, text).await;
+        let list = Playlist::new(name.to_string(), taken, channel, Arc::clone(http), status, self.zip_for(channel, None), messages.clone());
+        for url in &urls[..taken] {
+            let request = if is_valid_url(url) { request_for(url) } else { Err(messages.get("item_invalid_url", &[])) };
+            let result = match request {
+                Ok(request) => {
+                    let request = DownloadRequest { playlist: Some(name.to_string()), ..request };
+                    self.submit_as(http, request, None, Some(&list)).await.map_err(|e| e.to_string())
+                }
+                Err(reason) => Err(reason),
+            };
+            match result {
+                // The job reports back to the list itself
+                Ok(Submitted::Job { .. }) => {}
+                // A playlist has a summary of its own
+                Ok(Submitted::Playlist { .. } | Submitted::Duplicate(_) | Submitted::Joined { .. }) => list.item(url.clone()).finish(Ok(Vec::new())).await,
+                Err(reason) => list.item(url.clone()).finish(Err(reason)).await,
+            }
+        }
+        info!("Queued the {} URL(s) of {}", taken, name);
+    }
+}
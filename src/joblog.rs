@@ -0,0 +1,98 @@
+use anyhow::{Context, Result};
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use crate::history;
+use crate::jobs::JobId;
+
+// Logs stop growing past this, keeping how the run started; it's also under Discord's
+// upload limit, so /log can always attach one
+const MAX_LOG_BYTES: u64 = 8 * 1024 * 1024;
+
+// A file per job with everything its downloader printed, keeping the newest `keep` of them
+pub struct JobLogs {
+    dir: PathBuf,
+    keep: usize,
+}
+
+impl JobLogs {
+    pub fn new(dir: &str, keep: usize) -> Result<Self> {
+        if keep > 0 {
+            fs::create_dir_all(dir).with_context(|| format!("Failed to create job log directory: {}", dir))?;
+        }
+        Ok(JobLogs { dir: PathBuf::from(dir), keep })
+    }
+
+    pub fn path(&self, id: JobId) -> PathBuf {
+        self.dir.join(format!("job-{}.log", id))
+    }
+
+    // The job's log, appended to by each of its runs. Not having one only means less to go
+    // on when the job fails, so errors are just logged.
+    pub fn open(&self, id: JobId) -> Option<JobLog> {
+        if self.keep == 0 {
+            return None;
+        }
+        let path = self.path(id);
+        let file = match OpenOptions::new().create(true).append(true).open(&path) {
+            Ok(file) => file,
+            Err(e) => {
+                log::warn!("Failed to open {}: {}", path.display(), e);
+                return None;
+            }
+        };
+        let written = file.metadata().map(|metadata| metadata.len()).unwrap_or_default();
+        self.rotate();
+        Some(JobLog(Arc::new(Mutex::new(LogFile { file, written }))))
+    }
+
+    // Removes the oldest logs past `keep`
+    fn rotate(&self) {
+        let Ok(entries) = fs::read_dir(&self.dir) else {
+            return;
+        };
+        let mut logs: Vec<(std::time::SystemTime, PathBuf)> = entries
+            .flatten()
+            .filter(|entry| entry.file_name().to_string_lossy().starts_with("job-"))
+            .filter_map(|entry| Some((entry.metadata().ok()?.modified().ok()?, entry.path())))
+            .collect();
+        if logs.len() <= self.keep {
+            return;
+        }
+        logs.sort();
+        for (_, path) in &logs[..logs.len() - self.keep] {
+            if let Err(e) = fs::remove_file(path) {
+                log::warn!("Failed to remove old job log {}: {}", path.display(), e);
+            }
+        }
+    }
+}
+
+struct LogFile {
+    file: File,
+    written: u64,
+}
+
+// Shared by the tasks reading a process's stdout and stderr
+#[derive(Clone)]
+pub struct JobLog(Arc<Mutex<LogFile>>);
+
+impl JobLog {
+    // `source` says where the line came from, e.g. "stderr"
+    pub fn line(&self, source: &str, line: &str) {
+        let mut log = self.0.lock().unwrap();
+        if log.written >= MAX_LOG_BYTES {
+            return;
+        }
+        let line = format!("{} {}: {}\n", history::now(), source, line);
+        if log.written + line.len() as u64 > MAX_LOG_BYTES {
+            log.written = MAX_LOG_BYTES;
+            let _ = writeln!(log.file, "[log truncated]");
+            return;
+        }
+        log.written += line.len() as u64;
+        let _ = log.file.write_all(line.as_bytes());
+    }
+}
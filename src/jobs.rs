@@ -0,0 +1,181 @@
+use serenity::model::id::{ChannelId, GuildId, MessageId, UserId};
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use tokio::sync::{watch, Notify};
+
+use crate::progress::Progress;
+
+pub type JobId = u64;
+pub type JobFuture = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JobState {
+    Queued,
+    Running,
+}
+
+#[derive(Debug, Clone)]
+pub struct JobInfo {
+    pub id: JobId,
+    pub url: String,
+    pub requester: UserId,
+    pub channel: ChannelId,
+    pub guild: Option<GuildId>,
+    // Whether the results go to the requester alone
+    pub private: bool,
+    // The bot message reporting on this job; its Cancel button or reacting ❌ to it cancels the job
+    pub message: Option<MessageId>,
+    pub started: Instant,
+    pub state: JobState,
+    // Latest progress reported by yt-dlp while the job runs
+    pub progress: watch::Receiver<Option<Progress>>,
+    // Set for live streams; notifying it ends the recording and keeps what was recorded
+    pub recording: Option<Arc<Notify>>,
+}
+
+impl JobInfo {
+    // Others in the same guild may see it unless it's private; DM jobs have no guild
+    pub fn visible_to(&self, user: UserId, guild: Option<GuildId>) -> bool {
+        self.requester == user || guild.is_some() && self.guild == guild && !self.private
+    }
+
+    pub fn progress(&self) -> Option<Progress> {
+        *self.progress.borrow()
+    }
+
+    // Returns false if the job isn't recording a live stream
+    pub fn stop_recording(&self) -> bool {
+        match &self.recording {
+            Some(stop) => {
+                stop.notify_one();
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CancelReason {
+    User(UserId),
+    // The bot is shutting down and the grace period ran out
+    Shutdown,
+}
+
+// Resolves once the job is cancelled, yielding why.
+pub struct CancelSignal(watch::Receiver<Option<CancelReason>>);
+
+impl CancelSignal {
+    pub async fn cancelled(&mut self) -> CancelReason {
+        let reason = self.0.wait_for(Option::is_some).await.ok().and_then(|reason| *reason);
+        match reason {
+            Some(reason) => reason,
+            // The registry never drops a sender before the job is done with it
+            None => std::future::pending().await,
+        }
+    }
+}
+
+struct Entry {
+    info: JobInfo,
+    future: Option<JobFuture>,
+    cancel: watch::Sender<Option<CancelReason>>,
+}
+
+pub struct JobRegistry {
+    next_id: AtomicU64,
+    entries: Mutex<BTreeMap<JobId, Entry>>,
+}
+
+impl JobRegistry {
+    pub fn starting_after(last_id: JobId) -> Self {
+        JobRegistry {
+            next_id: AtomicU64::new(last_id),
+            entries: Mutex::default(),
+        }
+    }
+
+    pub fn next_id(&self) -> JobId {
+        self.next_id.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    // `job` builds the future that runs the job; it must watch the signal and stop
+    // early, reporting the cancellation itself, when it fires.
+    pub fn enqueue<F, Fut>(&self, mut info: JobInfo, job: F)
+    where
+        F: FnOnce(CancelSignal) -> Fut,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let (cancel, signal) = watch::channel(None);
+        info.state = JobState::Queued;
+        let entry = Entry {
+            future: Some(Box::pin(job(CancelSignal(signal)))),
+            info,
+            cancel,
+        };
+        self.entries.lock().unwrap().insert(entry.info.id, entry);
+    }
+
+    // Hands a queued job to a worker, or None if it was cancelled in the meantime.
+    pub fn start(&self, id: JobId) -> Option<JobFuture> {
+        let mut entries = self.entries.lock().unwrap();
+        let entry = entries.get_mut(&id)?;
+        let future = entry.future.take()?;
+        entry.info.state = JobState::Running;
+        entry.info.started = Instant::now();
+        Some(future)
+    }
+
+    pub fn finish(&self, id: JobId) {
+        self.entries.lock().unwrap().remove(&id);
+    }
+
+    pub fn get(&self, id: JobId) -> Option<JobInfo> {
+        self.entries.lock().unwrap().get(&id).map(|entry| entry.info.clone())
+    }
+
+    pub fn list(&self) -> Vec<JobInfo> {
+        self.entries.lock().unwrap()
+            .values()
+            .map(|entry| entry.info.clone())
+            .collect()
+    }
+
+    pub fn by_message(&self, message: MessageId) -> Vec<JobInfo> {
+        self.entries.lock().unwrap()
+            .values()
+            .filter(|entry| entry.info.message == Some(message))
+            .map(|entry| entry.info.clone())
+            .collect()
+    }
+
+    pub fn count(&self, state: JobState) -> usize {
+        self.entries.lock().unwrap()
+            .values()
+            .filter(|entry| entry.info.state == state)
+            .count()
+    }
+
+    pub fn cancel(&self, id: JobId, reason: CancelReason) -> Option<JobInfo> {
+        let mut entries = self.entries.lock().unwrap();
+        let entry = entries.get(&id)?;
+        let info = entry.info.clone();
+        entry.cancel.send_replace(Some(reason));
+        if info.state == JobState::Queued {
+            // Nothing is running yet, so report the cancellation right away instead of
+            // waiting for a worker to reach the job
+            let entry = entries.remove(&id)?;
+            if let Some(future) = entry.future {
+                tokio::spawn(async move {
+                    let _cancel = entry.cancel;
+                    future.await;
+                });
+            }
+        }
+        Some(info)
+    }
+}
@@ -0,0 +1,121 @@
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Server {
+    Jellyfin,
+    Plex,
+    // Any URL, which gets the download's directory and files POSTed to it as JSON
+    Webhook,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct LibrarySettings {
+    pub server: Server,
+    // Base URL of the server, or the webhook's URL
+    pub url: String,
+    // Jellyfin API key or Plex token
+    pub api_key: Option<String>,
+    // Directory (relative to output_dir unless absolute) -> library to scan for downloads in it:
+    // a Plex section ID or a Jellyfin library ID. Downloads elsewhere have Jellyfin scan every
+    // library, and aren't scanned for on Plex.
+    #[serde(default)]
+    pub sections: HashMap<String, String>,
+}
+
+// Asks a media server to pick up new downloads
+pub struct Library {
+    client: reqwest::Client,
+    server: Server,
+    url: String,
+    api_key: Option<String>,
+    // Longest first, so the first match is the most specific one
+    sections: Vec<(PathBuf, String)>,
+}
+
+impl Library {
+    pub fn new(output_dir: &str, settings: &LibrarySettings) -> Result<Self> {
+        if settings.server == Server::Plex && settings.api_key.is_none() {
+            bail!("library.api_key is needed for Plex");
+        }
+        let mut sections: Vec<_> = settings.sections.iter()
+            .map(|(dir, section)| (Path::new(output_dir).join(dir), section.clone()))
+            .collect();
+        sections.sort_by_key(|(dir, _)| std::cmp::Reverse(dir.as_os_str().len()));
+        Ok(Library {
+            client: reqwest::Client::new(),
+            server: settings.server,
+            url: settings.url.trim_end_matches('/').to_string(),
+            api_key: settings.api_key.clone(),
+            sections,
+        })
+    }
+
+    fn section_for(&self, dir: &Path) -> Option<&str> {
+        self.sections.iter()
+            .find(|(root, _)| dir.starts_with(root))
+            .map(|(_, section)| section.as_str())
+    }
+
+    // One scan per library the files landed in. Failures are only logged; the download
+    // itself went fine.
+    pub async fn refresh(&self, files: &[PathBuf]) {
+        let mut dirs: Vec<&Path> = files.iter().filter_map(|file| file.parent()).collect();
+        dirs.sort();
+        dirs.dedup();
+        let mut scanned: Vec<Option<&str>> = Vec::new();
+        for dir in dirs {
+            let section = self.section_for(dir);
+            // The webhook hears about every directory; the servers scan whole libraries
+            if self.server != Server::Webhook && scanned.contains(&section) {
+                continue;
+            }
+            scanned.push(section);
+            match self.request(dir, section, files).await {
+                Ok(true) => log::info!("Asked {:?} to scan for {}", self.server, dir.display()),
+                Ok(false) => {}
+                Err(e) => log::warn!("Failed to trigger a library scan for {}: {:#}", dir.display(), e),
+            }
+        }
+    }
+
+    // Returns whether a request was sent
+    async fn request(&self, dir: &Path, section: Option<&str>, files: &[PathBuf]) -> Result<bool> {
+        let request = match (self.server, section) {
+            (Server::Jellyfin, Some(library)) => self.client
+                .post(format!("{}/Items/{}/Refresh", self.url, library))
+                .query(&[("Recursive", "true")]),
+            (Server::Jellyfin, None) => self.client.post(format!("{}/Library/Refresh", self.url)),
+            (Server::Plex, Some(section)) => self.client
+                .get(format!("{}/library/sections/{}/refresh", self.url, section))
+                .query(&[("X-Plex-Token", self.api_key.as_deref().unwrap_or_default())]),
+            (Server::Plex, None) => return Ok(false),
+            (Server::Webhook, section) => {
+                let files: Vec<_> = files.iter()
+                    .filter(|file| file.parent() == Some(dir))
+                    .map(|file| file.display().to_string())
+                    .collect();
+                let body = serde_json::json!({
+                    "directory": dir.display().to_string(),
+                    "section": section,
+                    "files": files,
+                });
+                self.client.post(&self.url)
+                    .header(reqwest::header::CONTENT_TYPE, "application/json")
+                    .body(body.to_string())
+            }
+        };
+        let request = match (self.server, &self.api_key) {
+            (Server::Jellyfin, Some(key)) => request.header("X-Emby-Token", key),
+            (Server::Webhook, Some(key)) => request.bearer_auth(key),
+            _ => request,
+        };
+        request.send().await
+            .and_then(reqwest::Response::error_for_status)
+            .context("The request failed")?;
+        Ok(true)
+    }
+}
@@ -0,0 +1,83 @@
+use anyhow::Result;
+use serde::Deserialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::history::{self, History};
+use crate::messages::Messages;
+use crate::progress::format_bytes;
+use crate::storage::{hex, hmac_sha256, uri_encode};
+
+const MIB: u64 = 1024 * 1024;
+
+// The [links] section of the config
+#[derive(Debug, Clone, Deserialize)]
+pub struct LinkSettings {
+    // Where the dashboard server can be reached from, e.g. https://bot.example.com
+    pub public_url: String,
+    // Key the links are signed with; without one, a random key kept in the history database is
+    // used
+    pub secret: Option<String>,
+    #[serde(default = "default_ttl_hours")]
+    pub ttl_hours: u64,
+    // Bigger files aren't linked; 0 links any size
+    #[serde(default)]
+    pub max_mb: u64,
+}
+
+fn default_ttl_hours() -> u64 {
+    24
+}
+
+// Signs links to finished downloads, served from /files/ until they expire
+pub struct Links {
+    settings: LinkSettings,
+    secret: Vec<u8>,
+}
+
+impl Links {
+    pub fn new(settings: LinkSettings, history: &History) -> Result<Self> {
+        let secret = match &settings.secret {
+            Some(secret) => secret.clone().into_bytes(),
+            None => history.secret("links")?,
+        };
+        Ok(Links { settings, secret })
+    }
+
+    // A link to the file and when it expires as a Unix time, or None if it's too big to link
+    pub fn link(&self, file: &Path) -> Option<(String, i64)> {
+        let size = fs::metadata(file).ok()?.len();
+        if self.settings.max_mb > 0 && size > self.settings.max_mb * MIB {
+            return None;
+        }
+        let path = file.to_string_lossy();
+        let name = file.file_name().unwrap_or(file.as_os_str()).to_string_lossy();
+        let expires = history::now() + self.settings.ttl_hours as i64 * 3600;
+        let url = format!(
+            "{}/files/{}?path={}&expires={}&sig={}",
+            self.settings.public_url.trim_end_matches('/'),
+            uri_encode(&name, true),
+            uri_encode(&path, true),
+            expires,
+            self.signature(&path, expires)
+        );
+        Some((url, expires))
+    }
+
+    // What a link to `path` expiring at `expires` is signed with
+    pub fn signature(&self, path: &str, expires: i64) -> String {
+        hex(&hmac_sha256(&self.secret, format!("{}\n{}", path, expires).as_bytes()))
+    }
+
+    // A line for the result linking each file that isn't too big
+    pub fn describe(&self, files: &[PathBuf], messages: &Messages) -> Vec<String> {
+        files.iter()
+            .filter_map(|file| {
+                let (url, expires) = self.link(file)?;
+                let name = file.file_name().unwrap_or(file.as_os_str()).to_string_lossy();
+                let size = fs::metadata(file).map(|meta| format_bytes(meta.len())).unwrap_or_default();
+                Some(messages.get("download_link", &[("name", &name), ("url", &url), ("size", &size), ("expires", &expires)]))
+            })
+            .collect()
+    }
+}
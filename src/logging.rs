@@ -0,0 +1,49 @@
+use serde::Deserialize;
+use std::future::Future;
+use std::io::Write;
+
+use crate::jobs::JobId;
+
+tokio::task_local! {
+    static JOB: JobId;
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    #[default]
+    Text,
+    // One JSON object per line, for log collectors
+    Json,
+}
+
+// Runs a job's future so that everything it logs carries the job ID
+pub fn with_job<F: Future>(id: JobId, future: F) -> impl Future<Output = F::Output> {
+    JOB.scope(id, future)
+}
+
+fn current_job() -> Option<JobId> {
+    JOB.try_with(|id| *id).ok()
+}
+
+// Filtered by RUST_LOG as before
+pub fn init(format: LogFormat) {
+    let mut builder = env_logger::Builder::from_default_env();
+    match format {
+        LogFormat::Text => builder.format(|buf, record| {
+            let job = current_job().map(|id| format!(" job={}", id)).unwrap_or_default();
+            writeln!(buf, "[{} {:<5} {}{}] {}", buf.timestamp(), record.level(), record.target(), job, record.args())
+        }),
+        LogFormat::Json => builder.format(|buf, record| {
+            let line = serde_json::json!({
+                "timestamp": buf.timestamp().to_string(),
+                "level": record.level().as_str(),
+                "target": record.target(),
+                "job_id": current_job(),
+                "message": record.args().to_string(),
+            });
+            writeln!(buf, "{}", line)
+        }),
+    };
+    builder.init();
+}
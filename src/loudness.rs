@@ -0,0 +1,185 @@
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+
+use crate::ytdlp::{self, ProcessGroup, RunningProcess};
+
+const AUDIO_EXTENSIONS: &[&str] = &["mp3", "m4a", "opus", "ogg", "flac", "wav", "aac"];
+
+// The [loudness] section of the config, or a channel's loudness override
+#[derive(Debug, Clone, Deserialize)]
+pub struct LoudnessSettings {
+    // Integrated loudness to aim for, in LUFS
+    #[serde(default = "default_target")]
+    pub target: f64,
+    // Highest true peak, in dBTP
+    #[serde(default = "default_true_peak")]
+    pub true_peak: f64,
+    // Loudness range, in LU
+    #[serde(default = "default_range")]
+    pub range: f64,
+    // As ffmpeg's -b:a takes it, e.g. "192k" (default: the encoder's)
+    pub audio_bitrate: Option<String>,
+}
+
+fn default_target() -> f64 {
+    -16.0
+}
+
+fn default_true_peak() -> f64 {
+    -1.5
+}
+
+fn default_range() -> f64 {
+    11.0
+}
+
+impl LoudnessSettings {
+    fn filter(&self) -> String {
+        format!("loudnorm=I={}:TP={}:LRA={}", self.target, self.true_peak, self.range)
+    }
+}
+
+// What ffmpeg's loudnorm filter prints with print_format=json; it gives the numbers as strings
+#[derive(Debug, Deserialize)]
+struct Measurement {
+    input_i: String,
+    input_tp: String,
+    input_lra: String,
+    input_thresh: String,
+    target_offset: String,
+}
+
+// How loud a file was before and after normalizing
+#[derive(Debug, Clone)]
+pub struct Normalized {
+    pub path: PathBuf,
+    pub measured: f64,
+    pub target: f64,
+}
+
+impl Normalized {
+    pub fn describe(&self) -> String {
+        let name = self.path.file_name().unwrap_or(self.path.as_os_str()).to_string_lossy();
+        format!("`{}`: {:.1} LUFS → {:.1} LUFS", name, self.measured, self.target)
+    }
+}
+
+// Brings audio downloads to the same loudness with ffmpeg's loudnorm filter: a first pass
+// measures each file and a second applies the measurements, so levels change linearly
+// instead of being compressed on the fly
+pub struct Normalizer {
+    ffmpeg: String,
+}
+
+impl Normalizer {
+    pub fn new(ffmpeg: &str) -> Self {
+        Normalizer { ffmpeg: ffmpeg.to_string() }
+    }
+
+    // Returns the files the job ends up with and what was done to them. A file that can't be
+    // normalized is kept as it was, since the download itself worked.
+    pub async fn normalize_all(&self, files: Vec<PathBuf>, settings: &LoudnessSettings) -> (Vec<PathBuf>, Vec<Normalized>) {
+        let mut normalized = Vec::new();
+        for file in &files {
+            if !AUDIO_EXTENSIONS.contains(&extension(file).as_str()) {
+                continue;
+            }
+            match self.normalize(file, settings).await {
+                Ok(measured) => normalized.push(Normalized { path: file.clone(), measured, target: settings.target }),
+                Err(e) => log::warn!("Failed to normalize the loudness of {}: {:#}", file.display(), e),
+            }
+        }
+        (files, normalized)
+    }
+
+    // Returns the file's integrated loudness before normalizing
+    async fn normalize(&self, input: &Path, settings: &LoudnessSettings) -> Result<f64> {
+        let extension = extension(input);
+        let stem = input.file_stem().unwrap_or_default().to_string_lossy();
+        let partial = input.with_file_name(format!("{}.normalizing.{}", stem, extension));
+        log::info!("Normalizing the loudness of {}", input.display());
+        let stderr = self.run(&[
+            "-i".into(), input.as_os_str().to_string_lossy().into_owned(),
+            "-af".into(), format!("{}:print_format=json", settings.filter()),
+            "-f".into(), "null".into(), "-".into(),
+        ])
+        .await?;
+        let measurement = parse_measurement(&stderr)?;
+        let measured: f64 = measurement.input_i.parse()
+            .with_context(|| format!("ffmpeg measured an unreadable loudness: {}", measurement.input_i))?;
+        // Silence measures as -inf and can't be brought up to anything
+        if !measured.is_finite() {
+            bail!("the file is silent");
+        }
+        let filter = format!(
+            "{}:measured_I={}:measured_TP={}:measured_LRA={}:measured_thresh={}:offset={}:linear=true",
+            settings.filter(), measurement.input_i, measurement.input_tp, measurement.input_lra,
+            measurement.input_thresh, measurement.target_offset,
+        );
+        // loudnorm resamples to 192 kHz to find true peaks; 48 kHz is what every audio format takes
+        let mut args = vec![
+            "-i".into(), input.as_os_str().to_string_lossy().into_owned(),
+            "-map".into(), "0".into(), "-c".into(), "copy".into(),
+            "-af".into(), filter, "-c:a".into(), encoder(&extension).into(), "-ar".into(), "48000".into(),
+        ];
+        if let Some(bitrate) = &settings.audio_bitrate {
+            args.extend(["-b:a".into(), bitrate.clone()]);
+        }
+        args.extend(["-y".into(), partial.as_os_str().to_string_lossy().into_owned()]);
+        if let Err(e) = self.run(&args).await {
+            let _ = std::fs::remove_file(&partial);
+            return Err(e);
+        }
+        std::fs::rename(&partial, input)
+            .with_context(|| format!("Failed to move {} into place", partial.display()))?;
+        Ok(measured)
+    }
+
+    // Runs ffmpeg to completion, returning what it printed to stderr
+    async fn run(&self, args: &[String]) -> Result<String> {
+        let mut cmd = tokio::process::Command::new(&self.ffmpeg);
+        cmd.arg("-hide_banner").arg("-nostdin").arg("-nostats")
+            .arg("-loglevel").arg("info")
+            .args(args);
+        cmd.stdout(Stdio::null()).stderr(Stdio::piped());
+        cmd.kill_on_drop(true);
+        ytdlp::own_process_group(&mut cmd);
+        let child = cmd.spawn()
+            .with_context(|| format!("Failed to spawn {}", self.ffmpeg))?;
+        let mut group = ProcessGroup(child.id());
+        let _running = RunningProcess::start(group.0);
+        let output = child.wait_with_output().await.context("Failed to wait for ffmpeg");
+        group.disarm();
+        let output = output?;
+        let stderr = String::from_utf8_lossy(&output.stderr).into_owned();
+        if !output.status.success() {
+            bail!("ffmpeg failed with status: {}\nError output: {}", output.status, stderr.trim());
+        }
+        Ok(stderr)
+    }
+}
+
+// loudnorm prints its measurements as the last JSON object in ffmpeg's log
+fn parse_measurement(stderr: &str) -> Result<Measurement> {
+    let start = stderr.rfind('{').context("ffmpeg printed no loudness measurement")?;
+    let end = stderr[start..].find('}').context("ffmpeg printed no loudness measurement")?;
+    serde_json::from_str(&stderr[start..=start + end]).context("Failed to read ffmpeg's loudness measurement")
+}
+
+// Encoders for re-encoding each container after the filter, since its audio can't be copied
+fn encoder(extension: &str) -> &'static str {
+    match extension {
+        "mp3" => "libmp3lame",
+        "m4a" | "aac" => "aac",
+        "opus" => "libopus",
+        "ogg" => "libvorbis",
+        "flac" => "flac",
+        _ => "pcm_s16le",
+    }
+}
+
+fn extension(path: &Path) -> String {
+    path.extension().and_then(|extension| extension.to_str()).unwrap_or_default().to_ascii_lowercase()
+}
@@ -1,24 +1,489 @@
 use serenity::async_trait;
-use serenity::model::channel::Message;
-use serenity::model::gateway::Ready;
+use serenity::builder::{CreateMessage, CreateThread};
+use serenity::model::channel::{AutoArchiveDuration, Channel, Message, Reaction, ReactionType};
 use serenity::prelude::*;
 use regex::Regex;
-use std::process::Stdio;
 use std::fs;
 use std::env;
-use anyhow::{Result, Context as AnyhowContext};
-use serde::Deserialize;
+use anyhow::{bail, Result, Context as AnyhowContext};
+use serde::{Deserialize, Serialize};
 use config::Config;
 use log::{info, error};
 use config::Environment;
+use serenity::http::Http;
+use serenity::model::application::Interaction;
+use serenity::model::id::{ChannelId, GuildId, RoleId, UserId};
+use serenity::model::user::User;
+use std::collections::{HashMap, HashSet};
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+mod archive;
+mod archivist;
+mod auth;
+mod bandwidth;
+mod binary;
+mod bots;
+mod budget;
+mod clip;
+mod collisions;
+mod commands;
+mod confirm;
+mod cookies;
+mod dedupe;
+mod disk;
+mod domains;
+mod downloader;
+mod embed;
+mod errors;
+mod feeds;
+mod flags;
+mod format;
+mod geo;
+mod guilds;
+mod health;
+mod history;
+mod inflight;
+mod ingest;
+mod joblog;
+mod jobs;
+mod library;
+mod links;
+mod logging;
+mod loudness;
+mod messages;
+mod metrics;
+mod moderation;
+mod nfo;
+mod paths;
+mod playlist;
+mod postprocess;
+mod prefs;
+mod progress;
+mod proxy;
+mod queue;
+mod quota;
+mod reload;
+mod report;
+mod retention;
+mod retry;
+mod sandbox;
+mod scan;
+mod scheduler;
+mod site_args;
+mod stats;
+mod storage;
+mod supervisor;
+mod tagging;
+mod template;
+mod torrent;
+mod transcode;
+mod trigger;
+mod upload;
+mod usage;
+mod verify;
+mod web;
+mod webhooks;
+mod ytdlp;
+mod zip;
+
+use auth::Access;
+use bandwidth::{Allowance, Bandwidth, QuietHoursSettings};
+use bots::{Bot, BotSettings};
+use budget::Budget;
+use clip::Clip;
+use collisions::CollisionPolicy;
+use confirm::{Answer, Asked, Confirmations};
+use cookies::{CookieConfig, Cookies};
+use dedupe::DedupeSettings;
+use downloader::{Backend, Downloaders, GalleryDlSettings};
+use embed::{CardState, JobCard};
+use errors::Workaround;
+use feeds::{FeedSettings, Feeds};
+use format::{AudioFormat, FormatSpec};
+use geo::GeoSettings;
+use guilds::{GuildSettings, Guilds};
+use health::Gateway;
+use history::History;
+use inflight::{Claimed, InFlight, Waiter};
+use ingest::WatchSettings;
+use joblog::JobLogs;
+use jobs::{CancelReason, JobId, JobInfo, JobRegistry, JobState};
+use library::{Library, LibrarySettings};
+use links::{LinkSettings, Links};
+use logging::LogFormat;
+use loudness::{LoudnessSettings, Normalizer};
+use messages::{MessageSettings, Messages};
+use metrics::Metrics;
+use moderation::{Candidate, ModerationSettings, Veto};
+use playlist::{Playlist, PlaylistItem};
+use postprocess::PostProcessing;
+use prefs::Prefs;
+use progress::{format_bytes, format_duration, StatusMessage};
+use proxy::{ProxySetting, Proxies};
+use queue::{DownloadQueue, Priority};
+use quota::Quota;
+use reload::Reloadable;
+use retention::RetentionSettings;
+use retry::{RetryPolicies, RetryPolicy};
+use sandbox::SandboxSettings;
+use scan::ScanSettings;
+use scheduler::{Schedule, ScheduleSettings, ScheduledRun};
+use site_args::SiteArgs;
+use supervisor::ReconnectSettings;
+use storage::{Storage, StorageSettings};
+use tagging::{Tagger, TaggingSettings, Tags};
+use template::{OutputTemplate, TemplateValues};
+use torrent::TorrentSettings;
+use transcode::{TranscodeSettings, Transcoder};
+use trigger::Trigger;
+use usage::Meter;
+use webhooks::{JobDetails, WebhookSettings, Webhooks};
+use ytdlp::Metadata;
+use zip::{ZipSettings, Zipping, Zips};
+use tokio::sync::{watch, Notify};
+
+// Links past this many in one message are rejected
+const MAX_LINKS_PER_MESSAGE: usize = 10;
+
+// How often admins are reminded while the disk stays full
+const DISK_WARNING_INTERVAL: Duration = Duration::from_secs(60 * 60);
 
 #[derive(Debug, Deserialize)]
 struct Settings {
     discord_token: String,
+    // Gateway shards to connect with (default: as many as Discord recommends for the bot's guilds)
+    shard_count: Option<u32>,
+    // More Discord identities sharing this bot's queue, history and storage
+    #[serde(default)]
+    bots: Vec<BotSettings>,
     output_dir: String,
     guild_id: Option<u64>,
     channel_id: Option<u64>,
     cookies_path: Option<String>,
+    // Passed to yt-dlp's --cookies-from-browser when no cookies file applies, e.g. "firefox"
+    cookies_from_browser: Option<String>,
+    // Domain pattern -> cookies file for URLs on that site, instead of cookies_path
+    #[serde(default)]
+    site_cookies: HashMap<String, String>,
+    // Cookies of an account that's signed in (and old enough), offered as a retry when a
+    // download fails because the site wants one
+    login_cookies: Option<String>,
+    // yt-dlp arguments offered as another retry for those, e.g. a different YouTube player
+    // client through --extractor-args
+    #[serde(default)]
+    login_retry_args: Vec<String>,
+    #[serde(default = "default_max_concurrent_downloads")]
+    max_concurrent_downloads: usize,
+    #[serde(default)]
+    default_format: FormatSpec,
+    // Download audio instead of default_format when a request doesn't name a format
+    #[serde(default)]
+    audio_only: bool,
+    audio_format: Option<AudioFormat>,
+    // Guild ID -> format used when a request doesn't name one
+    #[serde(default)]
+    guild_formats: HashMap<String, FormatSpec>,
+    // Guild ID -> settings for that guild; with guild_id set, these guilds are allowed as well
+    #[serde(default)]
+    guilds: HashMap<String, GuildSettings>,
+    // Attach finished files to the completion message when they fit Discord's upload limit
+    #[serde(default = "default_true")]
+    upload_results: bool,
+    // Start a thread off each request's message for its jobs to post progress and results in
+    #[serde(default)]
+    job_threads: bool,
+    // Only the requester sees what was downloaded and how it went; everyone else sees the queue
+    #[serde(default)]
+    private_results: bool,
+    // Which messages are read for links: all_messages, mention_only or prefix
+    #[serde(default)]
+    trigger: Trigger,
+    // What messages start with for the prefix trigger, and to name a format explicitly
+    #[serde(default = "default_prefix")]
+    prefix: String,
+    // Answer messages without a link with "Invalid URL.", even when they weren't meant for the bot
+    #[serde(default)]
+    reply_on_no_url: bool,
+    #[serde(default = "default_database_path")]
+    database_path: String,
+    // A log file per job with its downloader's output, for /log
+    #[serde(default = "default_job_log_dir")]
+    job_log_dir: String,
+    #[serde(default = "default_job_logs_kept")]
+    job_logs_kept: usize,
+    // yt-dlp's -o template, relative to the output directory, plus the bot's own placeholders
+    output_template: Option<String>,
+    // Show/Season/Episode folders with .nfo sidecars for Jellyfin and Kodi, in place of
+    // output_template
+    #[serde(default)]
+    media_server_layout: bool,
+    // Keeps each video's info.json, thumbnail, description and comments beside it, filed
+    // away by the video's SHA-256; replaces output_template and media_server_layout
+    #[serde(default)]
+    archivist: bool,
+    // What's done when a download's file is already where the output template puts it,
+    // instead of leaving it to yt-dlp (default: yt-dlp's own handling)
+    on_collision: Option<CollisionPolicy>,
+    // Indexes output_dir by SHA-256 and replaces new downloads identical to a file already
+    // there with a link to it (default: off)
+    dedupe: Option<DedupeSettings>,
+    // yt-dlp --download-archive file, which also skips videos downloaded outside the bot
+    download_archive: Option<String>,
+    // Refuse new downloads when the output directory has less free space than this
+    min_free_bytes: Option<u64>,
+    // Also refuse downloads whose reported size would take free space below min_free_bytes
+    #[serde(default)]
+    estimate_size: bool,
+    // Ask the requester to confirm downloads the site reports as larger than this
+    confirm_above_bytes: Option<u64>,
+    // Channel ID for warnings and failure reports meant for admins, like the disk filling up
+    #[serde(alias = "admin_channel_id")]
+    admin_channel: Option<u64>,
+    // Channel ID where every finished download is posted, wherever it was requested
+    announce_channel_id: Option<u64>,
+    // yt-dlp executable to use instead of looking for it on PATH
+    ytdlp_path: Option<String>,
+    // Where yt-dlp is downloaded to when it isn't installed
+    #[serde(default = "default_ytdlp_dir")]
+    ytdlp_dir: String,
+    #[serde(default = "default_true")]
+    ytdlp_auto_download: bool,
+    // Re-queue downloads left unfinished by a restart or crash
+    #[serde(default = "default_true")]
+    resume_jobs: bool,
+    // How long a shutdown waits for running downloads before interrupting them
+    #[serde(default = "default_shutdown_grace_secs")]
+    shutdown_grace_secs: u64,
+    // Have /queue pause also suspend the running downloads instead of letting them finish
+    #[serde(default)]
+    pause_suspends_running: bool,
+    // Address for the web dashboard, e.g. "127.0.0.1:8080" (default: disabled)
+    dashboard_addr: Option<String>,
+    dashboard_token: Option<String>,
+    // Address for the /healthz and /readyz endpoints, e.g. "0.0.0.0:8081" (default: disabled)
+    health_addr: Option<String>,
+    // "text" or "json"; either way each line carries the job ID it was logged for
+    #[serde(default)]
+    log_format: LogFormat,
+    #[serde(default)]
+    retry: RetryPolicy,
+    // Backoff for restarting the Discord client after it exits with an error
+    #[serde(default)]
+    reconnect: ReconnectSettings,
+    // Domain -> retry policy for URLs on that site, replacing `retry`
+    #[serde(default)]
+    site_retries: HashMap<String, RetryPolicy>,
+    // Domain pattern -> extra yt-dlp arguments for URLs on that site
+    #[serde(default)]
+    site_args: HashMap<String, Vec<String>>,
+    // Enables gallery-dl for image hosts and URLs yt-dlp doesn't support
+    gallery_dl: Option<GalleryDlSettings>,
+    // Hands magnet links and .torrent URLs to a torrent client (default: off)
+    torrent: Option<TorrentSettings>,
+    // Proxy for every yt-dlp run, or a list to rotate through (default: none)
+    proxy: Option<ProxySetting>,
+    // Domain pattern -> proxy or list of proxies for URLs on that site, in place of proxy
+    #[serde(default)]
+    site_proxies: HashMap<String, ProxySetting>,
+    // Downloads running longer than this are stopped (default: no limit); live streams never are
+    max_download_secs: Option<u64>,
+    // Keep the .part files of downloads stopped that way instead of deleting them
+    #[serde(default)]
+    keep_partial_files: bool,
+    // Also check finished files with ffprobe, failing the job if they can't be read
+    #[serde(default)]
+    verify_with_ffprobe: bool,
+    ffprobe_path: Option<String>,
+    // ffmpeg for raw HLS and RTMP streams (default: "ffmpeg" on PATH)
+    ffmpeg_path: Option<String>,
+    // Download speed limit passed to yt-dlp's --limit-rate, e.g. "2M"
+    rate_limit: Option<String>,
+    // Fragments of HLS and DASH streams downloaded at once (default: 1)
+    concurrent_fragments: Option<u32>,
+    // Name on PATH or path of a downloader for yt-dlp to use instead of its own, e.g. "aria2c"
+    external_downloader: Option<String>,
+    // Passed to the external downloader, e.g. "-x 8 -s 8"
+    external_downloader_args: Option<String>,
+    // Times of day when downloads wait or are throttled
+    #[serde(default)]
+    quiet_hours: Vec<QuietHoursSettings>,
+    // Per-user limits on requests in the last hour and data downloaded in the last day
+    max_downloads_per_hour: Option<usize>,
+    max_gb_per_day: Option<f64>,
+    // Caps on everything each user and each channel has downloaded, in GB (default: none)
+    user_budget_gb: Option<f64>,
+    channel_budget_gb: Option<f64>,
+    // Ask admins to approve downloads over a budget instead of refusing them
+    #[serde(default)]
+    budget_approval: bool,
+    // Role IDs allowed to request downloads (default: everyone)
+    #[serde(default)]
+    allowed_roles: Vec<u64>,
+    // Role IDs that may also cancel anyone's downloads and use admin commands
+    #[serde(default)]
+    admin_roles: Vec<u64>,
+    // Role IDs that may also pass yt-dlp flags after `--`; admins always may
+    #[serde(default)]
+    trusted_roles: Vec<u64>,
+    // Role IDs whose downloads go in the high-priority lane, as admins' do
+    #[serde(default)]
+    high_priority_roles: Vec<u64>,
+    // Role IDs whose downloads go in the low-priority lane, with playlist entries
+    #[serde(default)]
+    low_priority_roles: Vec<u64>,
+    // Server boosters' downloads go in the high-priority lane too
+    #[serde(default = "default_true")]
+    boosters_high_priority: bool,
+    // The yt-dlp flags those users may pass (default: a safe set of --write-*, --embed-*,
+    // subtitle, playlist and SponsorBlock flags)
+    allowed_ytdlp_flags: Option<Vec<String>>,
+    #[serde(default)]
+    blocked_users: Vec<u64>,
+    // User IDs who may also request downloads by direct message (default: nobody)
+    #[serde(default)]
+    dm_users: Vec<u64>,
+    // Sites the bot may download from (default: all) and sites it never downloads from
+    #[serde(default)]
+    allowed_domains: Vec<String>,
+    #[serde(default)]
+    blocked_domains: Vec<String>,
+    // Checks that can refuse a download before it's queued, on top of the domains above
+    #[serde(default)]
+    moderation: ModerationSettings,
+    #[serde(default)]
+    post_processing: PostProcessing,
+    // Endpoints told about jobs starting, succeeding and failing
+    #[serde(default)]
+    webhooks: Vec<WebhookSettings>,
+    // Media server to scan for new downloads (default: none)
+    library: Option<LibrarySettings>,
+    // Re-encodes finished downloads with ffmpeg (default: off)
+    transcode: Option<TranscodeSettings>,
+    // Tags audio downloads with their artist, title, album and cover art (default: off)
+    tagging: Option<TaggingSettings>,
+    // Normalizes the loudness of audio downloads (default: off)
+    loudness: Option<LoudnessSettings>,
+    // Runs a virus scanner on finished downloads (default: off)
+    scan: Option<ScanSettings>,
+    #[serde(default = "default_max_concurrent_transcodes")]
+    max_concurrent_transcodes: usize,
+    // Isolation for the yt-dlp, gallery-dl and ffmpeg processes downloads run (default: none)
+    #[serde(default)]
+    sandbox: SandboxSettings,
+    // How yt-dlp gets past sites that only show videos in some countries (default: its own guess)
+    #[serde(default)]
+    geo: GeoSettings,
+    // Subtitle languages downloaded when a request doesn't name any (default: none)
+    subtitle_langs: Option<String>,
+    #[serde(default = "default_true")]
+    embed_subtitles: bool,
+    // Channel ID -> settings for that channel; when set, only these channels (and
+    // channel_id) are listened to
+    #[serde(default)]
+    channels: HashMap<String, ChannelSettings>,
+    // Where finished downloads are kept besides the output directory
+    #[serde(default)]
+    storage: StorageSettings,
+    // Channels or playlists downloaded again on a cron schedule to pick up new uploads
+    #[serde(default)]
+    schedules: Vec<ScheduleSettings>,
+    // How feeds followed with /subscribe are checked
+    #[serde(default)]
+    feeds: FeedSettings,
+    // The language the bot talks in and where translations or rewordings of its messages are
+    #[serde(default)]
+    messages: MessageSettings,
+    // Zipping results of several files, and linking zips too big for Discord
+    #[serde(default)]
+    zip: ZipSettings,
+    // Signed links to finished downloads, served by the dashboard server (default: none)
+    links: Option<LinkSettings>,
+    // Per-directory limits on how old and how large downloads may get before they're deleted
+    #[serde(default)]
+    retention: Vec<RetentionSettings>,
+    #[serde(default = "default_retention_interval_mins")]
+    retention_interval_mins: u64,
+    // Folder whose .txt and .csv lists of URLs are queued (default: none)
+    watch: Option<WatchSettings>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+struct ChannelSettings {
+    // Relative to output_dir unless absolute
+    output_dir: Option<String>,
+    format: Option<FormatSpec>,
+    // Replaces allowed_roles in this channel
+    allowed_roles: Option<Vec<u64>>,
+    // Replaces max_download_secs in this channel; 0 means no limit
+    max_download_secs: Option<u64>,
+    // Replaces channel_budget_gb for this channel
+    budget_gb: Option<f64>,
+    #[serde(default)]
+    post_processing: PostProcessing,
+    // Replaces transcode in this channel
+    transcode: Option<TranscodeSettings>,
+    // Replaces loudness in this channel
+    loudness: Option<LoudnessSettings>,
+    // Replaces scan in this channel
+    scan: Option<ScanSettings>,
+    // Replaces media_server_layout in this channel
+    media_server_layout: Option<bool>,
+    // Replaces archivist in this channel
+    archivist: Option<bool>,
+    // Replaces on_collision in this channel
+    on_collision: Option<CollisionPolicy>,
+    // Replaces job_threads in this channel
+    job_threads: Option<bool>,
+    // Replaces private_results in this channel
+    private_results: Option<bool>,
+    // Replaces zip.enabled in this channel
+    zip_results: Option<bool>,
+    // Replaces trigger in this channel
+    trigger: Option<Trigger>,
+}
+
+fn default_ytdlp_dir() -> String {
+    "data/bin".to_string()
+}
+
+fn default_retention_interval_mins() -> u64 {
+    60
+}
+
+fn default_shutdown_grace_secs() -> u64 {
+    30
+}
+
+fn default_prefix() -> String {
+    "!dl".to_string()
+}
+
+fn default_database_path() -> String {
+    "data/history.db".to_string()
+}
+
+fn default_job_log_dir() -> String {
+    "data/job-logs".to_string()
+}
+
+fn default_job_logs_kept() -> usize {
+    500
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_max_concurrent_downloads() -> usize {
+    2
+}
+
+fn default_max_concurrent_transcodes() -> usize {
+    1
 }
 
 impl Settings {
@@ -52,29 +517,1683 @@ impl Settings {
         }
         Ok(settings)
     }
+
+    fn cookie_config(&self) -> CookieConfig {
+        CookieConfig::new(self.cookies_path.clone(), self.cookies_from_browser.clone(), self.login_cookies.clone(), &self.site_cookies)
+    }
 }
 
 struct Handler {
     url_regex: Regex,
-    output_dir: String,
-    allowed_guild: Option<u64>,
-    allowed_channel: Option<u64>,
-    cookies_path: Option<String>,
+    // Swapped as a whole by /reload
+    live: RwLock<Arc<Reloadable>>,
+    cookies: Cookies,
+    login_retry_args: Vec<String>,
+    geo: GeoSettings,
+    jobs: Arc<JobRegistry>,
+    queue: Arc<DownloadQueue>,
+    history: Arc<History>,
+    feeds: Feeds,
+    zips: Arc<Zips>,
+    links: Option<Arc<Links>>,
+    in_flight: Arc<InFlight>,
+    job_logs: Arc<JobLogs>,
+    metrics: Arc<Metrics>,
+    guilds: Guilds,
+    prefs: Prefs,
+    upload_results: bool,
+    job_threads: bool,
+    private_results: bool,
+    trigger: Trigger,
+    prefix: String,
+    reply_on_no_url: bool,
+    retries: RetryPolicies,
+    site_args: SiteArgs,
+    proxies: Proxies,
+    downloaders: Arc<Downloaders>,
+    bandwidth: Arc<Bandwidth>,
+    max_download_secs: Option<u64>,
+    keep_partial_files: bool,
+    // Fragment and external downloader arguments for every download
+    downloader_args: Vec<String>,
+    // The ffprobe to verify downloads with, if they're checked with it
+    ffprobe: Option<String>,
+    output_template: OutputTemplate,
+    media_server_layout: bool,
+    archivist: bool,
+    on_collision: Option<CollisionPolicy>,
+    dedupe: Option<DedupeSettings>,
+    download_archive: Option<String>,
+    resume_jobs: bool,
+    resumed: AtomicBool,
+    pause_suspends_running: bool,
+    started: Instant,
+    min_free_bytes: Option<u64>,
+    estimate_size: bool,
+    confirm_above_bytes: Option<u64>,
+    budget_approval: bool,
+    confirmations: Confirmations,
+    admin_channel: Option<ChannelId>,
+    // With the guild it's in, whose downloads are the only ones it takes
+    announce_channel: Option<(ChannelId, GuildId)>,
+    // When admins were last told the disk is full, so they aren't told on every request
+    disk_warned: Mutex<Option<Instant>>,
+    post_processing: PostProcessing,
+    transcode: Option<TranscodeSettings>,
+    transcoder: Arc<Transcoder>,
+    tagging: Option<TaggingSettings>,
+    tagger: Arc<Tagger>,
+    loudness: Option<LoudnessSettings>,
+    normalizer: Arc<Normalizer>,
+    scan: Option<ScanSettings>,
+    library: Option<Arc<Library>>,
+    webhooks: Arc<Webhooks>,
+    storage: Arc<dyn Storage>,
+    subtitle_langs: Option<String>,
+    embed_subtitles: bool,
+    gateway: Arc<Gateway>,
+}
+
+// Saved with each job so it can be resumed after a restart
+#[derive(Clone, Serialize, Deserialize)]
+struct DownloadRequest {
+    url: String,
+    requester: UserId,
+    requester_name: String,
+    channel: ChannelId,
+    guild: Option<GuildId>,
+    format: FormatSpec,
+    // Download even if the same video and format was downloaded before
+    force: bool,
+    // Filled in once the URL has been probed
+    #[serde(default)]
+    archive_key: Option<String>,
+    // Title of the playlist this is an entry of
+    #[serde(default)]
+    playlist: Option<String>,
+    // Subtitle languages; None uses subtitle_langs from the config
+    #[serde(default)]
+    subtitles: Option<String>,
+    // Only this section of the video
+    #[serde(default)]
+    clip: Option<Clip>,
+    #[serde(default)]
+    requester_avatar: Option<String>,
+    #[serde(default)]
+    metadata: Metadata,
+    // Requested by direct message, so it goes to the requester's private folder
+    #[serde(default)]
+    dm: bool,
+    // A live stream, recorded until it ends or is stopped with /stream stop
+    #[serde(default)]
+    live: bool,
+    // Set for runs of a [[schedules]] entry, which only fetch what's new since the last run
+    #[serde(default)]
+    schedule: Option<ScheduledRun>,
+    // What downloads it; None until submitted, unless the requester picked one
+    #[serde(default)]
+    backend: Option<Backend>,
+    // Also split it into a file per chapter
+    #[serde(default)]
+    split_chapters: bool,
+    // Submitted through the REST API, where nobody is around to confirm a large download
+    #[serde(default)]
+    from_api: bool,
+    // Thread started off the request's message that the job posts in instead of `channel`
+    #[serde(default)]
+    thread: Option<ChannelId>,
+    // yt-dlp flags a trusted requester added, already checked against allowed_ytdlp_flags
+    #[serde(default)]
+    extra_args: Vec<String>,
+    // One of the site's own formats, picked from /probe's menu; replaces `format`
+    #[serde(default)]
+    format_id: Option<String>,
+    // Set when retrying a download that failed for wanting an account
+    #[serde(default)]
+    workaround: Option<Workaround>,
+    #[serde(default)]
+    priority: Priority,
+    // Country a trusted requester asked to download it as, replacing geo.country
+    #[serde(default)]
+    geo_country: Option<String>,
+    // From the requester's preferences, inside the directory the download would go to otherwise
+    #[serde(default)]
+    subfolder: Option<String>,
+    // Uploaded as a spoiler, as moderation.spoiler_results decided when it was submitted
+    #[serde(default)]
+    spoiler: bool,
+}
+
+impl DownloadRequest {
+    // Where questions about the job and its result are posted
+    fn reply_channel(&self) -> ChannelId {
+        self.thread.unwrap_or(self.channel)
+    }
+
+    // Names the format in history, the archive and the job's card
+    fn format_label(&self) -> String {
+        match &self.format_id {
+            Some(id) => format!("format {}", id),
+            None => self.format.to_string(),
+        }
+    }
+}
+
+// Where a job reports its progress and result
+enum Reporter {
+    Status(Option<StatusMessage>),
+    PlaylistItem(PlaylistItem),
+    // Scheduled runs post what they fetched instead
+    Digest,
+}
+
+enum Outcome {
+    Done(Vec<PathBuf>),
+    Failed(anyhow::Error),
+    Cancelled(CancelReason),
+}
+
+impl Outcome {
+    // What a playlist's summary says about the item
+    fn for_playlist(&self, messages: &Messages) -> Result<Vec<PathBuf>, String> {
+        match self {
+            Outcome::Done(files) => Ok(files.clone()),
+            Outcome::Failed(e) => Err(errors::describe(e, messages)),
+            Outcome::Cancelled(CancelReason::User(by)) => Err(messages.get("item_cancelled_by", &[("user", &by.mention())])),
+            Outcome::Cancelled(CancelReason::Shutdown) => Err(messages.get("item_interrupted", &[])),
+        }
+    }
+}
+
+enum Submitted {
+    Job { id: JobId, position: usize, card: Box<JobCard> },
+    Duplicate(history::Archived),
+    // Joined the job already downloading the same thing
+    Joined { id: JobId },
+    Playlist { title: String, queued: usize },
 }
 
 impl Handler {
-    fn is_allowed_guild(&self, guild_id: serenity::model::id::GuildId) -> bool {
-        match &self.allowed_guild {
-            Some(id) => guild_id.get() == *id,
+    fn is_allowed_guild(&self, guild_id: GuildId) -> bool {
+        match &self.live().allowed_guilds {
+            Some(ids) => ids.contains(&guild_id.get()),
             None => true,
         }
     }
+
+    fn guild_settings(&self, guild_id: GuildId) -> GuildSettings {
+        self.guilds.get(guild_id, self.live().guilds.get(&guild_id))
+    }
+
+    // An explicit request wins, then the channel's default, the guild's, and the global defaults
+    fn resolve_format(&self, requested: Option<FormatSpec>, guild_id: Option<GuildId>, channel_id: ChannelId) -> FormatSpec {
+        let live = self.live();
+        let format = requested
+            .or_else(|| live.channels.get(&channel_id.get()).and_then(|channel| channel.format))
+            .or_else(|| guild_id.and_then(|id| self.guild_settings(id).format))
+            .unwrap_or(if live.audio_only { FormatSpec::Audio(None) } else { live.default_format });
+        format.with_default_audio(live.audio_format)
+    }
+
+    fn is_allowed_location(&self, guild_id: Option<GuildId>, channel_id: ChannelId) -> bool {
+        if let Some(guild_id) = guild_id {
+            if !self.is_allowed_guild(guild_id) {
+                return false;
+            }
+        }
+        let live = self.live();
+        live.channels.is_empty() || live.channels.contains_key(&channel_id.get())
+    }
+
+    // Whether the request's location still lets it through, e.g. when resuming it
+    fn is_allowed_request(&self, request: &DownloadRequest) -> bool {
+        if request.dm {
+            self.live().auth.dm_access(request.requester) != Access::Denied
+        } else {
+            self.is_allowed_location(request.guild, request.channel)
+        }
+    }
+
+    fn timeout_for(&self, channel_id: ChannelId) -> Option<Duration> {
+        self.live().channels.get(&channel_id.get())
+            .and_then(|channel| channel.max_download_secs)
+            .or(self.max_download_secs)
+            .filter(|&secs| secs > 0)
+            .map(Duration::from_secs)
+    }
+
+    // What the bot says in a guild, in its language
+    fn messages(&self, guild_id: Option<GuildId>) -> Messages {
+        let language = guild_id.and_then(|id| self.guild_settings(id).language);
+        self.live().messages.messages(language.as_deref())
+    }
+
+    // Downloads requested in DMs are nobody else's business
+    fn announce_channel_for(&self, guild_id: Option<GuildId>, dm: bool) -> Option<ChannelId> {
+        if dm {
+            return None;
+        }
+        let guild_id = guild_id?;
+        self.guild_settings(guild_id).announce_channel_id
+            .map(ChannelId::new)
+            .or_else(|| self.announce_channel.filter(|&(_, guild)| guild == guild_id).map(|(channel, _)| channel))
+    }
+
+    fn post_processing_for(&self, channel_id: ChannelId) -> PostProcessing {
+        match self.live().channels.get(&channel_id.get()) {
+            Some(channel) => self.post_processing.merged(&channel.post_processing),
+            None => self.post_processing.clone(),
+        }
+    }
+
+    fn media_server_layout_for(&self, channel_id: ChannelId) -> bool {
+        self.live().channels.get(&channel_id.get())
+            .and_then(|channel| channel.media_server_layout)
+            .unwrap_or(self.media_server_layout)
+    }
+
+    fn archivist_for(&self, channel_id: ChannelId) -> bool {
+        self.live().channels.get(&channel_id.get())
+            .and_then(|channel| channel.archivist)
+            .unwrap_or(self.archivist)
+    }
+
+    fn on_collision_for(&self, channel_id: ChannelId) -> Option<CollisionPolicy> {
+        self.live().channels.get(&channel_id.get())
+            .and_then(|channel| channel.on_collision)
+            .or(self.on_collision)
+    }
+
+    fn private_results_for(&self, channel_id: ChannelId) -> bool {
+        self.live().channels.get(&channel_id.get())
+            .and_then(|channel| channel.private_results)
+            .unwrap_or(self.private_results)
+    }
+
+    fn trigger_for(&self, channel_id: ChannelId) -> Trigger {
+        self.live().channels.get(&channel_id.get())
+            .and_then(|channel| channel.trigger)
+            .unwrap_or(self.trigger)
+    }
+
+    // How results of several files are zipped, if the channel zips them
+    fn zip_for(&self, channel_id: ChannelId, guild_id: Option<GuildId>) -> Option<Zipping> {
+        let enabled = self.live().channels.get(&channel_id.get())
+            .and_then(|channel| channel.zip_results)
+            .unwrap_or(self.zips.settings().enabled);
+        enabled.then(|| Zipping { zips: Arc::clone(&self.zips), guild: guild_id })
+    }
+
+    // Job cards posted where everyone sees them leave the results out with private_results;
+    // the ephemeral replies to slash commands don't need to
+    fn hides_card(&self, request: &DownloadRequest, status: Option<&StatusMessage>) -> bool {
+        !request.dm && self.private_results_for(request.channel) && !matches!(status, Some(StatusMessage::Interaction(_)))
+    }
+
+    fn job_threads_for(&self, channel_id: ChannelId) -> bool {
+        self.live().channels.get(&channel_id.get())
+            .and_then(|channel| channel.job_threads)
+            .unwrap_or(self.job_threads)
+    }
+
+    // With job_threads on, starts the thread the jobs requested in `msg` report in. Requests
+    // that can't have one, like those in DMs or threads already, stay in their channel.
+    async fn job_thread(&self, http: &Http, msg: &Message, name: String) -> Option<ChannelId> {
+        if msg.guild_id.is_none() || !self.job_threads_for(msg.channel_id) {
+            return None;
+        }
+        // Thread names are limited to 100 characters
+        let thread = CreateThread::new(name.chars().take(100).collect::<String>())
+            .auto_archive_duration(AutoArchiveDuration::OneDay);
+        match msg.channel_id.create_thread_from_message(http, msg.id, thread).await {
+            Ok(thread) => Some(thread.id),
+            Err(e) => {
+                log::warn!("Failed to start a thread for message {} in {}: {}", msg.id, msg.channel_id, e);
+                None
+            }
+        }
+    }
+
+    fn transcode_for(&self, channel_id: ChannelId) -> Option<TranscodeSettings> {
+        self.live().channels.get(&channel_id.get())
+            .and_then(|channel| channel.transcode.clone())
+            .or_else(|| self.transcode.clone())
+    }
+
+    fn loudness_for(&self, channel_id: ChannelId) -> Option<LoudnessSettings> {
+        self.live().channels.get(&channel_id.get())
+            .and_then(|channel| channel.loudness.clone())
+            .or_else(|| self.loudness.clone())
+    }
+
+    fn scan_for(&self, channel_id: ChannelId) -> Option<ScanSettings> {
+        self.live().channels.get(&channel_id.get())
+            .and_then(|channel| channel.scan.clone())
+            .or_else(|| self.scan.clone())
+    }
+
+    // A guild's allowed_roles replace the global ones there
+    fn member_access(&self, user: UserId, guild_id: Option<GuildId>, channel_id: ChannelId, roles: &[RoleId], moderator: bool) -> Access {
+        let guild_roles = guild_id.and_then(|id| self.guild_settings(id).allowed_roles);
+        self.live().auth.access(user, channel_id, roles, moderator, guild_roles.as_deref())
+    }
+
+    fn budget_for(&self, channel_id: ChannelId) -> Budget {
+        let live = self.live();
+        live.budget.for_channel(live.channels.get(&channel_id.get()).and_then(|channel| channel.budget_gb))
+    }
+
+    fn quota_for(&self, guild_id: Option<GuildId>) -> Quota {
+        let quota = self.configured_quota(guild_id);
+        match guild_id {
+            Some(id) => {
+                let overrides = self.guilds.overrides(id);
+                quota.tightened(overrides.max_downloads_per_hour, overrides.max_gb_per_day)
+            }
+            None => quota,
+        }
+    }
+
+    // The quota from the config file alone, which /config can only lower
+    fn configured_quota(&self, guild_id: Option<GuildId>) -> Quota {
+        let live = self.live();
+        match guild_id.and_then(|id| live.guilds.get(&id)) {
+            Some(guild) => live.quota.overridden(guild.max_downloads_per_hour, guild.max_gb_per_day),
+            None => live.quota.overridden(None, None),
+        }
+    }
+
+    // Direct messages are kept apart in output_dir/dm/<user ID>. Otherwise the channel's own
+    // directory wins over the guild's; both are relative to output_dir.
+    fn output_dir_for(&self, channel_id: ChannelId, guild_id: Option<GuildId>, dm_user: Option<UserId>) -> String {
+        let live = self.live();
+        if let Some(user) = dm_user {
+            return Path::new(&live.output_dir).join("dm").join(user.to_string()).to_string_lossy().into_owned();
+        }
+        let dir = live.channels.get(&channel_id.get())
+            .and_then(|channel| channel.output_dir.clone())
+            .or_else(|| guild_id.and_then(|id| self.guild_settings(id).output_dir));
+        match dir {
+            Some(dir) => Path::new(&live.output_dir).join(dir).to_string_lossy().into_owned(),
+            None => live.output_dir.clone(),
+        }
+    }
+
+    // Fails once free space in the output directory drops below min_free_bytes, otherwise
+    // returns how much can still be used before it would
+    async fn check_disk_space(&self, http: &Http, output_dir: &str, guild_id: Option<GuildId>) -> Result<Option<u64>> {
+        let Some(min_free) = self.min_free_bytes else {
+            return Ok(None);
+        };
+        let Some((free, _)) = disk::space(Path::new(output_dir)) else {
+            return Ok(None);
+        };
+        if free >= min_free {
+            return Ok(Some(free - min_free));
+        }
+        let warn = {
+            let mut warned = self.disk_warned.lock().unwrap();
+            let due = warned.is_none_or(|at| at.elapsed() >= DISK_WARNING_INTERVAL);
+            if due {
+                *warned = Some(Instant::now());
+            }
+            due
+        };
+        if warn {
+            self.warn_admins(http, format!(
+                "Disk space is low: {} free, below the minimum of {}. New downloads are being refused.",
+                format_bytes(free),
+                format_bytes(min_free)
+            )).await;
+        }
+        bail!("{}", self.messages(guild_id).get("disk_full", &[]))
+    }
+
+    async fn warn_admins(&self, http: &Http, text: String) {
+        log::warn!("{}", text);
+        if let Some(channel) = self.admin_channel {
+            if let Err(e) = channel.say(http, truncate_message(text)).await {
+                error!("Failed to post in admin channel: {}", e);
+            }
+        }
+    }
+
+    // Queues the request, splitting playlists into one job per entry
+    async fn submit(&self, http: &Arc<Http>, request: DownloadRequest, status: Option<StatusMessage>) -> Result<Submitted> {
+        self.submit_as(http, request, status, None).await
+    }
+
+    // Like submit, but a single video queued as part of `list` reports back to it instead
+    async fn submit_as(
+        &self,
+        http: &Arc<Http>,
+        request: DownloadRequest,
+        status: Option<StatusMessage>,
+        list: Option<&Arc<Playlist>>,
+    ) -> Result<Submitted> {
+        let nsfw_channel = self.is_nsfw_channel(http, request.channel).await;
+        self.veto_request(http, &request, &Candidate::url(&request.url, nsfw_channel)).await?;
+        let allowance = self.quota_for(request.guild).check(&self.history, request.requester, request.guild)
+            .map_err(|exceeded| anyhow::anyhow!(exceeded.describe(&self.messages(request.guild))))?;
+        let output_dir = prefs::with_subfolder(
+            self.output_dir_for(request.channel, request.guild, request.dm.then_some(request.requester)),
+            request.subfolder.as_deref(),
+        );
+        let free = self.check_disk_space(http, &output_dir, request.guild).await?;
+        let (_, site_args) = self.extra_args_for(&request.url);
+        let cookies = self.cookies_for(&request.url, request.workaround);
+        let needs_ytdlp = request.clip.is_some() || request.format.is_audio() || request.split_chapters
+            || !request.extra_args.is_empty() || request.format_id.is_some() || request.workaround.is_some()
+            || request.geo_country.is_some();
+        let backend = self.downloaders.backend_for(&request.url, request.backend, needs_ytdlp);
+        if request.backend.is_some_and(|requested| requested != Backend::YtDlp) && needs_ytdlp {
+            bail!("{}", self.messages(request.guild).get("ytdlp_only", &[("backend", &backend)]));
+        }
+        let request = DownloadRequest { backend: Some(backend), ..request };
+        // The other backends' URLs are nothing yt-dlp can tell anything about
+        let info = if backend != Backend::YtDlp {
+            None
+        } else {
+            match ytdlp::probe(&request.url, &cookies, &site_args).await {
+                Ok(info) => Some(info),
+                Err(e) => {
+                    // Let the download itself report why the URL doesn't work
+                    log::warn!("Failed to probe {}: {}", request.url, e);
+                    None
+                }
+            }
+        };
+        if let Some(info) = &info {
+            self.veto_request(http, &request, &Candidate::probed(&request.url, info, nsfw_channel)).await?;
+        }
+        let estimated_size = info.as_ref().and_then(ytdlp::Info::estimated_size);
+        if let (Some(free), Some(size)) = (free, estimated_size) {
+            if self.estimate_size && size > free {
+                let (size, free) = (format_bytes(size), format_bytes(free));
+                bail!("{}", self.messages(request.guild).get("too_big_for_disk", &[("size", &size), ("free", &free)]));
+            }
+        }
+        let archive_key = info.as_ref().and_then(ytdlp::Info::archive_key);
+        let metadata = info.as_ref().map(ytdlp::Info::metadata).unwrap_or_default();
+        let live = info.as_ref().is_some_and(ytdlp::Info::is_live);
+        if let Some(clip) = request.clip {
+            if info.as_ref().is_some_and(ytdlp::Info::is_playlist) {
+                bail!("{}", self.messages(request.guild).get("clip_playlist", &[]));
+            }
+            if live {
+                bail!("{}", self.messages(request.guild).get("clip_live", &[]));
+            }
+            clip.check_duration(metadata.duration)?;
+        }
+        let candidate = match &info {
+            Some(info) => Candidate::probed(&request.url, info, nsfw_channel),
+            None => Candidate::url(&request.url, nsfw_channel),
+        };
+        let request = DownloadRequest { spoiler: self.live().moderation.spoiler(&candidate), ..request };
+        let Some(info) = info.filter(ytdlp::Info::is_playlist) else {
+            let request = DownloadRequest { archive_key, metadata, live, ..request };
+            if let Some(existing) = self.find_existing(&request) {
+                return Ok(Submitted::Duplicate(existing));
+            }
+            self.check_budget(http, &request, estimated_size).await?;
+            let confirm_above_bytes = self.confirm_above_bytes.filter(|_| !request.from_api);
+            if let Some(size) = estimated_size.filter(|&size| confirm_above_bytes.is_some_and(|limit| size > limit)) {
+                self.confirm_size(http, &request, size).await?;
+            }
+            let anonymous = self.hides_card(&request, status.as_ref());
+            let waiter = self.waiter_for(&request, list.map(|list| list.item(request.url.clone())));
+            let start = |waiter: Waiter| {
+                let reporter = waiter.item.map_or(Reporter::Status(status), Reporter::PlaylistItem);
+                self.start_download(http, request.clone(), reporter)
+            };
+            let (id, position) = match self.in_flight_key(&request) {
+                Some(key) => match self.in_flight.join_or_start(key, waiter, start)? {
+                    Claimed::Joined(id) => return Ok(Submitted::Joined { id }),
+                    Claimed::Started(id, position) => (id, position),
+                },
+                None => start(waiter)?,
+            };
+            let card = Box::new(JobCard::new(id, &request, self.messages(request.guild)).anonymous(anonymous));
+            return Ok(Submitted::Job { id, position, card });
+        };
+        // Entry sizes aren't known until each one is probed
+        self.check_budget(http, &request, None).await?;
+        let title = info.title.clone().unwrap_or_else(|| request.url.clone());
+        let zip = self.zip_for(request.channel, request.guild);
+        let messages = self.messages(request.guild);
+        let playlist = Playlist::new(title, info.entries.len(), request.reply_channel(), Arc::clone(http), status, zip, messages.clone());
+        let mut queued = 0;
+        let mut last_error = None;
+        for (index, entry) in info.entries.iter().enumerate() {
+            let url = entry.as_ref().and_then(ytdlp::PlaylistEntry::download_url);
+            let Some(url) = url else {
+                let item = playlist.item(messages.get("item_untitled", &[("url", &request.url), ("index", &(index + 1))]));
+                item.finish(Err(messages.get("item_unavailable", &[]))).await;
+                continue;
+            };
+            let title = entry.as_ref().and_then(|entry| entry.title.as_deref());
+            if let Err(veto) = self.moderate(&request, &Candidate { title, ..Candidate::url(url, nsfw_channel) }) {
+                playlist.item(url.to_owned()).finish(Err(veto.reason)).await;
+                continue;
+            }
+            if allowance.is_some_and(|allowed| queued >= allowed) {
+                playlist.item(url.to_owned()).finish(Err(messages.get("item_over_quota", &[]))).await;
+                continue;
+            }
+            let item_request = DownloadRequest {
+                url: url.to_owned(),
+                archive_key: entry.as_ref().and_then(ytdlp::PlaylistEntry::archive_key),
+                metadata: entry.as_ref().map(ytdlp::PlaylistEntry::metadata).unwrap_or_default(),
+                playlist: Some(playlist.title().to_owned()),
+                priority: request.priority.for_entries(),
+                ..request.clone()
+            };
+            if self.find_existing(&item_request).is_some() {
+                playlist.item(url.to_owned()).finish(Ok(Vec::new())).await;
+                continue;
+            }
+            // A failed submit drops the item, which counts it as cancelled. One that joins
+            // another job is finished by that job.
+            let waiter = self.waiter_for(&item_request, Some(playlist.item(url.to_owned())));
+            let start = |waiter: Waiter| {
+                let reporter = waiter.item.map_or(Reporter::Status(None), Reporter::PlaylistItem);
+                self.start_download(http, item_request.clone(), reporter)
+            };
+            let claimed = match self.in_flight_key(&item_request) {
+                Some(key) => self.in_flight.join_or_start(key, waiter, start),
+                None => start(waiter).map(|(id, position)| Claimed::Started(id, position)),
+            };
+            match claimed {
+                Ok(_) => queued += 1,
+                Err(e) => last_error = Some(e),
+            }
+        }
+        match last_error {
+            Some(e) if queued == 0 => Err(e),
+            _ => Ok(Submitted::Playlist { title: playlist.title().to_owned(), queued }),
+        }
+    }
+
+    // Builds the request for one link in a message from the text around it
+    // `trusted` requesters may add yt-dlp flags after `--`.
+    fn parse_request(&self, msg: &Message, url: &str, before: &str, after: &str, trusted: bool) -> Result<DownloadRequest, String> {
+        if !is_valid_url(url) {
+            return Err(self.messages(msg.guild_id).get("invalid_url", &[]));
+        }
+        // `!dl <url> <format>` names a format explicitly; in other messages a word
+        // after the URL only counts if it happens to be a valid format
+        let explicit = trigger::has_prefix(&msg.content, &self.prefix);
+        let audio_prefix = before.ends_with("audio:");
+        // `force`, `chapters`, `subs:<langs>`, `via:<backend>`, `region:<country>` and a time range
+        // may come before or after the format
+        let all_words: Vec<&str> = after.split_whitespace().collect();
+        let (options, flags) = match all_words.iter().position(|&word| word == "--") {
+            Some(index) => (&all_words[..index], &all_words[index + 1..]),
+            None => (&all_words[..], &[][..]),
+        };
+        let extra_args = self.ytdlp_flags(url, flags, trusted, &msg.author, msg.guild_id)?;
+        let mut words: Vec<&str> = options.iter().copied().take(6).collect();
+        let mut take_flag = |flag: &str| match words.iter().position(|word| word.eq_ignore_ascii_case(flag)) {
+            Some(index) => {
+                words.remove(index);
+                true
+            }
+            None => false,
+        };
+        let force = take_flag("force");
+        let split_chapters = take_flag("chapters");
+        let subs = words.iter().position(|word| word.to_ascii_lowercase().starts_with("subs:"));
+        let subtitles = match subs.map(|index| words.remove(index)) {
+            Some(word) => Some(format::parse_subtitle_langs(&word["subs:".len()..]).map_err(|e| e.to_string())?),
+            None => None,
+        };
+        // `via:<backend>` picks what downloads it
+        let via = words.iter().position(|word| word.to_ascii_lowercase().starts_with("via:"));
+        let backend = match via.map(|index| words.remove(index)) {
+            Some(word) => Some(self.requested_backend(&word["via:".len()..], msg.guild_id)?),
+            None => None,
+        };
+        // `region:<country>` downloads it as if from there
+        let region = words.iter().position(|word| word.to_ascii_lowercase().starts_with("region:"));
+        let geo_country = match region.map(|index| words.remove(index)) {
+            Some(_) if !trusted => return Err(self.messages(msg.guild_id).get("region_not_trusted", &[])),
+            Some(word) => Some(geo::parse_country(&word["region:".len()..])?),
+            None => None,
+        };
+        let clip = words.iter().enumerate().find_map(|(index, word)| Some((index, Clip::parse(word)?)));
+        let clip = match clip {
+            Some((index, clip)) => {
+                words.remove(index);
+                Some(clip.map_err(|e| e.to_string())?)
+            }
+            None => None,
+        };
+        let format = match words.first() {
+            Some(word) => match word.parse::<FormatSpec>() {
+                Ok(format) => Some(format),
+                Err(e) if explicit => return Err(e.to_string()),
+                Err(_) => None,
+            },
+            None => None,
+        };
+        let format = match format {
+            Some(format) if audio_prefix && !format.is_audio() => {
+                return Err(self.messages(msg.guild_id).get("audio_prefix_conflict", &[("format", &format)]));
+            }
+            None if audio_prefix => Some(FormatSpec::Audio(None)),
+            format => format,
+        };
+        let prefs = self.prefs.get(msg.author.id);
+        Ok(DownloadRequest {
+            url: url.to_owned(),
+            requester: msg.author.id,
+            requester_name: msg.author.name.clone(),
+            requester_avatar: Some(msg.author.face()),
+            channel: msg.channel_id,
+            guild: msg.guild_id,
+            format: self.resolve_format(format.or(prefs.format()), msg.guild_id, msg.channel_id),
+            force,
+            archive_key: None,
+            playlist: None,
+            subtitles: subtitles.or(prefs.subs),
+            clip,
+            metadata: Metadata::default(),
+            dm: msg.guild_id.is_none(),
+            live: false,
+            schedule: None,
+            backend,
+            split_chapters,
+            from_api: false,
+            thread: None,
+            extra_args,
+            format_id: None,
+            workaround: None,
+            priority: Priority::Normal,
+            geo_country,
+            subfolder: prefs.subfolder,
+            spoiler: false,
+        })
+    }
+
+    // The lane a requester's downloads go in, by their roles and whether they boost the server
+    fn priority_for(&self, access: Access, roles: &[RoleId], boosting: bool) -> Priority {
+        self.live().priorities.priority(access >= Access::Admin, roles, boosting)
+    }
+
+    // Admins and trusted_roles may pass yt-dlp flags
+    fn is_trusted(&self, access: Access, roles: &[RoleId]) -> bool {
+        access >= Access::Admin || self.live().auth.is_trusted(roles)
+    }
+
+    // Checks the yt-dlp flags a requester added, leaving a record of who passed what
+    fn ytdlp_flags(&self, url: &str, words: &[&str], trusted: bool, user: &User, guild_id: Option<GuildId>) -> Result<Vec<String>, String> {
+        if words.is_empty() {
+            return Ok(Vec::new());
+        }
+        if !trusted {
+            return Err(self.messages(guild_id).get("flags_not_trusted", &[]));
+        }
+        let with_cookies = !self.cookies.args_for(url).is_empty();
+        let args = self.live().ytdlp_flags.check(words, with_cookies, &self.messages(guild_id)).inspect_err(|e| {
+            log::warn!(target: "audit", "Refused yt-dlp flags for <{}> from {} ({}): {}", url, user.name, user.id, e)
+        })?;
+        info!(target: "audit", "{} ({}) passed yt-dlp flags for <{}>: {}", user.name, user.id, url, args.join(" "));
+        Ok(args)
+    }
+
+    // login_cookies for a signed-in retry, otherwise whatever the site usually gets
+    fn cookies_for(&self, url: &str, workaround: Option<Workaround>) -> Vec<String> {
+        match workaround {
+            Some(Workaround::LoginCookies) => self.cookies.login_args().unwrap_or_else(|| self.cookies.args_for(url)),
+            _ => self.cookies.args_for(url),
+        }
+    }
+
+    // The retries a job's failure card can offer when the site wants an account, leaving out
+    // the one it already tried
+    fn workarounds_after(&self, tried: Option<Workaround>) -> Vec<Workaround> {
+        let mut offered = Vec::new();
+        if self.cookies.has_login() {
+            offered.push(Workaround::LoginCookies);
+        }
+        if !self.login_retry_args.is_empty() {
+            offered.push(Workaround::RetryArgs);
+        }
+        offered.retain(|workaround| Some(*workaround) != tried);
+        offered
+    }
+
+    // A backend the requester named, if it's one this bot can use
+    fn requested_backend(&self, name: &str, guild_id: Option<GuildId>) -> Result<Backend, String> {
+        let backend: Backend = name.parse()?;
+        if !self.downloaders.is_available(backend) {
+            return Err(self.messages(guild_id).get("backend_unavailable", &[("backend", &backend)]));
+        }
+        Ok(backend)
+    }
+
+    // The proxy a yt-dlp run on `url` goes through, taking turns where there's a list, and the
+    // arguments to run it with. site_args come last so a site's own --proxy wins.
+    fn extra_args_for(&self, url: &str) -> (Option<String>, Vec<String>) {
+        let proxy = self.proxies.pick(url);
+        let mut args = proxy::args(proxy.as_deref());
+        args.extend(self.site_args.for_url(url));
+        (proxy, args)
+    }
+
+    // Runs the moderation checks, leaving a record of what was refused and why
+    fn moderate(&self, request: &DownloadRequest, candidate: &Candidate<'_>) -> Result<(), Veto> {
+        self.live().moderation.check(candidate, &self.messages(request.guild)).inspect_err(|veto| {
+            log::warn!(
+                target: "audit",
+                "Refused <{}> requested by {} ({}) in channel {} ({} check): {}",
+                candidate.url, request.requester_name, request.requester, request.channel, veto.check, veto.reason
+            )
+        })
+    }
+
+    // Like moderate, for the request itself rather than a playlist entry; with notify_admins
+    // the admin channel hears about it too
+    async fn veto_request(&self, http: &Http, request: &DownloadRequest, candidate: &Candidate<'_>) -> Result<(), Veto> {
+        let result = self.moderate(request, candidate);
+        if let Err(veto) = &result {
+            if self.live().moderation.notify_admins {
+                self.warn_admins(http, format!(
+                    "Refused <{}> requested by <@{}> in <#{}> ({} check): {}",
+                    candidate.url, request.requester, request.channel, veto.check, veto.reason
+                )).await;
+            }
+        }
+        result
+    }
+
+    // Threads go by their parent channel. Only looked up when a check needs it.
+    async fn is_nsfw_channel(&self, http: &Http, channel: ChannelId) -> bool {
+        if !self.live().moderation.needs_channel() {
+            return false;
+        }
+        let mut channel = channel;
+        loop {
+            match channel.to_channel(http).await {
+                Ok(Channel::Guild(guild_channel)) => match guild_channel.parent_id {
+                    Some(parent) if guild_channel.thread_metadata.is_some() => channel = parent,
+                    _ => return guild_channel.nsfw,
+                },
+                Ok(_) => return false,
+                Err(e) => {
+                    log::warn!("Failed to look up channel {}: {}", channel, e);
+                    return false;
+                }
+            }
+        }
+    }
+
+    // Asks the requester whether they really want a large download, failing unless they confirm
+    async fn confirm_size(&self, http: &Http, request: &DownloadRequest, size: u64) -> Result<()> {
+        let messages = self.messages(request.guild);
+        let question = messages.get("confirm_size", &[
+            ("user", &request.requester.mention()),
+            ("url", &request.url),
+            ("size", &format_bytes(size)),
+            ("confirm", &confirm::CONFIRM),
+            ("minutes", &(confirm::TIMEOUT.as_secs() / 60)),
+            ("reject", &confirm::REJECT),
+        ]);
+        let answer = self.confirmations.ask(http, request.reply_channel(), Asked::User(request.requester), question).await
+            .context("Failed to ask for confirmation")?;
+        match answer {
+            Answer::Confirmed => Ok(()),
+            Answer::Rejected => bail!("{}", messages.get("confirm_rejected", &[("url", &request.url)])),
+            Answer::TimedOut => bail!("{}", messages.get("confirm_timed_out", &[("url", &request.url)])),
+        }
+    }
+
+    // Refuses a download that would go over the requester's or the channel's storage budget,
+    // or with budget_approval, asks the admins whether it may go ahead anyway
+    async fn check_budget(&self, http: &Http, request: &DownloadRequest, size: Option<u64>) -> Result<()> {
+        let Err(over) = self.budget_for(request.channel).check(&self.history, request.requester, request.channel, size) else {
+            return Ok(());
+        };
+        let messages = self.messages(request.guild);
+        if !self.budget_approval {
+            bail!("{}", over.describe(&messages));
+        }
+        let (user, budget, minutes) = (request.requester.mention(), over.summary(&messages), confirm::APPROVAL_TIMEOUT.as_secs() / 60);
+        let mut args: Vec<(&str, &(dyn Display + Sync))> = vec![
+            ("url", &request.url),
+            ("user", &user),
+            ("budget", &budget),
+            ("confirm", &confirm::CONFIRM),
+            ("minutes", &minutes),
+            ("reject", &confirm::REJECT),
+        ];
+        let size = size.map(format_bytes);
+        let question = match &size {
+            Some(size) => {
+                args.push(("size", size));
+                messages.get("budget_approval_sized", &args)
+            }
+            None => messages.get("budget_approval", &args),
+        };
+        let answer = self.confirmations.ask(http, request.reply_channel(), Asked::Admins, question).await
+            .context("Failed to ask for approval")?;
+        match answer {
+            Answer::Confirmed => {
+                info!(target: "audit", "Over-budget download of <{}> by {} approved", request.url, request.requester);
+                Ok(())
+            }
+            Answer::Rejected => bail!("{}", over.describe(&messages)),
+            Answer::TimedOut => bail!("{}", messages.get("budget_timed_out", &[("url", &request.url), ("reason", &over.describe(&messages))])),
+        }
+    }
+
+    // Everything that makes a difference to what a job downloads and where it goes, so a
+    // request made while it runs can join it. Live streams are recorded from when they're
+    // asked for, so they're never joined.
+    fn in_flight_key(&self, request: &DownloadRequest) -> Option<String> {
+        if request.live {
+            return None;
+        }
+        let output_dir = prefs::with_subfolder(
+            self.output_dir_for(request.channel, request.guild, request.dm.then_some(request.requester)),
+            request.subfolder.as_deref(),
+        );
+        Some(format!(
+            "{}\n{}\n{:?}\n{:?}\n{:?}\n{:?}\n{:?}\n{}\n{:?}\n{:?}\n{}",
+            request.archive_key.as_deref().unwrap_or(&request.url),
+            request.format_label(),
+            request.clip,
+            request.backend,
+            request.extra_args,
+            request.format_id,
+            request.subtitles,
+            request.split_chapters,
+            request.geo_country,
+            request.workaround,
+            output_dir
+        ))
+    }
+
+    // Who to tell when the job a request joined is done
+    fn waiter_for(&self, request: &DownloadRequest, item: Option<PlaylistItem>) -> Waiter {
+        Waiter {
+            requester: request.requester,
+            channel: request.reply_channel(),
+            private: !request.dm && self.private_results_for(request.channel),
+            item,
+        }
+    }
+
+    // A previous download of the same video in the same format whose file is still there
+    fn find_existing(&self, request: &DownloadRequest) -> Option<history::Archived> {
+        // A clip is never the same file as the whole video or a different clip of it
+        if request.force || request.clip.is_some() {
+            return None;
+        }
+        self.history.archived(request.archive_key.as_deref()?, &request.format_label())
+            .filter(|existing| existing.is_imported() || Path::new(&existing.output_path).exists())
+    }
+
+    // Re-queues the jobs left unfinished by the last shutdown or crash. yt-dlp continues
+    // partially downloaded files, so long videos don't start over.
+    async fn resume_unfinished(&self, http: &Arc<Http>) {
+        let unfinished = match self.history.unfinished() {
+            Ok(unfinished) => unfinished,
+            Err(e) => {
+                error!("Failed to read unfinished jobs: {}", e);
+                return;
+            }
+        };
+        let mut singles = Vec::new();
+        let mut playlists: HashMap<(ChannelId, String), Vec<(JobId, DownloadRequest)>> = HashMap::new();
+        for (id, saved) in unfinished {
+            let request = saved.and_then(|saved| {
+                serde_json::from_str::<DownloadRequest>(&saved)
+                    .map_err(|e| log::warn!("Failed to parse saved job #{}: {}", id, e))
+                    .ok()
+            });
+            let Some(request) = request.filter(|request| self.is_allowed_request(request)) else {
+                self.history.finish_err(id, "Couldn't be resumed after a restart");
+                continue;
+            };
+            match request.playlist.clone() {
+                Some(title) => playlists.entry((request.reply_channel(), title)).or_default().push((id, request)),
+                None => singles.push((id, request)),
+            }
+        }
+        if singles.is_empty() && playlists.is_empty() {
+            return;
+        }
+        info!("Resuming {} job(s) and {} playlist(s) from before the restart", singles.len(), playlists.len());
+        for (id, request) in singles {
+            if request.schedule.is_some() {
+                if let Err(e) = self.run_job(http, id, request, Reporter::Digest) {
+                    error!("Failed to resume job #{}: {}", id, e);
+                }
+                continue;
+            }
+            let text = self.messages(request.guild).get("resuming", &[("id", &id), ("url", &request.url)]);
+            let status = send_status(http, request.reply_channel(), text).await;
+            if let Err(e) = self.run_job(http, id, request, Reporter::Status(status)) {
+                error!("Failed to resume job #{}: {}", id, e);
+            }
+        }
+        for ((channel, title), items) in playlists {
+            let guild_id = items.first().and_then(|(_, request)| request.guild);
+            let text = self.messages(guild_id).get("resuming_playlist", &[("count", &items.len()), ("title", &title)]);
+            let status = send_status(http, channel, text).await;
+            let zip = items.first().and_then(|(_, request)| self.zip_for(request.channel, request.guild));
+            let playlist = Playlist::new(title, items.len(), channel, Arc::clone(http), status, zip, self.messages(guild_id));
+            for (id, request) in items {
+                let reporter = Reporter::PlaylistItem(playlist.item(request.url.clone()));
+                if let Err(e) = self.run_job(http, id, request, reporter) {
+                    error!("Failed to resume job #{}: {}", id, e);
+                }
+            }
+        }
+    }
+
+    // Returns the new job's ID and its position in the queue (0 = starting now)
+    fn start_download(
+        &self,
+        http: &Arc<Http>,
+        request: DownloadRequest,
+        reporter: Reporter,
+    ) -> Result<(JobId, usize)> {
+        let id = self.jobs.next_id();
+        let saved = serde_json::to_string(&request)
+            .map_err(|e| log::error!("Failed to serialize job #{}: {}", id, e))
+            .ok();
+        self.history.record(history::NewEntry {
+            job_id: id,
+            requester: request.requester,
+            guild: request.guild,
+            channel: request.channel,
+            url: &request.url,
+            format: &request.format_label(),
+            request: saved.as_deref(),
+            title: request.metadata.title.as_deref(),
+            uploader: request.metadata.uploader.as_deref(),
+            private: !request.dm && self.private_results_for(request.channel),
+        });
+        let position = self.run_job(http, id, request, reporter)?;
+        Ok((id, position))
+    }
+
+    // Queues a job that's already recorded in the history, returning its queue position
+    fn run_job(
+        &self,
+        http: &Arc<Http>,
+        id: JobId,
+        request: DownloadRequest,
+        reporter: Reporter,
+    ) -> Result<usize> {
+        let status = match &reporter {
+            Reporter::Status(status) => status.as_ref(),
+            _ => None,
+        };
+        let anonymous = self.hides_card(&request, status);
+        let messages = self.messages(request.guild);
+        let card = JobCard::new(id, &request, messages.clone()).anonymous(anonymous);
+        // Results go to the requester alone
+        let private = !request.dm && self.private_results_for(request.channel);
+        let token = match status {
+            Some(StatusMessage::Interaction(token)) => Some(token.clone()),
+            _ => None,
+        };
+        let reply_channel = request.reply_channel();
+        // Private results go by DM, where no boosts raise the upload limit
+        let zipping = self.zip_for(request.channel, request.guild.filter(|_| !private));
+        let links = self.links.clone();
+        let in_flight = Arc::clone(&self.in_flight);
+        let release = self.in_flight.release_on_drop(id);
+        let format_label = request.format_label();
+        let priority = request.priority;
+        let DownloadRequest {
+            url, requester, requester_name, channel, guild, format, force, archive_key, playlist, subtitles, clip, metadata, dm, live, schedule, backend, split_chapters, extra_args, format_id, workaround, geo_country, subfolder, spoiler, ..
+        } = request;
+        let backend = backend.unwrap_or_default();
+        let subtitles = subtitles.or_else(|| self.subtitle_langs.clone());
+        let embed_subtitles = self.embed_subtitles;
+        let message = match &reporter {
+            Reporter::Status(status) => status.as_ref().and_then(StatusMessage::message_id),
+            Reporter::PlaylistItem(item) => item.message_id(),
+            Reporter::Digest => None,
+        };
+        let (progress_tx, progress_rx) = watch::channel(None);
+        let recording = live.then(|| Arc::new(Notify::new()));
+        let info = JobInfo {
+            id,
+            url: url.clone(),
+            requester,
+            channel,
+            guild,
+            private,
+            message,
+            started: Instant::now(),
+            state: JobState::Queued,
+            progress: progress_rx.clone(),
+            recording: recording.clone(),
+        };
+        let history = Arc::clone(&self.history);
+        let job_logs = Arc::clone(&self.job_logs);
+        let metrics = Arc::clone(&self.metrics);
+        let output_dir = prefs::with_subfolder(self.output_dir_for(channel, guild, dm.then_some(requester)), subfolder.as_deref());
+        let post_processing = self.post_processing_for(channel);
+        let transcode = self.transcode_for(channel);
+        let transcoder = Arc::clone(&self.transcoder);
+        let tagging = self.tagging.clone().filter(|_| format.is_audio()).map(|tagging| {
+            let tags = Tags::new(&metadata, playlist.as_deref(), tagging.parse_title);
+            (tagging, tags, metadata.thumbnail.clone())
+        });
+        let tagger = Arc::clone(&self.tagger);
+        let loudness = self.loudness_for(channel).filter(|_| format.is_audio());
+        let normalizer = Arc::clone(&self.normalizer);
+        let scan = self.scan_for(channel);
+        let duration = metadata.duration;
+        let transcoding = Arc::new(AtomicBool::new(false));
+        let archivist = self.archivist_for(channel);
+        // The .nfo sidecars are made from the .info.json archivist keeps
+        let sidecars = !archivist && self.media_server_layout_for(channel);
+        let output_template = match (archivist, sidecars) {
+            (true, _) => archivist::output_template(id),
+            (false, true) => nfo::OUTPUT_TEMPLATE.to_string(),
+            (false, false) => self.output_template.render(&TemplateValues {
+                requester: &requester_name,
+                requester_id: requester,
+                channel,
+            }),
+        };
+        let output_template = match clip {
+            Some(clip) => clip.apply_to_template(&output_template),
+            None => output_template,
+        };
+        // Archivist already keeps one copy of each file
+        let on_collision = self.on_collision_for(channel).filter(|_| !archivist);
+        let dedupe = self.dedupe.clone().filter(|_| !archivist);
+        let output_template = match on_collision {
+            Some(_) => collisions::output_template(id, &output_template),
+            None => output_template,
+        };
+        // Parsing checked the template itself, but not what was filled into it
+        if !paths::stays_inside(&output_template) {
+            bail!("{}", messages.get("template_outside", &[]));
+        }
+        let cookies = self.cookies_for(&url, workaround);
+        let offered = self.workarounds_after(workaround);
+        let download_archive = match (schedule, clip) {
+            (Some(_), _) => Some(self.download_archive.clone().unwrap_or_else(|| scheduler::DEFAULT_ARCHIVE.to_string())),
+            // yt-dlp's archive would skip clips of anything downloaded before, and the other way round
+            (None, Some(_)) => None,
+            (None, None) => self.download_archive.clone(),
+        };
+        let upload_results = self.upload_results;
+        let storage = Arc::clone(&self.storage);
+        let library = self.library.clone();
+        let webhooks = Arc::clone(&self.webhooks);
+        let details = JobDetails {
+            id,
+            url: url.clone(),
+            requester,
+            requester_name: requester_name.clone(),
+            channel,
+            guild,
+        };
+        let admin_channel = self.admin_channel;
+        // Not where the job already reports back, and not at all when only the requester may
+        // see what it downloaded
+        let announce = self.announce_channel_for(guild, dm)
+            .filter(|&announce| announce != reply_channel && !private)
+            .map(|announce| (announce, metadata.title.clone(), metadata.thumbnail.clone()));
+        let retry = self.retries.for_url(&url).clone();
+        let proxy = self.proxies.pick(&url);
+        let extra_args = match workaround {
+            // The flags were checked against the site's usual cookies
+            Some(Workaround::LoginCookies) => flags::without_cookie_leaks(extra_args),
+            Some(Workaround::RetryArgs) => extra_args.into_iter().chain(self.login_retry_args.iter().cloned()).collect(),
+            None => extra_args,
+        };
+        // The requester's flags go first so a site's own args win
+        let extra_args: Vec<String> = extra_args.into_iter().chain(self.site_args.for_url(&url)).collect();
+        let geo_args = self.geo.args(geo_country.as_deref());
+        let geo_retry = self.geo.retry_args(geo_country.as_deref())
+            .map(|args| (self.geo.retry_country.clone().unwrap_or_default().to_ascii_uppercase(), args));
+        let downloaders = Arc::clone(&self.downloaders);
+        let bandwidth = Arc::clone(&self.bandwidth);
+        let timeout = self.timeout_for(channel).filter(|_| !live);
+        let keep_partial_files = self.keep_partial_files;
+        let downloader_args = self.downloader_args.clone();
+        let ffprobe = self.ffprobe.clone();
+        let http = Arc::clone(http);
+        let submitted = self.queue.submit(info, priority, move |mut cancel| logging::with_job(id, async move {
+            let _release = release;
+            let editor = match &reporter {
+                Reporter::Status(Some(status)) => {
+                    let card = card.clone();
+                    let transcoding = Arc::clone(&transcoding);
+                    Some(progress::spawn_editor(
+                        Arc::clone(&http),
+                        status.clone(),
+                        move |progress| match (transcoding.load(Ordering::Relaxed), live) {
+                            (true, _) => card.render(CardState::Transcoding(Some(progress))),
+                            (false, true) => card.render(CardState::Recording(Some(progress))),
+                            (false, false) => card.render(CardState::Downloading(Some(progress))),
+                        },
+                        progress_rx,
+                    ))
+                }
+                _ => None,
+            };
+            let status_message = match &reporter {
+                Reporter::Status(Some(status)) => Some(status),
+                _ => None,
+            };
+            let meter = Meter::default();
+            let download = async {
+                let rate_limit = loop {
+                    match bandwidth.check(history::now()) {
+                        Allowance::Start(rate_limit) => break rate_limit,
+                        Allowance::DeferUntil(until) => {
+                            info!("Deferring job #{} for quiet hours until {}", id, until);
+                            if let Some(status) = status_message {
+                                let _ = status.edit_card(&http, card.render(CardState::Deferred(until))).await;
+                            }
+                            tokio::time::sleep(Duration::from_secs((until - history::now()).max(1) as u64)).await;
+                        }
+                    }
+                };
+                history.set_status(id, history::Status::Running);
+                metrics.download_started();
+                webhooks.job_started(&details);
+                if let Some(status) = status_message {
+                    let state = if live { CardState::Recording(None) } else { CardState::Downloading(None) };
+                    let _ = status.edit_card(&http, card.render(state)).await;
+                }
+                if let Some(proxy) = &proxy {
+                    log::info!("Downloading through proxy {}", proxy::redacted(proxy));
+                }
+                let started = Instant::now();
+                let job_log = job_logs.open(id);
+                let mut options = ytdlp::DownloadOptions {
+                    output_dir: &output_dir,
+                    output_template: &output_template,
+                    cookies: &cookies,
+                    format: &format,
+                    format_id: format_id.as_deref(),
+                    post_processing: &post_processing,
+                    subtitles: subtitles.as_deref(),
+                    embed_subtitles,
+                    download_archive: download_archive.as_deref(),
+                    force,
+                    only_new: schedule.is_some(),
+                    max_items: schedule.and_then(|schedule| schedule.max_items),
+                    extra_args: &extra_args,
+                    proxy: proxy.as_deref(),
+                    geo: &geo_args,
+                    live,
+                    stop: recording.as_deref(),
+                    rate_limit: rate_limit.as_deref(),
+                    downloader_args: &downloader_args,
+                    clip,
+                    // Retries count towards the same limit
+                    deadline: timeout.map(|timeout| tokio::time::Instant::now() + timeout),
+                    keep_partial_files,
+                    split_chapters,
+                    sidecars,
+                    archivist,
+                    log: job_log.as_ref(),
+                    meter: Some(&meter),
+                };
+                let mut attempt = 1;
+                let mut geo_retry = geo_retry.as_ref();
+                let result = loop {
+                    let result = downloaders.download(backend, &url, &options, &progress_tx).await
+                        .and_then(|files| paths::check_outputs(&output_dir, files));
+                    match result {
+                        Err(e) if e.is::<ytdlp::TimedOut>() => {
+                            let limit = timeout.map(|timeout| format_duration(timeout.as_secs())).unwrap_or_default();
+                            break Err(e.context(messages.get("timed_out", &[("limit", &limit)])));
+                        }
+                        // Doesn't count as an attempt, since it's a different way of asking
+                        Err(e) if geo_retry.is_some() && errors::classify(&e) == Some(errors::Failure::GeoBlocked) => {
+                            if let Some((country, args)) = geo_retry.take() {
+                                log::warn!("Job #{} is geo-blocked, retrying from {}: {}", id, country, short_error(&e));
+                                if let Some(status) = status_message {
+                                    let text = messages.get("geo_retrying", &[("country", &country)]);
+                                    let _ = status.edit_card(&http, card.render(CardState::Retrying(text))).await;
+                                }
+                                options.geo = args;
+                            }
+                        }
+                        Err(e) if retry.should_retry(attempt, &e.to_string()) => {
+                            let delay = retry.delay(attempt);
+                            log::warn!(
+                                "Attempt {}/{} of job #{} failed, retrying in {:?}: {}",
+                                attempt, retry.max_attempts, id, delay, short_error(&e)
+                            );
+                            if let Some(status) = status_message {
+                                let text = messages.get("retrying", &[
+                                    ("attempt", &attempt),
+                                    ("max", &retry.max_attempts),
+                                    ("reason", &errors::describe(&e, &messages)),
+                                    ("secs", &delay.as_secs()),
+                                ]);
+                                let _ = status.edit_card(&http, card.render(CardState::Retrying(text))).await;
+                            }
+                            tokio::time::sleep(delay).await;
+                            attempt += 1;
+                        }
+                        Err(e) if attempt > 1 => {
+                            break Err(anyhow::anyhow!("{}", messages.get("gave_up", &[("attempts", &attempt), ("error", &e)])));
+                        }
+                        result => break result,
+                    }
+                };
+                if let (Ok(files), true) = (&result, sidecars) {
+                    nfo::write_sidecars(files);
+                }
+                let elapsed = started.elapsed();
+                let result = match (result, &transcode) {
+                    (Ok(files), Some(transcode)) if !files.is_empty() => {
+                        transcoding.store(true, Ordering::Relaxed);
+                        progress_tx.send_replace(None);
+                        if let Some(status) = status_message {
+                            let _ = status.edit_card(&http, card.render(CardState::Transcoding(None))).await;
+                        }
+                        transcoder.transcode_all(files, transcode, duration, &progress_tx).await
+                    }
+                    (result, _) => result,
+                };
+                let result = match (result, &tagging) {
+                    (Ok(files), Some((tagging, tags, cover))) => Ok(tagger.tag_all(files, tags, cover.as_deref(), tagging).await),
+                    (result, _) => result,
+                };
+                // After tagging, so the measurements name the files the job ends up with
+                let (result, normalized) = match (result, &loudness) {
+                    (Ok(files), Some(loudness)) => {
+                        let (files, normalized) = normalizer.normalize_all(files, loudness).await;
+                        (Ok(files), normalized)
+                    }
+                    (result, _) => (result, Vec::new()),
+                };
+                // Catch broken files before they're archived, stored or posted
+                let result = match result {
+                    Ok(files) => verify::verify_all(&files, ffprobe.as_deref()).await.map(|verified| (files, verified, normalized)),
+                    Err(e) => Err(e),
+                };
+                // A flagged file fails the job, so it's never archived, stored or posted
+                let result = match (result, &scan) {
+                    (Ok(done), Some(scan)) => scan::scan_all(scan, &done.0, id).await.map(|()| done),
+                    (result, _) => result,
+                };
+                let result = match result {
+                    Ok((files, verified, normalized)) if archivist => archivist::file_away(&output_dir, files, &verified)
+                        .map(|files| (files, verified, normalized)),
+                    result => result,
+                };
+                let result = match (result, on_collision) {
+                    (Ok((files, mut verified, normalized)), Some(policy)) => {
+                        collisions::move_into_place(&output_dir, id, policy, files, &mut verified, &messages)
+                            .map(|(files, collided)| (files, verified, normalized, collided))
+                    }
+                    (result, _) => result.map(|(files, verified, normalized)| (files, verified, normalized, Vec::new())),
+                };
+                // Once the files are where they stay
+                let result = match (result, &dedupe) {
+                    (Ok((files, verified, normalized, mut notes)), Some(dedupe)) => {
+                        notes.extend(dedupe::link_duplicates(&output_dir, &history, dedupe, &verified, &messages));
+                        Ok((files, verified, normalized, notes))
+                    }
+                    (result, _) => result,
+                };
+                if let (Err(e), Some(log)) = (&result, &job_log) {
+                    log.line("bot", &format!("Job failed: {:#}", e));
+                }
+                (result, elapsed)
+            };
+            let mut verified = Vec::new();
+            let mut normalized = Vec::new();
+            let mut collided = Vec::new();
+            // Checked first so a job cancelled while queued never starts yt-dlp
+            let outcome = tokio::select! {
+                biased;
+                reason = cancel.cancelled() => {
+                    if reason != CancelReason::Shutdown {
+                        metrics.download_cancelled();
+                    }
+                    Outcome::Cancelled(reason)
+                }
+                (result, elapsed) = download => match result {
+                    Ok((files, checked, measured, collisions)) => {
+                        metrics.download_succeeded(total_size(&files), elapsed);
+                        verified = checked;
+                        normalized = measured;
+                        collided = collisions;
+                        Outcome::Done(files)
+                    }
+                    Err(e) => {
+                        metrics.download_failed(&url);
+                        Outcome::Failed(e)
+                    }
+                },
+            };
+            // Stop the editor first so a late progress edit can't overwrite the final state
+            if let Some(editor) = editor {
+                editor.abort();
+            }
+            // gallery-dl doesn't report progress, so its files are all there is to go by
+            let transferred = match (&outcome, meter.total()) {
+                (Outcome::Done(files), 0) => total_size(files),
+                (_, transferred) => transferred,
+            };
+            history.set_transferred(id, transferred);
+            metrics.transferred(guild, transferred);
+            match &outcome {
+                Outcome::Done(files) => {
+                    let first = verified.first();
+                    history.finish_ok(
+                        id,
+                        files.first().map(PathBuf::as_path),
+                        total_size(files),
+                        first.map(|file| file.sha256.as_str()),
+                        first.and_then(|file| file.duration),
+                    );
+                    if let (Some(key), Some(file), None) = (&archive_key, files.first(), clip) {
+                        history.archive(key, &format_label, id, file);
+                    }
+                    webhooks.job_succeeded(&details, files, total_size(files));
+                }
+                Outcome::Failed(e) => {
+                    history.finish_err(id, &e.to_string());
+                    webhooks.job_failed(&details, &report::public_error(e));
+                }
+                Outcome::Cancelled(CancelReason::User(_)) => history.set_status(id, history::Status::Cancelled),
+                Outcome::Cancelled(CancelReason::Shutdown) => history.set_status(id, history::Status::Interrupted),
+            }
+            // None when the storage backend failed
+            let stored = match &outcome {
+                Outcome::Done(files) if !files.is_empty() => {
+                    let values = TemplateValues { requester: &requester_name, requester_id: requester, channel };
+                    match storage.store(Path::new(&output_dir), files, &values).await {
+                        Ok(locations) => {
+                            let stored = describe_stored(files, &locations);
+                            history.set_locations(id, &stored);
+                            Some(stored)
+                        }
+                        Err(e) => {
+                            error!("Failed to store the files of job #{}: {:#}", id, e);
+                            None
+                        }
+                    }
+                }
+                _ => Some(Vec::new()),
+            };
+            if let (Outcome::Done(files), Some(library)) = (&outcome, &library) {
+                if !files.is_empty() {
+                    // Scans can take a while to be accepted, and nothing waits on them
+                    let (library, files) = (Arc::clone(library), files.clone());
+                    tokio::spawn(async move { library.refresh(&files).await });
+                }
+            }
+            // Whoever joined the job hears how it went where they asked, without the files
+            let waiters = in_flight.finish(id);
+            if !waiters.is_empty() {
+                let result = match &outcome {
+                    Outcome::Done(files) if files.is_empty() => messages.get("nothing_downloaded", &[("url", &url)]),
+                    Outcome::Done(_) => {
+                        let downloaded = messages.get("downloaded", &[("url", &url), ("format", &format), ("id", &id)]);
+                        std::iter::once(downloaded).chain(stored.clone().unwrap_or_default()).collect::<Vec<_>>().join("\n")
+                    }
+                    Outcome::Failed(e) => messages.get("failed", &[("url", &url), ("id", &id), ("reason", &errors::describe(e, &messages))]),
+                    Outcome::Cancelled(reason) => {
+                        let why = match reason {
+                            CancelReason::User(by) => messages.get("cancelled_by", &[("user", &by.mention())]),
+                            CancelReason::Shutdown => messages.get("interrupted", &[]),
+                        };
+                        messages.get("joined_cancelled", &[("url", &url), ("id", &id), ("reason", &why)])
+                    }
+                };
+                for waiter in waiters {
+                    if let Some(item) = waiter.item {
+                        if spoiler {
+                            item.mark_spoiler();
+                        }
+                        item.finish(outcome.for_playlist(&messages)).await;
+                        continue;
+                    }
+                    let content = truncate_message(messages.get("joined_finished", &[("user", &waiter.requester.mention()), ("result", &result)]));
+                    if waiter.private {
+                        upload::send_private(&http, waiter.requester, None, content, None).await;
+                    } else {
+                        let _ = waiter.channel.say(&http, content).await;
+                    }
+                }
+            }
+            if let (Outcome::Done(files), Some((announce, title, thumbnail))) = (&outcome, &announce) {
+                if !files.is_empty() {
+                    // Where the files ended up, or just their names when storing them failed
+                    let locations = stored.clone().filter(|stored| !stored.is_empty()).unwrap_or_else(|| {
+                        files.iter()
+                            .map(|file| format!("`{}`", file.file_name().unwrap_or(file.as_os_str()).to_string_lossy()))
+                            .collect()
+                    });
+                    let done = embed::Announcement {
+                        id,
+                        url: &url,
+                        title: title.as_deref(),
+                        thumbnail: thumbnail.as_deref(),
+                        requester,
+                        size: total_size(files),
+                        locations: &locations,
+                    };
+                    let message = CreateMessage::new().embed(done.render(&messages));
+                    if let Err(e) = announce.send_message(&http, message).await {
+                        error!("Failed to announce job #{} in {}: {}", id, announce, e);
+                    }
+                }
+            }
+            if let Outcome::Failed(error) = &outcome {
+                let report = report::FailureReport { job_id: id, url: &url, requester, channel, format: &format, error };
+                report::send_failure(&http, admin_channel, report).await;
+            }
+            let status = match reporter {
+                Reporter::Status(status) => status,
+                Reporter::Digest => {
+                    scheduler::post_digest(&http, channel, &url, &outcome, &messages).await;
+                    return;
+                }
+                Reporter::PlaylistItem(item) => {
+                    if spoiler {
+                        item.mark_spoiler();
+                    }
+                    item.finish(outcome.for_playlist(&messages)).await;
+                    return;
+                }
+            };
+            // The job ID is what admins look the full log up by
+            let admins_note = if admin_channel.is_some() {
+                format!("\n{}", messages.get("sent_to_admins", &[("id", &id)]))
+            } else {
+                format!("\n{}", messages.get("see_log", &[("id", &id)]))
+            };
+            let linked = match (&outcome, &links) {
+                (Outcome::Done(files), Some(links)) => links.describe(files, &messages),
+                _ => Vec::new(),
+            };
+            let state = match &outcome {
+                Outcome::Done(_) => {
+                    let lines = verified.iter().map(verify::Verified::describe)
+                        .chain(normalized.iter().map(loudness::Normalized::describe))
+                        .chain(collided.iter().cloned());
+                    CardState::Done(lines.chain(stored.clone().unwrap_or_default()).chain(linked.clone()).collect())
+                }
+                Outcome::Failed(e) => match errors::gate(e) {
+                    Some(gate) => CardState::Gated(format!("{}{}", gate.explain(&offered, workaround, &messages), admins_note), offered.clone()),
+                    None => CardState::Failed(format!("{}{}", errors::describe(e, &messages), admins_note)),
+                },
+                Outcome::Cancelled(CancelReason::User(by)) => CardState::Cancelled(messages.get("cancelled_by", &[("user", &by.mention())])),
+                Outcome::Cancelled(CancelReason::Shutdown) => CardState::Cancelled(messages.get("interrupted", &[])),
+            };
+            let updated = match &status {
+                Some(status) => status.edit_card(&http, card.render(state)).await.is_ok(),
+                None => false,
+            };
+            match outcome {
+                Outcome::Done(files) if files.is_empty() => {
+                    // yt-dlp exits cleanly without writing anything for videos in its archive
+                    let content = messages.get("nothing_downloaded", &[("url", &url)]);
+                    if private {
+                        upload::send_private(&http, requester, token.as_deref(), content, None).await;
+                    } else {
+                        let _ = reply_channel.say(&http, content).await;
+                    }
+                }
+                Outcome::Done(files) => {
+                    let (attachment, zipped) = match (&zipping, upload_results, private) {
+                        (Some(zipping), _, _) if files.len() > 1 => {
+                            let (attachment, line) = zipping.deliver(&http, &format!("job-{}", id), &files, spoiler, &messages).await;
+                            (attachment, Some(line))
+                        }
+                        (_, false, _) => (None, None),
+                        // DMs have no boosts raising the upload limit
+                        (_, true, false) => (upload::attachment_for(&http, guild, &files).await, None),
+                        (_, true, true) => (upload::attachment_for(&http, None, &files).await, None),
+                    };
+                    let attachment = attachment.map(|attachment| if spoiler { upload::spoiler(attachment) } else { attachment });
+                    let mut content = messages.get("downloaded", &[("url", &url), ("format", &format), ("id", &id)]);
+                    if split_chapters {
+                        // The whole video comes first, then its chapters
+                        content.push('\n');
+                        content.push_str(&messages.get("split_into_chapters", &[("count", &files.len().saturating_sub(1))]));
+                        for file in files.iter().skip(1) {
+                            let name = file.file_name().unwrap_or(file.as_os_str()).to_string_lossy();
+                            content.push_str(&format!("\n`{}`", name));
+                        }
+                    }
+                    for line in collided.iter().chain(&zipped).chain(&linked) {
+                        content.push('\n');
+                        content.push_str(line);
+                    }
+                    match &stored {
+                        Some(stored) => {
+                            for line in stored {
+                                content.push('\n');
+                                content.push_str(line);
+                            }
+                        }
+                        None => {
+                            content.push('\n');
+                            content.push_str(&messages.get("storage_failed", &[]));
+                        }
+                    }
+                    if private {
+                        upload::send_private(&http, requester, token.as_deref(), truncate_message(content), attachment).await;
+                    } else {
+                        upload::send_result(&http, reply_channel, truncate_message(content), attachment).await;
+                    }
+                }
+                // The status embed already says why
+                Outcome::Failed(_) if updated && !anonymous => {}
+                Outcome::Failed(e) => {
+                    // Without the card there are no buttons to retry with
+                    let reason = match errors::gate(&e) {
+                        Some(gate) => gate.explain(&[], workaround, &messages),
+                        None => errors::describe(&e, &messages),
+                    };
+                    let reason = format!("{}{}", reason, admins_note);
+                    let content = truncate_message(messages.get("failed", &[("url", &url), ("id", &id), ("reason", &reason)]));
+                    if private {
+                        upload::send_private(&http, requester, token.as_deref(), content, None).await;
+                    } else {
+                        let _ = reply_channel.say(&http, content).await;
+                    }
+                }
+                Outcome::Cancelled(_) => {}
+            }
+        }));
+        match submitted {
+            Ok(position) => Ok(position),
+            Err(e) => {
+                self.history.finish_err(id, &e.to_string());
+                // The queue only turns jobs away once it's closed for a shutdown
+                Err(anyhow::anyhow!(self.messages(guild).get("shutting_down", &[])))
+            }
+        }
+    }
+}
+
+async fn send_status(http: &Http, channel: ChannelId, text: String) -> Option<StatusMessage> {
+    match channel.say(http, text).await {
+        Ok(message) => Some(StatusMessage::Channel(message.channel_id, message.id)),
+        Err(e) => {
+            error!("Failed to send status message in {}: {}", channel, e);
+            None
+        }
+    }
+}
+
+// The last line of yt-dlp's output is usually the actual error
+fn short_error(error: &anyhow::Error) -> String {
+    let text = error.to_string();
+    text.lines()
+        .rev()
+        .find(|line| !line.trim().is_empty())
+        .unwrap_or("unknown error")
+        .trim()
+        .to_owned()
+}
+
+// Discord rejects messages longer than 2000 characters
+fn truncate_message(mut text: String) -> String {
+    if text.chars().count() > 2000 {
+        text = text.chars().take(1997).collect();
+        text.push_str("...");
+    }
+    text
+}
+
+// One line per stored file, linking it when the storage backend gave a URL
+fn describe_stored(files: &[PathBuf], locations: &[String]) -> Vec<String> {
+    files.iter()
+        .zip(locations)
+        .map(|(file, location)| {
+            let name = file.file_name().unwrap_or(file.as_os_str()).to_string_lossy();
+            if location.starts_with("https://") || location.starts_with("http://") {
+                format!("[{}](<{}>)", name, location)
+            } else {
+                format!("`{}`", location)
+            }
+        })
+        .collect()
+}
+
+fn total_size(files: &[PathBuf]) -> u64 {
+    files.iter()
+        .filter_map(|file| fs::metadata(file).ok())
+        .map(|metadata| metadata.len())
+        .sum()
 }
 
 fn is_valid_url(url: &str) -> bool {
-    // Basic URL validation: must start with http:// or https:// and have at least one dot
-    let re = Regex::new(r"^https?://[\w\-\.]+\.[a-zA-Z]{2,}(/\S*)?$" ).unwrap();
-    re.is_match(url)
+    // Basic URL validation: must start with http://, https:// or rtmp(s):// and have at least one dot
+    let re = Regex::new(r"^(https?|rtmps?)://[\w\-\.]+\.[a-zA-Z]{2,}(:\d+)?(/\S*)?$" ).unwrap();
+    // ...or be a magnet link naming a torrent
+    let magnet = Regex::new(r"^magnet:\?\S*\bxt=urn:btih:[0-9A-Za-z]{32,40}\b\S*$").unwrap();
+    re.is_match(url) || magnet.is_match(url)
+}
+
+// The lowercased host of an http(s) URL, without port or credentials
+fn url_host(url: &str) -> Option<String> {
+    let rest = url.split_once("://").map_or(url, |(_, rest)| rest);
+    let authority = rest.split(['/', '?', '#']).next()?;
+    let host = authority.rsplit('@').next()?;
+    let host = host.split(':').next()?;
+    (!host.is_empty()).then(|| host.to_lowercase())
+}
+
+// Whether `host` is `domain` or one of its subdomains
+fn host_matches(host: &str, domain: &str) -> bool {
+    host == domain || host.strip_suffix(domain).is_some_and(|prefix| prefix.ends_with('.'))
 }
 
 #[async_trait]
@@ -83,90 +2202,262 @@ impl EventHandler for Handler {
         if msg.author.bot {
             return;
         }
-        if let Some(guild_id) = msg.guild_id {
-            if !self.is_allowed_guild(guild_id) {
-                return;
-            }
+        let dm = msg.guild_id.is_none();
+        if dm && !self.live().auth.dms_enabled() || !dm && !self.is_allowed_location(msg.guild_id, msg.channel_id) {
+            return;
         }
-        if let Some(allowed_channel) = self.allowed_channel {
-            if msg.channel_id.get() != allowed_channel {
-                return;
-            }
+        // Everything in a DM is meant for the bot
+        let mentioned = msg.mentions_user_id(ctx.cache.current_user().id);
+        if !dm && !self.trigger_for(msg.channel_id).triggers(&msg.content, &self.prefix, mentioned) {
+            return;
         }
-        if let Some(url_match) = self.url_regex.find(&msg.content) {
-            if !is_valid_url(url_match.as_str()) {
-                let _ = msg.channel_id.say(&ctx.http, "Invalid URL.").await;
-                return;
+        // Options for a link are the words between it and the next link
+        let matches: Vec<_> = self.url_regex.find_iter(&msg.content).collect();
+        let mut seen = HashSet::new();
+        let links: Vec<_> = matches.iter()
+            .enumerate()
+            .filter(|(_, url_match)| seen.insert(url_match.as_str()))
+            .map(|(index, url_match)| {
+                let next = matches.get(index + 1).map_or(msg.content.len(), |next| next.start());
+                (url_match.as_str(), &msg.content[..url_match.start()], &msg.content[url_match.end()..next])
+            })
+            .collect();
+        let lists: Vec<_> = msg.attachments.iter().filter(|attachment| ingest::is_list_attachment(attachment)).collect();
+        let messages = self.messages(msg.guild_id);
+        if links.is_empty() && lists.is_empty() {
+            // Chatter is left alone; a link that's there but malformed is answered when it's parsed
+            let addressed = dm || mentioned || trigger::has_prefix(&msg.content, &self.prefix);
+            let reply = match (addressed, self.reply_on_no_url) {
+                (true, _) => Some("missing_url"),
+                (false, true) => Some("invalid_url"),
+                (false, false) => None,
+            };
+            if let Some(reply) = reply {
+                let _ = msg.channel_id.say(&ctx.http, messages.get(reply, &[])).await;
             }
-            if let Err(e) = msg.channel_id.say(&ctx.http, "OK! I will process that.").await {
-                log::error!("Failed to send acknowledgment: {}", e);
+            return;
+        }
+        let roles = msg.member.as_ref().map_or(&[][..], |member| &member.roles[..]);
+        let access = if dm {
+            self.live().auth.dm_access(msg.author.id)
+        } else {
+            self.member_access(msg.author.id, msg.guild_id, msg.channel_id, roles, false)
+        };
+        if access == Access::Denied {
+            let _ = msg.channel_id.say(&ctx.http, messages.get("not_allowed", &[])).await;
+            return;
+        }
+        let trusted = self.is_trusted(access, roles);
+        let boosting = msg.member.as_ref().is_some_and(|member| member.premium_since.is_some());
+        let priority = self.priority_for(access, roles, boosting);
+        // Attached lists of URLs are only read when the message has no links of its own
+        if links.is_empty() {
+            for attachment in lists {
+                self.ingest_attachment(&ctx.http, &msg, attachment, trusted, priority.for_entries()).await;
             }
-            let url = url_match.as_str().to_owned();
-            let output_dir = self.output_dir.clone();
-            let msg_channel = msg.channel_id;
-            let ctx_clone = ctx.clone();
-            let cookies_path = self.cookies_path.clone();
-            tokio::spawn(async move {
-                match download_url_with_cookies(
-                    &url,
-                    &output_dir,
-                    cookies_path.as_deref(),
-                ).await {
-                    Ok(_) => {
-                        let _ = msg_channel.say(&ctx_clone.http, format!("Downloaded: <{}>", url)).await;
+            return;
+        }
+        if let [(url, before, after)] = links[..] {
+            let request = match self.parse_request(&msg, url, before, after, trusted) {
+                Ok(request) => DownloadRequest { priority, ..request },
+                Err(reply) => {
+                    let _ = msg.channel_id.say(&ctx.http, reply).await;
+                    return;
+                }
+            };
+            let name = messages.get("thread_download", &[("url", &url.split_once("://").map_or(url, |(_, rest)| rest))]);
+            let request = DownloadRequest { thread: self.job_thread(&ctx.http, &msg, name).await, ..request };
+            // Send the acknowledgment first so the job can edit it with progress
+            let status = match request.reply_channel().say(&ctx.http, messages.get("accepted", &[])).await {
+                Ok(ack) => Some(StatusMessage::Channel(ack.channel_id, ack.id)),
+                Err(e) => {
+                    log::error!("Failed to send acknowledgment: {}", e);
+                    None
+                }
+            };
+            let update = match self.submit(&ctx.http, request, status.clone()).await {
+                Ok(Submitted::Job { position: 0, .. }) => None,
+                Ok(Submitted::Duplicate(existing)) => Some(messages.get("already_downloaded", &[
+                    ("when", &format!("<t:{}:R>", existing.downloaded_at)),
+                    ("what", &existing.describe(&messages)),
+                ])),
+                Ok(Submitted::Job { position, card, .. }) => {
+                    if let Some(status) = &status {
+                        let _ = status.edit_card(&ctx.http, card.render(CardState::Queued(position))).await;
                     }
-                    Err(e) => {
-                        let _ = msg_channel.say(&ctx_clone.http, format!("Failed to download {}: {}", url, e)).await;
+                    None
+                }
+                Ok(Submitted::Joined { id }) => Some(messages.get("joined", &[("url", &url), ("id", &id)])),
+                Ok(Submitted::Playlist { title, queued }) => {
+                    Some(messages.get("playlist_queued", &[("count", &queued), ("title", &title)]))
+                }
+                Err(e) => Some(e.to_string()),
+            };
+            if let (Some(status), Some(update)) = (status, update) {
+                let _ = status.edit(&ctx.http, update).await;
+            }
+            return;
+        }
+        // Several links become separate jobs without status messages of their own, summed up in one reply
+        let thread = self.job_thread(&ctx.http, &msg, messages.get("thread_downloads", &[("count", &links.len())])).await;
+        let reply_channel = thread.unwrap_or(msg.channel_id);
+        let text = messages.get("accepted_links", &[("count", &links.len())]);
+        let status = send_status(&ctx.http, reply_channel, text).await;
+        let mut accepted = 0;
+        let mut lines = Vec::new();
+        for (index, &(url, before, after)) in links.iter().enumerate() {
+            let result = if index >= MAX_LINKS_PER_MESSAGE {
+                Err(messages.get("too_many_links", &[("max", &MAX_LINKS_PER_MESSAGE)]))
+            } else {
+                match self.parse_request(&msg, url, before, after, trusted) {
+                    Ok(request) => {
+                        let request = DownloadRequest { thread, priority, ..request };
+                        self.submit(&ctx.http, request, None).await.map_err(|e| e.to_string())
                     }
+                    Err(reply) => Err(reply),
                 }
-            });
-        } else {
-            let _ = msg.channel_id.say(&ctx.http, "Invalid URL.").await;
+            };
+            if matches!(result, Ok(Submitted::Job { .. } | Submitted::Joined { .. } | Submitted::Playlist { .. })) {
+                accepted += 1;
+            }
+            let line = match result {
+                Ok(Submitted::Job { id, position: 0, .. }) => messages.get("link_downloading", &[("url", &url), ("id", &id)]),
+                Ok(Submitted::Job { id, position, .. }) => {
+                    messages.get("link_queued", &[("url", &url), ("id", &id), ("position", &position)])
+                }
+                Ok(Submitted::Joined { id }) => messages.get("link_joined", &[("url", &url), ("id", &id)]),
+                Ok(Submitted::Playlist { title, queued }) => {
+                    messages.get("link_playlist_queued", &[("url", &url), ("count", &queued), ("title", &title)])
+                }
+                Ok(Submitted::Duplicate(existing)) => messages.get("link_already_downloaded", &[
+                    ("url", &url),
+                    ("when", &format!("<t:{}:R>", existing.downloaded_at)),
+                    ("what", &existing.describe(&messages)),
+                ]),
+                Err(reason) => messages.get("link_failed", &[("url", &url), ("reason", &reason)]),
+            };
+            lines.push(line);
+        }
+        let summary = format!(
+            "{}\n{}",
+            messages.get("links_summary", &[("accepted", &accepted), ("count", &links.len())]),
+            lines.join("\n")
+        );
+        match status {
+            Some(status) => {
+                let _ = status.edit(&ctx.http, truncate_message(summary)).await;
+            }
+            None => {
+                let _ = reply_channel.say(&ctx.http, truncate_message(summary)).await;
+            }
         }
     }
 
-    async fn ready(&self, ctx: Context, ready: Ready) {
-        info!("Connected as {}", ready.user.name);
-        if let Some(ref allowed_guilds) = self.allowed_guild {
-            for guild in ready.guilds {
-                if guild.id.get() != *allowed_guilds {
-                    info!("Leaving unauthorized guild: {}", guild.id);
-                    if let Err(e) = guild.id.leave(&ctx.http).await {
-                        error!("Failed to leave guild {}: {}", guild.id, e);
-                    }
+    async fn reaction_add(&self, ctx: Context, reaction: Reaction) {
+        let Some(user) = reaction.user_id else {
+            return;
+        };
+        let ReactionType::Unicode(emoji) = &reaction.emoji else {
+            return;
+        };
+        // Only look up who may approve for questions meant for admins
+        let admin = self.confirmations.asks_admins(reaction.message_id) && match &reaction.member {
+            Some(member) => {
+                let moderator = auth::can_moderate(&ctx.http, reaction.channel_id, member).await;
+                self.member_access(user, reaction.guild_id, reaction.channel_id, &member.roles, moderator) >= Access::Admin
+            }
+            None => false,
+        };
+        if self.confirmations.answer(reaction.message_id, user, admin, emoji) || emoji != "❌" {
+            return;
+        }
+        let jobs = self.jobs.by_message(reaction.message_id);
+        if jobs.is_empty() {
+            return;
+        }
+        let access = match &reaction.member {
+            Some(member) => {
+                let mut access = self.member_access(user, reaction.guild_id, reaction.channel_id, &member.roles, false);
+                // Only look up permissions when someone other than the requester reacts
+                if access == Access::User && jobs.iter().any(|job| job.requester != user)
+                    && auth::can_moderate(&ctx.http, reaction.channel_id, member).await
+                {
+                    access = self.member_access(user, reaction.guild_id, reaction.channel_id, &member.roles, true);
+                }
+                access
+            }
+            None if reaction.guild_id.is_none() => self.live().auth.dm_access(user),
+            None => return,
+        };
+        if access == Access::Denied {
+            return;
+        }
+        for job in jobs {
+            if access.covers(user, reaction.guild_id, job.requester, job.guild) {
+                info!("Job #{} cancelled by {} via reaction", job.id, user);
+                self.jobs.cancel(job.id, CancelReason::User(user));
+            }
+        }
+    }
+
+    async fn interaction_create(&self, ctx: Context, interaction: Interaction) {
+        match interaction {
+            Interaction::Command(cmd) => self.on_command(&ctx, &cmd).await,
+            Interaction::Autocomplete(cmd) => self.on_autocomplete(&ctx, &cmd).await,
+            Interaction::Component(component) => self.on_component(&ctx, &component).await,
+            _ => {}
+        }
+    }
+}
+
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => {}
+                    _ = terminate.recv() => {}
+                }
+                return;
+            }
+            Err(e) => error!("Failed to listen for SIGTERM: {}", e),
+        }
+    }
+    // Closing the console window or shutting Windows down, as well as Ctrl+C
+    #[cfg(windows)]
+    {
+        use tokio::signal::windows::{ctrl_close, ctrl_shutdown};
+        match (ctrl_close(), ctrl_shutdown()) {
+            (Ok(mut close), Ok(mut shutdown)) => {
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => {}
+                    _ = close.recv() => {}
+                    _ = shutdown.recv() => {}
                 }
+                return;
             }
+            (Err(e), _) | (_, Err(e)) => error!("Failed to listen for console close events: {}", e),
         }
     }
+    let _ = tokio::signal::ctrl_c().await;
 }
 
-async fn download_url_with_cookies(
-    url: &str,
-    output_dir: &str,
-    cookies_path: Option<&str>,
-) -> Result<()> {
-    log::info!("Downloading URL: {}", url);
-    fs::create_dir_all(output_dir)
-        .with_context(|| format!("Failed to create output directory: {}", output_dir))?;
-    let mut cmd = tokio::process::Command::new("yt-dlp");
-    cmd.arg(url)
-        .arg("-P").arg(output_dir)
-        .arg("-o").arg("%(id)s.%(ext)s"); // Use video id as filename
-    if let Some(cookies) = cookies_path {
-        log::info!("Using cookies file: {}", cookies);
-        cmd.arg("--cookies").arg(cookies);
+// Tells each channel which of its downloads were left unfinished by the shutdown
+async fn announce_restart(handler: &Handler, http: &Http, unfinished: &[JobInfo], resume: bool) {
+    let mut by_channel: HashMap<(ChannelId, Option<GuildId>), Vec<JobId>> = HashMap::new();
+    for job in unfinished {
+        by_channel.entry((job.channel, job.guild)).or_default().push(job.id);
     }
-    cmd.stdout(Stdio::null());
-    cmd.stderr(Stdio::piped());
-    let child = cmd.spawn()
-        .with_context(|| "Failed to spawn yt-dlp process")?;
-    let output = child.wait_with_output().await
-        .with_context(|| "Failed to wait for yt-dlp process")?;
-    if output.status.success() {
-        Ok(())
-    } else {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        Err(anyhow::anyhow!("yt-dlp failed with status: {}\nError output: {}", output.status, stderr.trim()))
+    for ((channel, guild), mut ids) in by_channel {
+        ids.sort_unstable();
+        let ids: Vec<String> = ids.iter().map(|id| format!("#{}", id)).collect();
+        let key = if resume { "restarting_resume" } else { "restarting_saved" };
+        let text = handler.messages(guild).get(key, &[("count", &ids.len()), ("jobs", &ids.join(", "))]);
+        if let Err(e) = channel.say(http, truncate_message(text)).await {
+            error!("Failed to post restart notice in {}: {}", channel, e);
+        }
     }
 }
 
@@ -176,22 +2467,249 @@ async fn main() -> Result<()> {
     if std::env::var("RUST_LOG").is_err() {
         std::env::set_var("RUST_LOG", "serenity=warn,ytdlp_output_rs=info");
     }
-    env_logger::init();
     let settings = Settings::from_env_and_file()
         .context("Failed to load configuration from file or environment")?;
-    let url_regex = Regex::new(r"https?://\S+")
+    logging::init(settings.log_format);
+    let url_regex = Regex::new(r"((https?|rtmps?)://|magnet:\?)\S+")
         .context("Failed to compile URL regex")?;
-    let handler = Handler {
-        url_regex,
-        output_dir: settings.output_dir.clone(),
-        allowed_guild: settings.guild_id,
-        allowed_channel: settings.channel_id,
-        cookies_path: settings.cookies_path.clone(),
+    let live = Reloadable::new(&settings)?;
+    let output_template = match &settings.output_template {
+        Some(template) => OutputTemplate::parse(template).context("Invalid output_template")?,
+        None => OutputTemplate::default(),
+    };
+    binary::init(settings.ytdlp_path.as_deref(), &settings.ytdlp_dir, settings.ytdlp_auto_download).await
+        .context("Failed to set up yt-dlp")?;
+    sandbox::init(&settings.sandbox, &settings.output_dir).context("Invalid sandbox settings")?;
+    settings.geo.validate().map_err(anyhow::Error::msg).context("Invalid geo settings")?;
+    let subtitle_langs = settings.subtitle_langs.as_deref()
+        .map(format::parse_subtitle_langs)
+        .transpose()
+        .context("Invalid subtitle_langs")?;
+    let bandwidth = Bandwidth::new(settings.rate_limit.as_deref(), &settings.quiet_hours)?;
+    let external_downloader = settings.external_downloader.as_deref()
+        .map(|downloader| binary::find_executable("external_downloader", downloader))
+        .transpose()?;
+    if let Some(downloader) = &external_downloader {
+        log::info!("Using external downloader {}", downloader.display());
+    }
+    let library = settings.library.as_ref()
+        .map(|library| Library::new(&settings.output_dir, library).map(Arc::new))
+        .transpose()?;
+    let downloaders = Downloaders::new(
+        settings.gallery_dl.as_ref(),
+        settings.ffmpeg_path.as_deref().unwrap_or("ffmpeg"),
+        settings.torrent.as_ref(),
+    )?;
+    let schedules = settings.schedules.iter()
+        .map(Schedule::new)
+        .collect::<Result<Vec<_>>>()?;
+    let retention_rules = settings.retention.iter()
+        .map(|retention| retention::Rule::new(&settings.output_dir, retention))
+        .collect::<Result<Vec<_>>>()?;
+    let cookie_config = settings.cookie_config();
+    for file in cookie_config.missing_files() {
+        log::warn!("Cookies file {} doesn't exist", file);
+    }
+    let storage = storage::from_settings(&settings.storage).context("Invalid storage settings")?;
+    let history = Arc::new(History::open(&settings.database_path)?);
+    let guilds = Guilds::new(&history)?;
+    let prefs = Prefs::new(&history)?;
+    if let Some(dedupe) = &settings.dedupe {
+        let interval = Duration::from_secs(dedupe.interval_mins.max(1) * 60);
+        tokio::spawn(dedupe::run(settings.output_dir.clone(), Arc::clone(&history), interval));
+    }
+    if !retention_rules.is_empty() {
+        let interval = Duration::from_secs(settings.retention_interval_mins.max(1) * 60);
+        tokio::spawn(retention::run(retention_rules, Arc::clone(&history), interval));
+    }
+    let jobs = Arc::new(JobRegistry::starting_after(history.last_job_id()?));
+    let queue = Arc::new(DownloadQueue::new(settings.max_concurrent_downloads, Arc::clone(&jobs)));
+    let metrics = Arc::new(Metrics::default());
+    let gateway = Arc::new(Gateway::new());
+    if let Some(addr) = settings.health_addr.clone() {
+        let health = Arc::new(health::Health {
+            gateway: Arc::clone(&gateway),
+            queue: Arc::clone(&queue),
+            output_dir: settings.output_dir.clone(),
+        });
+        tokio::spawn(async move {
+            if let Err(e) = health::serve(&addr, health).await {
+                error!("{:#}", e);
+            }
+        });
+    }
+    // Nothing would serve what's linked
+    if settings.links.is_some() && settings.dashboard_addr.is_none() {
+        bail!("[links] needs dashboard_addr, which serves the links");
+    }
+    if settings.zip.public_url.is_some() && settings.dashboard_addr.is_none() {
+        bail!("zip.public_url needs dashboard_addr, which serves the zips");
+    }
+    let links = settings.links.clone().map(|links| Links::new(links, &history)).transpose()?.map(Arc::new);
+    // Kept across client restarts for everything posting outside of event handlers
+    let http = Arc::new(Http::new(&settings.discord_token));
+    // Other guilds' downloads mustn't show up in it
+    let announce_channel = match settings.announce_channel_id.map(ChannelId::new) {
+        Some(channel) => match channel.to_channel(&http).await.context("Failed to look up announce_channel_id")? {
+            Channel::Guild(announce) => Some((channel, announce.guild_id)),
+            _ => bail!("announce_channel_id must be a channel in a server"),
+        },
+        None => None,
     };
-    let mut client = Client::builder(&settings.discord_token, GatewayIntents::GUILD_MESSAGES | GatewayIntents::MESSAGE_CONTENT)
-        .event_handler(handler)
-        .await
-        .context("Failed to create Discord client")?;
-    client.start().await.context("Discord client exited with error")?;
-    Ok(())
+    let handler = Arc::new(Handler {
+        url_regex,
+        live: RwLock::new(Arc::new(live)),
+        cookies: Cookies::new(cookie_config),
+        login_retry_args: settings.login_retry_args.clone(),
+        geo: settings.geo.clone(),
+        jobs,
+        queue: Arc::clone(&queue),
+        history,
+        feeds: Feeds::new(settings.feeds.clone()),
+        zips: Arc::new(Zips::new(settings.zip.clone())),
+        links,
+        in_flight: Arc::default(),
+        job_logs: Arc::new(JobLogs::new(&settings.job_log_dir, settings.job_logs_kept)?),
+        metrics,
+        guilds,
+        prefs,
+        upload_results: settings.upload_results,
+        job_threads: settings.job_threads,
+        private_results: settings.private_results,
+        trigger: settings.trigger,
+        prefix: settings.prefix.clone(),
+        reply_on_no_url: settings.reply_on_no_url,
+        retries: RetryPolicies::new(settings.retry.clone(), settings.site_retries.clone()),
+        site_args: SiteArgs::new(&settings.site_args),
+        proxies: Proxies::new(settings.proxy.as_ref(), &settings.site_proxies),
+        downloaders: Arc::new(downloaders),
+        bandwidth: Arc::new(bandwidth),
+        max_download_secs: settings.max_download_secs,
+        keep_partial_files: settings.keep_partial_files,
+        downloader_args: ytdlp::downloader_args(
+            settings.concurrent_fragments,
+            external_downloader.as_deref(),
+            settings.external_downloader_args.as_deref(),
+        ),
+        ffprobe: settings.verify_with_ffprobe
+            .then(|| settings.ffprobe_path.clone().unwrap_or_else(|| "ffprobe".to_string())),
+        output_template,
+        media_server_layout: settings.media_server_layout,
+        archivist: settings.archivist,
+        on_collision: settings.on_collision,
+        dedupe: settings.dedupe.clone(),
+        download_archive: settings.download_archive.clone(),
+        resume_jobs: settings.resume_jobs,
+        resumed: AtomicBool::new(false),
+        pause_suspends_running: settings.pause_suspends_running,
+        started: Instant::now(),
+        min_free_bytes: settings.min_free_bytes,
+        estimate_size: settings.estimate_size,
+        confirm_above_bytes: settings.confirm_above_bytes,
+        budget_approval: settings.budget_approval,
+        confirmations: Confirmations::default(),
+        admin_channel: settings.admin_channel.map(ChannelId::new),
+        announce_channel,
+        disk_warned: Mutex::default(),
+        post_processing: settings.post_processing.clone(),
+        transcode: settings.transcode.clone(),
+        library,
+        webhooks: Arc::new(Webhooks::new(&settings.webhooks)),
+        transcoder: Arc::new(Transcoder::new(
+            settings.ffmpeg_path.as_deref().unwrap_or("ffmpeg"),
+            settings.max_concurrent_transcodes,
+        )),
+        tagging: settings.tagging.clone(),
+        tagger: Arc::new(Tagger::new(settings.ffmpeg_path.as_deref().unwrap_or("ffmpeg"))),
+        loudness: settings.loudness.clone(),
+        normalizer: Arc::new(Normalizer::new(settings.ffmpeg_path.as_deref().unwrap_or("ffmpeg"))),
+        scan: settings.scan.clone(),
+        storage,
+        subtitle_langs,
+        embed_subtitles: settings.embed_subtitles,
+        gateway,
+    });
+    if let Some(addr) = settings.dashboard_addr.clone() {
+        let token = settings.dashboard_token.clone()
+            .filter(|token| !token.is_empty())
+            .context("dashboard_token must be set to enable the dashboard")?;
+        let dashboard = Arc::new(web::Dashboard {
+            jobs: Arc::clone(&handler.jobs),
+            queue: Arc::clone(&queue),
+            history: Arc::clone(&handler.history),
+            metrics: Arc::clone(&handler.metrics),
+            output_dir: settings.output_dir.clone(),
+            token,
+            handler: Arc::clone(&handler),
+            http: Arc::clone(&http),
+        });
+        tokio::spawn(async move {
+            if let Err(e) = web::serve(&addr, dashboard).await {
+                error!("{:#}", e);
+            }
+        });
+    }
+    if !schedules.is_empty() {
+        info!("Running {} scheduled download(s)", schedules.len());
+        tokio::spawn(scheduler::run(Arc::clone(&handler), Arc::clone(&http), schedules));
+    }
+    tokio::spawn(feeds::run(Arc::clone(&handler), Arc::clone(&http)));
+    if let Some(watch) = settings.watch.clone() {
+        tokio::spawn(ingest::run(Arc::clone(&handler), Arc::clone(&http), watch));
+    }
+    let notify_after = Duration::from_secs(settings.reconnect.notify_after_mins * 60);
+    tokio::spawn({
+        let handler = Arc::clone(&handler);
+        let http = Arc::clone(&http);
+        async move { handler.watch_gateway(http, notify_after).await }
+    });
+    // One stop per client, so each is told to shut down
+    let stops: Vec<Arc<Notify>> = (0..=settings.bots.len()).map(|_| Arc::new(Notify::new())).collect();
+    let grace = Duration::from_secs(settings.shutdown_grace_secs);
+    let resume_jobs = settings.resume_jobs;
+    tokio::spawn({
+        let stops = stops.clone();
+        let handler = Arc::clone(&handler);
+        async move {
+            shutdown_signal().await;
+            info!("Shutting down, giving running downloads {}s to finish", grace.as_secs());
+            // Suspended downloads couldn't finish in the grace period
+            ytdlp::suspend_processes(false);
+            let unfinished = queue.shutdown(grace).await;
+            announce_restart(&handler, &http, &unfinished, resume_jobs).await;
+            for stop in stops {
+                stop.notify_one();
+            }
+        }
+    });
+    let intents = GatewayIntents::GUILD_MESSAGES
+        | GatewayIntents::MESSAGE_CONTENT
+        | GatewayIntents::GUILD_MESSAGE_REACTIONS
+        | GatewayIntents::DIRECT_MESSAGES
+        | GatewayIntents::DIRECT_MESSAGE_REACTIONS;
+    // The other bots failing for good leaves the main one running
+    let others: Vec<_> = settings.bots.iter()
+        .zip(&stops[1..])
+        .map(|(bot, stop)| {
+            info!("Also connecting as {}", bot.name);
+            let client = Arc::new(Bot::new(bot, Arc::clone(&handler)));
+            let (token, shard_count, reconnect, stop) = (bot.token.clone(), bot.shard_count, settings.reconnect.clone(), Arc::clone(stop));
+            tokio::spawn(async move {
+                let name = client.name.clone();
+                if let Err(e) = supervisor::run(client, &token, intents, shard_count, &reconnect, stop).await {
+                    error!("Bot {} stopped: {:#}", name, e);
+                }
+            })
+        })
+        .collect();
+    let main_bot = Arc::new(Bot::main(handler));
+    let result = supervisor::run(main_bot, &settings.discord_token, intents, settings.shard_count, &settings.reconnect, Arc::clone(&stops[0])).await;
+    // Also when the main bot gave up, so the process doesn't linger on the others
+    for stop in &stops[1..] {
+        stop.notify_one();
+    }
+    for other in others {
+        let _ = other.await;
+    }
+    result
 }
@@ -0,0 +1,480 @@
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+// The [messages] section of the config
+#[derive(Debug, Clone, Deserialize)]
+pub struct MessageSettings {
+    // Used where a guild didn't pick a language of its own
+    #[serde(default = "default_language")]
+    pub language: String,
+    // Where the <language>.toml files are
+    #[serde(default = "default_dir")]
+    pub dir: String,
+}
+
+impl Default for MessageSettings {
+    fn default() -> Self {
+        MessageSettings { language: default_language(), dir: default_dir() }
+    }
+}
+
+fn default_language() -> String {
+    "en".to_string()
+}
+
+fn default_dir() -> String {
+    "messages".to_string()
+}
+
+// What the bot says in its replies, job cards and posts, in English. A language file replaces
+// any of these by key, and whatever it leaves out stays English. {name} is filled in with a value.
+const ENGLISH: &[(&str, &str)] = &[
+    ("invalid_url", "Invalid URL."),
+    ("not_allowed", "Sorry, you're not allowed to request downloads."),
+    ("not_allowed_here", "This bot isn't enabled in this channel."),
+    ("not_allowed_bot", "Sorry, you're not allowed to use this bot."),
+    ("missing_url", "Missing URL."),
+    ("region_not_trusted", "Only trusted roles may pick a region."),
+    ("flags_not_trusted", "Only trusted roles may pass yt-dlp flags."),
+    ("accepted", "OK! I will process that."),
+    ("looking_up", "OK! Looking up <{url}>..."),
+    ("accepted_links", "OK! I will process those {count} links."),
+    ("already_downloaded", "Already downloaded {when} ({what}). Add `force` after the URL to download it again."),
+    ("already_downloaded_force_option", "Already downloaded {when} ({what}). Set `force` to download it again."),
+    ("joined", "OK! <{url}> is already downloading as job #{id}; you'll be told when it's done."),
+    ("joined_finished", "{user} {result}"),
+    ("joined_cancelled", "Job #{id} for <{url}> was stopped: {reason}"),
+    ("playlist_queued", "OK! Queued {count} items from playlist **{title}**."),
+    ("playlist_queued_as", "OK! Queued {count} items from playlist **{title}** ({format})."),
+    ("too_many_links", "only {max} links are taken per message"),
+    ("links_summary", "Accepted {accepted} of {count} links:"),
+    ("link_downloading", "✅ <{url}>: job #{id}, downloading"),
+    ("link_queued", "✅ <{url}>: job #{id}, position {position} in queue"),
+    ("link_playlist_queued", "✅ <{url}>: queued {count} items from playlist **{title}**"),
+    ("link_joined", "🔗 <{url}>: already downloading as job #{id}, joined it"),
+    ("link_already_downloaded", "⏭️ <{url}>: already downloaded {when} ({what})"),
+    ("link_failed", "❌ <{url}>: {reason}"),
+    ("disk_full", "Sorry, the disk is almost full, so downloads are paused until an admin frees up space."),
+    ("too_big_for_disk", "Sorry, this download (about {size}) would leave too little disk space ({free} to spare)."),
+    ("resuming", "Resuming job #{id} after a restart: <{url}>"),
+    ("resuming_playlist", "Resuming {count} item(s) of playlist **{title}** after a restart."),
+    ("geo_retrying", "Not available where the bot is, retrying from {country}"),
+    ("cancelled_by", "Cancelled by {user}"),
+    ("interrupted", "Interrupted by a restart"),
+    ("nothing_downloaded", "Nothing downloaded for <{url}>; it's probably in the download archive already."),
+    ("downloaded", "Downloaded: <{url}> ({format}, job #{id})"),
+    ("split_into_chapters", "Split into {count} chapters:"),
+    ("storage_failed", "Storing it with the storage backend failed."),
+    ("failed", "Failed to download <{url}> (job #{id}): {reason}"),
+    ("ytdlp_only", "Time ranges, audio extraction, chapter splitting, formats, regions and yt-dlp flags only work with yt-dlp, not {backend}."),
+    ("clip_playlist", "Time ranges only work for single videos, not playlists."),
+    ("clip_live", "Time ranges don't work for live streams."),
+    ("confirm_size", "{user} <{url}> is about {size}. React {confirm} within {minutes} minutes to download it anyway, or {reject} to drop it."),
+    ("confirm_rejected", "OK, not downloading <{url}>."),
+    ("confirm_timed_out", "No confirmation for <{url}>, so it wasn't downloaded."),
+    ("budget_approval", "<{url}> requested by {user} would go over {budget}. An admin can react {confirm} within {minutes} minutes to allow it, or {reject} to refuse."),
+    ("budget_approval_sized", "<{url}>, about {size}, requested by {user} would go over {budget}. An admin can react {confirm} within {minutes} minutes to allow it, or {reject} to refuse."),
+    ("budget_timed_out", "No admin approved <{url}> in time, so it wasn't downloaded. {reason}"),
+    ("template_outside", "The output template for this job leads outside the output directory."),
+    ("timed_out", "Timed out: the download took longer than {limit}"),
+    ("retrying", "Attempt {attempt}/{max} failed ({reason}), retrying in {secs}s"),
+    ("gave_up", "Gave up after {attempts} attempts: {error}"),
+    ("sent_to_admins", "The full error was sent to the admins as job #{id}."),
+    ("see_log", "Admins can see the full log with `/log {id}`."),
+    ("restarting_resume", "The bot is restarting. {count} download(s) here didn't finish and will resume when it's back: {jobs}"),
+    ("restarting_saved", "The bot is restarting. {count} download(s) here didn't finish and were saved: {jobs}"),
+    // Why a download failed
+    ("failure_geo_blocked", "The site doesn't make this video available where the bot is."),
+    ("failure_private", "This video is private."),
+    ("failure_removed", "This video was removed or doesn't exist."),
+    ("failure_rate_limited", "The site is limiting how often the bot can download; try again later."),
+    ("failure_unsupported", "The bot doesn't know how to download from this URL."),
+    ("failure_disk_full", "The bot ran out of disk space."),
+    ("failure_ffmpeg_missing", "The bot needs ffmpeg for this, and it isn't installed."),
+    ("failure_quarantined", "The virus scanner flagged this download, so it was quarantined for the admins to look at."),
+    ("gate_age_restricted", "The site only shows this video to signed-in adults."),
+    ("gate_login_required", "The site wants an account to show this video; it may be members-only or behind a bot check."),
+    ("gate_retry_either", "Use the buttons below to retry signed in, or another way."),
+    ("gate_retry_signed_in", "Use the button below to retry signed in."),
+    ("gate_retry_other_way", "Use the button below to retry another way."),
+    ("gate_signed_in_failed", "Retrying signed in didn't get past it."),
+    ("gate_other_way_failed", "Retrying another way didn't get past it."),
+    ("gate_set_login_cookies", "An admin can set login_cookies to the cookies of an account that can see it."),
+    // Job cards
+    ("progress_of", "{percent}% of {total}"),
+    ("progress_speed", "at {speed}/s"),
+    ("progress_eta", "ETA {eta}"),
+    ("button_cancel", "Cancel"),
+    ("button_retry", "Retry"),
+    ("button_retry_signed_in", "Retry signed in"),
+    ("button_retry_other_way", "Retry another way"),
+    ("button_pin", "Pin"),
+    ("button_link", "Get link"),
+    ("card_queued", "Queued at position {position}"),
+    ("card_deferred", "Waiting for quiet hours to end; starts <t:{until}:t> (<t:{until}:R>)"),
+    ("card_downloading", "Downloading: {progress}"),
+    ("card_downloading_started", "Downloading..."),
+    ("card_recording", "Still recording, {size} so far. Use `/stream stop` to end it."),
+    ("card_recording_started", "Recording the live stream... Use `/stream stop` to end it."),
+    ("card_transcoding", "Transcoding: {progress}"),
+    ("card_transcoding_started", "Transcoding..."),
+    ("card_retrying", "Retrying..."),
+    ("card_done", "Done"),
+    ("card_failed", "Failed: {reason}"),
+    ("card_done_private", "Done; the requester was sent the results."),
+    ("card_failed_private", "Failed; the requester was sent the details."),
+    ("card_title_private", "Download #{id}"),
+    ("card_footer_private", "Job #{id}"),
+    ("card_footer", "Job #{id} · {format} · requested by {user}"),
+    ("field_uploader", "Uploader"),
+    ("field_duration", "Duration"),
+    // Announcements, new uploads of subscriptions, /probe and /history
+    ("announce_requested_by", "Requested by"),
+    ("announce_size", "Size"),
+    ("announce_files", "Files"),
+    ("announce_footer", "Job #{id}"),
+    ("and_more", "...and {count} more"),
+    ("new_upload_from", "New from {feed}"),
+    ("probe_playlist", "Playlist of {count} videos; each one is downloaded as its own job."),
+    ("probe_live", "Live now. Downloading it records the stream until it ends or is stopped."),
+    ("probe_audio_only", "audio only"),
+    ("probe_available", "Available"),
+    ("probe_sizes", "Estimated sizes"),
+    ("probe_no_sizes", "The site doesn't report sizes."),
+    ("probe_nothing_downloaded", "Nothing was downloaded. Pick a format with `/download`."),
+    ("probe_nothing_downloaded_menu", "Nothing was downloaded. Pick a format with `/download`, or one of the site's own from the menu below."),
+    ("history_title", "Download history"),
+    ("history_by", "{link} by {uploader}"),
+    ("history_deleted", "(deleted)"),
+    ("history_filters", "Filters"),
+    ("history_footer", "Page {page}/{pages} · {total} downloads"),
+    // Playlists, and why one of their items wasn't downloaded
+    ("playlist_progress", "Playlist **{title}**: {done}/{total} items done"),
+    ("playlist_progress_failed", "Playlist **{title}**: {done}/{total} items done ({failed} failed)"),
+    ("playlist_finished", "Finished playlist **{title}**: {done}/{total} items downloaded."),
+    ("playlist_failures", "{count} failed:"),
+    ("playlist_failure", "- <{url}>: {reason}"),
+    ("item_untitled", "{url} (item {index})"),
+    ("item_unavailable", "unavailable"),
+    ("item_over_quota", "over the hourly download quota"),
+    ("item_cancelled", "cancelled"),
+    ("item_cancelled_by", "cancelled by {user}"),
+    ("item_interrupted", "interrupted by a restart"),
+    ("item_invalid_url", "not a valid URL"),
+    // Lists of URLs in an attachment or the watch folder
+    ("list_too_big", "`{name}` is too big to read as a list of URLs."),
+    ("list_unreadable", "Failed to read `{name}`."),
+    ("list_empty", "No URLs found in `{name}`."),
+    ("list_queuing", "OK! Queuing {count} URL(s) from `{name}`..."),
+    ("list_truncated", "Only the first {max} of a list are taken; {left} were left out."),
+    // Zips of several files
+    ("zip_failed", "Couldn't zip the files: {error}"),
+    ("zip_attached", "Zipped into {zip}, attached."),
+    ("zip_linked", "Zipped into {zip}: <{link}> (expires <t:{expires}:R>)"),
+    ("zip_too_big", "Zipped into {zip}, too big to attach; there's no zip.public_url to link it from."),
+    // Quotas, budgets and moderation
+    ("quota_downloads", "Sorry, you've reached your quota of {limit} downloads per hour. Try again at <t:{retry_at}:t>."),
+    ("quota_bytes", "Sorry, you've reached your quota of {limit} per day. Try again at <t:{retry_at}:t>."),
+    ("quota_only_lowered", "{key} can only be lowered from the bot's {limit}."),
+    ("budget_over_user", "Sorry, this download would go over your storage budget: {used} of {limit} used."),
+    ("budget_over_user_sized", "Sorry, this download (about {size}) would go over your storage budget: {used} of {limit} used."),
+    ("budget_over_channel", "Sorry, this download would go over this channel's storage budget: {used} of {limit} used."),
+    ("budget_over_channel_sized", "Sorry, this download (about {size}) would go over this channel's storage budget: {used} of {limit} used."),
+    ("budget_summary_user", "the requester's storage budget, {used} of {limit} used"),
+    ("budget_summary_channel", "the channel's storage budget, {used} of {limit} used"),
+    ("budget_used_of", "{used} of {limit}"),
+    ("budget_usage", "Storage used: {user} by you, {channel} in this channel."),
+    ("domain_blocked", "Sorry, downloads from {host} are blocked."),
+    ("domain_not_allowed", "Sorry, downloads from {host} aren't allowed."),
+    ("nsfw_domain", "Sorry, downloads from {host} only work in NSFW channels."),
+    ("nsfw_age_restricted", "Sorry, this video is age-restricted, so it can only be downloaded in NSFW channels."),
+    ("blocked_keyword", "Sorry, this video is blocked by the server's content rules."),
+    // yt-dlp flags passed after `--`
+    ("flags_too_many", "At most {max} yt-dlp flags can be passed."),
+    ("flags_not_a_flag", "`{flag}` isn't a yt-dlp flag."),
+    ("flags_not_allowed", "The yt-dlp flag `{flag}` isn't allowed."),
+    ("flags_leak_cookies", "`{flag}` would save the cookies used for this site, so it isn't allowed here."),
+    ("flags_no_value", "`{flag}` doesn't take a value."),
+    ("flags_needs_value", "`{flag}` needs a value."),
+    ("flags_invalid_value", "Invalid value for `{flag}`."),
+    // Subscriptions
+    ("archived_imported", "imported from another archive"),
+    ("archived_job", "job #{id}: `{path}`"),
+    ("episode_queued", "Queued as job #{id}."),
+    ("episode_duplicate", "Already downloaded ({what})."),
+    ("episode_joined", "Already downloading as job #{id}."),
+    ("episode_playlist", "Queued {count} items."),
+    ("episode_failed", "Not downloaded: {reason}"),
+    ("episodes_skipped", "🎙️ **{feed}** has {count} more new episode(s) that weren't downloaded, since only {max} are taken per check:"),
+    ("episode_skipped", "- {title} <{url}>"),
+    ("untitled", "Untitled"),
+    ("subscription", "**#{id}** {name} <{url}> in <#{channel}>"),
+    ("subscription_youtube", "**#{id}** YouTube channel {name} <{url}> in <#{channel}>"),
+    ("subscription_check_failed", "last check failed: {error}"),
+    ("subscription_checked", "checked <t:{when}:R>"),
+    // /usage and /stats
+    ("this_server", "this server"),
+    ("the_bot", "the bot"),
+    ("period_today", "today (UTC)"),
+    ("period_week", "in the last 7 days"),
+    ("period_month", "in the last 30 days"),
+    ("stats_total", "**{jobs}** job(s) and **{bytes}** transferred by {place} in the last {weeks} week(s)."),
+    ("stats_top_requesters", "**Top requesters**"),
+    ("stats_requester", "<@{user}>: {jobs} job(s), {bytes}"),
+    ("stats_top_sites", "**Top sites**"),
+    ("stats_site", "{site}: {jobs} job(s), {bytes}"),
+    ("stats_per_week", "**Per week**"),
+    ("stats_week", "Week of {week}: {gb} GB"),
+    // Scheduled downloads
+    ("digest", "**{count} new** from <{url}>:"),
+    ("digest_failed", "Scheduled download of <{url}> failed: {reason}"),
+    // Requests
+    ("shutting_down", "The bot is shutting down and not accepting new downloads."),
+    ("thread_download", "Download: {url}"),
+    ("thread_downloads", "{count} downloads"),
+    ("audio_prefix_conflict", "`audio:` can't be combined with {format}."),
+    ("audio_option_conflict", "The audio option can't be combined with {format}."),
+    ("backend_unavailable", "{backend} isn't set up on this bot."),
+    // Notes on a finished download's files
+    ("collision", "`{name}` was already there: {outcome} ({policy})"),
+    ("collision_overwritten", "replaced it with the new download"),
+    ("collision_skipped", "kept it and dropped the new download"),
+    ("collision_identical", "the new download was identical, so only this copy was kept"),
+    ("collision_renamed", "saved the new download beside it as `{name}`"),
+    ("deduped_hardlink", "`{name}` was identical to `{original}`, so it's now a hardlink to it"),
+    ("deduped_symlink", "`{name}` was identical to `{original}`, so it's now a symlink to it"),
+    ("download_link", "[Download `{name}`]({url}) ({size}, expires <t:{expires}:R>)"),
+    // Slash commands
+    ("unknown_command", "Unknown command: {name}"),
+    ("not_a_time_range", "'{range}' isn't a time range like 1:23-2:45."),
+    ("priority_admins_only", "Only admins can pick a download's priority."),
+    ("missing_query", "Missing search query."),
+    ("searching", "Searching for **{query}**..."),
+    ("no_results", "No results for **{query}**."),
+    ("search_placeholder", "Pick a video to download"),
+    ("results", "Results for **{query}**:"),
+    ("search_failed", "Search failed: {reason}"),
+    ("format_placeholder", "Download a specific format"),
+    ("probe_failed", "Failed to look up <{url}>: {reason}"),
+    ("downloading_format", "OK! Downloading format `{format}` of <{url}>..."),
+    ("no_such_download", "No download #{id} in the history."),
+    ("history_unreadable", "Couldn't read the download history."),
+    ("links_deleted", "The files of #{id} were deleted by the retention policy."),
+    ("links_downloaded_from", "#{id} was downloaded from <{url}>."),
+    ("links_no_files", "It didn't leave any files."),
+    ("links_unreadable", "Couldn't read where its files were stored."),
+    ("retry_running", "Job #{id} is still running."),
+    ("retry_not_saved", "Job #{id} can't be retried; its request wasn't saved."),
+    ("retry_unreadable", "Job #{id} can't be retried."),
+    ("retry_not_yours", "Job #{id} was requested by {user}; only they or an admin can retry it."),
+    ("retrying_url", "OK! Retrying <{url}>..."),
+    ("usage_admins_only", "Only admins can see the bot's usage."),
+    ("usage_operators_only", "Only the bot's admins can see the usage of every server."),
+    ("usage_total", "**{bytes}** transferred by {place} {period}, over {jobs} job(s)."),
+    ("usage_servers", "**Servers**"),
+    ("usage_direct_messages", "Direct messages"),
+    ("usage_server", "{name}: {bytes}"),
+    ("usage_users", "**Users**"),
+    ("usage_user", "{user}: {bytes}"),
+    ("stats_operators_only", "Only the bot's admins can see the stats of every server."),
+    ("log_operators_only", "Only the bot's admins can read job logs."),
+    ("missing_job", "Missing job ID."),
+    ("no_log", "There's no log for job #{id}; it may have been rotated out."),
+    ("log_of", "Log of job #{id}:"),
+    ("archive_operators_only", "Only the bot's admins can export or import the archive."),
+    ("archive_unreadable", "Failed to read the archive."),
+    ("archive_too_big", "The archive is {size}, too big to attach."),
+    ("archive_exported", "The archive, with {count} entries:"),
+    ("missing_archive", "Missing archive file."),
+    ("archive_import_too_big", "`{name}` is too big to import."),
+    ("archive_importing", "Importing `{name}`..."),
+    ("archive_imported", "Imported `{name}`: {added} new entries, {known} already in the archive."),
+    ("archive_skipped", "{count} lines weren't archive entries and were skipped."),
+    ("archive_import_failed", "Failed to import the archive."),
+    ("cookies_operators_only", "Only the bot's admins can reload cookies."),
+    ("config_unreadable", "Failed to read the config: {error}"),
+    ("cookies_reloaded", "Reloaded cookies: {cookies}."),
+    ("cookies_missing", "These files don't exist: {files}"),
+    ("config_admins_only", "Only admins can change the server's settings."),
+    ("config_in_server", "Use /config in a server."),
+    ("missing_setting", "Missing setting."),
+    ("missing_value", "Missing value."),
+    ("config_settings", "Settings for this server:"),
+    ("reload_operators_only", "Only the bot's admins can reload the settings."),
+    ("reloaded", "Reloaded the output directory, channels, guilds, default formats, quotas, roles, allowed and blocked users and domains, and cookies. Running downloads keep their old settings, and anything else in the config takes effect after a restart."),
+    ("reload_failed", "{error}. The old settings are still in use."),
+    ("ytdlp_not_working", "not working"),
+    ("ytdlp_not_responding", "not responding"),
+    ("status_version", "yt-dlp {version}, up for {uptime}"),
+    ("status_shards", "Shards: {connected}/{shards} connected, {guilds} guild(s), bots: {bots}"),
+    ("status_disk", "Disk: {free} free of {total}"),
+    ("status_today", "Today (UTC): {done} downloaded ({bytes}), {failed} failed, {cancelled} cancelled"),
+    ("status_paused", "The queue is paused; no queued downloads start until `/queue resume`."),
+    ("status_idle", "No active downloads."),
+    ("status_jobs", "{running}/{max} download(s) running, {queued} queued:"),
+    ("status_hidden", "{count} of them in other servers, DMs or with private results"),
+    ("status_running_progress", "running {secs}s, {progress}"),
+    ("status_running", "running {secs}s"),
+    ("status_queued", "queued"),
+    ("status_job", "#{id} <{url}> by {user} in {channel} ({state})"),
+    ("missing_values", "Missing values."),
+    ("missing_preference", "Missing preference."),
+    ("prefs_yours", "Your preferences, used when a request doesn't say otherwise:"),
+    ("queue_operators_only", "Only the bot's admins can pause or resume the queue."),
+    ("queue_already_paused", "The queue is already paused."),
+    ("queue_suspended", "suspended {count} running process(es) until it's resumed"),
+    ("queue_finishing", "{count} running download(s) will finish"),
+    ("queue_paused", "Paused the queue: {queued} queued download(s) wait for `/queue resume`, {running}."),
+    ("queue_not_paused", "The queue isn't paused."),
+    ("queue_resumed_suspended", "Resumed the queue and {suspended} suspended process(es); {queued} download(s) queued."),
+    ("queue_resumed", "Resumed the queue; {queued} download(s) queued."),
+    ("unknown_subcommand", "Unknown subcommand."),
+    ("priority_change_admins_only", "Only admins can change a download's priority."),
+    ("not_queued", "Job #{id} isn't waiting in the queue."),
+    ("missing_priority", "Missing priority."),
+    ("priority_moved", "Moved job #{id} to the {priority} priority lane."),
+    ("no_active_job", "No active job #{id}."),
+    ("cancel_not_yours", "Job #{id} was requested by {user}; only they or an admin can cancel it."),
+    ("cancelled", "Cancelled job #{id} (<{url}>)."),
+    ("not_recording", "Job #{id} isn't recording a live stream."),
+    ("no_recordings", "You aren't recording any live streams."),
+    ("several_recordings", "You're recording several live streams; pick one with the `job` option."),
+    ("stop_not_yours", "Job #{id} was requested by {user}; only they or an admin can stop it."),
+    ("not_recording_yet", "Job #{id} hasn't started recording yet; use /cancel to drop it."),
+    ("stopping_recording", "Stopping the recording of job #{id} (<{url}>); it'll be posted once it's saved."),
+    ("pin_not_yours", "You can only pin your own downloads."),
+    ("already_deleted", "#{id} was already deleted."),
+    ("history_unwritable", "Couldn't update the download history."),
+    ("pinned", "Pinned #{id}; the retention policy will keep it."),
+    ("unpinned", "Unpinned #{id}."),
+    ("subscribe_in_server", "Feeds can only be followed in servers."),
+    ("feed_unreadable", "Failed to read the feed at <{url}>: {error}"),
+    ("uploads_not_found", "Couldn't find the uploads of <{url}>: {error}"),
+    ("already_subscribed", "This channel already follows <{url}>."),
+    ("subscription_unsaved", "Couldn't save the subscription."),
+    ("subscribed", "Subscribed to **{title}** (#{id}). New episodes and uploads are downloaded here as they come out; it's checked every {minutes} minutes. The {skipped} already out were skipped."),
+    ("missing_subscription", "Missing subscription ID."),
+    ("subscriptions_unreadable", "Couldn't read the subscriptions."),
+    ("no_such_subscription", "No subscription #{id} in this server."),
+    ("unsubscribe_not_yours", "Subscription #{id} was made by {user}; only they or an admin can remove it."),
+    ("unsubscribed", "Unsubscribed from **{title}**."),
+    ("subscription_unremoved", "Couldn't remove the subscription."),
+    ("no_subscriptions", "No feeds are followed in this server. Add one with `/subscribe`."),
+    ("subscriptions", "**Subscriptions** ({count}):"),
+    ("page_gone", "That page can't be shown anymore."),
+    ("history_empty", "No downloads recorded yet."),
+    ("history_no_matches", "No downloads match."),
+    ("page_past_end", "Page {page} is past the end; there are {pages} page(s)."),
+    ("button_previous", "◀ Previous"),
+    ("button_next", "Next ▶"),
+    ("not_a_domain", "'{domain}' isn't a domain."),
+    ("dates_reversed", "The `to` date comes before the `from` date."),
+    ("not_a_date", "'{date}' isn't a date like 2024-05-31."),
+    ("filter_term", "matching \"{term}\""),
+    ("filter_domain", "from {domain}"),
+    ("filter_requester", "requested by {user}"),
+    ("filter_between", "{from} to {to}"),
+    ("filter_since", "since {from}"),
+    ("filter_until", "until {to}"),
+    ("size_unknown", "size unknown"),
+    ("plus_best_audio", "+ best audio"),
+    ("ytdlp_updating", "Updating yt-dlp..."),
+    ("ytdlp_updated", "yt-dlp is now at version {version}."),
+    ("ytdlp_update_failed", "Failed to update yt-dlp: {error}"),
+    ("ytdlp_operators_only", "Only admins can update yt-dlp."),
+    ("ytdlp_version", "yt-dlp {version} at `{path}`"),
+    ("ytdlp_failed", "Failed to run yt-dlp: {error}"),
+];
+
+// Every language file's messages, by language
+pub struct Catalog {
+    language: String,
+    languages: HashMap<String, HashMap<String, String>>,
+}
+
+impl Catalog {
+    // Reads each <language>.toml in the directory, if there is one. A file may only use the
+    // keys above, so a typo doesn't quietly leave a message in English.
+    pub fn load(settings: &MessageSettings) -> Result<Self> {
+        let mut languages = HashMap::new();
+        let dir = Path::new(&settings.dir);
+        if dir.is_dir() {
+            let entries = fs::read_dir(dir).with_context(|| format!("Failed to read {}", dir.display()))?;
+            for path in entries.flatten().map(|entry| entry.path()) {
+                let (Some(language), Some("toml")) = (path.file_stem().and_then(|stem| stem.to_str()), path.extension().and_then(|ext| ext.to_str())) else {
+                    continue;
+                };
+                let messages: HashMap<String, String> = config::Config::builder()
+                    .add_source(config::File::from(path.as_path()))
+                    .build()
+                    .and_then(config::Config::try_deserialize)
+                    .with_context(|| format!("Invalid messages in {}", path.display()))?;
+                if let Some(key) = messages.keys().find(|key| !ENGLISH.iter().any(|(known, _)| known == key)) {
+                    bail!("Unknown message '{}' in {}", key, path.display());
+                }
+                languages.insert(language.to_ascii_lowercase(), messages);
+            }
+        }
+        Ok(Catalog { language: settings.language.to_ascii_lowercase(), languages })
+    }
+
+    // The messages in `language`, or in the configured one when that's None
+    pub fn messages(self: &Arc<Self>, language: Option<&str>) -> Messages {
+        let language = language.unwrap_or(&self.language).to_ascii_lowercase();
+        Messages { catalog: Arc::clone(self), language }
+    }
+
+    fn template(&self, language: &str, key: &str) -> Option<&str> {
+        [language, &self.language]
+            .iter()
+            .find_map(|language| self.languages.get(*language)?.get(key))
+            .map(String::as_str)
+            .or_else(|| ENGLISH.iter().find(|(known, _)| *known == key).map(|(_, english)| *english))
+    }
+}
+
+// The messages for one guild
+#[derive(Clone)]
+pub struct Messages {
+    catalog: Arc<Catalog>,
+    language: String,
+}
+
+impl Messages {
+    // The message with each {name} replaced by its value. It's one pass over the template, so
+    // braces in the values themselves are left alone.
+    pub fn get(&self, key: &str, values: &[(&str, &(dyn Display + Sync))]) -> String {
+        let Some(template) = self.catalog.template(&self.language, key) else {
+            log::warn!("No message called {}", key);
+            return key.to_string();
+        };
+        let mut message = String::with_capacity(template.len());
+        let mut rest = template;
+        while let Some(start) = rest.find('{') {
+            message.push_str(&rest[..start]);
+            let after = &rest[start + 1..];
+            let value = after.find('}').and_then(|end| {
+                let value = values.iter().find(|(name, _)| *name == &after[..end])?;
+                Some((value.1, end))
+            });
+            match value {
+                Some((value, end)) => {
+                    message.push_str(&value.to_string());
+                    rest = &after[end + 1..];
+                }
+                // Not a placeholder, or one nothing was given for
+                None => {
+                    message.push('{');
+                    rest = after;
+                }
+            }
+        }
+        message.push_str(rest);
+        message
+    }
+}
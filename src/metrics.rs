@@ -0,0 +1,135 @@
+use serenity::model::id::GuildId;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::health::Shard;
+
+// Upper bounds of the download duration histogram buckets, in seconds
+const DURATION_BUCKETS: [f64; 9] = [10.0, 30.0, 60.0, 120.0, 300.0, 600.0, 1800.0, 3600.0, 7200.0];
+
+#[derive(Default)]
+pub struct Metrics {
+    started: AtomicU64,
+    succeeded: AtomicU64,
+    failed: AtomicU64,
+    cancelled: AtomicU64,
+    bytes: AtomicU64,
+    site_failures: Mutex<BTreeMap<String, u64>>,
+    // By guild ID, or "dm"
+    transferred: Mutex<BTreeMap<String, u64>>,
+    durations: Mutex<Histogram>,
+}
+
+#[derive(Default)]
+struct Histogram {
+    buckets: [u64; DURATION_BUCKETS.len()],
+    count: u64,
+    sum: f64,
+}
+
+// Values sampled when the metrics are scraped rather than counted as they happen
+pub struct Gauges {
+    pub queued: usize,
+    pub running: usize,
+    pub processes: usize,
+    // By bot name and shard ID
+    pub shards: Vec<((String, u32), Shard)>,
+}
+
+impl Metrics {
+    pub fn download_started(&self) {
+        self.started.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn download_succeeded(&self, bytes: u64, duration: Duration) {
+        self.succeeded.fetch_add(1, Ordering::Relaxed);
+        self.bytes.fetch_add(bytes, Ordering::Relaxed);
+        let secs = duration.as_secs_f64();
+        let mut durations = self.durations.lock().unwrap();
+        for (bucket, bound) in durations.buckets.iter_mut().zip(DURATION_BUCKETS) {
+            if secs <= bound {
+                *bucket += 1;
+            }
+        }
+        durations.count += 1;
+        durations.sum += secs;
+    }
+
+    pub fn download_failed(&self, url: &str) {
+        self.failed.fetch_add(1, Ordering::Relaxed);
+        let site = crate::url_host(url)
+            .map(|host| host.strip_prefix("www.").map(str::to_owned).unwrap_or(host))
+            .unwrap_or_else(|| "unknown".to_string());
+        *self.site_failures.lock().unwrap().entry(site).or_default() += 1;
+    }
+
+    pub fn download_cancelled(&self) {
+        self.cancelled.fetch_add(1, Ordering::Relaxed);
+    }
+
+    // What a finished job pulled over the network, however it ended
+    pub fn transferred(&self, guild: Option<GuildId>, bytes: u64) {
+        let guild = guild.map_or_else(|| "dm".to_string(), |id| id.to_string());
+        *self.transferred.lock().unwrap().entry(guild).or_default() += bytes;
+    }
+
+    // Renders everything in the Prometheus text exposition format
+    pub fn render(&self, gauges: &Gauges) -> String {
+        let mut out = String::new();
+        let counter = |out: &mut String, name: &str, help: &str, value: u64| {
+            let _ = writeln!(out, "# HELP {} {}\n# TYPE {} counter\n{} {}", name, help, name, name, value);
+        };
+        let gauge = |out: &mut String, name: &str, help: &str, value: usize| {
+            let _ = writeln!(out, "# HELP {} {}\n# TYPE {} gauge\n{} {}", name, help, name, name, value);
+        };
+        counter(&mut out, "ytdlp_downloads_started_total", "Downloads handed to yt-dlp.", self.started.load(Ordering::Relaxed));
+        counter(&mut out, "ytdlp_downloads_succeeded_total", "Downloads that finished successfully.", self.succeeded.load(Ordering::Relaxed));
+        counter(&mut out, "ytdlp_downloads_failed_total", "Downloads that failed after all retries.", self.failed.load(Ordering::Relaxed));
+        counter(&mut out, "ytdlp_downloads_cancelled_total", "Downloads cancelled by a user.", self.cancelled.load(Ordering::Relaxed));
+        counter(&mut out, "ytdlp_downloaded_bytes_total", "Size of all downloaded files.", self.bytes.load(Ordering::Relaxed));
+        gauge(&mut out, "ytdlp_queue_depth", "Jobs waiting for a free worker.", gauges.queued);
+        gauge(&mut out, "ytdlp_jobs_running", "Jobs currently being worked on.", gauges.running);
+        gauge(&mut out, "ytdlp_processes", "yt-dlp processes currently running.", gauges.processes);
+
+        out.push_str("# HELP ytdlp_shard_connected Whether each gateway shard is connected.\n");
+        out.push_str("# TYPE ytdlp_shard_connected gauge\n");
+        for ((bot, id), shard) in &gauges.shards {
+            let _ = writeln!(out, "ytdlp_shard_connected{{bot=\"{}\",shard=\"{}\"}} {}", escape_label(bot), id, u8::from(shard.connected));
+        }
+        out.push_str("# HELP ytdlp_shard_guilds Guilds each shard was given when it last became ready.\n");
+        out.push_str("# TYPE ytdlp_shard_guilds gauge\n");
+        for ((bot, id), shard) in &gauges.shards {
+            let _ = writeln!(out, "ytdlp_shard_guilds{{bot=\"{}\",shard=\"{}\"}} {}", escape_label(bot), id, shard.guilds);
+        }
+
+        out.push_str("# HELP ytdlp_site_failures_total Failed downloads by site.\n");
+        out.push_str("# TYPE ytdlp_site_failures_total counter\n");
+        for (site, count) in self.site_failures.lock().unwrap().iter() {
+            let _ = writeln!(out, "ytdlp_site_failures_total{{site=\"{}\"}} {}", escape_label(site), count);
+        }
+
+        out.push_str("# HELP ytdlp_transferred_bytes_total Bytes downloaders transferred, including failed and cancelled jobs, by guild.\n");
+        out.push_str("# TYPE ytdlp_transferred_bytes_total counter\n");
+        for (guild, bytes) in self.transferred.lock().unwrap().iter() {
+            let _ = writeln!(out, "ytdlp_transferred_bytes_total{{guild=\"{}\"}} {}", guild, bytes);
+        }
+
+        let durations = self.durations.lock().unwrap();
+        out.push_str("# HELP ytdlp_download_duration_seconds Time taken by successful downloads.\n");
+        out.push_str("# TYPE ytdlp_download_duration_seconds histogram\n");
+        for (bound, count) in DURATION_BUCKETS.iter().zip(durations.buckets) {
+            let _ = writeln!(out, "ytdlp_download_duration_seconds_bucket{{le=\"{}\"}} {}", bound, count);
+        }
+        let _ = writeln!(out, "ytdlp_download_duration_seconds_bucket{{le=\"+Inf\"}} {}", durations.count);
+        let _ = writeln!(out, "ytdlp_download_duration_seconds_sum {}", durations.sum);
+        let _ = writeln!(out, "ytdlp_download_duration_seconds_count {}", durations.count);
+        out
+    }
+}
+
+fn escape_label(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
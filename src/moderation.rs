@@ -0,0 +1,221 @@
+use serde::Deserialize;
+use std::fmt;
+
+use crate::domains::{self, DomainPolicy};
+use crate::messages::Messages;
+use crate::ytdlp::Info;
+
+// The [moderation] section of the config
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct ModerationSettings {
+    // Domain patterns as in allowed_domains that may only be downloaded in NSFW channels
+    #[serde(default)]
+    pub nsfw_domains: Vec<String>,
+    // Videos the site only shows to adults may only be downloaded in NSFW channels
+    #[serde(default)]
+    pub nsfw_age_restricted: bool,
+    // Results uploaded from NSFW channels, from nsfw_domains or of age-restricted videos are
+    // marked as spoilers
+    #[serde(default)]
+    pub spoiler_results: bool,
+    // Words or phrases that refuse a video whose title, description or tags have them
+    #[serde(default)]
+    pub blocked_keywords: Vec<String>,
+    // Also post refused requests in the admin channel
+    #[serde(default)]
+    pub notify_admins: bool,
+}
+
+// What a check knows about a request. The metadata is missing until the URL is probed,
+// and for sites yt-dlp can't tell anything about.
+pub struct Candidate<'a> {
+    pub url: &'a str,
+    pub title: Option<&'a str>,
+    pub description: Option<&'a str>,
+    pub tags: &'a [String],
+    pub age_limit: Option<u32>,
+    // Whether the channel it was requested in is marked NSFW; DMs aren't
+    pub nsfw_channel: bool,
+}
+
+impl<'a> Candidate<'a> {
+    // Before anything is known about the video
+    pub fn url(url: &'a str, nsfw_channel: bool) -> Self {
+        Candidate { url, title: None, description: None, tags: &[], age_limit: None, nsfw_channel }
+    }
+
+    pub fn probed(url: &'a str, info: &'a Info, nsfw_channel: bool) -> Self {
+        Candidate {
+            url,
+            title: info.title.as_deref(),
+            description: info.description.as_deref(),
+            tags: &info.tags,
+            age_limit: info.age_limit,
+            nsfw_channel,
+        }
+    }
+}
+
+// A check that can refuse a request before it's queued, saying why
+pub trait Check: Send + Sync {
+    // Shown in the logs with each refusal
+    fn name(&self) -> &'static str;
+    fn check(&self, candidate: &Candidate, messages: &Messages) -> Result<(), String>;
+}
+
+// A refusal, with the reason the requester is shown
+#[derive(Debug)]
+pub struct Veto {
+    pub check: &'static str,
+    pub reason: String,
+}
+
+impl fmt::Display for Veto {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.reason)
+    }
+}
+
+impl std::error::Error for Veto {}
+
+// The checks every request goes through, in order; the first to refuse it wins
+pub struct Moderation {
+    checks: Vec<Box<dyn Check>>,
+    // Whether any check cares about the channel, so it's only looked up then
+    needs_channel: bool,
+    pub notify_admins: bool,
+    // For spoiler_results
+    spoilers: Option<Vec<String>>,
+}
+
+impl Moderation {
+    pub fn new(settings: &ModerationSettings, domains: DomainPolicy) -> Self {
+        let mut checks: Vec<Box<dyn Check>> = vec![Box::new(DomainCheck(domains))];
+        let nsfw = NsfwCheck::new(settings);
+        let spoilers = settings.spoiler_results.then(|| nsfw_domains(settings));
+        let needs_channel = nsfw.is_some() || spoilers.is_some();
+        if let Some(nsfw) = nsfw {
+            checks.push(Box::new(nsfw));
+        }
+        if let Some(keywords) = KeywordCheck::new(&settings.blocked_keywords) {
+            checks.push(Box::new(keywords));
+        }
+        Moderation { checks, needs_channel, notify_admins: settings.notify_admins, spoilers }
+    }
+
+    pub fn needs_channel(&self) -> bool {
+        self.needs_channel
+    }
+
+    // Whether what's downloaded for the candidate is uploaded as a spoiler
+    pub fn spoiler(&self, candidate: &Candidate) -> bool {
+        let Some(domains) = &self.spoilers else {
+            return false;
+        };
+        candidate.nsfw_channel || on_domains(domains, candidate.url) || is_adult(candidate)
+    }
+
+    pub fn check(&self, candidate: &Candidate, messages: &Messages) -> Result<(), Veto> {
+        for check in &self.checks {
+            if let Err(reason) = check.check(candidate, messages) {
+                return Err(Veto { check: check.name(), reason });
+            }
+        }
+        Ok(())
+    }
+}
+
+// allowed_domains and blocked_domains
+struct DomainCheck(DomainPolicy);
+
+impl Check for DomainCheck {
+    fn name(&self) -> &'static str {
+        "domains"
+    }
+
+    fn check(&self, candidate: &Candidate, messages: &Messages) -> Result<(), String> {
+        self.0.check(candidate.url).map_err(|e| e.describe(messages))
+    }
+}
+
+struct NsfwCheck {
+    domains: Vec<String>,
+    age_restricted: bool,
+}
+
+impl NsfwCheck {
+    fn new(settings: &ModerationSettings) -> Option<Self> {
+        let domains = nsfw_domains(settings);
+        (!domains.is_empty() || settings.nsfw_age_restricted)
+            .then_some(NsfwCheck { domains, age_restricted: settings.nsfw_age_restricted })
+    }
+}
+
+fn nsfw_domains(settings: &ModerationSettings) -> Vec<String> {
+    settings.nsfw_domains.iter()
+        .map(|pattern| pattern.trim().trim_end_matches('.').to_lowercase())
+        .filter(|pattern| !pattern.is_empty())
+        .collect()
+}
+
+fn on_domains(domains: &[String], url: &str) -> bool {
+    let host = crate::url_host(url).unwrap_or_default();
+    domains.iter().any(|pattern| domains::matches(&host, pattern))
+}
+
+fn is_adult(candidate: &Candidate) -> bool {
+    candidate.age_limit.is_some_and(|limit| limit >= 18)
+}
+
+impl Check for NsfwCheck {
+    fn name(&self) -> &'static str {
+        "nsfw"
+    }
+
+    fn check(&self, candidate: &Candidate, messages: &Messages) -> Result<(), String> {
+        if candidate.nsfw_channel {
+            return Ok(());
+        }
+        if on_domains(&self.domains, candidate.url) {
+            let host = crate::url_host(candidate.url).unwrap_or_default();
+            return Err(messages.get("nsfw_domain", &[("host", &host)]));
+        }
+        if self.age_restricted && is_adult(candidate) {
+            return Err(messages.get("nsfw_age_restricted", &[]));
+        }
+        Ok(())
+    }
+}
+
+// blocked_keywords, matched case-insensitively
+struct KeywordCheck(Vec<String>);
+
+impl KeywordCheck {
+    fn new(keywords: &[String]) -> Option<Self> {
+        let keywords: Vec<String> = keywords.iter()
+            .map(|keyword| keyword.trim().to_lowercase())
+            .filter(|keyword| !keyword.is_empty())
+            .collect();
+        (!keywords.is_empty()).then_some(KeywordCheck(keywords))
+    }
+}
+
+impl Check for KeywordCheck {
+    fn name(&self) -> &'static str {
+        "keywords"
+    }
+
+    fn check(&self, candidate: &Candidate, messages: &Messages) -> Result<(), String> {
+        let fields = [candidate.title, candidate.description].into_iter().flatten()
+            .chain(candidate.tags.iter().map(String::as_str));
+        for field in fields {
+            let field = field.to_lowercase();
+            // Kept out of the reason, which is posted where everyone can see it
+            if let Some(keyword) = self.0.iter().find(|keyword| field.contains(keyword.as_str())) {
+                log::info!(target: "audit", "<{}> matched the blocked keyword {:?}", candidate.url, keyword);
+                return Err(messages.get("blocked_keyword", &[]));
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,102 @@
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// Show/Season/Episode folders as Jellyfin and Kodi expect them: the uploader is the show, the
+// upload year the season, and the upload month and day the episode number
+pub const OUTPUT_TEMPLATE: &str = "%(uploader|Unknown)s/Season %(upload_date>%Y|0000)s/\
+    %(uploader|Unknown)s - S%(upload_date>%Y|0000)sE%(upload_date>%m%d|0000)s - %(title)s [%(id)s].%(ext)s";
+
+// What's used of the .info.json yt-dlp writes beside each file with --write-info-json
+#[derive(Debug, Default, Deserialize)]
+struct VideoInfo {
+    id: Option<String>,
+    title: Option<String>,
+    uploader: Option<String>,
+    channel_id: Option<String>,
+    upload_date: Option<String>,
+    description: Option<String>,
+    duration: Option<f64>,
+    extractor_key: Option<String>,
+    #[serde(default)]
+    tags: Vec<String>,
+}
+
+// Writes an episode .nfo for each downloaded file from its .info.json, and a tvshow.nfo in its
+// show folder if there isn't one yet. The .info.json files are removed afterwards.
+pub fn write_sidecars(files: &[PathBuf]) {
+    for file in files {
+        let info_path = file.with_extension("info.json");
+        if !info_path.is_file() {
+            continue;
+        }
+        if let Err(e) = write_for(file, &info_path) {
+            log::warn!("Failed to write .nfo for {}: {:#}", file.display(), e);
+        }
+        let _ = fs::remove_file(&info_path);
+    }
+}
+
+fn write_for(file: &Path, info_path: &Path) -> Result<()> {
+    let json = fs::read(info_path).with_context(|| format!("Failed to read {}", info_path.display()))?;
+    let info: VideoInfo = serde_json::from_slice(&json).context("Failed to parse the video's metadata")?;
+    let nfo = file.with_extension("nfo");
+    fs::write(&nfo, episode_nfo(&info)).with_context(|| format!("Failed to write {}", nfo.display()))?;
+    // <show>/Season <year>/<file>
+    if let Some(show_dir) = file.parent().and_then(Path::parent) {
+        let tvshow = show_dir.join("tvshow.nfo");
+        if !tvshow.exists() {
+            fs::write(&tvshow, tvshow_nfo(&info)).with_context(|| format!("Failed to write {}", tvshow.display()))?;
+        }
+    }
+    Ok(())
+}
+
+fn episode_nfo(info: &VideoInfo) -> String {
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>\n<episodedetails>\n");
+    element(&mut xml, "title", info.title.as_deref());
+    element(&mut xml, "showtitle", info.uploader.as_deref());
+    let date = info.upload_date.as_deref().filter(|date| date.len() == 8);
+    element(&mut xml, "season", date.map(|date| &date[..4]));
+    element(&mut xml, "episode", date.map(|date| &date[4..]));
+    let aired = date.map(|date| format!("{}-{}-{}", &date[..4], &date[4..6], &date[6..]));
+    element(&mut xml, "aired", aired.as_deref());
+    element(&mut xml, "plot", info.description.as_deref());
+    let runtime = info.duration.map(|secs| ((secs / 60.0).round() as u64).to_string());
+    element(&mut xml, "runtime", runtime.as_deref());
+    for tag in &info.tags {
+        element(&mut xml, "tag", Some(tag));
+    }
+    if let Some(id) = &info.id {
+        let source = info.extractor_key.as_deref().unwrap_or("web").to_lowercase();
+        let _ = writeln!(xml, "  <uniqueid type=\"{}\" default=\"true\">{}</uniqueid>", escape(&source), escape(id));
+    }
+    xml.push_str("</episodedetails>\n");
+    xml
+}
+
+fn tvshow_nfo(info: &VideoInfo) -> String {
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>\n<tvshow>\n");
+    element(&mut xml, "title", info.uploader.as_deref());
+    if let Some(channel_id) = &info.channel_id {
+        let source = info.extractor_key.as_deref().unwrap_or("web").to_lowercase();
+        let _ = writeln!(xml, "  <uniqueid type=\"{}\" default=\"true\">{}</uniqueid>", escape(&source), escape(channel_id));
+    }
+    xml.push_str("</tvshow>\n");
+    xml
+}
+
+fn element(xml: &mut String, name: &str, value: Option<&str>) {
+    if let Some(value) = value.filter(|value| !value.is_empty()) {
+        let _ = writeln!(xml, "  <{}>{}</{}>", name, escape(value), name);
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
@@ -0,0 +1,57 @@
+use anyhow::{bail, Context, Result};
+use std::path::{Component, Path, PathBuf};
+
+// Whether a relative path (an output template, or a directory under output_dir) stays below
+// the directory it's joined to, going only by its text
+pub fn stays_inside(path: &str) -> bool {
+    let path = Path::new(path);
+    !path.is_absolute() && path.components().all(|component| matches!(component, Component::Normal(_) | Component::CurDir))
+}
+
+// Video titles and uploaders end up in file names and directories, so each file a download
+// wrote has to be a regular file inside `root`, reached without going through a symlink
+pub fn check_output(root: &Path, file: &Path) -> Result<()> {
+    let root = std::path::absolute(root)
+        .with_context(|| format!("Failed to resolve {}", root.display()))?;
+    let path = std::path::absolute(file)
+        .with_context(|| format!("Failed to resolve {}", file.display()))?;
+    let Ok(relative) = path.strip_prefix(&root) else {
+        bail!("{} is outside the output directory", file.display());
+    };
+    let mut current = root.clone();
+    for component in relative.components() {
+        let Component::Normal(name) = component else {
+            bail!("{} is outside the output directory", file.display());
+        };
+        current.push(name);
+        let metadata = std::fs::symlink_metadata(&current)
+            .with_context(|| format!("Failed to look at {}", current.display()))?;
+        if metadata.file_type().is_symlink() {
+            bail!("{} goes through a symlink", file.display());
+        }
+    }
+    if !std::fs::symlink_metadata(&path).is_ok_and(|metadata| metadata.is_file()) {
+        bail!("{} isn't a regular file", file.display());
+    }
+    // root itself may be a symlink someone set up on purpose, so both sides are compared
+    // with every link resolved
+    let canonical = path.canonicalize()
+        .with_context(|| format!("Failed to resolve {}", file.display()))?;
+    let canonical_root = root.canonicalize()
+        .with_context(|| format!("Failed to resolve {}", root.display()))?;
+    if !canonical.starts_with(&canonical_root) {
+        bail!("{} is outside the output directory", file.display());
+    }
+    Ok(())
+}
+
+// Checks every file a download reported before anything else touches it. Nothing is removed
+// when a check fails, since whatever a symlink points at isn't the bot's to delete.
+pub fn check_outputs(root: &str, files: Vec<PathBuf>) -> Result<Vec<PathBuf>> {
+    for file in &files {
+        check_output(Path::new(root), file).inspect_err(|e| {
+            log::warn!(target: "audit", "Refused a downloaded file: {:#}", e);
+        })?;
+    }
+    Ok(files)
+}
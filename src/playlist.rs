@@ -0,0 +1,165 @@
+use serenity::http::Http;
+use serenity::model::id::{ChannelId, MessageId};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::messages::Messages;
+use crate::progress::StatusMessage;
+use crate::zip::Zipping;
+use crate::{truncate_message, upload};
+
+// Failures listed in the final summary; the rest are only counted
+const MAX_LISTED_FAILURES: usize = 10;
+
+// Big playlists and lists can finish many items a second, more than the message can be edited
+const EDIT_INTERVAL: Duration = Duration::from_secs(3);
+
+// Tracks the sub-jobs a playlist was split into and reports on them as a whole.
+pub struct Playlist {
+    title: String,
+    total: usize,
+    channel: ChannelId,
+    http: Arc<Http>,
+    status: Option<StatusMessage>,
+    // Zips the items' files once they're all done, when the channel zips results
+    zip: Option<Zipping>,
+    messages: Messages,
+    tally: Mutex<Tally>,
+}
+
+#[derive(Default)]
+struct Tally {
+    done: usize,
+    failed: Vec<(String, String)>,
+    files: Vec<PathBuf>,
+    // Whether any item's files are spoilers, which makes the zip one
+    spoiler: bool,
+    edited: Option<Instant>,
+}
+
+impl Playlist {
+    pub fn new(
+        title: String,
+        total: usize,
+        channel: ChannelId,
+        http: Arc<Http>,
+        status: Option<StatusMessage>,
+        zip: Option<Zipping>,
+        messages: Messages,
+    ) -> Arc<Self> {
+        Arc::new(Playlist {
+            title,
+            total,
+            channel,
+            http,
+            status,
+            zip,
+            messages,
+            tally: Mutex::default(),
+        })
+    }
+
+    pub fn title(&self) -> &str {
+        &self.title
+    }
+
+    pub fn item(self: &Arc<Self>, url: String) -> PlaylistItem {
+        PlaylistItem {
+            playlist: Arc::clone(self),
+            url,
+            finished: false,
+        }
+    }
+
+    async fn item_finished(&self, url: String, outcome: Result<Vec<PathBuf>, String>) {
+        let (done, failed, finished, edit) = {
+            let mut tally = self.tally.lock().unwrap();
+            match outcome {
+                Ok(files) => {
+                    tally.done += 1;
+                    tally.files.extend(files);
+                }
+                Err(reason) => tally.failed.push((url, reason)),
+            }
+            let finished = tally.done + tally.failed.len() >= self.total;
+            let edit = finished || tally.edited.is_none_or(|edited| edited.elapsed() >= EDIT_INTERVAL);
+            if edit {
+                tally.edited = Some(Instant::now());
+            }
+            (tally.done, tally.failed.clone(), finished, edit)
+        };
+        if let Some(status) = self.status.as_ref().filter(|_| edit) {
+            let key = if failed.is_empty() { "playlist_progress" } else { "playlist_progress_failed" };
+            let text = self.messages.get(key, &[("title", &self.title), ("done", &done), ("total", &self.total), ("failed", &failed.len())]);
+            let _ = status.edit(&self.http, text).await;
+        }
+        if finished {
+            let summary = self.summary(done, &failed);
+            let (files, spoiler) = {
+                let mut tally = self.tally.lock().unwrap();
+                (std::mem::take(&mut tally.files), tally.spoiler)
+            };
+            let Some(zip) = self.zip.as_ref().filter(|_| files.len() > 1) else {
+                let _ = self.channel.say(&self.http, summary).await;
+                return;
+            };
+            let (attachment, line) = zip.deliver(&self.http, &self.title, &files, spoiler, &self.messages).await;
+            upload::send_result(&self.http, self.channel, truncate_message(format!("{}\n{}", summary, line)), attachment).await;
+        }
+    }
+
+    fn summary(&self, done: usize, failed: &[(String, String)]) -> String {
+        let messages = &self.messages;
+        let mut lines = vec![messages.get("playlist_finished", &[("title", &self.title), ("done", &done), ("total", &self.total)])];
+        if !failed.is_empty() {
+            lines.push(messages.get("playlist_failures", &[("count", &failed.len())]));
+            for (url, reason) in failed.iter().take(MAX_LISTED_FAILURES) {
+                lines.push(messages.get("playlist_failure", &[("url", url), ("reason", reason)]));
+            }
+            if failed.len() > MAX_LISTED_FAILURES {
+                lines.push(messages.get("and_more", &[("count", &(failed.len() - MAX_LISTED_FAILURES))]));
+            }
+        }
+        truncate_message(lines.join("\n"))
+    }
+}
+
+// One entry of a playlist. Dropping it without calling `finish`, e.g. because the
+// job was cancelled, still counts it so the playlist summary is always posted.
+pub struct PlaylistItem {
+    playlist: Arc<Playlist>,
+    url: String,
+    finished: bool,
+}
+
+impl PlaylistItem {
+    pub fn message_id(&self) -> Option<MessageId> {
+        self.playlist.status.as_ref().and_then(StatusMessage::message_id)
+    }
+
+    pub fn mark_spoiler(&self) {
+        self.playlist.tally.lock().unwrap().spoiler = true;
+    }
+
+    // With the files the item's job ended up with
+    pub async fn finish(mut self, outcome: Result<Vec<PathBuf>, String>) {
+        self.finished = true;
+        let url = std::mem::take(&mut self.url);
+        self.playlist.item_finished(url, outcome).await;
+    }
+}
+
+impl Drop for PlaylistItem {
+    fn drop(&mut self) {
+        if self.finished {
+            return;
+        }
+        let playlist = Arc::clone(&self.playlist);
+        let url = std::mem::take(&mut self.url);
+        tokio::spawn(async move {
+            let reason = playlist.messages.get("item_cancelled", &[]);
+            playlist.item_finished(url, Err(reason)).await;
+        });
+    }
+}
@@ -0,0 +1,42 @@
+use serde::Deserialize;
+
+// yt-dlp post-processing; unset fields fall back to the global [post_processing]
+// section and then to yt-dlp's defaults
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct PostProcessing {
+    pub embed_thumbnail: Option<bool>,
+    pub embed_metadata: Option<bool>,
+    pub embed_chapters: Option<bool>,
+    // Container to remux videos into with ffmpeg, e.g. "mkv" or "mp4"
+    pub remux_video: Option<String>,
+}
+
+impl PostProcessing {
+    // `overrides` wins wherever it sets a field
+    pub fn merged(&self, overrides: &PostProcessing) -> PostProcessing {
+        PostProcessing {
+            embed_thumbnail: overrides.embed_thumbnail.or(self.embed_thumbnail),
+            embed_metadata: overrides.embed_metadata.or(self.embed_metadata),
+            embed_chapters: overrides.embed_chapters.or(self.embed_chapters),
+            remux_video: overrides.remux_video.clone().or_else(|| self.remux_video.clone()),
+        }
+    }
+
+    pub fn ytdlp_args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        if self.embed_thumbnail == Some(true) {
+            args.push("--embed-thumbnail".to_string());
+        }
+        if self.embed_metadata == Some(true) {
+            args.push("--embed-metadata".to_string());
+        }
+        if self.embed_chapters == Some(true) {
+            args.push("--embed-chapters".to_string());
+        }
+        if let Some(container) = &self.remux_video {
+            args.push("--remux-video".to_string());
+            args.push(container.clone());
+        }
+        args
+    }
+}
@@ -0,0 +1,146 @@
+use anyhow::{anyhow, bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use serenity::model::id::UserId;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::RwLock;
+
+use crate::format::{self, FormatSpec};
+use crate::history::History;
+use crate::paths;
+
+// What /prefs can change, in the order it shows them
+pub const KEYS: &[&str] = &["format", "quality", "subs", "subfolder"];
+
+// A user's own defaults for what they request; the request's own options still win
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Preferences {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub format: Option<FormatSpec>,
+    // For video downloads: best, worst or a height, so format can stay "audio" for music
+    // links while other downloads get a quality of their own
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quality: Option<FormatSpec>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub subs: Option<String>,
+    // Relative to the directory the download would go to otherwise
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub subfolder: Option<String>,
+}
+
+impl Preferences {
+    // The format a request that doesn't name one gets, if any
+    pub fn format(&self) -> Option<FormatSpec> {
+        match self.format {
+            Some(format) if format.is_audio() => Some(format),
+            format => self.quality.or(format),
+        }
+    }
+
+    // Sets one field, or unsets it with None
+    fn set(&mut self, key: &str, value: Option<&str>) -> Result<()> {
+        let value = value.map(str::trim);
+        match key {
+            "format" => self.format = value.map(str::parse).transpose()?,
+            "quality" => self.quality = value.map(parse_quality).transpose()?,
+            "subs" => self.subs = value.map(format::parse_subtitle_langs).transpose()?,
+            "subfolder" => self.subfolder = value.map(parse_subfolder).transpose()?,
+            other => bail!("Unknown preference '{}'. Use one of: {}", other, KEYS.join(", ")),
+        }
+        Ok(())
+    }
+
+    // Sets every `key=value` of "format=audio subfolder=music", or none of them when one is invalid
+    fn set_all(&mut self, assignments: &str) -> Result<()> {
+        let mut updated = self.clone();
+        let mut any = false;
+        for assignment in assignments.split_whitespace() {
+            let (key, value) = assignment.split_once('=')
+                .ok_or_else(|| anyhow!("'{}' isn't a key=value pair like format=audio.", assignment))?;
+            updated.set(&key.to_ascii_lowercase(), Some(value))?;
+            any = true;
+        }
+        if !any {
+            bail!("Give preferences as key=value pairs, like format=audio subfolder=music.");
+        }
+        *self = updated;
+        Ok(())
+    }
+
+    pub fn describe(&self) -> String {
+        let values = [
+            self.format.map(|format| format.to_string()),
+            self.quality.map(|quality| quality.to_string()),
+            self.subs.clone(),
+            self.subfolder.as_ref().map(|dir| format!("`{}`", dir)),
+        ];
+        KEYS.iter()
+            .zip(values)
+            .map(|(key, value)| format!("{}: {}", key, value.unwrap_or_else(|| "(default)".to_string())))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+fn parse_quality(value: &str) -> Result<FormatSpec> {
+    let quality: FormatSpec = value.parse()?;
+    if quality.is_audio() {
+        bail!("The quality is for video downloads; set an audio format with format={} instead.", quality);
+    }
+    Ok(quality)
+}
+
+// Like a guild's output_dir, it must stay inside the directory it's added to
+fn parse_subfolder(dir: &str) -> Result<String> {
+    let dir = dir.trim_matches('/');
+    if dir.is_empty() || !paths::stays_inside(dir) {
+        bail!("The subfolder must be a relative path like my-music.");
+    }
+    Ok(dir.to_owned())
+}
+
+// The directory a download of someone with this subfolder goes to
+pub fn with_subfolder(output_dir: String, subfolder: Option<&str>) -> String {
+    match subfolder {
+        Some(subfolder) => Path::new(&output_dir).join(subfolder).to_string_lossy().into_owned(),
+        None => output_dir,
+    }
+}
+
+// Every user's preferences, kept in the database
+pub struct Prefs {
+    users: RwLock<HashMap<UserId, Preferences>>,
+}
+
+impl Prefs {
+    pub fn new(history: &History) -> Result<Self> {
+        let mut users = HashMap::new();
+        for (user, saved) in history.user_preferences()? {
+            let preferences = serde_json::from_str(&saved)
+                .with_context(|| format!("Invalid saved preferences for user {}", user))?;
+            users.insert(user, preferences);
+        }
+        Ok(Prefs { users: RwLock::new(users) })
+    }
+
+    pub fn get(&self, user: UserId) -> Preferences {
+        self.users.read().unwrap().get(&user).cloned().unwrap_or_default()
+    }
+
+    pub fn set(&self, history: &History, user: UserId, assignments: &str) -> Result<Preferences> {
+        self.update(history, user, |preferences| preferences.set_all(assignments))
+    }
+
+    pub fn unset(&self, history: &History, user: UserId, key: &str) -> Result<Preferences> {
+        self.update(history, user, |preferences| preferences.set(key, None))
+    }
+
+    fn update(&self, history: &History, user: UserId, change: impl FnOnce(&mut Preferences) -> Result<()>) -> Result<Preferences> {
+        let mut users = self.users.write().unwrap();
+        let mut preferences = users.get(&user).cloned().unwrap_or_default();
+        change(&mut preferences)?;
+        history.save_user_preferences(user, &serde_json::to_string(&preferences)?)?;
+        users.insert(user, preferences.clone());
+        Ok(preferences)
+    }
+}
@@ -0,0 +1,161 @@
+use serde::Serialize;
+use serenity::builder::{Builder, EditInteractionResponse, EditMessage};
+use serenity::http::Http;
+use serenity::model::id::{ChannelId, MessageId};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+
+use crate::embed::Card;
+use crate::messages::Messages;
+
+// Passed to yt-dlp's --progress-template; missing fields are printed as "NA".
+pub const TEMPLATE: &str = "download:[progress] %(progress.downloaded_bytes)s %(progress.total_bytes)s \
+    %(progress.total_bytes_estimate)s %(progress.speed)s %(progress.eta)s";
+
+// Discord rate-limits message edits, so don't update more often than this
+const EDIT_INTERVAL: Duration = Duration::from_secs(3);
+
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct Progress {
+    pub downloaded_bytes: u64,
+    pub total_bytes: Option<u64>,
+    pub speed: Option<f64>,
+    pub eta: Option<u64>,
+}
+
+impl Progress {
+    pub fn parse(line: &str) -> Option<Self> {
+        let mut fields = line.strip_prefix("[progress] ")?
+            .split_whitespace()
+            .map(|field| field.parse::<f64>().ok());
+        let mut next = || fields.next().flatten();
+        let downloaded = next()?;
+        let total = next();
+        let estimate = next();
+        let speed = next();
+        let eta = next();
+        Some(Progress {
+            downloaded_bytes: downloaded as u64,
+            total_bytes: total.or(estimate).map(|bytes| bytes as u64),
+            speed,
+            eta: eta.map(|secs| secs as u64),
+        })
+    }
+
+    pub fn percent(&self) -> Option<f64> {
+        match self.total_bytes {
+            Some(total) if total > 0 => Some((self.downloaded_bytes as f64 / total as f64 * 100.0).min(100.0)),
+            _ => None,
+        }
+    }
+
+    // How far along it is, e.g. "42.0% of 1.2 GiB at 3.4 MiB/s, ETA 5m"
+    pub fn describe(&self, messages: &Messages) -> String {
+        let mut text = match (self.percent(), self.total_bytes) {
+            (Some(percent), Some(total)) => messages.get("progress_of", &[("percent", &format!("{:.1}", percent)), ("total", &format_bytes(total))]),
+            _ => format_bytes(self.downloaded_bytes),
+        };
+        if let Some(speed) = self.speed {
+            text.push(' ');
+            text.push_str(&messages.get("progress_speed", &[("speed", &format_bytes(speed as u64))]));
+        }
+        if let Some(eta) = self.eta {
+            text.push_str(", ");
+            text.push_str(&messages.get("progress_eta", &[("eta", &format_duration(eta))]));
+        }
+        text
+    }
+}
+
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
+
+pub fn format_duration(secs: u64) -> String {
+    let (hours, minutes, seconds) = (secs / 3600, secs / 60 % 60, secs % 60);
+    if hours > 0 {
+        format!("{}:{:02}:{:02}", hours, minutes, seconds)
+    } else {
+        format!("{}:{:02}", minutes, seconds)
+    }
+}
+
+// The bot's reply to a request, which gets edited as the job progresses.
+#[derive(Debug, Clone)]
+pub enum StatusMessage {
+    Channel(ChannelId, MessageId),
+    Interaction(String),
+}
+
+impl StatusMessage {
+    pub fn message_id(&self) -> Option<MessageId> {
+        match self {
+            StatusMessage::Channel(_, message) => Some(*message),
+            StatusMessage::Interaction(_) => None,
+        }
+    }
+
+    pub async fn edit(&self, http: &Http, content: impl Into<String>) -> serenity::Result<()> {
+        match self {
+            StatusMessage::Channel(channel, message) => {
+                channel.edit_message(http, *message, EditMessage::new().content(content)).await?;
+            }
+            StatusMessage::Interaction(token) => {
+                EditInteractionResponse::new().content(content).execute(http, token).await?;
+            }
+        }
+        Ok(())
+    }
+
+    // Replaces the message's text with the job card
+    pub async fn edit_card(&self, http: &Http, card: Card) -> serenity::Result<()> {
+        match self {
+            StatusMessage::Channel(channel, message) => {
+                let edit = EditMessage::new().content("").embed(card.embed).components(card.buttons);
+                channel.edit_message(http, *message, edit).await?;
+            }
+            StatusMessage::Interaction(token) => {
+                let edit = EditInteractionResponse::new().content("").embed(card.embed).components(card.buttons);
+                edit.execute(http, token).await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// Edits `status` with the card rendered for the latest progress, at most once per
+// EDIT_INTERVAL, until the sending side is dropped.
+pub fn spawn_editor<F>(
+    http: Arc<Http>,
+    status: StatusMessage,
+    render: F,
+    mut progress: watch::Receiver<Option<Progress>>,
+) -> JoinHandle<()>
+where
+    F: Fn(Progress) -> Card + Send + 'static,
+{
+    tokio::spawn(async move {
+        while progress.changed().await.is_ok() {
+            let latest = *progress.borrow_and_update();
+            if let Some(latest) = latest {
+                if let Err(e) = status.edit_card(&http, render(latest)).await {
+                    log::warn!("Failed to update progress message: {}", e);
+                }
+            }
+            tokio::time::sleep(EDIT_INTERVAL).await;
+        }
+    })
+}
@@ -0,0 +1,89 @@
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::domains;
+
+// A single proxy, or a list that downloads take turns with, for sites that rate-limit by IP.
+// An empty string connects directly.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum ProxySetting {
+    One(String),
+    Rotate(Vec<String>),
+}
+
+#[derive(Debug)]
+struct Pool {
+    proxies: Vec<String>,
+    next: AtomicUsize,
+}
+
+impl Pool {
+    fn new(setting: &ProxySetting) -> Option<Self> {
+        let proxies = match setting {
+            ProxySetting::One(proxy) => vec![proxy.trim().to_string()],
+            ProxySetting::Rotate(proxies) => proxies.iter().map(|proxy| proxy.trim().to_string()).collect(),
+        };
+        if proxies.is_empty() {
+            return None;
+        }
+        Some(Pool { proxies, next: AtomicUsize::new(0) })
+    }
+
+    fn pick(&self) -> String {
+        let next = self.next.fetch_add(1, Ordering::Relaxed);
+        self.proxies[next % self.proxies.len()].clone()
+    }
+}
+
+// Which proxy yt-dlp goes through for each URL: the most specific site_proxies entry, then proxy
+#[derive(Debug, Default)]
+pub struct Proxies {
+    default: Option<Pool>,
+    sites: Vec<(String, Pool)>,
+}
+
+impl Proxies {
+    pub fn new(default: Option<&ProxySetting>, sites: &HashMap<String, ProxySetting>) -> Self {
+        let mut sites: Vec<_> = sites.iter()
+            .filter_map(|(pattern, setting)| Some((pattern.trim().trim_end_matches('.').to_lowercase(), Pool::new(setting)?)))
+            .collect();
+        // Longest first, so the first match is the most specific one
+        sites.sort_by_key(|(pattern, _)| std::cmp::Reverse(pattern.len()));
+        Proxies { default: default.and_then(Pool::new), sites }
+    }
+
+    // Takes the next proxy in turn from the pool the URL uses
+    pub fn pick(&self, url: &str) -> Option<String> {
+        let host = crate::url_host(url).unwrap_or_default();
+        self.sites.iter()
+            .find(|(pattern, _)| domains::matches(&host, pattern))
+            .map(|(_, pool)| pool)
+            .or(self.default.as_ref())
+            .map(Pool::pick)
+    }
+}
+
+pub fn args(proxy: Option<&str>) -> Vec<String> {
+    match proxy {
+        Some(proxy) => vec!["--proxy".to_string(), proxy.to_string()],
+        None => Vec::new(),
+    }
+}
+
+// For logs, without any password in the proxy URL
+pub fn redacted(proxy: &str) -> String {
+    if proxy.is_empty() {
+        return "none (direct connection)".to_string();
+    }
+    let (scheme, rest) = proxy.split_once("://").unwrap_or(("", proxy));
+    match rest.rsplit_once('@') {
+        Some((credentials, host)) => {
+            let user = credentials.split(':').next().unwrap_or_default();
+            let scheme = if scheme.is_empty() { String::new() } else { format!("{}://", scheme) };
+            format!("{}{}:***@{}", scheme, user, host)
+        }
+        None => proxy.to_string(),
+    }
+}
@@ -0,0 +1,296 @@
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use serenity::model::id::RoleId;
+use std::collections::{HashSet, VecDeque};
+use std::fmt;
+use std::future::Future;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::Notify;
+use tokio::task::JoinHandle;
+
+use crate::jobs::{CancelReason, CancelSignal, JobId, JobInfo, JobRegistry, JobState};
+
+// Which lane a job waits in
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Priority {
+    // Playlist entries and scheduled runs
+    Low,
+    #[default]
+    Normal,
+    // Admins, boosters and high_priority_roles
+    High,
+}
+
+impl Priority {
+    const ALL: [Priority; 3] = [Priority::High, Priority::Normal, Priority::Low];
+
+    // Out of every 7 jobs started while all lanes are busy, 4 are high, 2 normal and 1 low,
+    // so a busy high lane can't starve the others
+    fn weight(self) -> u32 {
+        match self {
+            Priority::High => 4,
+            Priority::Normal => 2,
+            Priority::Low => 1,
+        }
+    }
+
+    fn lane(self) -> usize {
+        self as usize
+    }
+
+    // Where the entries of a playlist requested at this priority go
+    pub fn for_entries(self) -> Self {
+        match self {
+            Priority::High => Priority::Normal,
+            Priority::Normal | Priority::Low => Priority::Low,
+        }
+    }
+}
+
+impl fmt::Display for Priority {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Priority::High => "high",
+            Priority::Normal => "normal",
+            Priority::Low => "low",
+        })
+    }
+}
+
+impl FromStr for Priority {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "high" => Ok(Priority::High),
+            "normal" => Ok(Priority::Normal),
+            "low" => Ok(Priority::Low),
+            other => Err(format!("Unknown priority '{}'; use high, normal or low.", other)),
+        }
+    }
+}
+
+// Queued job IDs by priority, started by weighted round-robin
+#[derive(Default)]
+struct Lanes {
+    queued: [VecDeque<JobId>; 3],
+    // What's left of each lane's share of the current round
+    credits: [u32; 3],
+    // Set on shutdown; nothing more is queued after it
+    closed: bool,
+    // Set by /queue pause; jobs are still queued but none are started
+    paused: bool,
+}
+
+impl Lanes {
+    fn pop(&mut self) -> Option<JobId> {
+        for _ in 0..2 {
+            for priority in Priority::ALL {
+                let lane = priority.lane();
+                if self.credits[lane] > 0 {
+                    if let Some(id) = self.queued[lane].pop_front() {
+                        self.credits[lane] -= 1;
+                        return Some(id);
+                    }
+                }
+            }
+            // Every lane with work waiting has used its share, so a new round starts
+            self.credits = [Priority::Low, Priority::Normal, Priority::High].map(Priority::weight);
+        }
+        None
+    }
+
+    // Jobs that would start before one queued now at `priority`, with it included
+    fn ahead_of(&self, priority: Priority) -> usize {
+        Priority::ALL.iter()
+            .filter(|other| **other >= priority)
+            .map(|other| self.queued[other.lane()].len())
+            .sum()
+    }
+}
+
+// Which lane each requester's downloads go in
+pub struct PriorityRoles {
+    high: HashSet<RoleId>,
+    low: HashSet<RoleId>,
+    boosters: bool,
+}
+
+impl PriorityRoles {
+    pub fn new(high: &[u64], low: &[u64], boosters: bool) -> Self {
+        PriorityRoles {
+            high: high.iter().copied().map(RoleId::new).collect(),
+            low: low.iter().copied().map(RoleId::new).collect(),
+            boosters,
+        }
+    }
+
+    // A high role wins over a low one
+    pub fn priority(&self, admin: bool, roles: &[RoleId], boosting: bool) -> Priority {
+        if admin || boosting && self.boosters || roles.iter().any(|role| self.high.contains(role)) {
+            Priority::High
+        } else if roles.iter().any(|role| self.low.contains(role)) {
+            Priority::Low
+        } else {
+            Priority::Normal
+        }
+    }
+}
+
+pub struct DownloadQueue {
+    lanes: Arc<Mutex<Lanes>>,
+    // Wakes a worker when a job is queued, or all of them on shutdown
+    ready: Arc<Notify>,
+    workers: Mutex<Vec<JoinHandle<()>>>,
+    jobs: Arc<JobRegistry>,
+    // Set on shutdown so workers stop starting queued jobs
+    stopping: Arc<AtomicBool>,
+    max_concurrent: usize,
+}
+
+impl DownloadQueue {
+    pub fn new(max_concurrent: usize, jobs: Arc<JobRegistry>) -> Self {
+        let max_concurrent = max_concurrent.max(1);
+        let lanes = Arc::new(Mutex::new(Lanes::default()));
+        let ready = Arc::new(Notify::new());
+        let stopping = Arc::new(AtomicBool::new(false));
+        let workers = (0..max_concurrent)
+            .map(|_| tokio::spawn(worker(Arc::clone(&lanes), Arc::clone(&ready), Arc::clone(&jobs), Arc::clone(&stopping))))
+            .collect();
+        DownloadQueue {
+            lanes,
+            ready,
+            workers: Mutex::new(workers),
+            jobs,
+            stopping,
+            max_concurrent,
+        }
+    }
+
+    pub fn max_concurrent(&self) -> usize {
+        self.max_concurrent
+    }
+
+    pub fn is_accepting(&self) -> bool {
+        !self.stopping.load(Ordering::SeqCst)
+    }
+
+    // Returns the job's position in the queue, or 0 if a worker is free to start it now.
+    pub fn submit<F, Fut>(&self, info: JobInfo, priority: Priority, job: F) -> Result<usize>
+    where
+        F: FnOnce(CancelSignal) -> Fut,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let mut lanes = self.lanes.lock().unwrap();
+        if lanes.closed {
+            return Err(anyhow!("The bot is shutting down and not accepting new downloads."));
+        }
+        let id = info.id;
+        self.jobs.enqueue(info, job);
+        lanes.queued[priority.lane()].push_back(id);
+        let pending = lanes.ahead_of(priority) + self.jobs.count(JobState::Running);
+        drop(lanes);
+        self.ready.notify_one();
+        Ok(pending.saturating_sub(self.max_concurrent))
+    }
+
+    // Stops starting queued jobs, leaving running ones alone. False if it was already paused.
+    pub fn pause(&self) -> bool {
+        !std::mem::replace(&mut self.lanes.lock().unwrap().paused, true)
+    }
+
+    // False if it wasn't paused
+    pub fn resume(&self) -> bool {
+        let resumed = std::mem::replace(&mut self.lanes.lock().unwrap().paused, false);
+        self.ready.notify_waiters();
+        resumed
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.lanes.lock().unwrap().paused
+    }
+
+    // Moves a queued job to another lane, at its back. False if the job isn't queued.
+    pub fn set_priority(&self, id: JobId, priority: Priority) -> bool {
+        let mut lanes = self.lanes.lock().unwrap();
+        for lane in &mut lanes.queued {
+            if let Some(index) = lane.iter().position(|queued| *queued == id) {
+                lane.remove(index);
+                lanes.queued[priority.lane()].push_back(id);
+                return true;
+            }
+        }
+        false
+    }
+
+    // Stops accepting and starting jobs, gives running ones up to `grace` to finish, then
+    // interrupts the rest. Returns the jobs that didn't finish: still queued or interrupted.
+    pub async fn shutdown(&self, grace: Duration) -> Vec<JobInfo> {
+        self.stopping.store(true, Ordering::SeqCst);
+        self.lanes.lock().unwrap().closed = true;
+        self.ready.notify_waiters();
+        let mut unfinished: Vec<JobInfo> = self.jobs.list()
+            .into_iter()
+            .filter(|job| job.state == JobState::Queued)
+            .collect();
+        // Live streams never finish by themselves, so keep what they've recorded so far
+        for job in self.jobs.list() {
+            if job.state == JobState::Running && job.stop_recording() {
+                log::info!("Stopping the recording of job #{} for shutdown", job.id);
+            }
+        }
+        let mut workers = std::mem::take(&mut *self.workers.lock().unwrap());
+        let finished = tokio::time::timeout(grace, async {
+            for worker in &mut workers {
+                let _ = worker.await;
+            }
+        });
+        if finished.await.is_err() {
+            for job in self.jobs.list() {
+                if job.state == JobState::Running {
+                    log::info!("Interrupting job #{} for shutdown", job.id);
+                    unfinished.extend(self.jobs.cancel(job.id, CancelReason::Shutdown));
+                }
+            }
+            // Interrupted jobs still get to record and report that they were stopped
+            for worker in workers {
+                let _ = worker.await;
+            }
+        }
+        unfinished
+    }
+}
+
+async fn worker(lanes: Arc<Mutex<Lanes>>, ready: Arc<Notify>, jobs: Arc<JobRegistry>, stopping: Arc<AtomicBool>) {
+    loop {
+        // Registered before looking, so a job queued in between isn't missed
+        let notified = ready.notified();
+        tokio::pin!(notified);
+        notified.as_mut().enable();
+        let next = {
+            let mut lanes = lanes.lock().unwrap();
+            // Whatever is still queued stays so shutdown can report it
+            if stopping.load(Ordering::SeqCst) || lanes.closed {
+                break;
+            }
+            if lanes.paused {
+                None
+            } else {
+                lanes.pop()
+            }
+        };
+        let Some(id) = next else {
+            notified.await;
+            continue;
+        };
+        if let Some(job) = jobs.start(id) {
+            // Run it as its own task so a panicking job doesn't take the worker down
+            let _ = tokio::spawn(job).await;
+        }
+        jobs.finish(id);
+    }
+}
@@ -0,0 +1,131 @@
+use serenity::model::id::{GuildId, UserId};
+
+use crate::history::{self, History};
+use crate::messages::Messages;
+use crate::progress::format_bytes;
+
+const HOUR: i64 = 60 * 60;
+const DAY: i64 = 24 * HOUR;
+
+// Per-user limits in each guild, counted from the download history so they survive restarts
+pub struct Quota {
+    downloads_per_hour: Option<usize>,
+    bytes_per_day: Option<u64>,
+}
+
+#[derive(Debug)]
+pub enum QuotaExceeded {
+    Downloads { limit: usize, retry_at: i64 },
+    Bytes { limit: u64, retry_at: i64 },
+}
+
+impl QuotaExceeded {
+    pub fn describe(&self, messages: &Messages) -> String {
+        match self {
+            QuotaExceeded::Downloads { limit, retry_at } => {
+                messages.get("quota_downloads", &[("limit", limit), ("retry_at", retry_at)])
+            }
+            QuotaExceeded::Bytes { limit, retry_at } => {
+                messages.get("quota_bytes", &[("limit", &format_bytes(*limit)), ("retry_at", retry_at)])
+            }
+        }
+    }
+}
+
+impl Quota {
+    pub fn new(downloads_per_hour: Option<usize>, gb_per_day: Option<f64>) -> Self {
+        // A limit of 0 means no limit
+        Quota {
+            downloads_per_hour: downloads_per_hour.filter(|&limit| limit > 0),
+            bytes_per_day: gb_per_day
+                .map(|gb| (gb * 1024.0 * 1024.0 * 1024.0) as u64)
+                .filter(|&limit| limit > 0),
+        }
+    }
+
+    // These limits with any that are set replaced, e.g. by a guild's own
+    pub fn overridden(&self, downloads_per_hour: Option<usize>, gb_per_day: Option<f64>) -> Self {
+        let mut quota = Quota::new(downloads_per_hour, gb_per_day);
+        if downloads_per_hour.is_none() {
+            quota.downloads_per_hour = self.downloads_per_hour;
+        }
+        if gb_per_day.is_none() {
+            quota.bytes_per_day = self.bytes_per_day;
+        }
+        quota
+    }
+
+    // These limits, lowered to any that are set and lower. Unlike overridden, nothing here
+    // can lift a limit, so it's what a guild's /config changes go through.
+    pub fn tightened(&self, downloads_per_hour: Option<usize>, gb_per_day: Option<f64>) -> Self {
+        let lower = Quota::new(downloads_per_hour, gb_per_day);
+        Quota {
+            downloads_per_hour: lowest(self.downloads_per_hour, lower.downloads_per_hour),
+            bytes_per_day: lowest(self.bytes_per_day, lower.bytes_per_day),
+        }
+    }
+
+    // Why a guild can't set these limits, if it can't
+    pub fn check_tightening(&self, downloads_per_hour: Option<usize>, gb_per_day: Option<f64>, messages: &Messages) -> Result<(), String> {
+        if let (Some(limit), Some(wanted)) = (self.downloads_per_hour, downloads_per_hour) {
+            if wanted == 0 || wanted > limit {
+                return Err(messages.get("quota_only_lowered", &[("key", &"max_downloads_per_hour"), ("limit", &limit)]));
+            }
+        }
+        if let (Some(limit), Some(wanted)) = (self.bytes_per_day, gb_per_day) {
+            if wanted <= 0.0 || (wanted * 1024.0 * 1024.0 * 1024.0) as u64 > limit {
+                return Err(messages.get("quota_only_lowered", &[("key", &"max_gb_per_day"), ("limit", &format_bytes(limit))]));
+            }
+        }
+        Ok(())
+    }
+
+    // Returns how many more downloads the user may request in the guild (or DMs) right now
+    // (None = unlimited). Quotas are best-effort: if the history can't be read the request is
+    // let through.
+    pub fn check(&self, history: &History, user: UserId, guild: Option<GuildId>) -> Result<Option<usize>, QuotaExceeded> {
+        let now = history::now();
+        if let Some(limit) = self.bytes_per_day {
+            match history.downloaded_since(user, guild, now - DAY) {
+                Ok(downloads) => {
+                    let mut used: u64 = downloads.iter().map(|(_, size)| size).sum();
+                    // The quota frees up once enough of the oldest downloads fall out of the window
+                    let mut retry_at = now;
+                    for (finished_at, size) in &downloads {
+                        if used < limit {
+                            break;
+                        }
+                        used -= size;
+                        retry_at = finished_at + DAY;
+                    }
+                    if retry_at > now {
+                        return Err(QuotaExceeded::Bytes { limit, retry_at });
+                    }
+                }
+                Err(e) => log::error!("Failed to check download quota for {}: {}", user, e),
+            }
+        }
+        let Some(limit) = self.downloads_per_hour else {
+            return Ok(None);
+        };
+        match history.requested_since(user, guild, now - HOUR) {
+            Ok(requests) if requests.len() >= limit => {
+                let oldest_counted = requests[requests.len() - limit];
+                Err(QuotaExceeded::Downloads { limit, retry_at: oldest_counted + HOUR })
+            }
+            Ok(requests) => Ok(Some(limit - requests.len())),
+            Err(e) => {
+                log::error!("Failed to check download quota for {}: {}", user, e);
+                Ok(None)
+            }
+        }
+    }
+}
+
+// The lower of two limits, where None is no limit
+fn lowest<T: Ord>(a: Option<T>, b: Option<T>) -> Option<T> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, b) => a.or(b),
+    }
+}
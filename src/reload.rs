@@ -0,0 +1,119 @@
+use anyhow::{Context, Result};
+use log::info;
+use serenity::model::id::GuildId;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+use crate::auth::Authorizer;
+use crate::budget::Budget;
+use crate::domains::DomainPolicy;
+use crate::flags::FlagPolicy;
+use crate::format::{AudioFormat, FormatSpec};
+use crate::guilds::GuildSettings;
+use crate::messages::Catalog;
+use crate::moderation::Moderation;
+use crate::queue::PriorityRoles;
+use crate::quota::Quota;
+use crate::{ChannelSettings, Handler, Settings};
+
+// The settings /reload can change while the bot stays connected. Queued and running jobs
+// keep what they were started with.
+pub struct Reloadable {
+    pub output_dir: String,
+    // guild_id and the guilds table's guilds, when guild_id is set
+    pub allowed_guilds: Option<HashSet<u64>>,
+    pub channels: HashMap<u64, ChannelSettings>,
+    pub guilds: HashMap<GuildId, GuildSettings>,
+    pub default_format: FormatSpec,
+    pub audio_only: bool,
+    pub audio_format: Option<AudioFormat>,
+    pub quota: Quota,
+    pub budget: Budget,
+    pub domains: DomainPolicy,
+    pub moderation: Moderation,
+    pub auth: Authorizer,
+    pub ytdlp_flags: FlagPolicy,
+    pub priorities: PriorityRoles,
+    pub messages: Arc<Catalog>,
+}
+
+impl Reloadable {
+    pub fn new(settings: &Settings) -> Result<Self> {
+        let mut guilds = HashMap::new();
+        for (guild, guild_settings) in &settings.guilds {
+            let guild: u64 = guild.parse()
+                .with_context(|| format!("Invalid guild ID in guilds: {}", guild))?;
+            guilds.insert(GuildId::new(guild), guild_settings.clone());
+        }
+        let allowed_guilds = settings.guild_id
+            .map(|id| guilds.keys().map(|guild| guild.get()).chain([id]).collect());
+        // guild_formats predates the guilds table, which wins where both set a format
+        for (guild, format) in &settings.guild_formats {
+            let guild: u64 = guild.parse()
+                .with_context(|| format!("Invalid guild ID in guild_formats: {}", guild))?;
+            let guild_settings: &mut GuildSettings = guilds.entry(GuildId::new(guild)).or_default();
+            guild_settings.format.get_or_insert(*format);
+        }
+        let mut channels = HashMap::new();
+        for (channel, channel_settings) in &settings.channels {
+            let channel: u64 = channel.parse()
+                .with_context(|| format!("Invalid channel ID in channels: {}", channel))?;
+            channels.insert(channel, channel_settings.clone());
+        }
+        // The older single-channel setting still works, alongside any channels table
+        if let Some(channel) = settings.channel_id {
+            channels.entry(channel).or_default();
+        }
+        let channel_roles = channels.iter()
+            .filter_map(|(&channel, settings)| Some((channel, settings.allowed_roles.clone()?)))
+            .collect();
+        Ok(Reloadable {
+            output_dir: settings.output_dir.clone(),
+            allowed_guilds,
+            channels,
+            guilds,
+            default_format: settings.default_format,
+            audio_only: settings.audio_only,
+            audio_format: settings.audio_format,
+            quota: Quota::new(settings.max_downloads_per_hour, settings.max_gb_per_day),
+            budget: Budget::new(settings.user_budget_gb, settings.channel_budget_gb),
+            domains: DomainPolicy::new(&settings.allowed_domains, &settings.blocked_domains),
+            moderation: Moderation::new(
+                &settings.moderation,
+                DomainPolicy::new(&settings.allowed_domains, &settings.blocked_domains),
+            ),
+            auth: Authorizer::new(
+                &settings.allowed_roles,
+                &settings.admin_roles,
+                &settings.trusted_roles,
+                &settings.blocked_users,
+                channel_roles,
+                &settings.dm_users,
+            ),
+            ytdlp_flags: FlagPolicy::new(settings.allowed_ytdlp_flags.as_deref())?,
+            priorities: PriorityRoles::new(
+                &settings.high_priority_roles,
+                &settings.low_priority_roles,
+                settings.boosters_high_priority,
+            ),
+            messages: Arc::new(Catalog::load(&settings.messages)?),
+        })
+    }
+}
+
+impl Handler {
+    pub(crate) fn live(&self) -> Arc<Reloadable> {
+        Arc::clone(&self.live.read().unwrap())
+    }
+
+    // Re-reads the config file and swaps in its settings all at once, along with the cookies.
+    // Nothing changes if the new config doesn't load.
+    pub(crate) fn reload(&self) -> Result<()> {
+        let settings = Settings::from_env_and_file().context("Failed to read the config")?;
+        let live = Reloadable::new(&settings)?;
+        *self.live.write().unwrap() = Arc::new(live);
+        self.cookies.replace(settings.cookie_config());
+        info!("Settings reloaded");
+        Ok(())
+    }
+}
@@ -0,0 +1,51 @@
+use regex::Regex;
+use serenity::builder::{CreateAttachment, CreateMessage};
+use serenity::http::Http;
+use serenity::model::id::{ChannelId, UserId};
+use std::sync::OnceLock;
+
+use crate::format::FormatSpec;
+use crate::jobs::JobId;
+use crate::{short_error, truncate_message};
+
+// Everything admins need to look into a failed download
+pub struct FailureReport<'a> {
+    pub job_id: JobId,
+    pub url: &'a str,
+    pub requester: UserId,
+    pub channel: ChannelId,
+    pub format: &'a FormatSpec,
+    pub error: &'a anyhow::Error,
+}
+
+// The error as shown to the requester: its last line, without local paths like the
+// cookies file or output directory
+pub fn public_error(error: &anyhow::Error) -> String {
+    static PATH: OnceLock<Regex> = OnceLock::new();
+    let path = PATH.get_or_init(|| Regex::new(r#"(^|[\s'"(\[=])(?:/|~/|[A-Za-z]:\\)[^\s'")\]]*"#).unwrap());
+    path.replace_all(&short_error(error), "${1}<path>").into_owned()
+}
+
+// Logs the full error output and posts it to the admin channel, attached as a file when
+// it doesn't fit in a message
+pub async fn send_failure(http: &Http, admin_channel: Option<ChannelId>, report: FailureReport<'_>) {
+    let error = format!("{:#}", report.error);
+    log::error!("Job #{} ({}) failed: {}", report.job_id, report.url, error);
+    let Some(admin_channel) = admin_channel else {
+        return;
+    };
+    let header = format!(
+        "**Job #{} failed**\nURL: <{}>\nRequested by <@{}> in <#{}>\nFormat: {}",
+        report.job_id, report.url, report.requester, report.channel, report.format
+    );
+    let inline = format!("{}\n```\n{}\n```", header, error.replace("```", "'''"));
+    let message = if inline.chars().count() <= 2000 {
+        CreateMessage::new().content(inline)
+    } else {
+        let attachment = CreateAttachment::bytes(error.into_bytes(), format!("job-{}-error.txt", report.job_id));
+        CreateMessage::new().content(truncate_message(header)).add_file(attachment)
+    };
+    if let Err(e) = admin_channel.send_message(http, message).await {
+        log::error!("Failed to post failure report in admin channel: {}", e);
+    }
+}
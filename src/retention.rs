@@ -0,0 +1,179 @@
+use anyhow::{bail, Result};
+use log::{error, info, warn};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use crate::history::History;
+use crate::jobs::JobId;
+use crate::progress::format_bytes;
+
+// Files this new may still be in use by the job that wrote them, e.g. while uploading
+const MIN_AGE: Duration = Duration::from_secs(60 * 60);
+
+// yt-dlp's leftovers of downloads still running
+pub const IN_PROGRESS_SUFFIXES: &[&str] = &[".part", ".ytdl", ".temp"];
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct RetentionSettings {
+    // Relative to output_dir unless absolute
+    pub dir: String,
+    pub max_age_days: Option<u64>,
+    // Oldest files go first once the directory holds more than this
+    pub max_total_gb: Option<f64>,
+}
+
+#[derive(Debug)]
+pub struct Rule {
+    dir: PathBuf,
+    max_age: Option<Duration>,
+    max_total: Option<u64>,
+}
+
+impl Rule {
+    pub fn new(output_dir: &str, settings: &RetentionSettings) -> Result<Self> {
+        if settings.max_age_days.is_none() && settings.max_total_gb.is_none() {
+            bail!("retention for {} needs max_age_days or max_total_gb", settings.dir);
+        }
+        Ok(Rule {
+            dir: Path::new(output_dir).join(&settings.dir),
+            max_age: settings.max_age_days.map(|days| Duration::from_secs(days * 24 * 60 * 60)),
+            max_total: settings.max_total_gb.map(|gb| (gb * 1024.0 * 1024.0 * 1024.0) as u64),
+        })
+    }
+}
+
+struct File {
+    path: PathBuf,
+    size: u64,
+    modified: SystemTime,
+}
+
+// The bot's downloads by canonical path, so they can be matched with what's on disk
+struct Known {
+    jobs: HashMap<PathBuf, JobId>,
+    pinned: Vec<PathBuf>,
+}
+
+impl Known {
+    fn load(history: &History) -> Result<Self> {
+        let mut known = Known { jobs: HashMap::new(), pinned: Vec::new() };
+        for file in history.stored_files()? {
+            let Ok(path) = std::fs::canonicalize(&file.output_path) else {
+                continue;
+            };
+            if file.pinned {
+                known.pinned.push(path.clone());
+            }
+            known.jobs.insert(path, file.job_id);
+        }
+        Ok(known)
+    }
+
+    // Files next to a pinned download that share its name, like subtitles or thumbnails, are
+    // kept along with it
+    fn is_pinned(&self, path: &Path) -> bool {
+        self.pinned.iter().any(|pinned| {
+            let stem = pinned.file_stem().unwrap_or_default().to_string_lossy();
+            pinned.parent() == path.parent()
+                && path.file_name().is_some_and(|name| name.to_string_lossy().starts_with(stem.as_ref()))
+        })
+    }
+}
+
+// Checks the rules every `interval`, deleting what they no longer allow
+pub async fn run(rules: Vec<Rule>, history: Arc<History>, interval: Duration) {
+    let rules = Arc::new(rules);
+    loop {
+        let (rules, history) = (Arc::clone(&rules), Arc::clone(&history));
+        // Walking large directories blocks
+        match tokio::task::spawn_blocking(move || sweep(&rules, &history)).await {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => error!("Retention sweep failed: {:#}", e),
+            Err(e) => error!("Retention sweep panicked: {}", e),
+        }
+        tokio::time::sleep(interval).await;
+    }
+}
+
+fn sweep(rules: &[Rule], history: &History) -> Result<()> {
+    let known = Known::load(history)?;
+    let now = SystemTime::now();
+    for rule in rules {
+        let mut files = Vec::new();
+        match std::fs::canonicalize(&rule.dir) {
+            Ok(dir) => scan(&dir, &mut files),
+            // Nothing has been downloaded there yet
+            Err(_) => continue,
+        }
+        let mut deleted = (0, 0);
+        for file in expired(rule, files, now, &known) {
+            match std::fs::remove_file(&file.path) {
+                Ok(()) => {
+                    info!(target: "audit", "Retention deleted {} ({})", file.path.display(), format_bytes(file.size));
+                    if let Some(&job_id) = known.jobs.get(&file.path) {
+                        history.mark_deleted(job_id);
+                    }
+                    deleted = (deleted.0 + 1, deleted.1 + file.size);
+                }
+                Err(e) => warn!("Retention couldn't delete {}: {}", file.path.display(), e),
+            }
+        }
+        if deleted.0 > 0 {
+            info!("Retention freed {} in {} ({} files)", format_bytes(deleted.1), rule.dir.display(), deleted.0);
+        }
+    }
+    Ok(())
+}
+
+// Everything past max_age, then the oldest of the rest until the total fits in max_total.
+// Pinned and recent files are never picked but still count towards the total.
+fn expired(rule: &Rule, mut files: Vec<File>, now: SystemTime, known: &Known) -> Vec<File> {
+    files.sort_by_key(|file| file.modified);
+    let mut total: u64 = files.iter().map(|file| file.size).sum();
+    let mut expired = Vec::new();
+    for file in files {
+        let age = now.duration_since(file.modified).unwrap_or_default();
+        if age < MIN_AGE || known.is_pinned(&file.path) {
+            continue;
+        }
+        let too_old = rule.max_age.is_some_and(|max| age > max);
+        let too_big = rule.max_total.is_some_and(|max| total > max);
+        if too_old || too_big {
+            total -= file.size;
+            expired.push(file);
+        }
+    }
+    expired
+}
+
+fn scan(dir: &Path, files: &mut Vec<File>) {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            warn!("Retention couldn't read {}: {}", dir.display(), e);
+            return;
+        }
+    };
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        // Hidden files include the health check's probe and anything else that isn't a download
+        if name.starts_with('.') || IN_PROGRESS_SUFFIXES.iter().any(|suffix| name.ends_with(suffix)) {
+            continue;
+        }
+        let Ok(metadata) = entry.metadata() else {
+            continue;
+        };
+        if metadata.is_dir() {
+            scan(&entry.path(), files);
+        } else if metadata.is_file() {
+            files.push(File {
+                path: entry.path(),
+                size: metadata.len(),
+                modified: metadata.modified().unwrap_or_else(|_| SystemTime::now()),
+            });
+        }
+    }
+}
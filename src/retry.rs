@@ -0,0 +1,144 @@
+use serde::Deserialize;
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::hash::BuildHasher;
+use std::time::Duration;
+
+// yt-dlp output that means trying again later may work
+const TRANSIENT_PATTERNS: &[&str] = &[
+    "http error 429",
+    "too many requests",
+    "http error 500",
+    "http error 502",
+    "http error 503",
+    "http error 504",
+    "timed out",
+    "connection reset",
+    "connection aborted",
+    "connection refused",
+    "remote end closed connection",
+    "temporary failure in name resolution",
+    "network is unreachable",
+    "incompleteread",
+    "unable to download webpage",
+    "giving up after",
+];
+
+// ...unless it also says something that no amount of retrying will fix
+const PERMANENT_PATTERNS: &[&str] = &[
+    "unsupported url",
+    "video unavailable",
+    "private video",
+    "this video is not available",
+    "has been removed",
+    "http error 404",
+    "http error 410",
+    "sign in to confirm your age",
+    "requested format is not available",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    Transient,
+    Permanent,
+}
+
+pub fn classify(error: &str) -> ErrorKind {
+    let error = error.to_lowercase();
+    let matches = |patterns: &[&str]| patterns.iter().any(|pattern| error.contains(pattern));
+    if matches(TRANSIENT_PATTERNS) && !matches(PERMANENT_PATTERNS) {
+        ErrorKind::Transient
+    } else {
+        ErrorKind::Permanent
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct RetryPolicy {
+    // Total number of tries, including the first one
+    #[serde(default = "default_max_attempts")]
+    pub max_attempts: u32,
+    #[serde(default = "default_base_delay_secs")]
+    pub base_delay_secs: f64,
+    #[serde(default = "default_max_delay_secs")]
+    pub max_delay_secs: f64,
+    // Fraction of each delay that is randomized, so retries from several jobs spread out
+    #[serde(default = "default_jitter")]
+    pub jitter: f64,
+}
+
+fn default_max_attempts() -> u32 {
+    3
+}
+
+fn default_base_delay_secs() -> f64 {
+    5.0
+}
+
+fn default_max_delay_secs() -> f64 {
+    120.0
+}
+
+fn default_jitter() -> f64 {
+    0.2
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: default_max_attempts(),
+            base_delay_secs: default_base_delay_secs(),
+            max_delay_secs: default_max_delay_secs(),
+            jitter: default_jitter(),
+        }
+    }
+}
+
+impl RetryPolicy {
+    // Delay before retrying after `attempt` (1-based) failed, doubling each time
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let exponent = attempt.saturating_sub(1).min(30) as i32;
+        let delay = (self.base_delay_secs * 2f64.powi(exponent)).min(self.max_delay_secs);
+        let jitter = self.jitter.clamp(0.0, 1.0);
+        // Scale by a random factor in [1 - jitter, 1 + jitter]
+        let factor = 1.0 + jitter * (2.0 * random_unit() - 1.0);
+        Duration::from_secs_f64((delay * factor).max(0.0))
+    }
+
+    pub fn should_retry(&self, attempt: u32, error: &str) -> bool {
+        attempt < self.max_attempts && classify(error) == ErrorKind::Transient
+    }
+}
+
+// The default policy plus per-site overrides keyed by domain, which also cover subdomains
+#[derive(Debug, Clone, Default)]
+pub struct RetryPolicies {
+    default: RetryPolicy,
+    sites: HashMap<String, RetryPolicy>,
+}
+
+impl RetryPolicies {
+    pub fn new(default: RetryPolicy, sites: HashMap<String, RetryPolicy>) -> Self {
+        let sites = sites.into_iter()
+            .map(|(domain, policy)| (domain.trim_start_matches('.').to_lowercase(), policy))
+            .collect();
+        RetryPolicies { default, sites }
+    }
+
+    pub fn for_url(&self, url: &str) -> &RetryPolicy {
+        let Some(host) = crate::url_host(url) else {
+            return &self.default;
+        };
+        self.sites.iter()
+            .filter(|(domain, _)| crate::host_matches(&host, domain))
+            // The most specific domain wins
+            .max_by_key(|(domain, _)| domain.len())
+            .map_or(&self.default, |(_, policy)| policy)
+    }
+}
+
+// A random number in [0, 1) from the std hasher's per-instance random keys
+fn random_unit() -> f64 {
+    let bits = RandomState::new().hash_one(0u8);
+    (bits >> 11) as f64 / (1u64 << 53) as f64
+}
@@ -0,0 +1,174 @@
+use anyhow::{bail, Result};
+use serde::Deserialize;
+use std::ffi::OsStr;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+use crate::binary;
+
+static SANDBOX: OnceLock<Sandbox> = OnceLock::new();
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum IoClass {
+    // Only gets the disk when nothing else wants it
+    Idle,
+    BestEffort,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct SandboxSettings {
+    // Run downloads as this user and group ID, which needs the bot to run as root. The user
+    // has to be able to write to output_dir and read the cookies files.
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+    // CPU priority from 0 (normal) to 19 (lowest)
+    pub nice: Option<u8>,
+    pub ionice: Option<IoClass>,
+    // cgroup limits, set through `systemd-run --scope` as systemd's MemoryMax= and CPUQuota=
+    // take them, e.g. "2G" and "50%"
+    pub memory_max: Option<String>,
+    pub cpu_quota: Option<String>,
+    // Command the downloader runs inside, e.g. bubblewrap or firejail with its arguments;
+    // `{output_dir}` in them becomes the download's output directory
+    #[serde(default)]
+    pub wrapper: Vec<String>,
+}
+
+// How yt-dlp, gallery-dl and ffmpeg downloads are run: each program in front of the next,
+// ending with the downloader itself
+#[derive(Debug)]
+struct Sandbox {
+    systemd_run: Option<PathBuf>,
+    nice: Option<(PathBuf, u8)>,
+    ionice: Option<(PathBuf, IoClass)>,
+    wrapper: Vec<String>,
+    uid: Option<u32>,
+    gid: Option<u32>,
+    memory_max: Option<String>,
+    cpu_quota: Option<String>,
+    // Stands in for {output_dir} when a command has no output directory of its own
+    output_dir: String,
+}
+
+// Checks that the programs the settings need are there; until this is called, commands run
+// as they are
+pub fn init(settings: &SandboxSettings, output_dir: &str) -> Result<()> {
+    if settings.nice.is_some_and(|nice| nice > 19) {
+        bail!("sandbox.nice must be between 0 and 19");
+    }
+    let cgroup = settings.memory_max.is_some() || settings.cpu_quota.is_some();
+    let systemd_run = cgroup.then(|| binary::find_executable("systemd-run (for sandbox.memory_max and cpu_quota)", "systemd-run")).transpose()?;
+    let nice = settings.nice
+        .map(|nice| Ok::<_, anyhow::Error>((binary::find_executable("nice (for sandbox.nice)", "nice")?, nice)))
+        .transpose()?;
+    let ionice = settings.ionice
+        .map(|class| Ok::<_, anyhow::Error>((binary::find_executable("ionice (for sandbox.ionice)", "ionice")?, class)))
+        .transpose()?;
+    if let Some(program) = settings.wrapper.first() {
+        binary::find_executable("sandbox.wrapper", program)?;
+    }
+    let sandbox = Sandbox {
+        systemd_run,
+        nice,
+        ionice,
+        wrapper: settings.wrapper.clone(),
+        uid: settings.uid,
+        gid: settings.gid,
+        memory_max: settings.memory_max.clone(),
+        cpu_quota: settings.cpu_quota.clone(),
+        output_dir: output_dir.to_string(),
+    };
+    if sandbox.is_active() {
+        log::info!("Running downloads sandboxed: {}", sandbox.describe());
+    }
+    let _ = SANDBOX.set(sandbox);
+    Ok(())
+}
+
+// A command running `program` inside the configured sandbox, for a download into `output_dir`
+pub fn command(program: impl AsRef<OsStr>, output_dir: Option<&str>) -> tokio::process::Command {
+    match SANDBOX.get().filter(|sandbox| sandbox.is_active()) {
+        Some(sandbox) => sandbox.command(program.as_ref(), output_dir),
+        None => tokio::process::Command::new(program),
+    }
+}
+
+impl Sandbox {
+    fn is_active(&self) -> bool {
+        self.systemd_run.is_some() || self.nice.is_some() || self.ionice.is_some() || !self.wrapper.is_empty()
+            || self.uid.is_some() || self.gid.is_some()
+    }
+
+    fn describe(&self) -> String {
+        let mut parts = Vec::new();
+        if let Some(uid) = self.uid {
+            parts.push(format!("uid {}", uid));
+        }
+        if let Some(gid) = self.gid {
+            parts.push(format!("gid {}", gid));
+        }
+        if let Some((_, nice)) = &self.nice {
+            parts.push(format!("nice {}", nice));
+        }
+        if let Some((_, class)) = &self.ionice {
+            parts.push(format!("ionice {:?}", class));
+        }
+        if let Some(memory) = &self.memory_max {
+            parts.push(format!("memory limit {}", memory));
+        }
+        if let Some(cpu) = &self.cpu_quota {
+            parts.push(format!("CPU quota {}", cpu));
+        }
+        if let Some(wrapper) = self.wrapper.first() {
+            parts.push(format!("inside {}", wrapper));
+        }
+        parts.join(", ")
+    }
+
+    fn command(&self, program: &OsStr, output_dir: Option<&str>) -> tokio::process::Command {
+        let mut chain: Vec<std::ffi::OsString> = Vec::new();
+        if let Some(systemd_run) = &self.systemd_run {
+            chain.extend([systemd_run.as_os_str().into(), "--scope".into(), "--quiet".into(), "--collect".into()]);
+            if let Some(memory) = &self.memory_max {
+                chain.extend(["-p".into(), format!("MemoryMax={}", memory).into()]);
+            }
+            if let Some(cpu) = &self.cpu_quota {
+                chain.extend(["-p".into(), format!("CPUQuota={}", cpu).into()]);
+            }
+            // systemd-run switches user itself once the scope is set up
+            if let Some(uid) = self.uid {
+                chain.push(format!("--uid={}", uid).into());
+            }
+            if let Some(gid) = self.gid {
+                chain.push(format!("--gid={}", gid).into());
+            }
+            chain.push("--".into());
+        }
+        if let Some((nice, level)) = &self.nice {
+            chain.extend([nice.as_os_str().into(), "-n".into(), level.to_string().into()]);
+        }
+        if let Some((ionice, class)) = &self.ionice {
+            let class = match class {
+                IoClass::Idle => "3",
+                IoClass::BestEffort => "2",
+            };
+            chain.extend([ionice.as_os_str().into(), "-c".into(), class.into()]);
+        }
+        let output_dir = output_dir.unwrap_or(&self.output_dir);
+        chain.extend(self.wrapper.iter().map(|arg| arg.replace("{output_dir}", output_dir).into()));
+        chain.push(program.into());
+        let mut cmd = tokio::process::Command::new(&chain[0]);
+        cmd.args(&chain[1..]);
+        #[cfg(unix)]
+        if self.systemd_run.is_none() {
+            if let Some(gid) = self.gid {
+                cmd.gid(gid);
+            }
+            if let Some(uid) = self.uid {
+                cmd.uid(uid);
+            }
+        }
+        cmd
+    }
+}
@@ -0,0 +1,107 @@
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::time::Duration;
+
+use crate::jobs::JobId;
+use crate::ytdlp::{self, ProcessGroup, RunningProcess};
+
+// The [scan] section of the config, or a channel's scan override
+#[derive(Debug, Clone, Deserialize)]
+pub struct ScanSettings {
+    // The scanner and its arguments, with {file} replaced by the file to scan (or the file
+    // added at the end when there's no {file})
+    pub command: Vec<String>,
+    // Exit codes that mean the file is infected; 0 means clean and anything else is an error
+    #[serde(default = "default_infected_exit_codes")]
+    pub infected_exit_codes: Vec<i32>,
+    // Where flagged files are moved to
+    #[serde(default = "default_quarantine_dir")]
+    pub quarantine_dir: String,
+    #[serde(default = "default_timeout_secs")]
+    pub timeout_secs: u64,
+}
+
+// clamscan's
+fn default_infected_exit_codes() -> Vec<i32> {
+    vec![1]
+}
+
+fn default_quarantine_dir() -> String {
+    "data/quarantine".to_string()
+}
+
+fn default_timeout_secs() -> u64 {
+    600
+}
+
+// Runs the scanner on each file. Fails on the first infected file, after moving it to the
+// quarantine directory, or if the scanner can't tell.
+pub async fn scan_all(settings: &ScanSettings, files: &[PathBuf], id: JobId) -> Result<()> {
+    for file in files {
+        let Some(report) = scan(settings, file).await.with_context(|| format!("Failed to scan {}", file.display()))? else {
+            continue;
+        };
+        let name = file.file_name().unwrap_or(file.as_os_str()).to_string_lossy();
+        let quarantined = quarantine(settings, file, id)?;
+        log::warn!(target: "audit", "The virus scanner flagged {} of job #{}; moved it to {}", file.display(), id, quarantined.display());
+        return Err(anyhow::anyhow!("{}", report))
+            .with_context(|| format!("Moved to {}", quarantined.display()))
+            .with_context(|| format!("`{}` was flagged by the virus scanner and quarantined", name));
+    }
+    Ok(())
+}
+
+// What the scanner said about an infected file, or None for a clean one
+async fn scan(settings: &ScanSettings, file: &Path) -> Result<Option<String>> {
+    let Some((program, args)) = settings.command.split_first() else {
+        bail!("scan.command is empty");
+    };
+    let path = file.to_string_lossy();
+    let mut args: Vec<String> = args.iter().map(|arg| arg.replace("{file}", &path)).collect();
+    if !settings.command.iter().any(|arg| arg.contains("{file}")) {
+        args.push(path.into_owned());
+    }
+    log::info!("Scanning {}", file.display());
+    let mut cmd = tokio::process::Command::new(program);
+    cmd.args(&args);
+    cmd.stdin(Stdio::null()).stdout(Stdio::piped()).stderr(Stdio::piped());
+    cmd.kill_on_drop(true);
+    ytdlp::own_process_group(&mut cmd);
+    let child = cmd.spawn().with_context(|| format!("Failed to spawn {}", program))?;
+    let mut group = ProcessGroup(child.id());
+    let _running = RunningProcess::start(group.0);
+    let output = tokio::time::timeout(Duration::from_secs(settings.timeout_secs), child.wait_with_output()).await;
+    let output = match output {
+        Ok(output) => {
+            group.disarm();
+            output.context("Failed to wait for the scanner")?
+        }
+        // Dropping the group kills the scanner
+        Err(_) => bail!("the scanner took longer than {}s", settings.timeout_secs),
+    };
+    let said = format!("{}\n{}", String::from_utf8_lossy(&output.stdout).trim(), String::from_utf8_lossy(&output.stderr).trim());
+    match output.status.code() {
+        Some(0) => Ok(None),
+        Some(code) if settings.infected_exit_codes.contains(&code) => Ok(Some(said.trim().to_string())),
+        _ => bail!("{} failed with status: {}\nOutput: {}", program, output.status, said.trim()),
+    }
+}
+
+// Moves a flagged file out of the output directory, named after its job so it can be found
+fn quarantine(settings: &ScanSettings, file: &Path, id: JobId) -> Result<PathBuf> {
+    let dir = Path::new(&settings.quarantine_dir);
+    std::fs::create_dir_all(dir)
+        .with_context(|| format!("Failed to create quarantine directory: {}", dir.display()))?;
+    let name = file.file_name().unwrap_or(file.as_os_str()).to_string_lossy();
+    let target = dir.join(format!("job-{}-{}", id, name));
+    // The quarantine may be on another filesystem, where renaming doesn't work
+    if std::fs::rename(file, &target).is_err() {
+        std::fs::copy(file, &target)
+            .with_context(|| format!("Failed to quarantine {}", file.display()))?;
+        std::fs::remove_file(file)
+            .with_context(|| format!("Failed to remove {} after quarantining it", file.display()))?;
+    }
+    Ok(target)
+}
@@ -0,0 +1,269 @@
+use anyhow::{anyhow, bail, Context, Result};
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use serenity::http::Http;
+use serenity::model::channel::Channel;
+use serenity::model::id::ChannelId;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::errors;
+use crate::format::FormatSpec;
+use crate::history;
+use crate::messages::Messages;
+use crate::queue::Priority;
+use crate::template::civil_date;
+use crate::ytdlp::Metadata;
+use crate::{truncate_message, DownloadRequest, Handler, Outcome, Reporter};
+
+// Used for scheduled downloads when no download_archive is configured, since they rely on
+// it to only fetch what's new
+pub const DEFAULT_ARCHIVE: &str = "data/schedule-archive.txt";
+
+// Files named in a digest; the rest are only counted
+const MAX_LISTED_FILES: usize = 20;
+
+// One [[schedules]] entry in the config
+#[derive(Debug, Clone, Deserialize)]
+pub struct ScheduleSettings {
+    pub url: String,
+    // "minute hour day-of-month month day-of-week", in UTC
+    pub cron: String,
+    // Channel ID the digest of new downloads is posted to
+    pub channel: u64,
+    pub format: Option<FormatSpec>,
+    // Only look at this many of the newest items, e.g. so the first run doesn't fetch a whole channel
+    pub max_items: Option<usize>,
+}
+
+pub struct Schedule {
+    url: String,
+    cron: Cron,
+    channel: ChannelId,
+    format: Option<FormatSpec>,
+    max_items: Option<usize>,
+}
+
+impl Schedule {
+    pub fn new(settings: &ScheduleSettings) -> Result<Self> {
+        let cron = Cron::parse(&settings.cron)
+            .with_context(|| format!("Invalid cron expression for {}", settings.url))?;
+        Ok(Schedule {
+            url: settings.url.clone(),
+            cron,
+            channel: ChannelId::new(settings.channel),
+            format: settings.format,
+            max_items: settings.max_items,
+        })
+    }
+}
+
+// Saved with a scheduled job's request
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct ScheduledRun {
+    pub max_items: Option<usize>,
+}
+
+// A cron expression, each field a bit set of the values it matches
+struct Cron {
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    // Cron matches either day field when both are restricted
+    any_day: bool,
+    any_weekday: bool,
+}
+
+impl Cron {
+    fn parse(expr: &str) -> Result<Self> {
+        let fields: Vec<&str> = expr.split_whitespace().collect();
+        let [minutes, hours, days, months, weekdays] = fields[..] else {
+            bail!("expected 5 fields, got {}", fields.len());
+        };
+        Ok(Cron {
+            minutes: parse_field(minutes, 0, 59)?,
+            hours: parse_field(hours, 0, 23)?,
+            days: parse_field(days, 1, 31)?,
+            months: parse_field(months, 1, 12)?,
+            // 7 is also Sunday
+            weekdays: {
+                let weekdays = parse_field(weekdays, 0, 7)?;
+                (weekdays | weekdays >> 7) & 0x7f
+            },
+            any_day: days == "*",
+            any_weekday: weekdays == "*",
+        })
+    }
+
+    fn matches(&self, secs: i64) -> bool {
+        let days_since_epoch = secs.div_euclid(86_400);
+        let (_, month, day) = civil_date(days_since_epoch);
+        // The epoch was a Thursday
+        let weekday = (days_since_epoch + 4).rem_euclid(7);
+        let minute = secs.div_euclid(60).rem_euclid(60);
+        let hour = secs.div_euclid(3600).rem_euclid(24);
+        let bit = |set: u64, value: i64| set & (1 << value) != 0;
+        let day_matches = bit(self.days, i64::from(day));
+        let weekday_matches = bit(self.weekdays, weekday);
+        let day_ok = match (self.any_day, self.any_weekday) {
+            (false, false) => day_matches || weekday_matches,
+            _ => day_matches && weekday_matches,
+        };
+        bit(self.minutes, minute) && bit(self.hours, hour) && bit(self.months, i64::from(month)) && day_ok
+    }
+
+    // The first matching minute after `secs`, looking up to four years ahead for Feb 29
+    fn next_after(&self, secs: i64) -> Option<i64> {
+        let start = secs.div_euclid(60) * 60 + 60;
+        (0..4 * 366 * 24 * 60)
+            .map(|minute| start + minute * 60)
+            .find(|&time| self.matches(time))
+    }
+}
+
+// Parses a field like "*", "5", "1-5", "*/15", "0-30/10" or a comma-separated list of those
+fn parse_field(field: &str, min: u32, max: u32) -> Result<u64> {
+    let mut set = 0;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, step.parse::<u32>().ok().filter(|&step| step > 0)
+                .ok_or_else(|| anyhow!("invalid step in '{}'", part))?),
+            None => (part, 1),
+        };
+        let (start, end) = match range {
+            "*" => (min, max),
+            _ => match range.split_once('-') {
+                Some((start, end)) => (parse_value(start, part)?, parse_value(end, part)?),
+                // "5/10" means from 5 to the end
+                None if step > 1 => (parse_value(range, part)?, max),
+                None => {
+                    let value = parse_value(range, part)?;
+                    (value, value)
+                }
+            },
+        };
+        if start < min || end > max || start > end {
+            bail!("'{}' is outside {}-{}", part, min, max);
+        }
+        for value in (start..=end).step_by(step as usize) {
+            set |= 1 << value;
+        }
+    }
+    Ok(set)
+}
+
+fn parse_value(value: &str, part: &str) -> Result<u32> {
+    value.parse().map_err(|_| anyhow!("invalid value in '{}'", part))
+}
+
+// Starts each schedule's download whenever its cron expression comes due, forever
+pub async fn run(handler: Arc<Handler>, http: Arc<Http>, schedules: Vec<Schedule>) {
+    let now = history::now();
+    let mut due: Vec<Option<i64>> = schedules.iter().map(|schedule| schedule.cron.next_after(now)).collect();
+    loop {
+        let next = due.iter()
+            .enumerate()
+            .filter_map(|(index, time)| Some((index, (*time)?)))
+            .min_by_key(|&(_, time)| time);
+        let Some((index, time)) = next else {
+            return;
+        };
+        let wait = (time - history::now()).max(0) as u64;
+        tokio::time::sleep(Duration::from_secs(wait)).await;
+        handler.run_schedule(&http, &schedules[index]).await;
+        due[index] = schedules[index].cron.next_after(time);
+    }
+}
+
+impl Handler {
+    async fn run_schedule(&self, http: &Arc<Http>, schedule: &Schedule) {
+        let bot = match http.get_current_user().await {
+            Ok(bot) => bot,
+            Err(e) => {
+                error!("Skipping scheduled download of {}: {}", schedule.url, e);
+                return;
+            }
+        };
+        // Don't pile up runs while a slow one is still going
+        let running = self.jobs.list().into_iter()
+            .any(|job| job.requester == bot.id && job.url == schedule.url);
+        if running {
+            info!("Skipping scheduled download of {}: the last run hasn't finished", schedule.url);
+            return;
+        }
+        // Scheduled runs follow the settings of the guild they post in
+        let guild = match schedule.channel.to_channel(http).await {
+            Ok(Channel::Guild(channel)) => Some(channel.guild_id),
+            Ok(_) => None,
+            Err(e) => {
+                log::warn!("Failed to look up channel {}: {}", schedule.channel, e);
+                None
+            }
+        };
+        let output_dir = self.output_dir_for(schedule.channel, guild, None);
+        if let Err(e) = self.check_disk_space(http, &output_dir, guild).await {
+            log::warn!("Skipping scheduled download of {}: {}", schedule.url, e);
+            return;
+        }
+        let request = DownloadRequest {
+            url: schedule.url.clone(),
+            requester: bot.id,
+            requester_name: bot.name.clone(),
+            requester_avatar: Some(bot.face()),
+            channel: schedule.channel,
+            guild,
+            format: self.resolve_format(schedule.format, guild, schedule.channel),
+            force: false,
+            archive_key: None,
+            playlist: None,
+            subtitles: None,
+            clip: None,
+            metadata: Metadata::default(),
+            dm: false,
+            live: false,
+            schedule: Some(ScheduledRun { max_items: schedule.max_items }),
+            backend: None,
+            split_chapters: false,
+            from_api: false,
+            thread: None,
+            extra_args: Vec::new(),
+            format_id: None,
+            workaround: None,
+            // Nobody is waiting on them
+            priority: Priority::Low,
+            geo_country: None,
+            subfolder: None,
+            spoiler: false,
+        };
+        match self.start_download(http, request, Reporter::Digest) {
+            Ok((id, _)) => info!("Started scheduled job #{} for {}", id, schedule.url),
+            Err(e) => error!("Failed to start scheduled download of {}: {}", schedule.url, e),
+        }
+    }
+}
+
+// Lists what a scheduled run fetched; runs that found nothing new stay quiet
+pub async fn post_digest(http: &Http, channel: ChannelId, url: &str, outcome: &Outcome, messages: &Messages) {
+    let text = match outcome {
+        Outcome::Done(files) if files.is_empty() => return,
+        Outcome::Done(files) => {
+            let mut lines = vec![messages.get("digest", &[("count", &files.len()), ("url", &url)])];
+            for file in files.iter().take(MAX_LISTED_FILES) {
+                let name = file.file_name().map(PathBuf::from).unwrap_or_else(|| file.clone());
+                lines.push(format!("- `{}`", name.display()));
+            }
+            if files.len() > MAX_LISTED_FILES {
+                lines.push(messages.get("and_more", &[("count", &(files.len() - MAX_LISTED_FILES))]));
+            }
+            lines.join("\n")
+        }
+        Outcome::Failed(e) => messages.get("digest_failed", &[("url", &url), ("reason", &errors::describe(e, messages))]),
+        Outcome::Cancelled(_) => return,
+    };
+    if let Err(e) = channel.say(http, truncate_message(text)).await {
+        error!("Failed to post digest in {}: {}", channel, e);
+    }
+}
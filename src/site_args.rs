@@ -0,0 +1,32 @@
+use std::collections::HashMap;
+
+use crate::domains;
+
+// Extra yt-dlp arguments for URLs on particular sites, keyed by domain pattern as in
+// allowed_domains. Every matching entry applies, more specific patterns last so their
+// arguments win over the others'.
+#[derive(Debug, Clone, Default)]
+pub struct SiteArgs {
+    profiles: Vec<(String, Vec<String>)>,
+}
+
+impl SiteArgs {
+    pub fn new(profiles: &HashMap<String, Vec<String>>) -> Self {
+        let mut profiles: Vec<_> = profiles.iter()
+            .map(|(pattern, args)| (pattern.trim().trim_end_matches('.').to_lowercase(), args.clone()))
+            .collect();
+        // A longer pattern matching the same host is the more specific one
+        profiles.sort_by_key(|(pattern, _)| pattern.len());
+        SiteArgs { profiles }
+    }
+
+    pub fn for_url(&self, url: &str) -> Vec<String> {
+        let Some(host) = crate::url_host(url) else {
+            return Vec::new();
+        };
+        self.profiles.iter()
+            .filter(|(pattern, _)| domains::matches(&host, pattern))
+            .flat_map(|(_, args)| args.iter().cloned())
+            .collect()
+    }
+}
@@ -0,0 +1,126 @@
+use anyhow::Result;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use std::io::Write;
+
+use crate::history::{self, Stats, WEEK_OFFSET};
+use crate::messages::Messages;
+use crate::progress::format_bytes;
+use crate::template::civil_date;
+
+const WEEK: i64 = 7 * 86_400;
+
+// The chart's size in pixels, and the space left around the bars
+const WIDTH: usize = 480;
+const HEIGHT: usize = 200;
+const MARGIN: usize = 12;
+
+// Discord's dark theme, so the chart doesn't stand out from the reply it's in
+const BACKGROUND: [u8; 3] = [0x31, 0x33, 0x38];
+const GRID: [u8; 3] = [0x4e, 0x50, 0x58];
+const BAR: [u8; 3] = [0x58, 0x65, 0xf2];
+const CURRENT_BAR: [u8; 3] = [0x9b, 0xa4, 0xf7];
+
+// When the first of the last `weeks` weeks started, the current one included, as a Unix time
+pub fn since(weeks: u32) -> i64 {
+    let now = history::now();
+    now - (now - WEEK_OFFSET).rem_euclid(WEEK) - (i64::from(weeks) - 1) * WEEK
+}
+
+// Bytes per week from `since` to the current week, with the weeks without downloads as zero
+pub fn weekly(stats: &Stats, since: i64, weeks: u32) -> Vec<(i64, u64)> {
+    (0..i64::from(weeks))
+        .map(|week| since + week * WEEK)
+        .map(|start| {
+            let bytes = stats.by_week.iter().find(|(week, _)| *week == start).map(|(_, bytes)| *bytes);
+            (start, bytes.unwrap_or_default())
+        })
+        .collect()
+}
+
+pub fn summary(stats: &Stats, weekly: &[(i64, u64)], place: &str, messages: &Messages) -> String {
+    let mut lines = vec![messages.get("stats_total", &[
+        ("jobs", &stats.jobs),
+        ("bytes", &format_bytes(stats.total)),
+        ("place", &place),
+        ("weeks", &weekly.len()),
+    ])];
+    if !stats.by_user.is_empty() {
+        lines.push(messages.get("stats_top_requesters", &[]));
+        for (user, jobs, bytes) in &stats.by_user {
+            lines.push(messages.get("stats_requester", &[("user", user), ("jobs", jobs), ("bytes", &format_bytes(*bytes))]));
+        }
+    }
+    if !stats.by_domain.is_empty() {
+        lines.push(messages.get("stats_top_sites", &[]));
+        for (domain, jobs, bytes) in &stats.by_domain {
+            lines.push(messages.get("stats_site", &[("site", domain), ("jobs", jobs), ("bytes", &format_bytes(*bytes))]));
+        }
+    }
+    lines.push(messages.get("stats_per_week", &[]));
+    for (start, bytes) in weekly {
+        let (year, month, day) = civil_date(start.div_euclid(86_400));
+        let week = format!("{:04}-{:02}-{:02}", year, month, day);
+        lines.push(messages.get("stats_week", &[("week", &week), ("gb", &format!("{:.2}", *bytes as f64 / 1e9))]));
+    }
+    lines.join("\n")
+}
+
+// A PNG bar chart of the bytes per week, oldest on the left and the current week lighter.
+// The gridlines split the tallest bar in quarters; the numbers are in the summary.
+pub fn chart(weekly: &[(i64, u64)]) -> Result<Vec<u8>> {
+    let mut pixels = vec![BACKGROUND; WIDTH * HEIGHT];
+    let (left, top) = (MARGIN, MARGIN);
+    let (width, height) = (WIDTH - 2 * MARGIN, HEIGHT - 2 * MARGIN);
+    let baseline = top + height;
+    for quarter in 0..=4 {
+        let y = baseline - height * quarter / 4;
+        pixels[y * WIDTH + left..y * WIDTH + left + width].fill(GRID);
+    }
+    let most = weekly.iter().map(|(_, bytes)| *bytes).max().unwrap_or_default().max(1);
+    let slot = width / weekly.len().max(1);
+    let gap = (slot / 5).max(1);
+    for (i, (_, bytes)) in weekly.iter().enumerate() {
+        let bar = ((*bytes as f64 / most as f64) * height as f64).round() as usize;
+        let colour = if i + 1 == weekly.len() { CURRENT_BAR } else { BAR };
+        let x = left + i * slot + gap / 2;
+        for y in baseline - bar..baseline {
+            pixels[y * WIDTH + x..y * WIDTH + x + slot - gap].fill(colour);
+        }
+    }
+    encode_png(&pixels)
+}
+
+// An 8-bit RGB PNG of WIDTH by HEIGHT pixels, each row unfiltered
+fn encode_png(pixels: &[[u8; 3]]) -> Result<Vec<u8>> {
+    let mut raw = Vec::with_capacity(HEIGHT * (WIDTH * 3 + 1));
+    for row in pixels.chunks(WIDTH) {
+        raw.push(0);
+        raw.extend(row.iter().flatten());
+    }
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(&raw)?;
+    let data = encoder.finish()?;
+
+    let mut header = Vec::with_capacity(13);
+    header.extend((WIDTH as u32).to_be_bytes());
+    header.extend((HEIGHT as u32).to_be_bytes());
+    // Bit depth, RGB colour, then the default compression, filtering and no interlacing
+    header.extend([8, 2, 0, 0, 0]);
+
+    let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+    chunk(&mut png, b"IHDR", &header);
+    chunk(&mut png, b"IDAT", &data);
+    chunk(&mut png, b"IEND", &[]);
+    Ok(png)
+}
+
+fn chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend((data.len() as u32).to_be_bytes());
+    png.extend(kind);
+    png.extend(data);
+    let mut crc = crc32fast::Hasher::new();
+    crc.update(kind);
+    crc.update(data);
+    png.extend(crc.finalize().to_be_bytes());
+}
@@ -0,0 +1,320 @@
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use serenity::async_trait;
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::template::{civil_date, OutputTemplate, TemplateValues};
+
+// Presigned links can't outlive a week
+const MAX_LINK_EXPIRY_SECS: u64 = 7 * 24 * 60 * 60;
+
+// How long the presigned URL used for the upload itself stays valid
+const UPLOAD_EXPIRY_SECS: u64 = 60 * 60;
+
+// Where finished downloads end up
+#[async_trait]
+pub trait Storage: Send + Sync {
+    // Stores the files of a finished download, returning where each one went (a link or a
+    // remote path) if the backend moved them anywhere
+    async fn store(&self, output_dir: &Path, files: &[PathBuf], values: &TemplateValues<'_>) -> Result<Vec<String>>;
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    #[default]
+    Local,
+    S3,
+    Rclone,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct StorageSettings {
+    #[serde(default)]
+    pub backend: Backend,
+    pub bucket: Option<String>,
+    pub region: Option<String>,
+    // Defaults to AWS; set it for other S3-compatible services
+    pub endpoint: Option<String>,
+    pub access_key_id: Option<String>,
+    pub secret_access_key: Option<String>,
+    // Prepended to object keys, e.g. "downloads/"
+    #[serde(default)]
+    pub prefix: String,
+    // Address the bucket as part of the path instead of the host name, as MinIO needs
+    #[serde(default)]
+    pub path_style: bool,
+    #[serde(default = "default_link_expiry_secs")]
+    pub link_expiry_secs: u64,
+    // Keep the local copy after uploading (default: true for s3, false for rclone)
+    pub keep_local: Option<bool>,
+    // rclone remote name, as set up with `rclone config`
+    pub remote: Option<String>,
+    // Directory on the remote, which may use the output_template placeholders
+    pub remote_path: Option<String>,
+    // Passed to rclone's --bwlimit, e.g. "10M"
+    pub bandwidth_limit: Option<String>,
+    #[serde(default = "default_rclone_retries")]
+    pub retries: u32,
+    #[serde(default = "default_rclone_path")]
+    pub rclone_path: String,
+}
+
+fn default_link_expiry_secs() -> u64 {
+    MAX_LINK_EXPIRY_SECS
+}
+
+fn default_rclone_retries() -> u32 {
+    3
+}
+
+fn default_rclone_path() -> String {
+    "rclone".to_string()
+}
+
+pub fn from_settings(settings: &StorageSettings) -> Result<Arc<dyn Storage>> {
+    match settings.backend {
+        Backend::Local => Ok(Arc::new(Local)),
+        Backend::S3 => Ok(Arc::new(S3::new(settings)?)),
+        Backend::Rclone => Ok(Arc::new(Rclone::new(settings)?)),
+    }
+}
+
+// Leaves downloads in the output directory
+pub struct Local;
+
+#[async_trait]
+impl Storage for Local {
+    async fn store(&self, _output_dir: &Path, _files: &[PathBuf], _values: &TemplateValues<'_>) -> Result<Vec<String>> {
+        Ok(Vec::new())
+    }
+}
+
+// Uploads downloads to an S3-compatible bucket, authenticating with presigned URLs (SigV4)
+pub struct S3 {
+    client: reqwest::Client,
+    scheme: String,
+    host: String,
+    bucket: String,
+    region: String,
+    access_key_id: String,
+    secret_access_key: String,
+    prefix: String,
+    path_style: bool,
+    link_expiry_secs: u64,
+    keep_local: bool,
+}
+
+impl S3 {
+    fn new(settings: &StorageSettings) -> Result<Self> {
+        let required = |value: &Option<String>, name: &str| {
+            value.clone()
+                .filter(|value| !value.is_empty())
+                .with_context(|| format!("storage.{} must be set for the s3 backend", name))
+        };
+        let region = settings.region.clone().unwrap_or_else(|| "us-east-1".to_string());
+        let endpoint = settings.endpoint.clone()
+            .unwrap_or_else(|| format!("https://s3.{}.amazonaws.com", region));
+        let Some((scheme, host)) = endpoint.trim_end_matches('/').split_once("://") else {
+            bail!("storage.endpoint must be a URL like https://s3.example.com");
+        };
+        if settings.link_expiry_secs == 0 || settings.link_expiry_secs > MAX_LINK_EXPIRY_SECS {
+            bail!("storage.link_expiry_secs must be between 1 and {}", MAX_LINK_EXPIRY_SECS);
+        }
+        Ok(S3 {
+            client: reqwest::Client::new(),
+            scheme: scheme.to_string(),
+            host: host.to_string(),
+            bucket: required(&settings.bucket, "bucket")?,
+            region,
+            access_key_id: required(&settings.access_key_id, "access_key_id")?,
+            secret_access_key: required(&settings.secret_access_key, "secret_access_key")?,
+            prefix: settings.prefix.clone(),
+            path_style: settings.path_style,
+            link_expiry_secs: settings.link_expiry_secs,
+            keep_local: settings.keep_local.unwrap_or(true),
+        })
+    }
+
+    async fn upload(&self, key: &str, file: &Path) -> Result<()> {
+        let size = tokio::fs::metadata(file).await
+            .with_context(|| format!("Failed to read {}", file.display()))?
+            .len();
+        let body = tokio::fs::File::open(file).await
+            .with_context(|| format!("Failed to open {}", file.display()))?;
+        let url = self.presign("PUT", key, UPLOAD_EXPIRY_SECS, SystemTime::now());
+        let response = self.client.put(url)
+            .header(reqwest::header::CONTENT_LENGTH, size)
+            .body(body)
+            .send().await
+            .with_context(|| format!("Failed to upload {}", file.display()))?;
+        if !response.status().is_success() {
+            let status = response.status();
+            let text = response.text().await.unwrap_or_default();
+            bail!("Uploading {} failed with {}: {}", file.display(), status, text.trim());
+        }
+        Ok(())
+    }
+
+    // A URL that lets anyone holding it make this one request until it expires
+    fn presign(&self, method: &str, key: &str, expires_secs: u64, now: SystemTime) -> String {
+        let secs = now.duration_since(UNIX_EPOCH).unwrap_or(Duration::ZERO).as_secs();
+        let (year, month, day) = civil_date((secs / 86_400) as i64);
+        let date = format!("{:04}{:02}{:02}", year, month, day);
+        let time = format!("{}T{:02}{:02}{:02}Z", date, secs / 3600 % 24, secs / 60 % 60, secs % 60);
+        let (host, path) = if self.path_style {
+            (self.host.clone(), format!("/{}/{}", self.bucket, key))
+        } else {
+            (format!("{}.{}", self.bucket, self.host), format!("/{}", key))
+        };
+        let path = uri_encode(&path, false);
+        let scope = format!("{}/{}/s3/aws4_request", date, self.region);
+        // Already in the sorted order the signature needs
+        let query = [
+            ("X-Amz-Algorithm", "AWS4-HMAC-SHA256".to_string()),
+            ("X-Amz-Credential", format!("{}/{}", self.access_key_id, scope)),
+            ("X-Amz-Date", time.clone()),
+            ("X-Amz-Expires", expires_secs.to_string()),
+            ("X-Amz-SignedHeaders", "host".to_string()),
+        ]
+        .iter()
+        .map(|(name, value)| format!("{}={}", name, uri_encode(value, true)))
+        .collect::<Vec<_>>()
+        .join("&");
+        let canonical_request = format!("{}\n{}\n{}\nhost:{}\n\nhost\nUNSIGNED-PAYLOAD", method, path, query, host);
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+            time,
+            scope,
+            hex(&Sha256::digest(canonical_request.as_bytes()))
+        );
+        let mut key = format!("AWS4{}", self.secret_access_key).into_bytes();
+        for part in [date.as_str(), self.region.as_str(), "s3", "aws4_request"] {
+            key = hmac_sha256(&key, part.as_bytes()).to_vec();
+        }
+        let signature = hex(&hmac_sha256(&key, string_to_sign.as_bytes()));
+        format!("{}://{}{}?{}&X-Amz-Signature={}", self.scheme, host, path, query, signature)
+    }
+}
+
+#[async_trait]
+impl Storage for S3 {
+    async fn store(&self, output_dir: &Path, files: &[PathBuf], _values: &TemplateValues<'_>) -> Result<Vec<String>> {
+        let mut links = Vec::new();
+        for file in files {
+            let relative = file.strip_prefix(output_dir).ok()
+                .or_else(|| file.file_name().map(Path::new))
+                .unwrap_or(file);
+            let key = format!("{}{}", self.prefix, relative.to_string_lossy().replace('\\', "/"));
+            self.upload(&key, file).await?;
+            log::info!("Uploaded {} to s3://{}/{}", file.display(), self.bucket, key);
+            links.push(self.presign("GET", &key, self.link_expiry_secs, SystemTime::now()));
+            if !self.keep_local {
+                if let Err(e) = tokio::fs::remove_file(file).await {
+                    log::warn!("Failed to remove {} after uploading it: {}", file.display(), e);
+                }
+            }
+        }
+        Ok(links)
+    }
+}
+
+// Hands downloads to `rclone move` (or `copy`, to keep them) for any remote rclone supports
+pub struct Rclone {
+    rclone_path: String,
+    remote: String,
+    remote_path: Option<OutputTemplate>,
+    bandwidth_limit: Option<String>,
+    retries: u32,
+    keep_local: bool,
+}
+
+impl Rclone {
+    fn new(settings: &StorageSettings) -> Result<Self> {
+        let remote = settings.remote.clone()
+            .map(|remote| remote.trim_end_matches(':').to_string())
+            .filter(|remote| !remote.is_empty())
+            .context("storage.remote must be set for the rclone backend")?;
+        let remote_path = settings.remote_path.as_deref()
+            .map(OutputTemplate::parse)
+            .transpose()
+            .context("Invalid storage.remote_path")?;
+        Ok(Rclone {
+            rclone_path: settings.rclone_path.clone(),
+            remote,
+            remote_path,
+            bandwidth_limit: settings.bandwidth_limit.clone(),
+            retries: settings.retries.max(1),
+            keep_local: settings.keep_local.unwrap_or(false),
+        })
+    }
+}
+
+#[async_trait]
+impl Storage for Rclone {
+    async fn store(&self, _output_dir: &Path, files: &[PathBuf], values: &TemplateValues<'_>) -> Result<Vec<String>> {
+        let dir = self.remote_path.as_ref()
+            .map(|template| template.render(values).trim_matches('/').to_string())
+            .unwrap_or_default();
+        let destination = format!("{}:{}", self.remote, dir);
+        let mut stored = Vec::new();
+        for file in files {
+            let mut cmd = tokio::process::Command::new(&self.rclone_path);
+            cmd.arg(if self.keep_local { "copy" } else { "move" })
+                .arg(file)
+                .arg(&destination)
+                .arg("--retries").arg(self.retries.to_string());
+            if let Some(limit) = &self.bandwidth_limit {
+                cmd.arg("--bwlimit").arg(limit);
+            }
+            cmd.kill_on_drop(true);
+            let output = cmd.output().await
+                .with_context(|| format!("Failed to run {}", self.rclone_path))?;
+            if !output.status.success() {
+                let stderr = String::from_utf8_lossy(&output.stderr);
+                bail!("rclone failed with status: {}\nError output: {}", output.status, stderr.trim());
+            }
+            let name = file.file_name().map(|name| name.to_string_lossy()).unwrap_or_default();
+            let path = match dir.as_str() {
+                "" => format!("{}:{}", self.remote, name),
+                dir => format!("{}:{}/{}", self.remote, dir, name),
+            };
+            log::info!("Stored {} at {}", file.display(), path);
+            stored.push(path);
+        }
+        Ok(stored)
+    }
+}
+
+// Percent-encodes everything but unreserved characters, and '/' too unless it's a path
+pub fn uri_encode(text: &str, encode_slash: bool) -> String {
+    let mut encoded = String::new();
+    for byte in text.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => encoded.push(byte as char),
+            b'/' if !encode_slash => encoded.push('/'),
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+pub fn hmac_sha256(key: &[u8], data: &[u8]) -> [u8; 32] {
+    const BLOCK_SIZE: usize = 64;
+    let mut block = [0u8; BLOCK_SIZE];
+    if key.len() > BLOCK_SIZE {
+        block[..32].copy_from_slice(&Sha256::digest(key));
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+    let pad = |byte: u8| block.iter().map(|b| b ^ byte).collect::<Vec<_>>();
+    let inner = Sha256::new().chain_update(pad(0x36)).chain_update(data).finalize();
+    Sha256::new().chain_update(pad(0x5c)).chain_update(inner).finalize().into()
+}
+
+pub fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
@@ -0,0 +1,163 @@
+use anyhow::{bail, Context as _, Result};
+use log::warn;
+use serde::Deserialize;
+use serenity::gateway::GatewayError;
+use serenity::http::Http;
+use serenity::prelude::*;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
+
+use crate::progress::format_duration;
+use crate::bots::Bot;
+use crate::Handler;
+
+// A client that stayed up this long was working, so its failure starts a fresh retry budget
+const STABLE_AFTER: Duration = Duration::from_secs(600);
+
+const GATEWAY_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ReconnectSettings {
+    // Failed connections in a row before the bot gives up and exits
+    #[serde(default = "default_max_attempts")]
+    pub max_attempts: u32,
+    #[serde(default = "default_initial_delay_secs")]
+    pub initial_delay_secs: u64,
+    #[serde(default = "default_max_delay_secs")]
+    pub max_delay_secs: u64,
+    // Tell the admin channel once the gateway has been down this long
+    #[serde(default = "default_notify_after_mins")]
+    pub notify_after_mins: u64,
+}
+
+impl Default for ReconnectSettings {
+    fn default() -> Self {
+        ReconnectSettings {
+            max_attempts: default_max_attempts(),
+            initial_delay_secs: default_initial_delay_secs(),
+            max_delay_secs: default_max_delay_secs(),
+            notify_after_mins: default_notify_after_mins(),
+        }
+    }
+}
+
+fn default_max_attempts() -> u32 {
+    10
+}
+
+fn default_initial_delay_secs() -> u64 {
+    5
+}
+
+fn default_max_delay_secs() -> u64 {
+    300
+}
+
+fn default_notify_after_mins() -> u64 {
+    10
+}
+
+impl ReconnectSettings {
+    fn delay(&self, failures: u32) -> Duration {
+        let secs = self.initial_delay_secs.saturating_mul(1 << failures.saturating_sub(1).min(16));
+        Duration::from_secs(secs.min(self.max_delay_secs))
+    }
+}
+
+// Runs the Discord client until `stop` is notified, starting a new one with exponential
+// backoff whenever it exits with an error, and giving up after too many failures in a row.
+// Every shard shares the one handler, so there's a single queue however many there are.
+pub async fn run(
+    bot: Arc<Bot>,
+    token: &str,
+    intents: GatewayIntents,
+    shard_count: Option<u32>,
+    settings: &ReconnectSettings,
+    stop: Arc<Notify>,
+) -> Result<()> {
+    let mut failures = 0;
+    loop {
+        let handler = bot.handler();
+        let mut client = Client::builder(token, intents)
+            .event_handler_arc(Arc::clone(&bot))
+            .await
+            .context("Failed to create Discord client")?;
+        let started = Instant::now();
+        // The client can be stuck reconnecting when the gateway is unreachable, so don't wait
+        // for it to notice the shard manager shutting down
+        let start = async {
+            match shard_count {
+                Some(count) => client.start_shards(count).await,
+                None => client.start_autosharded().await,
+            }
+        };
+        let result = tokio::select! {
+            result = start => result,
+            _ = stop.notified() => {
+                client.shard_manager.shutdown_all().await;
+                return Ok(());
+            }
+        };
+        handler.gateway.disconnect_all(&bot.name);
+        // The client only returns Ok once its shards were shut down on purpose
+        let Err(e) = result else {
+            return Ok(());
+        };
+        if is_fatal(&e) {
+            return Err(e).context("Discord refused the connection");
+        }
+        if started.elapsed() > STABLE_AFTER {
+            failures = 0;
+        }
+        failures += 1;
+        if failures > settings.max_attempts {
+            bail!("Discord client for {} failed {} times in a row, last with: {}", bot.name, failures, e);
+        }
+        let delay = settings.delay(failures);
+        warn!(
+            "Discord client for {} exited ({}), reconnecting in {}s (attempt {}/{})",
+            bot.name, e, delay.as_secs(), failures, settings.max_attempts
+        );
+        tokio::select! {
+            _ = tokio::time::sleep(delay) => {}
+            _ = stop.notified() => return Ok(()),
+        }
+    }
+}
+
+// Errors that reconnecting can't fix
+fn is_fatal(error: &serenity::Error) -> bool {
+    matches!(
+        error,
+        serenity::Error::Gateway(
+            GatewayError::InvalidAuthentication
+                | GatewayError::NoAuthentication
+                | GatewayError::InvalidGatewayIntents
+                | GatewayError::DisallowedGatewayIntents
+        )
+    )
+}
+
+impl Handler {
+    // Tells the admin channel when the gateway has been down for `after`, and when it's back.
+    // Posting only needs the REST API, which often still works while the gateway doesn't.
+    pub(crate) async fn watch_gateway(&self, http: Arc<Http>, after: Duration) {
+        let mut notified = false;
+        loop {
+            tokio::time::sleep(GATEWAY_CHECK_INTERVAL).await;
+            match self.gateway.down_for() {
+                Some(down) if down >= after && !notified => {
+                    notified = true;
+                    let text = format!("The Discord gateway connection has been down for {}.", format_duration(down.as_secs()));
+                    self.warn_admins(&http, text).await;
+                }
+                None if notified => {
+                    notified = false;
+                    self.warn_admins(&http, "The Discord gateway connection is back.".to_string()).await;
+                }
+                _ => {}
+            }
+        }
+    }
+}
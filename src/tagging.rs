@@ -0,0 +1,214 @@
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+
+use crate::ytdlp::{self, Metadata, ProcessGroup, RunningProcess};
+
+const AUDIO_EXTENSIONS: &[&str] = &["mp3", "m4a", "opus", "ogg", "flac"];
+
+// Containers ffmpeg can embed cover art in; the others only get tags
+const COVER_EXTENSIONS: &[&str] = &["mp3", "m4a", "flac"];
+
+// Thumbnails bigger than this aren't embedded
+const MAX_COVER_BYTES: usize = 10 * 1024 * 1024;
+
+// Bracketed parts of a video title that aren't part of the track's name, e.g. "(Official Video)"
+const TITLE_NOISE: &[&str] = &["official", "lyric", "audio", "video", "visualizer", "hd", "hq", "4k", "remastered"];
+
+// The [tagging] section of the config
+#[derive(Debug, Clone, Deserialize)]
+pub struct TaggingSettings {
+    // Take the artist from titles like "Artist - Track" when the site doesn't name one
+    #[serde(default = "crate::default_true")]
+    pub parse_title: bool,
+    #[serde(default = "crate::default_true")]
+    pub cover_art: bool,
+    // Rename each file to "Artist - Title.ext"
+    #[serde(default)]
+    pub rename: bool,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Tags {
+    pub artist: Option<String>,
+    pub title: Option<String>,
+    pub album: Option<String>,
+}
+
+impl Tags {
+    // What the site says the track is, falling back to what its title and uploader suggest.
+    // Playlist entries without an album of their own get the playlist's title.
+    pub fn new(metadata: &Metadata, playlist: Option<&str>, parse_title: bool) -> Self {
+        let parsed = metadata.title.as_deref()
+            .filter(|_| parse_title)
+            .and_then(split_title);
+        let (parsed_artist, parsed_title) = match parsed {
+            Some((artist, title)) => (Some(artist), Some(title)),
+            None if parse_title => (None, metadata.title.as_deref().map(clean_title)),
+            None => (None, metadata.title.clone()),
+        };
+        // YouTube's auto-generated channels are named "<artist> - Topic"
+        let uploader = metadata.uploader.as_deref()
+            .map(|uploader| uploader.strip_suffix(" - Topic").unwrap_or(uploader).to_string());
+        Tags {
+            artist: metadata.artist.clone().or(parsed_artist).or(uploader),
+            title: metadata.track.clone().or(parsed_title),
+            album: metadata.album.clone().or_else(|| playlist.map(str::to_owned)),
+        }
+    }
+
+    fn ffmpeg_args(&self) -> Vec<String> {
+        [("artist", &self.artist), ("title", &self.title), ("album", &self.album)]
+            .into_iter()
+            .filter_map(|(key, value)| Some(["-metadata".to_string(), format!("{}={}", key, value.as_deref()?)]))
+            .flatten()
+            .collect()
+    }
+
+    // "Artist - Title", the name a file is renamed to
+    fn file_stem(&self) -> Option<String> {
+        Some(sanitize(&format!("{} - {}", self.artist.as_deref()?, self.title.as_deref()?)))
+    }
+}
+
+// "Artist - Track (Official Video)" into ("Artist", "Track")
+fn split_title(title: &str) -> Option<(String, String)> {
+    let (artist, track) = [" - ", " – ", " — "].iter().find_map(|separator| title.split_once(separator))?;
+    let (artist, track) = (artist.trim(), clean_title(track));
+    (!artist.is_empty() && !track.is_empty()).then(|| (artist.to_string(), track))
+}
+
+// Drops bracketed noise like "[Official Audio]" from the end of a title
+fn clean_title(title: &str) -> String {
+    let mut title = title.trim();
+    while let Some(open) = title.rfind(['(', '[']).filter(|_| title.ends_with([')', ']'])) {
+        let inside = title[open + 1..title.len() - 1].to_lowercase();
+        if !inside.split_whitespace().any(|word| TITLE_NOISE.contains(&word)) {
+            break;
+        }
+        title = title[..open].trim_end();
+    }
+    title.to_string()
+}
+
+// Keeps a title from adding directories to the path or upsetting the filesystem
+fn sanitize(name: &str) -> String {
+    let name: String = name.chars()
+        .map(|c| if c.is_control() || "/\\:*?\"<>|".contains(c) { '_' } else { c })
+        .collect();
+    name.trim_matches(|c: char| c == '.' || c.is_whitespace()).to_string()
+}
+
+fn extension(path: &Path) -> String {
+    path.extension().and_then(|extension| extension.to_str()).unwrap_or_default().to_ascii_lowercase()
+}
+
+// Writes tags, and cover art where the container takes it, into audio downloads with ffmpeg
+pub struct Tagger {
+    ffmpeg: String,
+    client: reqwest::Client,
+}
+
+impl Tagger {
+    pub fn new(ffmpeg: &str) -> Self {
+        Tagger { ffmpeg: ffmpeg.to_string(), client: reqwest::Client::new() }
+    }
+
+    // Returns the files the job ends up with. A file that can't be tagged is kept as it was,
+    // since the download itself worked.
+    pub async fn tag_all(&self, files: Vec<PathBuf>, tags: &Tags, cover: Option<&str>, settings: &TaggingSettings) -> Vec<PathBuf> {
+        let mut tagged = Vec::new();
+        for file in files {
+            if !AUDIO_EXTENSIONS.contains(&extension(&file).as_str()) {
+                tagged.push(file);
+                continue;
+            }
+            match self.tag(&file, tags, cover.filter(|_| settings.cover_art), settings.rename).await {
+                Ok(output) => tagged.push(output),
+                Err(e) => {
+                    log::warn!("Failed to tag {}: {:#}", file.display(), e);
+                    tagged.push(file);
+                }
+            }
+        }
+        tagged
+    }
+
+    async fn tag(&self, input: &Path, tags: &Tags, cover: Option<&str>, rename: bool) -> Result<PathBuf> {
+        let extension = extension(input);
+        let stem = input.file_stem().unwrap_or_default().to_string_lossy();
+        let partial = input.with_file_name(format!("{}.tagging.{}", stem, extension));
+        let target = match tags.file_stem().filter(|_| rename) {
+            Some(name) => input.with_file_name(format!("{}.{}", name, extension)),
+            None => input.to_path_buf(),
+        };
+        // Never overwrite another download that happens to have the same artist and title
+        let target = if target != input && target.exists() { input.to_path_buf() } else { target };
+        let cover_file = match cover.filter(|_| COVER_EXTENSIONS.contains(&extension.as_str())) {
+            Some(url) => self.fetch_cover(url, &input.with_file_name(format!("{}.cover", stem))).await
+                .map_err(|e| log::warn!("Not embedding cover art in {}: {:#}", input.display(), e))
+                .ok(),
+            None => None,
+        };
+        log::info!("Tagging {}", input.display());
+        let mut cmd = tokio::process::Command::new(&self.ffmpeg);
+        cmd.arg("-hide_banner").arg("-nostdin").arg("-nostats")
+            .arg("-loglevel").arg("error")
+            .arg("-i").arg(input);
+        match &cover_file {
+            Some(cover) => {
+                cmd.arg("-i").arg(cover)
+                    .args(["-map", "0:a", "-map", "1:v", "-c:a", "copy", "-c:v", "mjpeg"])
+                    .args(["-disposition:v:0", "attached_pic"]);
+            }
+            // Keeps any cover yt-dlp embedded
+            None => {
+                cmd.args(["-map", "0", "-c", "copy"]);
+            }
+        }
+        cmd.args(tags.ffmpeg_args())
+            .arg("-y")
+            .arg(&partial);
+        cmd.stdout(Stdio::null()).stderr(Stdio::piped());
+        cmd.kill_on_drop(true);
+        ytdlp::own_process_group(&mut cmd);
+        let child = cmd.spawn()
+            .with_context(|| format!("Failed to spawn {}", self.ffmpeg))?;
+        let mut group = ProcessGroup(child.id());
+        let _running = RunningProcess::start(group.0);
+        let output = child.wait_with_output().await.context("Failed to wait for ffmpeg");
+        group.disarm();
+        if let Some(cover) = &cover_file {
+            let _ = std::fs::remove_file(cover);
+        }
+        let output = output?;
+        if !output.status.success() {
+            let _ = std::fs::remove_file(&partial);
+            bail!("ffmpeg failed with status: {}\nError output: {}", output.status, String::from_utf8_lossy(&output.stderr).trim());
+        }
+        std::fs::rename(&partial, &target)
+            .with_context(|| format!("Failed to move {} into place", partial.display()))?;
+        if target != input {
+            std::fs::remove_file(input)
+                .with_context(|| format!("Failed to remove {}", input.display()))?;
+        }
+        Ok(target)
+    }
+
+    async fn fetch_cover(&self, url: &str, path: &Path) -> Result<PathBuf> {
+        let response = self.client.get(url).send().await
+            .and_then(reqwest::Response::error_for_status)
+            .context("Failed to download the thumbnail")?;
+        if response.content_length().is_some_and(|length| length > MAX_COVER_BYTES as u64) {
+            bail!("the thumbnail is too big");
+        }
+        let bytes = response.bytes().await.context("Failed to download the thumbnail")?;
+        if bytes.len() > MAX_COVER_BYTES {
+            bail!("the thumbnail is too big");
+        }
+        std::fs::write(path, &bytes)
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        Ok(path.to_path_buf())
+    }
+}
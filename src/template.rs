@@ -0,0 +1,125 @@
+use anyhow::{bail, Result};
+use serenity::model::id::{ChannelId, UserId};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::paths;
+
+// Placeholders the bot fills in before handing the template to yt-dlp's -o
+const PLACEHOLDERS: &[&str] = &["requester", "requester_id", "channel", "date"];
+
+// A yt-dlp output template, which may also contain PLACEHOLDERS in braces
+#[derive(Debug, Clone)]
+pub struct OutputTemplate(String);
+
+pub struct TemplateValues<'a> {
+    pub requester: &'a str,
+    pub requester_id: UserId,
+    pub channel: ChannelId,
+}
+
+impl OutputTemplate {
+    // Checked at startup so a typo fails fast instead of on every download
+    pub fn parse(template: &str) -> Result<Self> {
+        if template.trim().is_empty() {
+            bail!("output template is empty");
+        }
+        let mut rest = template;
+        while let Some(start) = rest.find(['{', '%']) {
+            let tail = &rest[start + 1..];
+            if rest[start..].starts_with('{') {
+                let Some(end) = tail.find('}') else {
+                    bail!("unclosed '{{' in output template: {}", template);
+                };
+                let name = &tail[..end];
+                if !PLACEHOLDERS.contains(&name) {
+                    bail!("unknown placeholder {{{}}} in output template (known: {})", name, PLACEHOLDERS.join(", "));
+                }
+                rest = &tail[end + 1..];
+            } else if let Some(field) = tail.strip_prefix('(') {
+                let Some(end) = field.find(')') else {
+                    bail!("unclosed '%(' in output template: {}", template);
+                };
+                // Every field needs a conversion type such as `s` or `d` after it
+                let after = &field[end + 1..];
+                let Some(conversion) = after.find(|c: char| c.is_ascii_alphabetic()) else {
+                    bail!("missing conversion type after %({}) in output template", &field[..end]);
+                };
+                rest = &after[conversion + 1..];
+            } else {
+                // `%%` or another printf-style sequence that yt-dlp passes through
+                rest = tail.get(1..).unwrap_or_default();
+            }
+        }
+        if !paths::stays_inside(template) {
+            bail!("output template must stay inside the output directory: {}", template);
+        }
+        Ok(OutputTemplate(template.to_owned()))
+    }
+
+    pub fn render(&self, values: &TemplateValues<'_>) -> String {
+        self.0
+            .replace("{requester_id}", &values.requester_id.to_string())
+            .replace("{requester}", &sanitize(values.requester))
+            .replace("{channel}", &values.channel.to_string())
+            .replace("{date}", &today())
+    }
+}
+
+impl Default for OutputTemplate {
+    fn default() -> Self {
+        // Use video id as filename
+        OutputTemplate("%(id)s.%(ext)s".to_string())
+    }
+}
+
+// Keeps a user-supplied value from adding directories or yt-dlp fields to the path
+fn sanitize(value: &str) -> String {
+    let value: String = value
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect::<String>()
+        .replace('%', "%%");
+    match value.trim_matches('.') {
+        "" => "_".to_string(),
+        _ => value,
+    }
+}
+
+// Today's UTC date as YYYY-MM-DD
+fn today() -> String {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default();
+    let (year, month, day) = civil_date((secs / 86_400) as i64);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+// Converts days since the epoch to a (year, month, day) date (Howard Hinnant's algorithm)
+pub fn civil_date(days: i64) -> (i64, u32, u32) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month as u32, day as u32)
+}
+
+// The inverse of civil_date
+pub fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = year - i64::from(month <= 2);
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let month_index = i64::from(if month > 2 { month - 3 } else { month + 9 });
+    let day_of_year = (153 * month_index + 2) / 5 + i64::from(day) - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
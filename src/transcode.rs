@@ -0,0 +1,193 @@
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::time::Instant;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, BufReader};
+use tokio::sync::{watch, Semaphore};
+
+use crate::progress::Progress;
+use crate::ytdlp::{self, ProcessGroup, RunningProcess};
+
+// Only audio and video get transcoded; thumbnails, subtitles and images are left alone
+const MEDIA_EXTENSIONS: &[&str] = &["mp4", "webm", "mkv", "mov", "avi", "flv", "m4a", "mp3", "opus", "ogg", "flac", "wav", "aac"];
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct TranscodeSettings {
+    // ffmpeg encoder names, e.g. "libx264" or "aac"; "copy" keeps the stream as it is
+    pub video_codec: Option<String>,
+    pub audio_codec: Option<String>,
+    // Extension of the transcoded file, e.g. "mp4" (default: the original's)
+    pub container: Option<String>,
+    // As ffmpeg's -b:v and -b:a take them, e.g. "2M" or "128k"
+    pub video_bitrate: Option<String>,
+    pub audio_bitrate: Option<String>,
+    // Keep the original beside the transcoded file instead of replacing it
+    #[serde(default)]
+    pub keep_original: bool,
+}
+
+impl TranscodeSettings {
+    fn ffmpeg_args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        let options = [
+            ("-c:v", &self.video_codec),
+            ("-b:v", &self.video_bitrate),
+            ("-c:a", &self.audio_codec),
+            ("-b:a", &self.audio_bitrate),
+        ];
+        for (flag, value) in options {
+            if let Some(value) = value {
+                args.extend([flag.to_string(), value.clone()]);
+            }
+        }
+        args
+    }
+}
+
+// Runs ffmpeg on finished downloads, at most `max_concurrent` at a time however many
+// downloads are running, since encoding takes far more CPU than downloading
+pub struct Transcoder {
+    ffmpeg: String,
+    slots: Semaphore,
+}
+
+impl Transcoder {
+    pub fn new(ffmpeg: &str, max_concurrent: usize) -> Self {
+        Transcoder { ffmpeg: ffmpeg.to_string(), slots: Semaphore::new(max_concurrent.max(1)) }
+    }
+
+    // Transcodes each media file, returning the files the job ends up with. `duration` is the
+    // video's, to tell how far along ffmpeg is.
+    pub async fn transcode_all(
+        &self,
+        files: Vec<PathBuf>,
+        settings: &TranscodeSettings,
+        duration: Option<u64>,
+        progress: &watch::Sender<Option<Progress>>,
+    ) -> Result<Vec<PathBuf>> {
+        let _slot = self.slots.acquire().await.context("The transcoder has shut down")?;
+        let mut transcoded = Vec::new();
+        for file in files {
+            if !is_media(&file) {
+                transcoded.push(file);
+                continue;
+            }
+            let output = self.transcode(&file, settings, duration, progress).await
+                .with_context(|| format!("Failed to transcode {}", file.display()))?;
+            if settings.keep_original {
+                transcoded.push(file);
+            }
+            transcoded.push(output);
+        }
+        Ok(transcoded)
+    }
+
+    async fn transcode(
+        &self,
+        input: &Path,
+        settings: &TranscodeSettings,
+        duration: Option<u64>,
+        progress: &watch::Sender<Option<Progress>>,
+    ) -> Result<PathBuf> {
+        let extension = settings.container.as_deref()
+            .or_else(|| input.extension().and_then(|extension| extension.to_str()))
+            .unwrap_or("mkv");
+        let stem = input.file_stem().unwrap_or_default().to_string_lossy();
+        // Written beside the original under a name of its own, then moved into place
+        let partial = input.with_file_name(format!("{}.transcoding.{}", stem, extension));
+        let target = input.with_file_name(format!("{}.{}", stem, extension));
+        let target = if target == input && settings.keep_original {
+            input.with_file_name(format!("{}.transcoded.{}", stem, extension))
+        } else {
+            target
+        };
+        log::info!("Transcoding {} to {}", input.display(), target.display());
+        let mut cmd = tokio::process::Command::new(&self.ffmpeg);
+        cmd.arg("-hide_banner").arg("-nostdin").arg("-nostats")
+            .arg("-loglevel").arg("error")
+            .arg("-i").arg(input)
+            .args(settings.ffmpeg_args())
+            .arg("-progress").arg("pipe:1")
+            .arg("-y")
+            .arg(&partial);
+        cmd.stdout(Stdio::piped()).stderr(Stdio::piped());
+        cmd.kill_on_drop(true);
+        ytdlp::own_process_group(&mut cmd);
+        let mut child = cmd.spawn()
+            .with_context(|| format!("Failed to spawn {}", self.ffmpeg))?;
+        let mut group = ProcessGroup(child.id());
+        let _running = RunningProcess::start(group.0);
+        let mut stderr = child.stderr.take().context("ffmpeg stderr was not captured")?;
+        let stderr_task = tokio::spawn(async move {
+            let mut buf = String::new();
+            let _ = stderr.read_to_string(&mut buf).await;
+            buf
+        });
+        let stdout = child.stdout.take().context("ffmpeg stdout was not captured")?;
+        let mut lines = BufReader::new(stdout).lines();
+        let started = Instant::now();
+        let mut state = FfmpegProgress::default();
+        while let Some(line) = lines.next_line().await.context("Failed to read ffmpeg output")? {
+            if let Some(update) = state.parse(&line, duration, started) {
+                progress.send_replace(Some(update));
+            }
+        }
+        let status = child.wait().await.context("Failed to wait for ffmpeg")?;
+        group.disarm();
+        let stderr = stderr_task.await.unwrap_or_default();
+        if !status.success() {
+            let _ = std::fs::remove_file(&partial);
+            bail!("ffmpeg failed with status: {}\nError output: {}", status, stderr.trim());
+        }
+        if !settings.keep_original {
+            std::fs::remove_file(input)
+                .with_context(|| format!("Failed to remove {}", input.display()))?;
+        }
+        std::fs::rename(&partial, &target)
+            .with_context(|| format!("Failed to move {} into place", partial.display()))?;
+        Ok(target)
+    }
+}
+
+fn is_media(path: &Path) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| MEDIA_EXTENSIONS.contains(&extension.to_ascii_lowercase().as_str()))
+}
+
+// Collects the key=value lines of ffmpeg's -progress output, which come in blocks ending
+// with a progress= line
+#[derive(Default)]
+struct FfmpegProgress {
+    size: u64,
+    out_secs: f64,
+}
+
+impl FfmpegProgress {
+    fn parse(&mut self, line: &str, duration: Option<u64>, started: Instant) -> Option<Progress> {
+        let (key, value) = line.split_once('=')?;
+        match key {
+            "total_size" => self.size = value.trim().parse().unwrap_or(self.size),
+            // Microseconds, despite the name
+            "out_time_ms" | "out_time_us" => {
+                self.out_secs = value.trim().parse::<f64>().map_or(self.out_secs, |micros| micros / 1_000_000.0);
+            }
+            "progress" => {
+                let elapsed = started.elapsed().as_secs_f64();
+                let done = duration
+                    .map(|duration| self.out_secs / duration as f64)
+                    .filter(|&done| done > 0.01);
+                // The final size is extrapolated from how much of the video is done
+                return Some(Progress {
+                    downloaded_bytes: self.size,
+                    total_bytes: done.map(|done| (self.size as f64 / done.min(1.0)) as u64),
+                    speed: (elapsed > 0.0).then(|| self.size as f64 / elapsed),
+                    eta: done.map(|done| (elapsed / done.min(1.0) - elapsed) as u64),
+                });
+            }
+            _ => {}
+        }
+        None
+    }
+}
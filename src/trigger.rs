@@ -0,0 +1,31 @@
+use serde::Deserialize;
+
+// Which messages in a channel the bot looks for links in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Trigger {
+    // Every message in the channel
+    #[default]
+    AllMessages,
+    // Only messages that @mention the bot
+    MentionOnly,
+    // Only messages starting with the prefix, like `!dl <url>`
+    Prefix,
+}
+
+impl Trigger {
+    pub fn triggers(self, content: &str, prefix: &str, mentioned: bool) -> bool {
+        match self {
+            Trigger::AllMessages => true,
+            Trigger::MentionOnly => mentioned,
+            Trigger::Prefix => has_prefix(content, prefix),
+        }
+    }
+}
+
+// `!dl <url>` has the prefix `!dl`, `!dlx <url>` doesn't
+pub fn has_prefix(content: &str, prefix: &str) -> bool {
+    content.trim_start()
+        .strip_prefix(prefix)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with(char::is_whitespace))
+}
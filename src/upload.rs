@@ -0,0 +1,82 @@
+use serenity::builder::{Builder, CreateAttachment, CreateInteractionResponseFollowup, CreateMessage};
+use serenity::http::Http;
+use serenity::model::guild::PremiumTier;
+use serenity::model::id::{ChannelId, GuildId, UserId};
+use std::path::PathBuf;
+
+const MIB: u64 = 1024 * 1024;
+
+pub async fn upload_limit(http: &Http, guild: Option<GuildId>) -> u64 {
+    let tier = match guild {
+        Some(guild) => match guild.to_partial_guild(http).await {
+            Ok(guild) => guild.premium_tier,
+            Err(e) => {
+                log::warn!("Failed to look up boost tier of guild {}: {}", guild, e);
+                PremiumTier::Tier0
+            }
+        },
+        None => PremiumTier::Tier0,
+    };
+    match tier {
+        PremiumTier::Tier2 => 50 * MIB,
+        PremiumTier::Tier3 => 100 * MIB,
+        _ => 10 * MIB,
+    }
+}
+
+// Returns the download as an attachment if it is a single file under the upload limit
+pub async fn attachment_for(http: &Http, guild: Option<GuildId>, files: &[PathBuf]) -> Option<CreateAttachment> {
+    let [file] = files else {
+        return None;
+    };
+    let size = tokio::fs::metadata(file).await.ok()?.len();
+    if size == 0 || size > upload_limit(http, guild).await {
+        return None;
+    }
+    match CreateAttachment::path(file).await {
+        Ok(attachment) => Some(attachment),
+        Err(e) => {
+            log::warn!("Failed to read {} for upload: {}", file.display(), e);
+            None
+        }
+    }
+}
+
+// Discord blurs attachments whose names start with SPOILER_ until they're clicked
+pub fn spoiler(mut attachment: CreateAttachment) -> CreateAttachment {
+    if !attachment.filename.starts_with("SPOILER_") {
+        attachment.filename = format!("SPOILER_{}", attachment.filename);
+    }
+    attachment
+}
+
+// Posts `content` with the attachment, falling back to text alone if Discord rejects the file
+pub async fn send_result(http: &Http, channel: ChannelId, content: String, attachment: Option<CreateAttachment>) {
+    if let Some(attachment) = attachment {
+        let message = CreateMessage::new().content(&content).add_file(attachment);
+        match channel.send_message(http, message).await {
+            Ok(_) => return,
+            Err(e) => log::warn!("Failed to upload result to channel {}: {}", channel, e),
+        }
+    }
+    let _ = channel.say(http, content).await;
+}
+
+// Shows the result to the requester alone: as an ephemeral follow-up of their slash command
+// while its token lasts (15 minutes), otherwise in a DM
+pub async fn send_private(http: &Http, requester: UserId, token: Option<&str>, content: String, attachment: Option<CreateAttachment>) {
+    if let Some(token) = token {
+        let mut followup = CreateInteractionResponseFollowup::new().content(&content).ephemeral(true);
+        if let Some(attachment) = attachment.clone() {
+            followup = followup.add_file(attachment);
+        }
+        match followup.execute(http, (None, token)).await {
+            Ok(_) => return,
+            Err(e) => log::info!("Failed to send the result to {} as a follow-up, sending a DM instead: {}", requester, e),
+        }
+    }
+    match requester.create_dm_channel(http).await {
+        Ok(dm) => send_result(http, dm.id, content, attachment).await,
+        Err(e) => log::warn!("Failed to DM the result to {}: {}", requester, e),
+    }
+}
@@ -0,0 +1,67 @@
+use std::sync::Mutex;
+
+use crate::history;
+use crate::messages::Messages;
+
+// Bytes a job pulled over the network, added up from its downloader's progress: each file
+// counts up from zero, so a count that goes down means the next file (or attempt) started
+#[derive(Default)]
+pub struct Meter(Mutex<Counted>);
+
+#[derive(Default)]
+struct Counted {
+    finished: u64,
+    current: u64,
+}
+
+impl Meter {
+    pub fn observe(&self, downloaded: u64) {
+        let mut counted = self.0.lock().unwrap();
+        if downloaded < counted.current {
+            counted.finished += counted.current;
+        }
+        counted.current = downloaded;
+    }
+
+    pub fn total(&self) -> u64 {
+        let counted = self.0.lock().unwrap();
+        counted.finished + counted.current
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Period {
+    Today,
+    Week,
+    Month,
+}
+
+impl Period {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "today" => Some(Period::Today),
+            "week" => Some(Period::Week),
+            "month" => Some(Period::Month),
+            _ => None,
+        }
+    }
+
+    // When the period started, as a Unix time
+    pub fn since(self) -> i64 {
+        let now = history::now();
+        match self {
+            Period::Today => now - now.rem_euclid(86_400),
+            Period::Week => now - 7 * 86_400,
+            Period::Month => now - 30 * 86_400,
+        }
+    }
+
+    pub fn describe(self, messages: &Messages) -> String {
+        let key = match self {
+            Period::Today => "period_today",
+            Period::Week => "period_week",
+            Period::Month => "period_month",
+        };
+        messages.get(key, &[])
+    }
+}
@@ -0,0 +1,86 @@
+use anyhow::{bail, Context, Result};
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use crate::progress::{format_bytes, format_duration};
+
+// What checking a finished file found
+#[derive(Debug, Clone)]
+pub struct Verified {
+    pub path: PathBuf,
+    pub size: u64,
+    // From ffprobe, when verify_with_ffprobe is on
+    pub duration: Option<f64>,
+    pub sha256: String,
+}
+
+impl Verified {
+    pub fn describe(&self) -> String {
+        let name = self.path.file_name().unwrap_or(self.path.as_os_str()).to_string_lossy();
+        let mut text = format!("`{}`: {}", name, format_bytes(self.size));
+        if let Some(duration) = self.duration {
+            text.push_str(&format!(", {}", format_duration(duration as u64)));
+        }
+        text.push_str(&format!("\nSHA-256 `{}`", self.sha256));
+        text
+    }
+}
+
+// Fails if any file yt-dlp reported is missing, empty or (with ffprobe) not a readable container
+pub async fn verify_all(files: &[PathBuf], ffprobe: Option<&str>) -> Result<Vec<Verified>> {
+    let mut verified = Vec::new();
+    for file in files {
+        verified.push(verify(file, ffprobe).await?);
+    }
+    Ok(verified)
+}
+
+async fn verify(path: &Path, ffprobe: Option<&str>) -> Result<Verified> {
+    let size = match tokio::fs::metadata(path).await {
+        Ok(metadata) => metadata.len(),
+        Err(e) => bail!("The downloaded file {} is missing: {}", path.display(), e),
+    };
+    if size == 0 {
+        bail!("The downloaded file {} is empty", path.display());
+    }
+    let duration = match ffprobe {
+        Some(ffprobe) => probe_duration(ffprobe, path).await?,
+        None => None,
+    };
+    let owned = path.to_owned();
+    // Hashing a large video takes a while, so keep it off the async workers
+    let sha256 = tokio::task::spawn_blocking(move || sha256_file(&owned)).await??;
+    Ok(Verified { path: path.to_owned(), size, duration, sha256 })
+}
+
+async fn probe_duration(ffprobe: &str, path: &Path) -> Result<Option<f64>> {
+    let output = tokio::process::Command::new(ffprobe)
+        .args(["-v", "error", "-show_entries", "format=duration", "-of", "default=noprint_wrappers=1:nokey=1"])
+        .arg(path)
+        .kill_on_drop(true)
+        .output()
+        .await
+        .with_context(|| format!("Failed to run {}", ffprobe))?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        bail!("ffprobe couldn't read the downloaded file {}: {}", path.display(), stderr.trim());
+    }
+    // Some containers, e.g. raw audio streams, have no duration
+    Ok(String::from_utf8_lossy(&output.stdout).trim().parse().ok())
+}
+
+pub fn sha256_file(path: &Path) -> Result<String> {
+    let mut file = File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0; 1 << 16];
+    loop {
+        let read = file.read(&mut buf).with_context(|| format!("Failed to read {}", path.display()))?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buf[..read]);
+    }
+    Ok(hasher.finalize().iter().map(|byte| format!("{:02x}", byte)).collect())
+}
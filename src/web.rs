@@ -0,0 +1,444 @@
+use anyhow::{Context, Result};
+use axum::body::Body;
+use axum::extract::{Path as UrlPath, Query, State};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::{Html, IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use serde::{Deserialize, Serialize};
+use serenity::http::Http;
+use serenity::model::channel::Channel;
+use serenity::model::id::ChannelId;
+use std::path::Path;
+use std::sync::Arc;
+use tokio_util::io::ReaderStream;
+
+use crate::clip::Clip;
+use crate::disk;
+use crate::embed::CardState;
+use crate::format::{self, FormatSpec};
+use crate::history::{self, Failure, History};
+use crate::jobs::{JobId, JobInfo, JobRegistry, JobState};
+use crate::metrics::{Gauges, Metrics};
+use crate::progress::Progress;
+use crate::queue::{DownloadQueue, Priority};
+use crate::ytdlp::{self, Metadata};
+use crate::{is_valid_url, send_status, DownloadRequest, Handler, Submitted};
+
+const DASHBOARD_HTML: &str = include_str!("dashboard.html");
+const RECENT_FAILURES: usize = 20;
+
+pub struct Dashboard {
+    pub jobs: Arc<JobRegistry>,
+    pub queue: Arc<DownloadQueue>,
+    pub history: Arc<History>,
+    pub metrics: Arc<Metrics>,
+    pub output_dir: String,
+    pub token: String,
+    // For jobs submitted through the API, which report to Discord like any other
+    pub handler: Arc<Handler>,
+    pub http: Arc<Http>,
+}
+
+#[derive(Serialize)]
+struct StatusResponse {
+    max_concurrent: usize,
+    running: Vec<JobResponse>,
+    queued: Vec<JobResponse>,
+    recent_failures: Vec<Failure>,
+    disk: Option<DiskResponse>,
+}
+
+#[derive(Serialize)]
+struct JobResponse {
+    id: u64,
+    url: String,
+    requester: String,
+    channel: String,
+    elapsed_secs: u64,
+    progress: Option<Progress>,
+    percent: Option<f64>,
+}
+
+// Body of POST /api/jobs; the options are those of /download
+#[derive(Deserialize)]
+struct NewJob {
+    url: String,
+    // Channel the job reports its progress and result to
+    channel: Snowflake,
+    format: Option<String>,
+    #[serde(default)]
+    force: bool,
+    subs: Option<String>,
+    // A time range like "1:23-2:45"
+    clip: Option<String>,
+    via: Option<String>,
+    #[serde(default)]
+    chapters: bool,
+}
+
+// Accepted as a number or a string, since JavaScript can't hold every snowflake as a number
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Snowflake {
+    Number(u64),
+    Text(String),
+}
+
+impl Snowflake {
+    fn channel(&self) -> Option<ChannelId> {
+        let id = match self {
+            Snowflake::Number(id) => *id,
+            Snowflake::Text(id) => id.trim().parse().ok()?,
+        };
+        (id != 0).then(|| ChannelId::new(id))
+    }
+}
+
+#[derive(Serialize)]
+#[serde(tag = "result", rename_all = "snake_case")]
+enum SubmitResponse {
+    Queued { id: JobId, position: usize },
+    // The same download was already running as job `id`
+    Joined { id: JobId },
+    Playlist { title: String, queued: usize },
+    Duplicate { id: JobId, output_path: String, downloaded_at: i64 },
+}
+
+// GET /api/jobs/:id: what the history has on the job, with progress while it runs
+#[derive(Serialize)]
+struct JobStatusResponse {
+    id: JobId,
+    url: String,
+    requester: String,
+    format: String,
+    status: String,
+    requested_at: i64,
+    output_path: Option<String>,
+    file_size: Option<u64>,
+    progress: Option<Progress>,
+    percent: Option<f64>,
+}
+
+#[derive(Serialize)]
+struct DiskResponse {
+    free_bytes: u64,
+    total_bytes: u64,
+}
+
+impl From<JobInfo> for JobResponse {
+    fn from(job: JobInfo) -> Self {
+        let progress = job.progress();
+        JobResponse {
+            id: job.id,
+            url: job.url,
+            // Snowflakes don't fit in a JavaScript number
+            requester: job.requester.to_string(),
+            channel: job.channel.to_string(),
+            elapsed_secs: job.started.elapsed().as_secs(),
+            percent: progress.and_then(|progress| progress.percent()),
+            progress,
+        }
+    }
+}
+
+pub async fn serve(addr: &str, dashboard: Arc<Dashboard>) -> Result<()> {
+    let app = Router::new()
+        .route("/", get(|| async { Html(DASHBOARD_HTML) }))
+        .route("/api/status", get(status))
+        .route("/api/jobs", post(create_job))
+        .route("/api/jobs/:id", get(job_status))
+        .route("/metrics", get(metrics))
+        .route("/zip/:token", get(zip_file))
+        .route("/files/:name", get(linked_file))
+        .with_state(dashboard);
+    let listener = tokio::net::TcpListener::bind(addr).await
+        .with_context(|| format!("Failed to bind dashboard to {}", addr))?;
+    log::info!("Dashboard listening on http://{}", addr);
+    axum::serve(listener, app).await.context("Dashboard server failed")
+}
+
+async fn status(State(dashboard): State<Arc<Dashboard>>, headers: HeaderMap) -> Response {
+    if !authorized(&headers, &dashboard.token) {
+        return (StatusCode::UNAUTHORIZED, "Missing or invalid token").into_response();
+    }
+    let (running, queued): (Vec<_>, Vec<_>) = dashboard.jobs.list()
+        .into_iter()
+        .partition(|job| job.state == JobState::Running);
+    let recent_failures = dashboard.history.recent_failures(RECENT_FAILURES).unwrap_or_else(|e| {
+        log::error!("Failed to read recent failures: {}", e);
+        Vec::new()
+    });
+    let disk = disk::space(Path::new(&dashboard.output_dir))
+        .map(|(free_bytes, total_bytes)| DiskResponse { free_bytes, total_bytes });
+    Json(StatusResponse {
+        max_concurrent: dashboard.queue.max_concurrent(),
+        running: running.into_iter().map(JobResponse::from).collect(),
+        queued: queued.into_iter().map(JobResponse::from).collect(),
+        recent_failures,
+        disk,
+    })
+    .into_response()
+}
+
+async fn create_job(
+    State(dashboard): State<Arc<Dashboard>>,
+    headers: HeaderMap,
+    Json(job): Json<NewJob>,
+) -> Response {
+    if !authorized(&headers, &dashboard.token) {
+        return (StatusCode::UNAUTHORIZED, "Missing or invalid token").into_response();
+    }
+    let request = match job_request(&dashboard, job).await {
+        Ok(request) => request,
+        Err(e) => return error_response(StatusCode::BAD_REQUEST, e),
+    };
+    log::info!(target: "audit", "<{}> submitted through the API for channel {}", request.url, request.channel);
+    let http = &dashboard.http;
+    let messages = dashboard.handler.messages(request.guild);
+    let url = request.url.clone();
+    let status = send_status(http, request.channel, messages.get("accepted", &[])).await;
+    let submitted = dashboard.handler.submit(http, request, status.clone()).await;
+    let update = match &submitted {
+        Ok(Submitted::Job { position, card, .. }) => {
+            if let Some(status) = status.as_ref().filter(|_| *position > 0) {
+                let _ = status.edit_card(http, card.render(CardState::Queued(*position))).await;
+            }
+            None
+        }
+        Ok(Submitted::Duplicate(existing)) => Some(messages.get("already_downloaded", &[
+            ("when", &format!("<t:{}:R>", existing.downloaded_at)),
+            ("what", &existing.describe(&messages)),
+        ])),
+        Ok(Submitted::Joined { id }) => Some(messages.get("joined", &[("url", &url), ("id", id)])),
+        Ok(Submitted::Playlist { title, queued }) => {
+            Some(messages.get("playlist_queued", &[("count", queued), ("title", title)]))
+        }
+        Err(e) => Some(e.to_string()),
+    };
+    if let (Some(status), Some(update)) = (&status, update) {
+        let _ = status.edit(http, update).await;
+    }
+    match submitted {
+        Ok(Submitted::Job { id, position, .. }) => {
+            (StatusCode::ACCEPTED, Json(SubmitResponse::Queued { id, position })).into_response()
+        }
+        Ok(Submitted::Joined { id }) => (StatusCode::ACCEPTED, Json(SubmitResponse::Joined { id })).into_response(),
+        Ok(Submitted::Playlist { title, queued }) => {
+            (StatusCode::ACCEPTED, Json(SubmitResponse::Playlist { title, queued })).into_response()
+        }
+        Ok(Submitted::Duplicate(existing)) => Json(SubmitResponse::Duplicate {
+            id: existing.job_id,
+            output_path: existing.output_path,
+            downloaded_at: existing.downloaded_at,
+        })
+        .into_response(),
+        Err(e) => error_response(StatusCode::UNPROCESSABLE_ENTITY, e.to_string()),
+    }
+}
+
+// Builds the request the way /download would, with the bot as the requester
+async fn job_request(dashboard: &Dashboard, job: NewJob) -> Result<DownloadRequest, String> {
+    let handler = &dashboard.handler;
+    let url = job.url.trim();
+    if !is_valid_url(url) {
+        return Err("Invalid URL.".to_string());
+    }
+    let channel = job.channel.channel().ok_or_else(|| "Invalid channel ID.".to_string())?;
+    let guild = match channel.to_channel(&dashboard.http).await {
+        Ok(Channel::Guild(channel)) => Some(channel.guild_id),
+        Ok(_) => None,
+        Err(e) => return Err(format!("Can't post in channel {}: {}", channel, e)),
+    };
+    // The same guilds and channels as requests made in Discord
+    if !handler.is_allowed_location(guild, channel) {
+        return Err(handler.messages(guild).get("not_allowed_here", &[]));
+    }
+    let format = match job.format.as_deref().map(str::parse::<FormatSpec>) {
+        Some(Ok(format)) => Some(format),
+        Some(Err(e)) => return Err(e.to_string()),
+        None => None,
+    };
+    let subtitles = match job.subs.as_deref().map(format::parse_subtitle_langs) {
+        Some(Ok(langs)) => Some(langs),
+        Some(Err(e)) => return Err(e.to_string()),
+        None => None,
+    };
+    let clip = match job.clip.as_deref().map(str::trim) {
+        Some(range) => match Clip::parse(range) {
+            Some(Ok(clip)) => Some(clip),
+            Some(Err(e)) => return Err(e.to_string()),
+            None => return Err(format!("'{}' isn't a time range like 1:23-2:45.", range)),
+        },
+        None => None,
+    };
+    let backend = match job.via.as_deref() {
+        Some(name) => Some(handler.requested_backend(name, guild)?),
+        None => None,
+    };
+    let bot = dashboard.http.get_current_user().await.map_err(|e| format!("Failed to look up the bot user: {}", e))?;
+    Ok(DownloadRequest {
+        url: url.to_owned(),
+        requester: bot.id,
+        requester_name: bot.name.clone(),
+        requester_avatar: Some(bot.face()),
+        channel,
+        guild,
+        format: handler.resolve_format(format, guild, channel),
+        force: job.force,
+        archive_key: None,
+        playlist: None,
+        subtitles,
+        clip,
+        metadata: Metadata::default(),
+        dm: false,
+        live: false,
+        schedule: None,
+        backend,
+        split_chapters: job.chapters,
+        from_api: true,
+        thread: None,
+        extra_args: Vec::new(),
+        format_id: None,
+        workaround: None,
+        priority: Priority::Normal,
+        geo_country: None,
+        subfolder: None,
+        spoiler: false,
+    })
+}
+
+async fn job_status(
+    State(dashboard): State<Arc<Dashboard>>,
+    headers: HeaderMap,
+    UrlPath(id): UrlPath<JobId>,
+) -> Response {
+    if !authorized(&headers, &dashboard.token) {
+        return (StatusCode::UNAUTHORIZED, "Missing or invalid token").into_response();
+    }
+    let entry = match dashboard.history.entry(id) {
+        Ok(Some(entry)) => entry,
+        Ok(None) => return error_response(StatusCode::NOT_FOUND, format!("There's no job #{}.", id)),
+        Err(e) => {
+            log::error!("Failed to look up job #{}: {}", id, e);
+            return error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to read the history".to_string());
+        }
+    };
+    let progress = dashboard.jobs.get(id).and_then(|job| job.progress());
+    Json(JobStatusResponse {
+        id,
+        url: entry.url,
+        requester: entry.requester.to_string(),
+        format: entry.format,
+        status: entry.status,
+        requested_at: entry.requested_at,
+        output_path: entry.output_path,
+        file_size: entry.file_size,
+        percent: progress.and_then(|progress| progress.percent()),
+        progress,
+    })
+    .into_response()
+}
+
+// The token in the link is all it takes, so zips can be shared from Discord until they expire
+async fn zip_file(State(dashboard): State<Arc<Dashboard>>, UrlPath(token): UrlPath<String>) -> Response {
+    let Some(path) = dashboard.handler.zips.file(&token) else {
+        return (StatusCode::NOT_FOUND, "This link has expired").into_response();
+    };
+    let file = match tokio::fs::File::open(&path).await {
+        Ok(file) => file,
+        Err(e) => {
+            log::warn!("Failed to open {}: {}", path.display(), e);
+            return (StatusCode::NOT_FOUND, "This link has expired").into_response();
+        }
+    };
+    let headers = [
+        (header::CONTENT_TYPE, "application/zip".to_string()),
+        (header::CONTENT_DISPOSITION, attachment(&path)),
+    ];
+    (headers, Body::from_stream(ReaderStream::new(file))).into_response()
+}
+
+#[derive(Deserialize)]
+struct FileQuery {
+    path: String,
+    expires: i64,
+    sig: String,
+}
+
+// Signed links to finished downloads; the name in the URL is only there for the browser
+async fn linked_file(State(dashboard): State<Arc<Dashboard>>, UrlPath(_name): UrlPath<String>, Query(query): Query<FileQuery>) -> Response {
+    let Some(links) = &dashboard.handler.links else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    if !constant_time_eq(query.sig.as_bytes(), links.signature(&query.path, query.expires).as_bytes()) {
+        return (StatusCode::FORBIDDEN, "This link isn't valid").into_response();
+    }
+    if query.expires <= history::now() {
+        return (StatusCode::GONE, "This link has expired").into_response();
+    }
+    let path = Path::new(&query.path);
+    let file = match tokio::fs::File::open(path).await {
+        Ok(file) => file,
+        Err(e) => {
+            log::warn!("Failed to open {}: {}", path.display(), e);
+            return (StatusCode::NOT_FOUND, "This file is gone").into_response();
+        }
+    };
+    let size = file.metadata().await.map(|meta| meta.len()).unwrap_or_default();
+    let headers = [
+        (header::CONTENT_TYPE, "application/octet-stream".to_string()),
+        (header::CONTENT_LENGTH, size.to_string()),
+        (header::CONTENT_DISPOSITION, attachment(path)),
+    ];
+    (headers, Body::from_stream(ReaderStream::new(file))).into_response()
+}
+
+// Quotes and backslashes would end or escape the quoted name, and control characters aren't
+// allowed in a header at all
+fn attachment(path: &Path) -> String {
+    let name: String = path.file_name().unwrap_or_default().to_string_lossy()
+        .chars()
+        .map(|c| match c {
+            '"' => '\'',
+            '\\' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect();
+    format!("attachment; filename=\"{}\"", name)
+}
+
+fn error_response(status: StatusCode, error: String) -> Response {
+    (status, Json(serde_json::json!({ "error": error }))).into_response()
+}
+
+// Left unauthenticated for scrapers; it only exposes counts and site names
+async fn metrics(State(dashboard): State<Arc<Dashboard>>) -> impl IntoResponse {
+    let gauges = Gauges {
+        queued: dashboard.jobs.count(JobState::Queued),
+        running: dashboard.jobs.count(JobState::Running),
+        processes: ytdlp::running_processes(),
+        shards: dashboard.handler.gateway.shards(),
+    };
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        dashboard.metrics.render(&gauges),
+    )
+}
+
+// Expects `Authorization: Bearer <token>`
+fn authorized(headers: &HeaderMap, token: &str) -> bool {
+    let provided = headers.get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    match provided {
+        Some(provided) => constant_time_eq(provided.as_bytes(), token.as_bytes()),
+        None => false,
+    }
+}
+
+// Compares without returning early, so response times don't reveal how much of the token matched
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
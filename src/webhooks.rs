@@ -0,0 +1,158 @@
+use serde::{Deserialize, Serialize};
+use serenity::model::id::{ChannelId, GuildId, UserId};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::history;
+use crate::jobs::JobId;
+use crate::storage::{hex, hmac_sha256};
+
+// Deliveries are tried this many times in all, waiting twice as long after each failure
+const ATTEMPTS: u32 = 4;
+const FIRST_RETRY_DELAY: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Event {
+    Started,
+    Succeeded,
+    Failed,
+}
+
+impl Event {
+    fn name(&self) -> &'static str {
+        match self {
+            Event::Started => "started",
+            Event::Succeeded => "succeeded",
+            Event::Failed => "failed",
+        }
+    }
+}
+
+fn default_events() -> Vec<Event> {
+    vec![Event::Started, Event::Succeeded, Event::Failed]
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct WebhookSettings {
+    pub url: String,
+    // Signs each body with HMAC-SHA256, sent as X-Signature-256: sha256=<hex>
+    pub secret: Option<String>,
+    #[serde(default = "default_events")]
+    pub events: Vec<Event>,
+}
+
+// What every event says about the job
+#[derive(Debug, Clone)]
+pub struct JobDetails {
+    pub id: JobId,
+    pub url: String,
+    pub requester: UserId,
+    pub requester_name: String,
+    pub channel: ChannelId,
+    pub guild: Option<GuildId>,
+}
+
+// Discord IDs are strings so JavaScript doesn't round them
+#[derive(Debug, Serialize)]
+struct Payload<'a> {
+    event: Event,
+    job_id: JobId,
+    url: &'a str,
+    requester: String,
+    requester_name: &'a str,
+    channel: String,
+    guild: Option<String>,
+    // The first file, then all of them
+    file: Option<String>,
+    files: Vec<String>,
+    size: Option<u64>,
+    error: Option<&'a str>,
+    timestamp: i64,
+}
+
+// Tells other services about jobs as they start and finish, without holding the jobs up
+pub struct Webhooks {
+    client: reqwest::Client,
+    hooks: Vec<Arc<WebhookSettings>>,
+}
+
+impl Webhooks {
+    pub fn new(hooks: &[WebhookSettings]) -> Self {
+        Webhooks {
+            client: reqwest::Client::new(),
+            hooks: hooks.iter().cloned().map(Arc::new).collect(),
+        }
+    }
+
+    pub fn job_started(&self, job: &JobDetails) {
+        self.send(Event::Started, job, &[], None, None);
+    }
+
+    pub fn job_succeeded(&self, job: &JobDetails, files: &[PathBuf], size: u64) {
+        self.send(Event::Succeeded, job, files, Some(size), None);
+    }
+
+    pub fn job_failed(&self, job: &JobDetails, error: &str) {
+        self.send(Event::Failed, job, &[], None, Some(error));
+    }
+
+    fn send(&self, event: Event, job: &JobDetails, files: &[PathBuf], size: Option<u64>, error: Option<&str>) {
+        if !self.hooks.iter().any(|hook| hook.events.contains(&event)) {
+            return;
+        }
+        let files: Vec<String> = files.iter().map(|file| file.display().to_string()).collect();
+        let payload = Payload {
+            event,
+            job_id: job.id,
+            url: &job.url,
+            requester: job.requester.to_string(),
+            requester_name: &job.requester_name,
+            channel: job.channel.to_string(),
+            guild: job.guild.map(|guild| guild.to_string()),
+            file: files.first().cloned(),
+            files,
+            size,
+            error,
+            timestamp: history::now(),
+        };
+        let body = match serde_json::to_string(&payload) {
+            Ok(body) => body,
+            Err(e) => {
+                log::error!("Failed to serialize webhook payload for job #{}: {}", job.id, e);
+                return;
+            }
+        };
+        for hook in self.hooks.iter().filter(|hook| hook.events.contains(&event)) {
+            let (client, hook, body) = (self.client.clone(), Arc::clone(hook), body.clone());
+            let id = job.id;
+            tokio::spawn(async move { deliver(&client, &hook, event, body, id).await });
+        }
+    }
+}
+
+async fn deliver(client: &reqwest::Client, hook: &WebhookSettings, event: Event, body: String, id: JobId) {
+    let signature = hook.secret.as_ref()
+        .map(|secret| format!("sha256={}", hex(&hmac_sha256(secret.as_bytes(), body.as_bytes()))));
+    let mut delay = FIRST_RETRY_DELAY;
+    for attempt in 1..=ATTEMPTS {
+        let mut request = client.post(&hook.url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .header("X-Webhook-Event", event.name())
+            .timeout(Duration::from_secs(10))
+            .body(body.clone());
+        if let Some(signature) = &signature {
+            request = request.header("X-Signature-256", signature);
+        }
+        match request.send().await.and_then(reqwest::Response::error_for_status) {
+            Ok(_) => return,
+            Err(e) if attempt < ATTEMPTS => {
+                log::warn!("Webhook {} failed for job #{} (attempt {}/{}), retrying in {:?}: {}", hook.url, id, attempt, ATTEMPTS, delay, e);
+                tokio::time::sleep(delay).await;
+                delay *= 2;
+            }
+            Err(e) => log::error!("Giving up on webhook {} for job #{}: {}", hook.url, id, e),
+        }
+    }
+}
@@ -0,0 +1,814 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
+use tokio::sync::{watch, Notify};
+use tokio::time::Instant;
+
+use crate::archivist;
+use crate::binary;
+use crate::clip::Clip;
+use crate::format::FormatSpec;
+use crate::joblog::JobLog;
+use crate::postprocess::PostProcessing;
+use crate::progress::{self, Progress};
+use crate::sandbox;
+use crate::usage::Meter;
+
+// Prefixes the final path of each file yt-dlp writes
+const FILE_MARKER: &str = "[file] ";
+
+// Prefixes the path of each file yt-dlp is about to download, whose leftovers are removed
+// if the download times out
+const PARTIAL_MARKER: &str = "[partial] ";
+
+// yt-dlp's exit code when --break-on-existing (or --max-downloads) stops it early
+const BREAK_EXIT_CODE: i32 = 101;
+
+static RUNNING_PROCESSES: AtomicUsize = AtomicUsize::new(0);
+
+pub fn running_processes() -> usize {
+    RUNNING_PROCESSES.load(Ordering::Relaxed)
+}
+
+// Process groups of the running downloads and transcodes, so a paused queue can suspend them
+static GROUPS: Mutex<Vec<u32>> = Mutex::new(Vec::new());
+static SUSPENDED: AtomicBool = AtomicBool::new(false);
+
+// Counts a yt-dlp (or gallery-dl) process in RUNNING_PROCESSES for as long as it's alive,
+// along with its process group when it has one of its own
+pub struct RunningProcess(Option<u32>);
+
+impl RunningProcess {
+    pub fn start(group: Option<u32>) -> Self {
+        RUNNING_PROCESSES.fetch_add(1, Ordering::Relaxed);
+        if let Some(pgid) = group {
+            let mut groups = GROUPS.lock().unwrap();
+            groups.push(pgid);
+            // A job that was already running when its processes were suspended started another
+            if SUSPENDED.load(Ordering::SeqCst) {
+                signal_group(pgid, Signal::Stop);
+            }
+        }
+        RunningProcess(group)
+    }
+}
+
+impl Drop for RunningProcess {
+    fn drop(&mut self) {
+        RUNNING_PROCESSES.fetch_sub(1, Ordering::Relaxed);
+        if let Some(pgid) = self.0 {
+            GROUPS.lock().unwrap().retain(|group| *group != pgid);
+        }
+    }
+}
+
+// Stops every running process group in place (SIGSTOP), or lets them carry on (SIGCONT).
+// Returns how many there were; always none on Windows, which has no such signals.
+pub fn suspend_processes(suspend: bool) -> usize {
+    let groups = GROUPS.lock().unwrap();
+    SUSPENDED.store(suspend, Ordering::SeqCst);
+    let signal = if suspend { Signal::Stop } else { Signal::Continue };
+    for pgid in groups.iter() {
+        signal_group(*pgid, signal);
+    }
+    if cfg!(unix) { groups.len() } else { 0 }
+}
+
+#[derive(Clone, Copy)]
+enum Signal {
+    Stop,
+    Continue,
+}
+
+fn signal_group(pgid: u32, signal: Signal) {
+    #[cfg(unix)]
+    {
+        let signal = match signal {
+            Signal::Stop => libc::SIGSTOP,
+            Signal::Continue => libc::SIGCONT,
+        };
+        // SAFETY: killpg has no memory-safety preconditions
+        unsafe {
+            libc::killpg(pgid as libc::pid_t, signal);
+        }
+    }
+    #[cfg(windows)]
+    let _ = (pgid, signal);
+}
+
+pub struct DownloadOptions<'a> {
+    pub output_dir: &'a str,
+    pub output_template: &'a str,
+    // --cookies or --cookies-from-browser, from CookieConfig
+    pub cookies: &'a [String],
+    pub format: &'a FormatSpec,
+    // A format selector picked from the site's own formats, used instead of `format`
+    pub format_id: Option<&'a str>,
+    pub post_processing: &'a PostProcessing,
+    // Languages to download subtitles for, as passed to --sub-langs
+    pub subtitles: Option<&'a str>,
+    pub embed_subtitles: bool,
+    // yt-dlp's own archive of downloaded IDs, which skips anything already in it
+    pub download_archive: Option<&'a str>,
+    // Download again even if the file or an archive entry already exists
+    pub force: bool,
+    // Stop at the first playlist item that's already in the archive, for newest-first lists
+    pub only_new: bool,
+    // Only the first this many playlist items
+    pub max_items: Option<usize>,
+    // From site_args, passed last so they override the bot's own arguments
+    pub extra_args: &'a [String],
+    // Passed to --proxy; an empty string connects directly
+    pub proxy: Option<&'a str>,
+    // --geo-bypass-country and friends, from GeoSettings
+    pub geo: &'a [String],
+    // Record a live stream from its start rather than from now
+    pub live: bool,
+    // Notified to end a live recording early; what's been recorded is kept
+    pub stop: Option<&'a Notify>,
+    // Passed to --limit-rate
+    pub rate_limit: Option<&'a str>,
+    // --concurrent-fragments and --downloader, from `downloader_args`
+    pub downloader_args: &'a [String],
+    // Only download this section, cut precisely at its ends
+    pub clip: Option<Clip>,
+    // yt-dlp is killed if it's still running at this point
+    pub deadline: Option<Instant>,
+    pub keep_partial_files: bool,
+    // Also write a file per chapter, next to the whole video
+    pub split_chapters: bool,
+    // Write the .info.json and thumbnail that media server sidecars are made from
+    pub sidecars: bool,
+    // Keep the site's metadata, thumbnail, description and comments beside the video
+    pub archivist: bool,
+    // Where the downloader's output is kept for /log
+    pub log: Option<&'a JobLog>,
+    // Counts what's transferred, for /usage
+    pub meter: Option<&'a Meter>,
+}
+
+// Arguments for how yt-dlp fetches HLS and DASH fragments: several at once, and optionally
+// through an external downloader such as aria2c with its own arguments
+pub fn downloader_args(concurrent_fragments: Option<u32>, downloader: Option<&Path>, downloader_args: Option<&str>) -> Vec<String> {
+    let mut args = Vec::new();
+    if let Some(fragments) = concurrent_fragments.filter(|&fragments| fragments > 1) {
+        args.extend(["--concurrent-fragments".to_string(), fragments.to_string()]);
+    }
+    if let Some(downloader) = downloader {
+        args.extend(["--downloader".to_string(), downloader.display().to_string()]);
+        if let Some(extra) = downloader_args {
+            // yt-dlp keys downloader arguments by the downloader's name
+            let name = downloader.file_stem().unwrap_or_default().to_string_lossy();
+            args.extend(["--downloader-args".to_string(), format!("{}:{}", name, extra)]);
+        }
+    }
+    args
+}
+
+// Returned when a download runs past its deadline
+#[derive(Debug)]
+pub struct TimedOut;
+
+impl fmt::Display for TimedOut {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "The download ran past its deadline and was killed")
+    }
+}
+
+impl std::error::Error for TimedOut {}
+
+pub async fn download(
+    url: &str,
+    options: &DownloadOptions<'_>,
+    progress: &watch::Sender<Option<Progress>>,
+) -> Result<Vec<PathBuf>> {
+    log::info!("Downloading URL: {}", url);
+    let output_dir = options.output_dir;
+    fs::create_dir_all(output_dir)
+        .with_context(|| format!("Failed to create output directory: {}", output_dir))?;
+    let mut cmd = sandbox::command(binary::path(), Some(output_dir));
+    utf8_output(&mut cmd);
+    cmd.arg(url)
+        .arg("-P").arg(output_dir)
+        .arg("-o").arg(options.output_template)
+        .arg("--newline")
+        .arg("--progress-template").arg(progress::TEMPLATE)
+        // --print implies --quiet, so progress has to be re-enabled explicitly
+        .arg("--progress")
+        .arg("--print").arg(format!("before_dl:{}%(filename)s", PARTIAL_MARKER))
+        .arg("--print").arg(format!("after_move:{}%(filepath)s", FILE_MARKER))
+        .args(options.post_processing.ytdlp_args());
+    match options.format_id {
+        Some(id) => cmd.arg("-f").arg(id),
+        None => cmd.args(options.format.ytdlp_args()),
+    };
+    // Audio files have nowhere to put subtitles
+    if let Some(langs) = options.subtitles.filter(|_| !options.format.is_audio()) {
+        cmd.arg("--write-subs").arg("--sub-langs").arg(langs);
+        if options.embed_subtitles {
+            cmd.arg("--embed-subs");
+        }
+    }
+    cmd.args(options.cookies);
+    if options.force {
+        cmd.arg("--force-overwrites");
+    } else {
+        // yt-dlp's default, but resumed jobs rely on it to pick up their .part files
+        cmd.arg("--continue");
+        if let Some(archive) = options.download_archive {
+            cmd.arg("--download-archive").arg(archive);
+            if options.only_new {
+                cmd.arg("--break-on-existing");
+            }
+        }
+    }
+    if let Some(max_items) = options.max_items {
+        cmd.arg("--playlist-end").arg(max_items.to_string());
+    }
+    if let Some(clip) = options.clip {
+        cmd.arg("--download-sections").arg(clip.section_arg())
+            .arg("--force-keyframes-at-cuts");
+    }
+    if let Some(rate) = options.rate_limit {
+        cmd.arg("--limit-rate").arg(rate);
+    }
+    cmd.args(options.downloader_args);
+    if let Some(proxy) = options.proxy {
+        cmd.arg("--proxy").arg(proxy);
+    }
+    cmd.args(options.geo);
+    if options.live {
+        cmd.arg("--live-from-start");
+    }
+    if options.sidecars {
+        // Named the way Jellyfin and Kodi look for episode thumbnails
+        let stem = options.output_template.strip_suffix(".%(ext)s").unwrap_or(options.output_template);
+        cmd.arg("--write-info-json")
+            .arg("--write-thumbnail").arg("--convert-thumbnails").arg("jpg")
+            .arg("-o").arg(format!("thumbnail:{}-thumb.%(ext)s", stem));
+    }
+    if options.archivist {
+        cmd.args(archivist::YTDLP_ARGS);
+    }
+    if options.split_chapters {
+        cmd.arg("--split-chapters")
+            .arg("-o").arg(format!("chapter:{}", chapter_template(options.output_template)));
+    }
+    cmd.args(options.extra_args);
+    cmd.stdout(Stdio::piped());
+    // Cancelling a job drops this future, which must take yt-dlp down with it
+    cmd.kill_on_drop(true);
+    own_process_group(&mut cmd);
+    cmd.stderr(Stdio::piped());
+    let mut child = cmd.spawn()
+        .with_context(|| "Failed to spawn yt-dlp process")?;
+    let mut group = ProcessGroup(child.id());
+    let _running = RunningProcess::start(group.0);
+    if let Some(log) = options.log {
+        log.line("bot", &format!("Running yt-dlp for {}", url));
+    }
+    // Drain stderr concurrently so yt-dlp can't block on a full pipe
+    let stderr = child.stderr.take().context("yt-dlp stderr was not captured")?;
+    let stderr_task = tokio::spawn(read_stderr(stderr, options.log.cloned()));
+    let stdout = child.stdout.take().context("yt-dlp stdout was not captured")?;
+    let mut lines = BufReader::new(stdout).lines();
+    let mut files = Vec::new();
+    let mut partial: Vec<PathBuf> = Vec::new();
+    let mut stopped = false;
+    loop {
+        let line = tokio::select! {
+            line = lines.next_line() => line.with_context(|| "Failed to read yt-dlp output")?,
+            _ = stop_requested(options.stop), if !stopped => {
+                // Like Ctrl+C, which makes yt-dlp and ffmpeg finish the file they're writing
+                log::info!("Stopping the recording of {}", url);
+                group.interrupt();
+                stopped = true;
+                continue;
+            }
+            _ = deadline_passed(options.deadline) => {
+                log::warn!("Killing yt-dlp for {}: it ran past its deadline", url);
+                group.terminate();
+                let _ = child.kill().await;
+                if !options.keep_partial_files {
+                    for target in &partial {
+                        remove_partial_files(target);
+                    }
+                }
+                return Err(TimedOut.into());
+            }
+        };
+        let Some(line) = line else {
+            break;
+        };
+        if let Some(update) = Progress::parse(&line) {
+            if let Some(meter) = options.meter {
+                meter.observe(update.downloaded_bytes);
+            }
+            progress.send_replace(Some(update));
+            continue;
+        }
+        if let Some(log) = options.log {
+            log.line("stdout", &line);
+        }
+        if let Some(path) = line.strip_prefix(PARTIAL_MARKER) {
+            partial.push(PathBuf::from(path));
+        } else if let Some(path) = line.strip_prefix(FILE_MARKER) {
+            files.push(PathBuf::from(path));
+        }
+    }
+    let status = child.wait().await
+        .with_context(|| "Failed to wait for yt-dlp process")?;
+    group.disarm();
+    let stderr = stderr_task.await.unwrap_or_default();
+    if let Some(log) = options.log {
+        log.line("bot", &format!("yt-dlp exited with {}", status));
+    }
+    // yt-dlp exits with an error when interrupted, even after saving the recording
+    if status.success() || stopped || options.only_new && status.code() == Some(BREAK_EXIT_CODE) {
+        if (options.sidecars || options.archivist) && !options.cookies.is_empty() {
+            for file in &files {
+                strip_cookies(&file.with_extension("info.json"));
+            }
+        }
+        if options.split_chapters {
+            let chapters: Vec<PathBuf> = files.iter().flat_map(|file| chapter_files(file)).collect();
+            files.extend(chapters);
+        }
+        Ok(files)
+    } else {
+        Err(anyhow::anyhow!("yt-dlp failed with status: {}\nError output: {}", status, stderr.trim()))
+    }
+}
+
+// All of a process's stderr, each line also going to the job's log as it comes
+pub async fn read_stderr(stderr: impl AsyncRead + Unpin, log: Option<JobLog>) -> String {
+    let mut buf = String::new();
+    let mut lines = BufReader::new(stderr).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        if let Some(log) = &log {
+            log.line("stderr", &line);
+        }
+        buf.push_str(&line);
+        buf.push('\n');
+    }
+    buf
+}
+
+// The .info.json includes the cookies sent for each format, which mustn't be kept beside the
+// download. If they can't be taken out, the whole file goes.
+fn strip_cookies(info_path: &Path) {
+    let Ok(json) = fs::read(info_path) else {
+        return;
+    };
+    let stripped = serde_json::from_slice(&json).and_then(|mut info| {
+        remove_cookies(&mut info);
+        serde_json::to_vec(&info)
+    });
+    let written = stripped.map_err(anyhow::Error::from)
+        .and_then(|json| fs::write(info_path, json).map_err(anyhow::Error::from));
+    if let Err(e) = written {
+        log::warn!("Couldn't take the cookies out of {}, removing it: {:#}", info_path.display(), e);
+        let _ = fs::remove_file(info_path);
+    }
+}
+
+fn remove_cookies(value: &mut serde_json::Value) {
+    match value {
+        serde_json::Value::Object(fields) => {
+            fields.remove("cookies");
+            if let Some(serde_json::Value::Object(headers)) = fields.get_mut("http_headers") {
+                headers.remove("Cookie");
+            }
+            fields.values_mut().for_each(remove_cookies);
+        }
+        serde_json::Value::Array(items) => items.iter_mut().for_each(remove_cookies),
+        _ => {}
+    }
+}
+
+// Chapter files are named after the whole video's file, so they can be found next to it
+fn chapter_template(output_template: &str) -> String {
+    let stem = output_template.strip_suffix(".%(ext)s").unwrap_or(output_template);
+    format!("{} - %(section_number)03d %(section_title)s.%(ext)s", stem)
+}
+
+// yt-dlp only prints the whole video's path, so its chapters are looked for beside it
+fn chapter_files(file: &Path) -> Vec<PathBuf> {
+    let (Some(dir), Some(stem)) = (file.parent(), file.file_stem()) else {
+        return Vec::new();
+    };
+    let prefix = format!("{} - ", stem.to_string_lossy());
+    let dir = if dir.as_os_str().is_empty() { Path::new(".") } else { dir };
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut chapters: Vec<PathBuf> = entries.flatten()
+        .filter(|entry| entry.file_name().to_string_lossy().starts_with(&prefix))
+        .map(|entry| file.with_file_name(entry.file_name()))
+        .filter(|path| path.extension().is_none_or(|extension| extension != "part"))
+        .collect();
+    chapters.sort();
+    chapters
+}
+
+// Windows consoles default to a legacy code page that can't hold every title, and the bot
+// reads yt-dlp's output as UTF-8
+fn utf8_output(cmd: &mut tokio::process::Command) {
+    if cfg!(windows) {
+        cmd.arg("--encoding").arg("utf-8");
+    }
+}
+
+// Starts the process in a group of its own for ProcessGroup to stop
+pub fn own_process_group(cmd: &mut tokio::process::Command) {
+    #[cfg(unix)]
+    cmd.process_group(0);
+    #[cfg(windows)]
+    cmd.creation_flags(CREATE_NEW_PROCESS_GROUP);
+}
+
+#[cfg(windows)]
+const CREATE_NEW_PROCESS_GROUP: u32 = 0x0000_0200;
+
+#[cfg(windows)]
+const CTRL_BREAK_EVENT: u32 = 1;
+
+#[cfg(windows)]
+#[link(name = "kernel32")]
+extern "system" {
+    fn GenerateConsoleCtrlEvent(ctrl_event: u32, process_group_id: u32) -> i32;
+}
+
+// Cancelling a download drops it mid-way, and killing yt-dlp alone would leave
+// any ffmpeg it started running, so the whole process group is terminated.
+// Windows has no process groups to signal, so there the process tree goes instead.
+pub struct ProcessGroup(pub Option<u32>);
+
+impl ProcessGroup {
+    pub fn disarm(&mut self) {
+        self.0 = None;
+    }
+
+    pub fn terminate(&mut self) {
+        #[cfg(unix)]
+        if let Some(pgid) = self.0.take() {
+            // SAFETY: killpg has no memory-safety preconditions
+            unsafe {
+                libc::killpg(pgid as libc::pid_t, libc::SIGTERM);
+            }
+            // A suspended group only acts on it once it's continued
+            if SUSPENDED.load(Ordering::SeqCst) {
+                signal_group(pgid, Signal::Continue);
+            }
+        }
+        #[cfg(windows)]
+        if let Some(pid) = self.0.take() {
+            // /T takes along every process it started; not waited for, since this runs in drop
+            let _ = std::process::Command::new("taskkill")
+                .args(["/T", "/F", "/PID", &pid.to_string()])
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .spawn();
+        }
+    }
+
+    // Asks a recording to finish its file and exit. On Windows that's Ctrl+Break, which
+    // ffmpeg treats like Ctrl+C.
+    pub fn interrupt(&self) {
+        #[cfg(unix)]
+        if let Some(pgid) = self.0 {
+            // SAFETY: killpg has no memory-safety preconditions
+            unsafe {
+                libc::killpg(pgid as libc::pid_t, libc::SIGINT);
+            }
+        }
+        #[cfg(windows)]
+        if let Some(pid) = self.0 {
+            // SAFETY: GenerateConsoleCtrlEvent only takes plain integers
+            unsafe {
+                GenerateConsoleCtrlEvent(CTRL_BREAK_EVENT, pid);
+            }
+        }
+    }
+}
+
+pub async fn deadline_passed(deadline: Option<Instant>) {
+    match deadline {
+        Some(deadline) => tokio::time::sleep_until(deadline).await,
+        None => std::future::pending().await,
+    }
+}
+
+// Removes what yt-dlp leaves of an unfinished file: its .part and .ytdl files, and those of
+// the separate video and audio formats it was going to merge into it
+fn remove_partial_files(target: &Path) {
+    let (Some(dir), Some(stem)) = (target.parent(), target.file_stem()) else {
+        return;
+    };
+    let prefix = format!("{}.", stem.to_string_lossy());
+    // Relative to the working directory when output_dir is
+    let dir = if dir.as_os_str().is_empty() { Path::new(".") } else { dir };
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let leftover = name.ends_with(".part") || name.ends_with(".ytdl") || name.contains(".part-Frag");
+        if name.starts_with(&prefix) && leftover {
+            match fs::remove_file(entry.path()) {
+                Ok(()) => log::info!("Removed partial file {}", entry.path().display()),
+                Err(e) => log::warn!("Failed to remove partial file {}: {}", entry.path().display(), e),
+            }
+        }
+    }
+}
+
+pub async fn stop_requested(stop: Option<&Notify>) {
+    match stop {
+        Some(stop) => stop.notified().await,
+        None => std::future::pending().await,
+    }
+}
+
+impl Drop for ProcessGroup {
+    fn drop(&mut self) {
+        self.terminate();
+    }
+}
+
+// Metadata from `yt-dlp --flat-playlist -J`; playlists list their entries without resolving them
+#[derive(Debug, Deserialize)]
+pub struct Info {
+    #[serde(rename = "_type")]
+    pub kind: Option<String>,
+    pub id: Option<String>,
+    pub extractor_key: Option<String>,
+    pub filesize: Option<u64>,
+    pub filesize_approx: Option<u64>,
+    // Set when the best format is separate video and audio streams
+    #[serde(default)]
+    pub requested_formats: Vec<FormatInfo>,
+    // Every format on offer, for /probe
+    #[serde(default)]
+    pub formats: Vec<FormatInfo>,
+    pub title: Option<String>,
+    pub uploader: Option<String>,
+    // For channel subscriptions
+    pub channel_id: Option<String>,
+    pub thumbnail: Option<String>,
+    pub duration: Option<f64>,
+    pub is_live: Option<bool>,
+    // Set by music sites, and YouTube for songs it knows
+    pub artist: Option<String>,
+    pub track: Option<String>,
+    pub album: Option<String>,
+    // For the moderation checks
+    pub description: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    pub age_limit: Option<u32>,
+    #[serde(default)]
+    pub entries: Vec<Option<PlaylistEntry>>,
+}
+
+// What status embeds show about a video; saved with the job so resumed jobs keep it
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Metadata {
+    pub title: Option<String>,
+    pub uploader: Option<String>,
+    pub thumbnail: Option<String>,
+    pub duration: Option<u64>,
+    // For tagging audio downloads
+    pub artist: Option<String>,
+    pub track: Option<String>,
+    pub album: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FormatInfo {
+    pub format_id: Option<String>,
+    pub ext: Option<String>,
+    pub filesize: Option<u64>,
+    pub filesize_approx: Option<u64>,
+    pub height: Option<u32>,
+    pub fps: Option<f64>,
+    // Total bitrate in kbit/s
+    pub tbr: Option<f64>,
+    // "none" for streams without video or audio; missing when the site doesn't say
+    pub vcodec: Option<String>,
+    pub acodec: Option<String>,
+    pub format_note: Option<String>,
+}
+
+impl FormatInfo {
+    pub fn size(&self) -> Option<u64> {
+        self.filesize.or(self.filesize_approx)
+    }
+
+    // What -f is given to download this format: video-only streams get the best audio added,
+    // falling back to the stream alone
+    pub fn selector(&self) -> Option<String> {
+        let id = self.format_id.as_deref()?;
+        if self.is_video_only() {
+            Some(format!("{0}+ba/{0}", id))
+        } else {
+            Some(id.to_string())
+        }
+    }
+
+    // E.g. "1080p60 mp4 · avc1 · video only"
+    pub fn describe(&self) -> String {
+        let mut parts = Vec::new();
+        let resolution = match (self.height.filter(|_| self.has_video()), self.fps) {
+            (Some(height), Some(fps)) if fps > 30.0 => format!("{}p{}", height, fps.round()),
+            (Some(height), _) => format!("{}p", height),
+            (None, _) if self.is_audio_only() => "audio".to_string(),
+            (None, _) => self.format_note.clone().unwrap_or_else(|| "unknown".to_string()),
+        };
+        parts.push(match &self.ext {
+            Some(ext) => format!("{} {}", resolution, ext),
+            None => resolution,
+        });
+        // Codec names like "avc1.640028" are shortened to the codec
+        let codec = |codec: &Option<String>| codec.as_deref().filter(|codec| *codec != "none").map(|codec| codec.split('.').next().unwrap_or(codec).to_string());
+        let codecs: Vec<String> = [codec(&self.vcodec), codec(&self.acodec)].into_iter().flatten().collect();
+        if !codecs.is_empty() {
+            parts.push(codecs.join("+"));
+        }
+        if self.is_video_only() {
+            parts.push("video only".to_string());
+        }
+        parts.join(" · ")
+    }
+
+    fn has_video(&self) -> bool {
+        self.vcodec.as_deref() != Some("none")
+    }
+
+    fn has_audio(&self) -> bool {
+        self.acodec.as_deref() != Some("none")
+    }
+
+    fn is_audio_only(&self) -> bool {
+        self.has_audio() && !self.has_video()
+    }
+
+    pub fn is_video_only(&self) -> bool {
+        self.has_video() && !self.has_audio()
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PlaylistEntry {
+    pub id: Option<String>,
+    pub ie_key: Option<String>,
+    pub url: Option<String>,
+    pub webpage_url: Option<String>,
+    // Shown for search results
+    pub title: Option<String>,
+    pub uploader: Option<String>,
+    pub channel: Option<String>,
+    pub duration: Option<f64>,
+}
+
+impl Info {
+    pub fn is_playlist(&self) -> bool {
+        self.kind.as_deref() == Some("playlist") && !self.entries.is_empty()
+    }
+
+    pub fn is_live(&self) -> bool {
+        self.is_live == Some(true)
+    }
+
+    pub fn metadata(&self) -> Metadata {
+        Metadata {
+            title: self.title.clone(),
+            uploader: self.uploader.clone(),
+            thumbnail: self.thumbnail.clone(),
+            duration: self.duration.map(|secs| secs as u64),
+            artist: self.artist.clone(),
+            track: self.track.clone(),
+            album: self.album.clone(),
+        }
+    }
+
+    pub fn archive_key(&self) -> Option<String> {
+        archive_key(self.extractor_key.as_deref()?, self.id.as_deref()?)
+    }
+
+    // Size of yt-dlp's default format choice, when the site reports it
+    pub fn estimated_size(&self) -> Option<u64> {
+        if let Some(size) = self.filesize.or(self.filesize_approx) {
+            return Some(size);
+        }
+        self.requested_formats.iter()
+            .map(FormatInfo::size)
+            .sum()
+    }
+
+    // Heights of the video formats on offer, highest first
+    pub fn heights(&self) -> Vec<u32> {
+        let mut heights: Vec<u32> = self.formats.iter()
+            .filter(|format| format.has_video())
+            .filter_map(|format| format.height)
+            .collect();
+        heights.sort_unstable_by(|a, b| b.cmp(a));
+        heights.dedup();
+        heights
+    }
+
+    // The formats someone could pick from, best first: yt-dlp lists them worst first, and
+    // storyboards are images rather than video
+    pub fn pickable_formats(&self) -> Vec<&FormatInfo> {
+        self.formats.iter()
+            .rev()
+            .filter(|format| format.format_id.is_some() && format.ext.as_deref() != Some("mhtml"))
+            .filter(|format| format.has_video() || format.has_audio())
+            .collect()
+    }
+
+    pub fn has_audio_only(&self) -> bool {
+        self.formats.iter().any(FormatInfo::is_audio_only)
+    }
+
+    // Roughly what a download in `format` would take, choosing formats the way its yt-dlp
+    // format selector does. None when the site doesn't report the sizes involved. Audio
+    // conversions to another codec end up a different size.
+    pub fn size_for(&self, format: FormatSpec) -> Option<u64> {
+        match format {
+            FormatSpec::Best => self.estimated_size(),
+            FormatSpec::MaxHeight(max) => self.video_size(|height| height <= max, true),
+            FormatSpec::Worst => self.video_size(|_| true, false),
+            FormatSpec::Audio(_) => self.audio(true)?.size(),
+        }
+    }
+
+    // The best (or worst) video whose height fits, plus audio unless it already has some
+    fn video_size(&self, fits: impl Fn(u32) -> bool, best: bool) -> Option<u64> {
+        let videos = self.formats.iter()
+            .filter(|format| format.has_video() && format.height.is_some_and(&fits));
+        let rank = |format: &&FormatInfo| (format.height, format.size());
+        let video = if best { videos.max_by_key(rank) } else { videos.min_by_key(rank) }?;
+        match self.audio(best) {
+            Some(audio) if !video.has_audio() => Some(video.size()? + audio.size()?),
+            _ => video.size(),
+        }
+    }
+
+    fn audio(&self, best: bool) -> Option<&FormatInfo> {
+        let audio = self.formats.iter().filter(|format| format.is_audio_only());
+        if best { audio.max_by_key(|format| format.size()) } else { audio.min_by_key(|format| format.size()) }
+    }
+}
+
+impl PlaylistEntry {
+    pub fn archive_key(&self) -> Option<String> {
+        archive_key(self.ie_key.as_deref()?, self.id.as_deref()?)
+    }
+
+    // What a flat entry tells about the video before it's probed itself
+    pub fn metadata(&self) -> Metadata {
+        Metadata {
+            title: self.title.clone(),
+            uploader: self.uploader.clone().or_else(|| self.channel.clone()),
+            duration: self.duration.map(|secs| secs as u64),
+            ..Metadata::default()
+        }
+    }
+
+    // Flat entries usually carry a full URL, but some extractors only give an ID
+    pub fn download_url(&self) -> Option<&str> {
+        [self.webpage_url.as_deref(), self.url.as_deref()]
+            .into_iter()
+            .flatten()
+            .find(|url| url.starts_with("http://") || url.starts_with("https://"))
+    }
+}
+
+// Identifies a video the same way yt-dlp's download archive does, e.g. "youtube dQw4w9WgXcQ"
+pub fn archive_key(extractor: &str, id: &str) -> Option<String> {
+    (!extractor.is_empty() && !id.is_empty()).then(|| format!("{} {}", extractor.to_lowercase(), id))
+}
+
+pub async fn probe(url: &str, cookies: &[String], extra_args: &[String]) -> Result<Info> {
+    let mut cmd = sandbox::command(binary::path(), None);
+    utf8_output(&mut cmd);
+    cmd.arg("--flat-playlist").arg("-J").arg(url)
+        .args(cookies)
+        .args(extra_args);
+    cmd.kill_on_drop(true);
+    let _running = RunningProcess::start(None);
+    let output = cmd.output().await
+        .with_context(|| "Failed to run yt-dlp")?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(anyhow::anyhow!("yt-dlp failed with status: {}\nError output: {}", output.status, stderr.trim()));
+    }
+    serde_json::from_slice(&output.stdout).context("Failed to parse yt-dlp metadata")
+}
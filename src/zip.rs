@@ -0,0 +1,310 @@
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use serenity::builder::CreateAttachment;
+use serenity::http::Http;
+use serenity::model::id::GuildId;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use crate::history;
+use crate::messages::Messages;
+use crate::progress::format_bytes;
+use crate::storage::hex;
+use crate::template::civil_date;
+use crate::upload;
+
+const MIB: u64 = 1024 * 1024;
+
+// Without ZIP64, sizes and offsets have to fit in 32 bits
+const MAX_ZIP_BYTES: u64 = u32::MAX as u64;
+
+// The [zip] section of the config
+#[derive(Debug, Clone, Deserialize)]
+pub struct ZipSettings {
+    // Whether results of several files are zipped; channels can turn it on or off for themselves
+    #[serde(default)]
+    pub enabled: bool,
+    // Results adding up to more than this aren't zipped
+    #[serde(default = "default_max_mb")]
+    pub max_mb: u64,
+    // Where the zips are written, and how long they're kept and linked
+    #[serde(default = "default_dir")]
+    pub dir: String,
+    #[serde(default = "default_link_expiry_mins")]
+    pub link_expiry_mins: u64,
+    // Where the dashboard server can be reached from, e.g. https://bot.example.com; zips too big
+    // for Discord are linked from there
+    pub public_url: Option<String>,
+}
+
+impl Default for ZipSettings {
+    fn default() -> Self {
+        ZipSettings {
+            enabled: false,
+            max_mb: default_max_mb(),
+            dir: default_dir(),
+            link_expiry_mins: default_link_expiry_mins(),
+            public_url: None,
+        }
+    }
+}
+
+fn default_max_mb() -> u64 {
+    500
+}
+
+fn default_dir() -> String {
+    "data/zips".to_string()
+}
+
+fn default_link_expiry_mins() -> u64 {
+    60
+}
+
+// A zip made for a result, and where it can be downloaded from
+pub struct Zipped {
+    pub path: PathBuf,
+    pub size: u64,
+    // The link, and when it expires as a Unix time
+    pub link: Option<(String, i64)>,
+}
+
+// The zips that can still be downloaded, by the token in their link
+pub struct Zips {
+    settings: ZipSettings,
+    links: Mutex<HashMap<String, (PathBuf, i64)>>,
+}
+
+impl Zips {
+    // Zips left from before a restart have no links anymore. Only the ones named like the bot
+    // names them are removed, in case dir is shared with other files.
+    pub fn new(settings: ZipSettings) -> Self {
+        if let Ok(entries) = fs::read_dir(&settings.dir) {
+            for path in entries.flatten().map(|entry| entry.path()) {
+                if is_made_here(&path) {
+                    let _ = fs::remove_file(&path);
+                }
+            }
+        }
+        Zips { settings, links: Mutex::default() }
+    }
+
+    pub fn settings(&self) -> &ZipSettings {
+        &self.settings
+    }
+
+    // Zips the files into <name>.zip, each named by its path inside the directory they share
+    pub async fn zip(&self, name: &str, files: &[PathBuf]) -> Result<Zipped> {
+        self.expire();
+        let total: u64 = files.iter().filter_map(|file| fs::metadata(file).ok()).map(|meta| meta.len()).sum();
+        let limit = (self.settings.max_mb * MIB).min(MAX_ZIP_BYTES);
+        if total > limit {
+            bail!("The files add up to {}, more than the {} zips may hold", format_bytes(total), format_bytes(limit));
+        }
+        let token = token()?;
+        let dir = Path::new(&self.settings.dir);
+        fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+        let path = dir.join(format!("{}-{}.zip", file_name(name), &token[..8]));
+        let base = shared_dir(files);
+        let entries: Vec<(PathBuf, String)> = files.iter()
+            .map(|file| {
+                let name = file.strip_prefix(&base).unwrap_or(file.as_path());
+                let name = name.components().map(|part| part.as_os_str().to_string_lossy()).collect::<Vec<_>>().join("/");
+                (file.clone(), name)
+            })
+            .collect();
+        let target = path.clone();
+        tokio::task::spawn_blocking(move || write_zip(&target, &entries))
+            .await
+            .context("Zipping panicked")?
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        let size = fs::metadata(&path).map(|meta| meta.len()).unwrap_or_default();
+        let expires = history::now() + self.settings.link_expiry_mins as i64 * 60;
+        let link = self.settings.public_url.as_ref().map(|base| {
+            (format!("{}/zip/{}", base.trim_end_matches('/'), token), expires)
+        });
+        self.links.lock().unwrap().insert(token, (path.clone(), expires));
+        Ok(Zipped { path, size, link })
+    }
+
+    // The zip a link leads to, while it hasn't expired
+    pub fn file(&self, token: &str) -> Option<PathBuf> {
+        self.expire();
+        self.links.lock().unwrap().get(token).map(|(path, _)| path.clone())
+    }
+
+    fn expire(&self) {
+        let now = history::now();
+        self.links.lock().unwrap().retain(|_, (path, expires)| {
+            if *expires > now {
+                return true;
+            }
+            let _ = fs::remove_file(&*path);
+            false
+        });
+    }
+}
+
+// Zipping a result for where it's posted
+pub struct Zipping {
+    pub zips: Arc<Zips>,
+    // Whose upload limit the zip has to fit in to be attached
+    pub guild: Option<GuildId>,
+}
+
+impl Zipping {
+    // The zip as an attachment when it's small enough for Discord, and a line saying where it is
+    pub async fn deliver(&self, http: &Http, name: &str, files: &[PathBuf], spoiler: bool, messages: &Messages) -> (Option<CreateAttachment>, String) {
+        let zipped = match self.zips.zip(name, files).await {
+            Ok(zipped) => zipped,
+            Err(e) => {
+                log::warn!("Failed to zip {}: {:#}", name, e);
+                return (None, messages.get("zip_failed", &[("error", &e)]));
+            }
+        };
+        let file_name = zipped.path.file_name().unwrap_or(zipped.path.as_os_str()).to_string_lossy();
+        let described = format!("`{}` ({})", file_name, format_bytes(zipped.size));
+        if let Some(attachment) = upload::attachment_for(http, self.guild, std::slice::from_ref(&zipped.path)).await {
+            let attachment = if spoiler { upload::spoiler(attachment) } else { attachment };
+            return (Some(attachment), messages.get("zip_attached", &[("zip", &described)]));
+        }
+        match &zipped.link {
+            Some((link, expires)) => (None, messages.get("zip_linked", &[("zip", &described), ("link", link), ("expires", expires)])),
+            None => (None, messages.get("zip_too_big", &[("zip", &described)])),
+        }
+    }
+}
+
+// <name>-<first 8 hex digits of the token>.zip
+fn is_made_here(path: &Path) -> bool {
+    if path.extension().is_none_or(|ext| ext != "zip") {
+        return false;
+    }
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    stem.rsplit_once('-').is_some_and(|(name, token)| {
+        !name.is_empty() && token.len() == 8 && token.chars().all(|c| c.is_ascii_digit() || ('a'..='f').contains(&c))
+    })
+}
+
+// The deepest directory all the files are in
+fn shared_dir(files: &[PathBuf]) -> PathBuf {
+    let mut shared = files.first().and_then(|file| file.parent()).map(Path::to_path_buf).unwrap_or_default();
+    for file in files {
+        while !file.starts_with(&shared) {
+            if !shared.pop() {
+                break;
+            }
+        }
+    }
+    shared
+}
+
+// Titles can have anything in them
+fn file_name(name: &str) -> String {
+    let name: String = name.chars()
+        .map(|c| if c.is_alphanumeric() || c == '-' || c == '_' { c } else { '_' })
+        .take(40)
+        .collect();
+    if name.is_empty() { "download".to_string() } else { name }
+}
+
+// 128 bits from the OS's random number generator, so the link can't be guessed
+pub fn token() -> Result<String> {
+    let mut bytes = [0; 16];
+    getrandom::getrandom(&mut bytes).context("Failed to get random bytes")?;
+    Ok(hex(&bytes))
+}
+
+// The entries are stored as they are, since videos and music don't compress. Each entry's
+// CRC-32 and size are filled into its header once its data is written.
+fn write_zip(path: &Path, entries: &[(PathBuf, String)]) -> Result<()> {
+    let mut out = BufWriter::new(File::create(path)?);
+    let (time, date) = dos_time(history::now());
+    let mut central = Vec::new();
+    let mut offset = 0u64;
+    for (file, name) in entries {
+        let mut input = File::open(file).with_context(|| format!("Failed to open {}", file.display()))?;
+        let mut header = Vec::with_capacity(30 + name.len());
+        header.extend(0x04034b50u32.to_le_bytes());
+        header.extend(local_fields(time, date, 0, 0));
+        header.extend((name.len() as u16).to_le_bytes());
+        header.extend(0u16.to_le_bytes());
+        header.extend(name.as_bytes());
+        out.write_all(&header)?;
+
+        let mut crc = crc32fast::Hasher::new();
+        let mut size = 0u64;
+        let mut buf = vec![0; 1 << 16];
+        loop {
+            let read = input.read(&mut buf).with_context(|| format!("Failed to read {}", file.display()))?;
+            if read == 0 {
+                break;
+            }
+            crc.update(&buf[..read]);
+            out.write_all(&buf[..read])?;
+            size += read as u64;
+        }
+        if offset + header.len() as u64 + size > MAX_ZIP_BYTES {
+            bail!("The zip would be bigger than {}", format_bytes(MAX_ZIP_BYTES));
+        }
+        let crc = crc.finalize();
+        // CRC-32, compressed size and size sit 14 bytes into the local header
+        out.seek(SeekFrom::Start(offset + 14))?;
+        out.write_all(&crc.to_le_bytes())?;
+        out.write_all(&(size as u32).to_le_bytes())?;
+        out.write_all(&(size as u32).to_le_bytes())?;
+        out.seek(SeekFrom::End(0))?;
+
+        central.extend(0x02014b50u32.to_le_bytes());
+        // Made by and needed: version 2.0
+        central.extend(20u16.to_le_bytes());
+        central.extend(local_fields(time, date, crc, size as u32));
+        central.extend((name.len() as u16).to_le_bytes());
+        // Extra field, comment, disk number, internal and external attributes
+        central.extend([0; 12]);
+        central.extend((offset as u32).to_le_bytes());
+        central.extend(name.as_bytes());
+        offset += header.len() as u64 + size;
+    }
+    let entries_count = entries.len() as u16;
+    let mut end = Vec::with_capacity(22);
+    end.extend(0x06054b50u32.to_le_bytes());
+    // This disk and the disk the directory starts on
+    end.extend([0; 4]);
+    end.extend(entries_count.to_le_bytes());
+    end.extend(entries_count.to_le_bytes());
+    end.extend((central.len() as u32).to_le_bytes());
+    end.extend((offset as u32).to_le_bytes());
+    end.extend(0u16.to_le_bytes());
+    out.write_all(&central)?;
+    out.write_all(&end)?;
+    out.flush()?;
+    Ok(())
+}
+
+// What local and central headers share: version needed, flags (UTF-8 names), method
+// (stored), time, date, CRC-32, compressed size and size
+fn local_fields(time: u16, date: u16, crc: u32, size: u32) -> Vec<u8> {
+    let mut fields = Vec::with_capacity(22);
+    fields.extend(20u16.to_le_bytes());
+    fields.extend(0x0800u16.to_le_bytes());
+    fields.extend(0u16.to_le_bytes());
+    fields.extend(time.to_le_bytes());
+    fields.extend(date.to_le_bytes());
+    fields.extend(crc.to_le_bytes());
+    fields.extend(size.to_le_bytes());
+    fields.extend(size.to_le_bytes());
+    fields
+}
+
+// MS-DOS time and date, in UTC
+fn dos_time(secs: i64) -> (u16, u16) {
+    let (year, month, day) = civil_date(secs.div_euclid(86_400));
+    let of_day = secs.rem_euclid(86_400);
+    let time = ((of_day / 3600) << 11) | ((of_day / 60 % 60) << 5) | ((of_day % 60) / 2);
+    let date = ((year.max(1980) - 1980) << 9) | (i64::from(month) << 5) | i64::from(day);
+    (time as u16, date as u16)
+}